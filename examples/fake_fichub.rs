@@ -0,0 +1,267 @@
+//! A stand-in for the fichub API, AO3 work pages and an OpenID Connect provider, for local
+//! development and `test.sh`.
+//!
+//! Run with `cargo run --example fake_fichub` and point the server at it with
+//! `FICAI_FICHUB_URL=http://127.0.0.1:8081` and `FICAI_AO3_URL=http://127.0.0.1:8081`. For
+//! fichub, the URL being looked up picks the behavior:
+//!
+//! * containing `hang-15`: answers normally, but only after 15 seconds
+//! * containing `error-500`: fails with status 500
+//! * containing `not-found`: answers like fichub does for URLs it can't handle
+//! * containing `growing`: gains a chapter with every lookup
+//! * anything else: answers with made-up metadata derived from the URL. URLs with the same
+//!   `fic=<key>` parameter get the same id, as if they were copies of the same fic. URLs with the
+//!   same `dup=<key>` parameter get different ids, but the same title and author. URLs with the
+//!   same `author=<key>` parameter are by the same author, other than everything else. URLs with
+//!   `series=<key>&part=<n>` are part `n` of the same series. URLs with `status=<status>` report
+//!   that status instead of `ongoing`.
+//!
+//! `GET /works/<id>` answers with a page for any AO3 work.
+//!
+//! `GET /page/<name>` answers with a page that has link preview tags, except for `huge`, which is
+//! a 128 KiB page, and `redirect`, which redirects to `localhost` instead of `127.0.0.1`.
+//!
+//! `GET /requests?q=<url>` tells how many lookups of a URL were made so far.
+//!
+//! Under `/oidc` is an OpenID Connect provider for `FICAI_OAUTH_ISSUER`, with the client secret
+//! `oidc-secret`. Its authorization endpoint logs in whoever is given as `login_hint` right away;
+//! emails containing `unverified` are reported as such.
+//!
+//! `POST /captcha/siteverify` checks CAPTCHA tokens for `FICAI_CAPTCHA_VERIFY_URL`, with the
+//! secret `captcha-secret`. Tokens starting with `solved` pass.
+
+use reqwest::Url;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use warp::http::StatusCode;
+use warp::{Filter as _, Reply as _};
+
+#[derive(Deserialize)]
+struct Q {
+    q: String,
+}
+
+#[tokio::main]
+async fn main() {
+    let listen: SocketAddr = std::env::var("FAKE_FICHUB_LISTEN")
+        .unwrap_or_else(|_| "127.0.0.1:8081".to_string())
+        .parse()
+        .expect("FAKE_FICHUB_LISTEN is not a socket address");
+    let requests = Arc::new(Mutex::new(HashMap::<String, u64>::new()));
+
+    let epub = {
+        let requests = requests.clone();
+        warp::path!("api" / "v0" / "epub")
+            .and(warp::query::<Q>())
+            .then(move |Q { q }: Q| {
+                let n = {
+                    let mut requests = requests.lock().unwrap();
+                    let n = requests.entry(q.clone()).or_default();
+                    *n += 1;
+                    *n
+                };
+                lookup(q, n)
+            })
+    };
+    let work = warp::path!("works" / u64).map(|id| {
+        warp::reply::html(format!(
+            r#"<html><body>
+<dl class="work meta group">
+  <dd class="fandom tags"><ul class="commas">
+    <li><a class="tag" href="/tags/Worm%20-%20Wildbow/works">Worm - Wildbow</a></li>
+    <li><a class="tag" href="/tags/Pok%C3%A9mon/works">Pokémon</a></li>
+  </ul></dd>
+  <dd class="published">2020-01-02</dd>
+  <dd class="status">2021-03-04</dd>
+  <dd class="words">12,345</dd>
+  <dd class="chapters">3/3</dd>
+  <dd class="series"><span class="series"><span class="position">Part 2 of <a href="/series/77">Fake &amp; series</a></span></span></dd>
+</dl>
+<div id="workskin">
+  <h2 class="title heading">
+    Fake AO3 work {} &amp; co
+  </h2>
+  <h3 class="byline heading"><a rel="author" href="/users/fake/pseuds/fake">fake</a></h3>
+</div>
+</body></html>"#,
+            id
+        ))
+    });
+    let page = warp::path!("page" / String).map(|name: String| match name.as_str() {
+        "huge" => warp::reply::html("x".repeat(128 * 1024)).into_response(),
+        "redirect" => warp::redirect::temporary(
+            "http://localhost:8081/page/elsewhere"
+                .parse::<warp::http::Uri>()
+                .unwrap(),
+        )
+        .into_response(),
+        _ => warp::reply::html(format!(
+            r#"<html><head>
+<title>ignored</title>
+<meta property="og:site_name" content="Fake Fiction">
+<meta content="Fake page {} &amp; co" property="og:title" />
+</head><body></body></html>"#,
+            name
+        ))
+        .into_response(),
+    });
+    let count = warp::path!("requests")
+        .and(warp::query::<Q>())
+        .map(move |Q { q }: Q| {
+            let n = requests.lock().unwrap().get(&q).copied().unwrap_or(0);
+            warp::reply::json(&json!({ "requests": n }))
+        });
+
+    let discovery = warp::path!("oidc" / ".well-known" / "openid-configuration").map(move || {
+        warp::reply::json(&json!({
+            "issuer": format!("http://{}/oidc", listen),
+            "authorization_endpoint": format!("http://{}/oidc/authorize", listen),
+            "token_endpoint": format!("http://{}/oidc/token", listen),
+            "userinfo_endpoint": format!("http://{}/oidc/userinfo", listen),
+        }))
+    });
+    let authorize = warp::path!("oidc" / "authorize")
+        .and(warp::query::<HashMap<String, String>>())
+        .map(|q: HashMap<String, String>| {
+            let mut url = Url::parse(&q["redirect_uri"]).unwrap();
+            url.query_pairs_mut()
+                .append_pair("code", &q["login_hint"])
+                .append_pair("state", &q["state"]);
+            warp::redirect::temporary(url.as_str().parse::<warp::http::Uri>().unwrap())
+        });
+    // The code is the email, and so is the access token.
+    let token = warp::path!("oidc" / "token")
+        .and(warp::post())
+        .and(warp::body::form::<HashMap<String, String>>())
+        .map(|form: HashMap<String, String>| {
+            if form.get("client_secret").map(String::as_str) != Some("oidc-secret") {
+                return warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "invalid_client" })),
+                    StatusCode::UNAUTHORIZED,
+                );
+            }
+            warp::reply::with_status(
+                warp::reply::json(&json!({
+                    "access_token": form["code"],
+                    "token_type": "Bearer",
+                })),
+                StatusCode::OK,
+            )
+        });
+    let userinfo = warp::path!("oidc" / "userinfo")
+        .and(warp::header::<String>("authorization"))
+        .map(|authorization: String| {
+            let email = authorization.trim_start_matches("Bearer ");
+            warp::reply::json(&json!({
+                "sub": hex::encode(&Sha256::digest(email.as_bytes())[..8]),
+                "email": email,
+                "email_verified": !email.contains("unverified"),
+            }))
+        });
+
+    let siteverify = warp::path!("captcha" / "siteverify")
+        .and(warp::post())
+        .and(warp::body::form::<HashMap<String, String>>())
+        .map(|form: HashMap<String, String>| {
+            if form.get("secret").map(String::as_str) != Some("captcha-secret") {
+                return warp::reply::json(&json!({
+                    "success": false,
+                    "error-codes": ["invalid-input-secret"],
+                }));
+            }
+            let solved = form
+                .get("response")
+                .is_some_and(|token| token.starts_with("solved"));
+            warp::reply::json(&json!({
+                "success": solved,
+                "error-codes": if solved { vec![] } else { vec!["invalid-input-response"] },
+            }))
+        });
+
+    println!("fake fichub listening on {}", listen);
+    warp::serve(
+        epub.or(work)
+            .or(page)
+            .or(count)
+            .or(discovery)
+            .or(authorize)
+            .or(token)
+            .or(userinfo)
+            .or(siteverify),
+    )
+    .run(listen)
+    .await;
+}
+
+async fn lookup(q: String, n: u64) -> warp::reply::WithStatus<warp::reply::Json> {
+    if q.contains("hang-15") {
+        tokio::time::sleep(std::time::Duration::from_secs(15)).await;
+    }
+    if q.contains("error-500") {
+        return warp::reply::with_status(
+            warp::reply::json(&json!({ "error": "internal server error" })),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        );
+    }
+    if q.contains("not-found") {
+        return warp::reply::with_status(
+            warp::reply::json(&json!({ "err": 1, "msg": "unsupported url" })),
+            StatusCode::OK,
+        );
+    }
+    let key = param(&q, "fic").unwrap_or(&q);
+    let id = hex::encode(&Sha256::digest(key.as_bytes())[..4]);
+    let (chapters, updated) = if q.contains("growing") {
+        (10 + n, format!("2022-05-{:02}T07:08:09", n))
+    } else {
+        (12, "2022-05-06T07:08:09".to_string())
+    };
+    let title = match param(&q, "dup") {
+        Some(dup) => format!("Fake fic {}", dup),
+        None => format!("Fake fic {}", id),
+    };
+    let (author, author_id) = match param(&q, "author") {
+        Some(key) => (format!("Author {}", key), format!("author-{}", key)),
+        None => ("Fake author".to_string(), "fake-author".to_string()),
+    };
+    let series = param(&q, "series").map(|key| {
+        json!({
+            "id": format!("series-{}", key),
+            "title": format!("Series {}", key),
+            "position": param(&q, "part").and_then(|p| p.parse::<i32>().ok()),
+        })
+    });
+    warp::reply::with_status(
+        warp::reply::json(&json!({
+            "err": 0,
+            "urlId": id,
+            "meta": {
+                "id": id,
+                "title": title,
+                "source": q,
+                "author": author,
+                "authorId": author_id,
+                "words": 123456,
+                "chapters": chapters,
+                "status": param(&q, "status").unwrap_or("ongoing"),
+                "updated": updated,
+                "rawExtendedMeta": { "fandoms": ["Worm", "Pact"], "series": series },
+            },
+        })),
+        StatusCode::OK,
+    )
+}
+
+fn param<'a>(url: &'a str, name: &str) -> Option<&'a str> {
+    let (_, query) = url.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
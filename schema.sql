@@ -1,25 +1,513 @@
+-- Bump together with `meta::SCHEMA_VERSION` whenever this file changes, and add the change to
+-- `migrate::migrate` for existing databases.
+create table schema_version (
+    version integer primary key
+);
+
+insert into schema_version (version) values (42);
+
 create sequence account_id_seq as bigint;
 
+-- Declared from least to most privileged, so roles can be compared with `>=`.
+create type account_role as enum ('user', 'moderator', 'admin');
+
+create type account_status as enum ('active', 'suspended', 'banned');
+
+create type rate_limit_tier as enum ('normal', 'trusted-importer', 'restricted');
+
+create type account_kind as enum ('person', 'service');
+
 create table account (
     id bigint primary key default nextval('account_id_seq')
   , email varchar(256) not null constraint account_email_u unique
+    -- What others see of the account instead of its email, unique regardless of case.
+  , display_name varchar(32)
+    -- Whether others see the account's stats on its public profile.
+  , profile_stats_public boolean not null default false
+    -- Client settings synced across browsers, see `preferences::SyncedPreferences`.
+  , preferences jsonb not null default '{}'
   , password_hash varchar(1024) not null
+  , role account_role not null default 'user'
+    -- Set by moderators, see `account_status`. Suspensions end at `suspended_until`.
+  , status account_status not null default 'active'
+  , status_reason text
+  , suspended_until timestamptz
+    -- Which write limit applies, see `write_limit::WriteLimiter`.
+  , rate_limit_tier rate_limit_tier not null default 'normal'
+    -- Service accounts can't log in and only use tokens, see `service_account`. Their email is
+    -- `service:<id>`.
+  , kind account_kind not null default 'person'
+  , created_ip inet
+    -- IANA time zone name, used to format timestamps for display.
+  , timezone varchar(64) not null default 'UTC'
+    -- Deleted accounts keep their row, anonymized, so that what references them stays valid.
+  , deleted_at timestamptz
+    -- The invite the account was created with, see `invite`.
+  , invite_id bigint
+  , created_at timestamptz not null default now()
 );
 
 alter sequence account_id_seq owned by account.id;
 
+create unique index account_display_name_u on account (lower(display_name));
+
+create sequence invite_id_seq as bigint;
+
+-- Codes needed to create an account. To bootstrap, insert one without `created_by`, e.g.
+-- `insert into invite (code) values ('...')` for one that can be used any number of times.
+create table invite (
+    id bigint primary key default nextval('invite_id_seq')
+  , code varchar(64) not null constraint invite_code_u unique
+  , created_by bigint references account(id)
+    -- How many accounts may be created with it, or null for no limit.
+  , max_uses integer check (max_uses > 0)
+  , uses integer not null default 0
+  , expires_at timestamptz
+  , created_at timestamptz not null default now()
+);
+
+alter sequence invite_id_seq owned by invite.id;
+
+alter table account add constraint account_invite_id_fk foreign key (invite_id) references invite(id);
+
+create index invite_created_by_idx on invite (created_by);
+
+-- Signals on behalf of the server itself, e.g. `fandom:` tags taken from fic metadata, see
+-- `signal::SYSTEM_ACCOUNT_ID`. Its password hash can't be verified, so nobody can log in as it.
+insert into account (id, email, password_hash) values (0, 'system', '');
+
+-- Grants on top of what an account's role implies.
+create type permission as enum ('tag-curation', 'user-moderation', 'data-export', 'settings');
+
+create table account_permission (
+    account_id bigint not null references account(id)
+  , permission permission not null
+  , primary key (account_id, permission)
+);
+
 create table session (
     id bytea primary key
   , account_id bigint not null references account(id)
+  , created_ip inet
+  , user_agent varchar(512)
+  , created_at timestamptz not null default now()
+  , last_used_at timestamptz not null default now()
+    -- Sessions end at `expires_at`, or once unused for `idle_timeout`, whichever comes first.
+  , expires_at timestamptz not null
+  , idle_timeout interval not null
+);
+
+create index session_account_id_idx on session (account_id);
+
+create sequence token_id_seq as bigint;
+
+-- Personal API tokens, keyed by the SHA-256 of the token, see `api_token`.
+create table token (
+    id bigint primary key default nextval('token_id_seq')
+  , account_id bigint not null references account(id)
+  , name varchar(256) not null
+  , token_hash bytea not null constraint token_hash_u unique
+  , scopes varchar(32)[] not null check (scopes <@ array['read', 'write-signals']::varchar(32)[])
+  , created_at timestamptz not null default now()
+  , last_used_at timestamptz
+);
+
+alter sequence token_id_seq owned by token.id;
+
+create index token_account_id_idx on token (account_id);
+
+-- Accounts' identities at OpenID Connect providers, see `oauth`.
+create table oauth_identity (
+    issuer varchar(512) not null
+  , subject varchar(256) not null
+  , account_id bigint not null references account(id)
+  , created_at timestamptz not null default now()
+  , primary key (issuer, subject)
+);
+
+create index oauth_identity_account_id_idx on oauth_identity (account_id);
+
+-- Accounts' secrets for two-factor authentication with TOTP, see `totp`. Until `enabled_at` is set,
+-- enabling it is pending confirmation with a first code.
+create table account_totp (
+    account_id bigint primary key references account(id)
+  , secret bytea not null
+  , enabled_at timestamptz
+    -- The time step of the last code used, which can't be used again.
+  , last_used_step bigint
+  , created_at timestamptz not null default now()
+);
+
+-- Unused recovery codes of accounts with two-factor authentication, keyed by their SHA-256.
+create table totp_recovery_code (
+    code_hash bytea primary key
+  , account_id bigint not null references account(id)
+);
+
+create index totp_recovery_code_account_id_idx on totp_recovery_code (account_id);
+
+create type account_event_kind as enum (
+    'login', 'oauth-login', 'magic-link-login', 'password-change', 'password-reset', 'email-change', 'session-revoke',
+    'other-sessions-revoke', 'two-factor-enable', 'two-factor-disable'
+);
+
+create sequence account_event_id_seq as bigint;
+
+-- What happened to accounts, for their owners to audit, see `account_event`.
+create table account_event (
+    id bigint primary key default nextval('account_event_id_seq')
+  , account_id bigint not null references account(id)
+  , kind account_event_kind not null
+    -- The client that caused the event.
+  , ip inet
+  , user_agent varchar(512)
+  , created_at timestamptz not null default now()
+);
+
+alter sequence account_event_id_seq owned by account_event.id;
+
+create index account_event_account_id_idx on account_event (account_id, id);
+
+-- Failed logins, by the email tried and the address trying it, see `login_throttle`. Rows are
+-- deleted once out of the throttle's window.
+create table login_failure (
+    email varchar(256) not null
+  , ip inet
+  , failed_at timestamptz not null default now()
+);
+
+create index login_failure_email_idx on login_failure (email, failed_at);
+create index login_failure_ip_idx on login_failure (ip, failed_at);
+create index login_failure_failed_at_idx on login_failure (failed_at);
+
+-- Outstanding password reset tokens, keyed by the SHA-256 of the token that was sent by email.
+-- Tokens are deleted once used.
+create table password_reset (
+    token_hash bytea primary key
+  , account_id bigint not null references account(id)
+  , created_at timestamptz not null default now()
+  , expires_at timestamptz not null
 );
 
--- Used for levenshtein in tag search.
-create extension if not exists fuzzystrmatch;
+create index password_reset_account_id_idx on password_reset (account_id);
+
+-- Outstanding login links, keyed by the SHA-256 of the token that was sent by email. Tokens are
+-- deleted once used, along with the account's others.
+create table magic_link (
+    token_hash bytea primary key
+  , account_id bigint not null references account(id)
+  , created_at timestamptz not null default now()
+  , expires_at timestamptz not null
+);
+
+create index magic_link_account_id_idx on magic_link (account_id);
+
+-- Pending email changes, keyed by the SHA-256 of the token that was sent to the new address.
+-- An account has at most one; it is deleted once confirmed.
+create table email_change (
+    token_hash bytea primary key
+  , account_id bigint not null references account(id)
+  , new_email varchar(256) not null
+  , created_at timestamptz not null default now()
+  , expires_at timestamptz not null
+);
+
+create index email_change_account_id_idx on email_change (account_id);
+
+-- Used for similarity matching in tag autocomplete.
+create extension if not exists pg_trgm;
 
 create table signal (
     account_id bigint not null references account(id)
   , url varchar(1024) not null
+    -- As given by the user who first signalled it.
   , tag varchar(1024) not null
+    -- Lowercased and NFC-normalized, see `tag::canonicalize`. Signals are grouped by this.
+  , tag_canonical varchar(1024) not null
   , signal boolean not null
-  , primary key (account_id, url, tag)
+  , created_at timestamptz not null default now()
+  , updated_at timestamptz not null default now()
+    -- For tag search, see `tag_search::TagSearch::get`.
+  , tag_document tsvector not null
+        generated always as (setweight(to_tsvector('english', tag_canonical), 'A')) stored
+  , primary key (account_id, url, tag_canonical)
+);
+
+create index signal_tag_canonical_idx on signal (tag_canonical);
+-- Tag autocomplete: prefix matches and trigram similarity.
+create index signal_tag_prefix_idx on signal (tag_canonical text_pattern_ops);
+create index signal_tag_trgm_idx on signal using gin (tag_canonical gin_trgm_ops);
+-- Trending tags.
+create index signal_created_at_idx on signal (created_at);
+-- Tag search.
+create index signal_tag_document_idx on signal using gin (tag_document);
+
+-- Declared from least to most severe.
+create type warning_severity as enum ('mild', 'moderate', 'severe');
+
+-- Tables below that are keyed by tag store its canonical form.
+create table tag_meta (
+    tag varchar(1024) primary key
+  , description text
+  , category varchar(64)
+    -- How clients should render the tag. Color as `#rrggbb`, icon as a name from the client's
+    -- icon set.
+  , color varchar(7)
+  , icon varchar(64)
+  , warning warning_severity
+    -- For tag search, along with `signal.tag_document`. The name ranks above the description.
+  , document tsvector not null generated always as (
+        setweight(to_tsvector('english', tag), 'A')
+            || setweight(to_tsvector('english', coalesce(description, '')), 'B')
+    ) stored
+);
+
+create index tag_meta_document_idx on tag_meta using gin (document);
+
+create type proposal_status as enum ('open', 'approved', 'rejected');
+
+-- Categories proposed for uncategorized tags by the background inference job. Decided ones are
+-- kept, so that they aren't proposed again.
+create table tag_category_proposal (
+    tag varchar(1024) primary key
+  , category varchar(64) not null
+  , reason text not null
+  , created_at timestamptz not null default now()
+  , status proposal_status not null default 'open'
+  , decided_by bigint references account(id)
+  , decided_at timestamptz
+);
+
+-- Labels a tag is displayed with in other languages. Signals on a label are stored on its tag.
+create table tag_translation (
+    tag varchar(1024) not null
+    -- Lowercased BCP 47 language tag, e.g. `de` or `pt-br`.
+  , locale varchar(35) not null
+  , label varchar(1024) not null
+  , label_canonical varchar(1024) not null
+  , primary key (tag, locale)
+);
+
+create index tag_translation_label_idx on tag_translation (label_canonical text_pattern_ops);
+
+-- Tags that were merged into another one. Signals on an alias are stored on the tag it points to.
+create table tag_alias (
+    alias varchar(1024) primary key
+  , tag varchar(1024) not null
+);
+
+-- Tags that were used for the first time, awaiting review by moderators.
+create table tag_review_queue (
+    tag varchar(1024) primary key
+  , display varchar(1024) not null
+    -- Who used the tag first, and where.
+  , account_id bigint not null references account(id)
+  , url varchar(1024) not null
+  , created_at timestamptz not null default now()
+);
+
+-- Signals per tag and day of creation, recomputed periodically by a background job.
+create table tag_stats_daily (
+    tag varchar(1024) not null
+  , day date not null
+  , signals_for bigint not null
+  , signals_against bigint not null
+  , primary key (tag, day)
+);
+
+-- Signals per fic and day of their last change, recomputed periodically by a background job.
+create table fic_stats_daily (
+    fic_id varchar(64) not null
+  , day date not null
+  , signals bigint not null
+  , primary key (fic_id, day)
+);
+
+create index fic_stats_daily_day_idx on fic_stats_daily (day);
+
+-- Signalling for `tag` also signals for `implied`, e.g. a ship implies its fandom.
+create table tag_implication (
+    tag varchar(1024) not null
+  , tag_display varchar(1024) not null
+  , implied varchar(1024) not null
+  , implied_canonical varchar(1024) not null
+  , created_by bigint not null references account(id)
+  , created_at timestamptz not null default now()
+  , primary key (tag, implied_canonical)
+);
+
+-- Tags an account follows, for its feed.
+create table tag_subscription (
+    account_id bigint not null references account(id)
+  , tag varchar(1024) not null
+    -- As given when subscribing, shown until the tag is used.
+  , display varchar(1024) not null
+  , created_at timestamptz not null default now()
+  , primary key (account_id, tag)
+);
+
+create index tag_subscription_tag_idx on tag_subscription (tag);
+
+create sequence tag_alias_proposal_id_seq as bigint;
+
+-- Merges proposed by users, to be approved or rejected by a moderator.
+create table tag_alias_proposal (
+    id bigint primary key default nextval('tag_alias_proposal_id_seq')
+  , from_tag varchar(1024) not null
+  , from_canonical varchar(1024) not null
+  , into_tag varchar(1024) not null
+  , into_canonical varchar(1024) not null
+  , proposed_by bigint not null references account(id)
+  , status proposal_status not null default 'open'
+  , created_at timestamptz not null default now()
+  , decided_by bigint references account(id)
+  , decided_at timestamptz
+);
+
+alter sequence tag_alias_proposal_id_seq owned by tag_alias_proposal.id;
+
+create index tag_alias_proposal_status_idx on tag_alias_proposal (status);
+
+create table tag_alias_proposal_vote (
+    proposal_id bigint not null references tag_alias_proposal(id)
+  , account_id bigint not null references account(id)
+  , primary key (proposal_id, account_id)
+);
+
+create sequence tag_history_id_seq as bigint;
+
+create table tag_history (
+    id bigint primary key default nextval('tag_history_id_seq')
+  , action varchar(16) not null
+  , from_tag varchar(1024) not null
+    -- Null for deletions.
+  , to_tag varchar(1024)
+  , account_id bigint not null references account(id)
+  , signals_moved bigint not null
+  , signals_merged bigint not null
+  , created_at timestamptz not null default now()
+);
+
+alter sequence tag_history_id_seq owned by tag_history.id;
+
+create index tag_history_from_tag_idx on tag_history (from_tag);
+create index tag_history_to_tag_idx on tag_history (to_tag);
+
+-- Authors of fics, keyed by the metadata provider's id for them.
+create table author (
+    id varchar(128) primary key
+  , name text not null
+);
+
+-- Series of fics, keyed by the metadata provider's id for them.
+create table series (
+    id varchar(128) primary key
+  , title text not null
+);
+
+-- Normalized from what providers report, see `fichub::FicStatus`.
+create type fic_status as enum ('ongoing', 'complete', 'abandoned');
+
+-- Fic metadata from the metadata providers, keyed by the provider's id.
+create table fic (
+    id varchar(64) primary key
+  , title text not null
+  , source text not null
+  , site text
+  , author text
+  , author_id varchar(128) references author(id)
+  , series text
+  , series_id varchar(128) references series(id)
+  , series_position int
+  , words bigint
+  , chapters int
+  , status fic_status
+  , fandoms text[] not null default '{}'
+  , updated timestamptz
+  , fetched_at timestamptz not null default now()
+    -- Set when the same fic was seen first under another id, e.g. on another site.
+  , canonical_id varchar(64) references fic(id)
+);
+
+create index fic_author_id_idx on fic (author_id);
+create index fic_status_idx on fic (status);
+create index fic_series_id_idx on fic (series_id);
+
+create type lookup_failure as enum ('unsupported', 'unavailable');
+
+-- Which fic a URL was last found to point to, and when. Lookups within the cache TTL are served
+-- from here instead of asking fichub again.
+create table fic_url_cache (
+    url varchar(1024) primary key
+    -- Null if the URL was never found.
+  , fic_id varchar(64) references fic(id)
+  , fetched_at timestamptz not null default now()
+    -- The last lookup failed, if set. Failures are answered with until the failure TTL passes.
+  , failure lookup_failure
+  , failure_reason text
+  , failed_at timestamptz
+  , check (fic_id is not null or failed_at is not null)
+);
+
+-- Which fic a URL belongs to, by the fic's `canonical_id` if it has one. Signals on all URLs of a
+-- fic are counted together.
+create table fic_url (
+    url varchar(1024) primary key
+  , fic_id varchar(64) not null references fic(id)
+);
+
+create index fic_url_fic_id_idx on fic_url (fic_id);
+
+create sequence fic_update_id_seq as bigint;
+
+-- New chapters, later update dates or changes of status noticed when refreshing fic metadata.
+create table fic_update (
+    id bigint primary key default nextval('fic_update_id_seq')
+  , fic_id varchar(64) not null references fic(id)
+  , chapters_before int
+  , chapters int
+  , status_before fic_status
+  , status fic_status
+  , updated timestamptz
+  , detected_at timestamptz not null default now()
+);
+
+alter sequence fic_update_id_seq owned by fic_update.id;
+
+create index fic_update_fic_id_idx on fic_update (fic_id);
+
+create table bex_release_artifact (
+    version varchar(64) primary key
+  , filename varchar(256) not null
+  , content_type varchar(256) not null
+  , sha256 bytea not null
+  , content bytea not null
+  , uploaded_by bigint not null references account(id)
+  , uploaded_at timestamptz not null default now()
+);
+
+-- Pairs of accounts that likely belong to the same person, as found by the last detection run.
+create table duplicate_account_candidate (
+    account_id_a bigint not null references account(id)
+  , account_id_b bigint not null references account(id)
+  , reasons text[] not null
+  , detected_at timestamptz not null default now()
+  , primary key (account_id_a, account_id_b)
+  , check (account_id_a < account_id_b)
+);
+
+-- Tags an account never wants to see in signal listings.
+create table blocked_tag (
+    account_id bigint not null references account(id)
+  , tag varchar(1024) not null
+  , primary key (account_id, tag)
+);
+
+-- Recently renamed, merged or deleted tags, so lookups of the old name can point to the successor.
+create table tag_tombstone (
+    tag varchar(1024) primary key
+  , successor varchar(1024)
+  , successor_canonical varchar(1024)
+  , created_at timestamptz not null default now()
+  , expires_at timestamptz not null
 );
@@ -0,0 +1,169 @@
+use chrono::{DateTime, Utc};
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{cached, reject, ApiError, CachePolicy, ErrorWrap, Timestamp};
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, Client};
+use crate::DB;
+
+const MAX_EVENTS_LIMIT: i64 = 100;
+const DEFAULT_EVENTS_LIMIT: i64 = 20;
+
+/// What happened to an account that its owner may want to know about, in case it wasn't them.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(type_name = "account_event_kind", rename_all = "kebab-case")]
+pub enum EventKind {
+    /// Logged in with the password.
+    Login,
+    /// Logged in through the OAuth provider.
+    OauthLogin,
+    /// Logged in with a link sent by email.
+    MagicLinkLogin,
+    PasswordChange,
+    /// Set a new password through a password reset email.
+    PasswordReset,
+    EmailChange,
+    /// Logged out one other session.
+    SessionRevoke,
+    /// Logged out every other session.
+    OtherSessionsRevoke,
+    TwoFactorEnable,
+    TwoFactorDisable,
+}
+
+/// Adds an event to the account's log. Failures are logged rather than failing whatever the event
+/// is about, which has happened by now.
+pub(crate) async fn record(account_id: i64, kind: EventKind, client: &Client, pool: &DB) {
+    if let Err(e) = sqlx::query(
+        "insert into account_event (account_id, kind, ip, user_agent) values ($1, $2, $3::inet, $4)",
+    )
+    .bind(account_id)
+    .bind(kind)
+    .bind(client.ip().map(|ip| ip.to_string()))
+    .bind(client.user_agent())
+    .execute(pool)
+    .await
+    {
+        eprintln!("failed to record account event {:?}: {:?}", kind, e);
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct EventRow {
+    id: i64,
+    kind: EventKind,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = AccountEvent)]
+pub struct Event {
+    id: i64,
+    kind: EventKind,
+    at: Timestamp,
+    /// The address and user agent of the client that caused it.
+    ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = AccountEvents)]
+pub struct Events {
+    events: Vec<Event>,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventsQ {
+    /// Only events older than the one with this id, to page through the log.
+    before: Option<i64>,
+    #[param(default = 20, maximum = 100)]
+    limit: Option<i64>,
+}
+
+/// List what happened to the current account, newest first.
+///
+/// Logins, password and email changes, session revocations and changes to two-factor
+/// authentication, with the client that caused them, so that owners can spot access that wasn't
+/// theirs.
+#[utoipa::path(
+    get,
+    path = "/accounts/events",
+    tag = "accounts",
+    params(EventsQ),
+    responses(
+        (status = 200, description = "Success.", body = Events),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_account_events(
+    account: AccountSession,
+    q: EventsQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let rows = sqlx::query_as::<_, EventRow>(
+        "
+select id, kind, host(ip) as ip, user_agent, created_at
+from account_event
+where account_id = $1 and ($2::bigint is null or id < $2)
+order by id desc
+limit $3
+        ",
+    )
+    .bind(account.id)
+    .bind(q.before)
+    .bind(
+        q.limit
+            .unwrap_or(DEFAULT_EVENTS_LIMIT)
+            .clamp(0, MAX_EVENTS_LIMIT),
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to list account events: {:?}", e);
+        ApiError::Internal
+    })?;
+    let tz = account.tz();
+    let events = rows
+        .into_iter()
+        .map(|r| Event {
+            id: r.id,
+            kind: r.kind,
+            at: Timestamp::new(r.created_at, tz),
+            ip: r.ip,
+            user_agent: r.user_agent,
+        })
+        .collect();
+    Ok(json(&Events { events }).into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_account_events))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_account_events = warp::path!("v1" / "accounts" / "events")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .and(warp::query::<EventsQ>())
+        .and(ctx.pool())
+        .then(get_account_events)
+        .and_then(reject);
+    cached(
+        CachePolicy::Never,
+        &[],
+        get_account_events.map(Reply::into_response),
+    )
+    .boxed()
+}
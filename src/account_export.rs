@@ -0,0 +1,156 @@
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt as _;
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use http::Response;
+use hyper::body::{Bytes, Sender};
+use hyper::Body;
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+use warp::{Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{cached, CachePolicy, ErrorWrap};
+use crate::preferences::Preferences;
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, Role};
+use crate::DB;
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ExportedAccount {
+    id: i64,
+    #[schema(format = Email)]
+    email: String,
+    display_name: Option<String>,
+    role: Role,
+    timezone: String,
+    profile_stats_public: bool,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ExportedSignal {
+    url: String,
+    /// As the account spelled it.
+    tag: String,
+    signal: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+/// The document [`write_export`] writes piece by piece, for the API specification.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct AccountExport {
+    account: ExportedAccount,
+    preferences: Preferences,
+    /// Oldest first.
+    signals: Vec<ExportedSignal>,
+}
+
+async fn send(sender: &mut Sender, data: Vec<u8>) -> eyre::Result<()> {
+    sender
+        .send_data(Bytes::from(data))
+        .await
+        .map_err(|_| eyre::eyre!("the client went away"))
+}
+
+/// Writes the export for [`export_account`]. Signals are sent as they come out of the database,
+/// so large accounts are never held in memory at once.
+async fn write_export(account_id: i64, pool: &DB, sender: &mut Sender) -> eyre::Result<()> {
+    let account = sqlx::query_as::<_, ExportedAccount>(
+        "
+select id, email, display_name, role, timezone, profile_stats_public, created_at
+from account
+where id = $1
+        ",
+    )
+    .bind(account_id)
+    .fetch_one(pool)
+    .await?;
+    let preferences = Preferences::get(account_id, pool).await?;
+    let mut head = br#"{"account":"#.to_vec();
+    serde_json::to_writer(&mut head, &account)?;
+    head.extend_from_slice(br#","preferences":"#);
+    serde_json::to_writer(&mut head, &preferences)?;
+    head.extend_from_slice(br#","signals":["#);
+    send(sender, head).await?;
+
+    let mut signals = sqlx::query_as::<_, ExportedSignal>(
+        "
+select url, tag, signal, created_at, updated_at
+from signal
+where account_id = $1
+order by created_at, url, tag_canonical
+        ",
+    )
+    .bind(account_id)
+    .fetch(pool);
+    let mut first = true;
+    while let Some(signal) = signals.try_next().await? {
+        let mut item = if first { Vec::new() } else { b",".to_vec() };
+        first = false;
+        serde_json::to_writer(&mut item, &signal)?;
+        send(sender, item).await?;
+    }
+    send(sender, b"]}".to_vec()).await
+}
+
+/// Download everything the current account told us about itself.
+///
+/// Account details, preferences and every signal with its timestamps, as one JSON document. The
+/// response is streamed; if it is cut off early, the document is incomplete.
+#[utoipa::path(
+    get,
+    path = "/accounts/export",
+    tag = "accounts",
+    responses(
+        (
+            status = 200,
+            description = "Success.",
+            body = AccountExport,
+            headers(("Content-Disposition" = String)),
+        ),
+        (status = 403, description = "Not logged in.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn export_account(account: AccountSession, pool: DB) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        if let Err(e) = write_export(account.id, &pool, &mut sender).await {
+            eprintln!("failed to export account {}: {:?}", account.id, e);
+            sender.abort();
+        }
+    });
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        http::HeaderValue::from_static("application/json"),
+    );
+    headers.insert(
+        CONTENT_DISPOSITION,
+        http::HeaderValue::from_static(r#"attachment; filename="ficai-export.json""#),
+    );
+    response
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(export_account))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let export_account = warp::path!("v1" / "accounts" / "export")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(export_account);
+    cached(
+        CachePolicy::Never,
+        &[],
+        export_account.map(Reply::into_response),
+    )
+    .boxed()
+}
@@ -0,0 +1,178 @@
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{Filter, Reply};
+
+use crate::api_token::Scope;
+use crate::context::Context;
+use crate::httputil::{cached, reply_json, CachePolicy, ErrorWrap, TimeWindow};
+use crate::routes::Routes;
+use crate::tag_stats::{Bucket, StatsPoint};
+use crate::usermgmt::AccountSession;
+use crate::DB;
+
+const TOP_TAGS: i64 = 10;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AccountStatsQ {
+    #[param(inline, default = "day")]
+    bucket: Option<Bucket>,
+    /// How far back to go.
+    #[param(inline, default = "90d")]
+    window: Option<TimeWindow>,
+}
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TopTag {
+    tag: String,
+    signals_for: i64,
+    signals_against: i64,
+}
+
+/// What an account contributed, for it to see; see `profile::Profile` for what others see.
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountStats {
+    /// URLs the account signalled any tag for or against.
+    urls_tagged: i64,
+    signals_for: i64,
+    signals_against: i64,
+    /// The 10 tags the account signalled most, for or against, most first.
+    top_tags: Vec<TopTag>,
+    bucket: Bucket,
+    /// Signals the account added in each bucket of the window, in its time zone, oldest first,
+    /// including buckets without any.
+    series: Vec<StatsPoint>,
+}
+
+impl AccountStats {
+    /// Counted live, unlike tag stats. The window defaults to 90 days.
+    pub async fn get(account: &AccountSession, q: AccountStatsQ, pool: &DB) -> eyre::Result<Self> {
+        let (urls_tagged, signals_for, signals_against) = sqlx::query_as::<_, (i64, i64, i64)>(
+            "
+select
+    count(distinct url),
+    count(1) filter (where signal),
+    count(1) filter (where not signal)
+from signal
+where account_id = $1
+                ",
+        )
+        .bind(account.id)
+        .fetch_one(pool)
+        .await?;
+        let top_tags = sqlx::query_as::<_, TopTag>(
+            "
+select
+    tag_canonical as tag,
+    count(1) filter (where signal) as signals_for,
+    count(1) filter (where not signal) as signals_against
+from signal
+where account_id = $1
+group by tag_canonical
+order by count(1) desc, tag
+limit $2
+            ",
+        )
+        .bind(account.id)
+        .bind(TOP_TAGS)
+        .fetch_all(pool)
+        .await?;
+        let bucket = q.bucket.unwrap_or(Bucket::Day);
+        let window = q.window.map_or(chrono::Duration::days(90), |w| w.0);
+        let tz = account.tz();
+        let since = (chrono::Utc::now() - window)
+            .with_timezone(&tz)
+            .date_naive();
+        let series = sqlx::query_as::<_, StatsPoint>(
+            "
+with bucket as (
+    select generate_series(
+        date_trunc($3, $2::date),
+        date_trunc($3, now() at time zone $4),
+        ('1 ' || $3)::interval
+    )::date as start
+), day as (
+    select
+        (created_at at time zone $4)::date as day,
+        count(1) filter (where signal) as signals_for,
+        count(1) filter (where not signal) as signals_against
+    from signal
+    where account_id = $1 and (created_at at time zone $4)::date >= $2
+    group by 1
+)
+select
+    b.start,
+    coalesce(sum(d.signals_for), 0)::bigint as signals_for,
+    coalesce(sum(d.signals_against), 0)::bigint as signals_against
+from bucket b
+left join day d
+    on date_trunc($3, d.day)::date = b.start
+group by b.start
+order by b.start
+            ",
+        )
+        .bind(account.id)
+        .bind(since)
+        .bind(bucket.as_str())
+        .bind(tz.name())
+        .fetch_all(pool)
+        .await?;
+        Ok(Self {
+            urls_tagged,
+            signals_for,
+            signals_against,
+            top_tags,
+            bucket,
+            series,
+        })
+    }
+}
+
+/// Get what the current account contributed, e.g. for a "your contributions" panel.
+///
+/// Counted live. The series is in the account's time zone, and counts signals in the bucket they
+/// were created in. API tokens need the `read` scope.
+#[utoipa::path(
+    get,
+    path = "/accounts/stats",
+    tag = "accounts",
+    params(AccountStatsQ),
+    responses(
+        (status = 200, description = "Success.", body = AccountStats),
+        (status = 400, description = "The bucket or window is invalid.", body = ErrorWrap),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = []), ("bearerAuth" = [])),
+)]
+async fn get_account_stats(
+    account: AccountSession,
+    q: AccountStatsQ,
+    pool: DB,
+) -> eyre::Result<AccountStats> {
+    AccountStats::get(&account, q, &pool)
+        .await
+        .wrap_err("failed to get account stats")
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_account_stats))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_account_stats = warp::path!("v1" / "accounts" / "stats")
+        .and(warp::get())
+        .and(ctx.authenticate_scoped(Scope::Read))
+        .and(warp::query::<AccountStatsQ>())
+        .and(ctx.pool())
+        .then(get_account_stats)
+        .and_then(reply_json);
+    cached(
+        CachePolicy::Never,
+        &[],
+        get_account_stats.map(Reply::into_response),
+    )
+    .boxed()
+}
@@ -0,0 +1,196 @@
+use chrono::{DateTime, Utc};
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{json_body, reject, ApiError, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+/// Set by moderators, see [`put_account_status`]. Suspensions end by themselves at
+/// `suspended_until`; bans last until lifted.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "account_status", rename_all = "lowercase")]
+pub enum AccountStatus {
+    Active,
+    Suspended,
+    Banned,
+}
+
+/// Why an account may not be used, told to its holder whenever they try.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = AccountRestriction)]
+pub struct Restriction {
+    status: AccountStatus,
+    reason: Option<String>,
+    /// When a suspension ends; `null` for bans.
+    until: Option<DateTime<Utc>>,
+}
+
+impl Restriction {
+    /// `None` for active accounts, and for suspended ones whose suspension is over.
+    pub fn of(
+        status: AccountStatus,
+        reason: Option<String>,
+        until: Option<DateTime<Utc>>,
+    ) -> Option<Self> {
+        let restricted = match status {
+            AccountStatus::Active => false,
+            AccountStatus::Suspended => until.is_none_or(|until| until > Utc::now()),
+            AccountStatus::Banned => true,
+        };
+        restricted.then_some(Self {
+            status,
+            reason,
+            until,
+        })
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self.status {
+            AccountStatus::Banned => "account banned",
+            _ => "account suspended",
+        }
+    }
+}
+
+/// Fails with [`ApiError::AccountRestricted`] if the account may not log in.
+pub(crate) async fn check(account_id: i64, pool: &DB) -> Result<(), ApiError> {
+    let (status, reason, until) =
+        sqlx::query_as::<_, (AccountStatus, Option<String>, Option<DateTime<Utc>>)>(
+            "select status, status_reason, suspended_until from account where id = $1",
+        )
+        .bind(account_id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| {
+            eprintln!("failed to get account status: {:?}", e);
+            ApiError::Internal
+        })?;
+    match Restriction::of(status, reason, until) {
+        Some(restriction) => Err(ApiError::AccountRestricted(restriction)),
+        None => Ok(()),
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = PutAccountStatusQ)]
+pub struct PutStatusQ {
+    status: AccountStatus,
+    /// Shown to the account holder. Ignored when reinstating.
+    #[serde(default)]
+    reason: Option<String>,
+    /// Required for suspensions, and only for them.
+    #[serde(default)]
+    until: Option<DateTime<Utc>>,
+}
+
+/// An account's status after a moderator changed it.
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = AccountStatusUpdate)]
+pub struct StatusR {
+    id: i64,
+    status: AccountStatus,
+    reason: Option<String>,
+    until: Option<DateTime<Utc>>,
+}
+
+/// Suspend, ban or reinstate an account.
+///
+/// Requires the user-moderation permission.
+///
+/// Restricted accounts keep their sessions, but every authenticated request and login fails with a
+/// 403 telling the reason and, for suspensions, when they end. Suspensions end by themselves.
+#[utoipa::path(
+    put,
+    path = "/admin/accounts/{accountId}/status",
+    tag = "admin",
+    params(
+        ("accountId" = i64, Path),
+    ),
+    request_body = PutStatusQ,
+    responses(
+        (status = 200, description = "Success.", body = StatusR),
+        (
+            status = 400,
+            description = "Bad request, e.g. a suspension without an end, or your own account.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 404, description = "No such account.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn put_account_status(
+    account_id: i64,
+    moderator: AccountSession,
+    q: PutStatusQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    if account_id == moderator.id {
+        return Err(ApiError::BadRequest("cannot change your own status".into()));
+    }
+    let (reason, until) = match q.status {
+        AccountStatus::Active => (None, None),
+        AccountStatus::Suspended => match q.until {
+            Some(until) if until > Utc::now() => (q.reason, Some(until)),
+            _ => {
+                return Err(ApiError::BadRequest(
+                    "suspensions need an end in the future".into(),
+                ))
+            }
+        },
+        AccountStatus::Banned => {
+            if q.until.is_some() {
+                return Err(ApiError::BadRequest(
+                    "bans have no end; suspend instead".into(),
+                ));
+            }
+            (q.reason, None)
+        }
+    };
+    let status = sqlx::query_as::<_, StatusR>(
+        "
+update account set status = $2, status_reason = $3, suspended_until = $4
+where id = $1 and id <> 0 and deleted_at is null
+returning id, status, status_reason as reason, suspended_until as until
+        ",
+    )
+    .bind(account_id)
+    .bind(q.status)
+    .bind(reason)
+    .bind(until)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to update account status: {:?}", e);
+        ApiError::Internal
+    })?;
+    match status {
+        Some(status) => Ok(json(&status).into_response()),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(put_account_status))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let put_account_status = warp::path!("v1" / "admin" / "accounts" / i64 / "status")
+        .and(warp::put())
+        .and(ctx.require_permission(Permission::UserModeration))
+        .and(json_body::<PutStatusQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(put_account_status)
+        .and_then(reject);
+    put_account_status.map(Reply::into_response).boxed()
+}
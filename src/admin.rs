@@ -0,0 +1,475 @@
+use chrono::{DateTime, Utc};
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::account_status::AccountStatus;
+use crate::context::Context;
+use crate::httputil::{json_body, reject, ApiError, ErrorWrap, Timestamp};
+use crate::routes::Routes;
+use crate::service_account::AccountKind;
+use crate::usermgmt::{close_account, AccountSession, DeletedSignals, Permission, Role};
+use crate::write_limit::RateLimitTier;
+use crate::DB;
+
+/// Role and explicitly granted permissions of an account.
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountRoles {
+    id: i64,
+    #[schema(format = Email)]
+    email: String,
+    role: Role,
+    /// Explicitly granted permissions; those implied by `role` are not listed.
+    #[schema(value_type = Vec<Permission>)]
+    permissions: Vec<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountRolesList {
+    accounts: Vec<AccountRoles>,
+}
+
+/// List accounts with elevated roles or explicit permissions. Requires the admin role.
+#[utoipa::path(
+    get,
+    path = "/admin/roles",
+    tag = "admin",
+    responses(
+        (
+            status = 200,
+            description = "Success.",
+            body = AccountRolesList,
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_roles(_account: AccountSession, pool: DB) -> Result<Response<Body>, ApiError> {
+    let accounts = sqlx::query_as::<_, AccountRoles>(
+        "
+select
+    a.id,
+    a.email,
+    a.role,
+    array_remove(array_agg(p.permission::text order by p.permission), null) as permissions
+from account a
+left join account_permission p
+    on p.account_id = a.id
+group by a.id
+having a.role <> 'user' or count(p.permission) > 0
+order by a.id
+        ",
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("{:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&AccountRolesList { accounts }).into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PutRolesQ {
+    role: Role,
+    /// Replaces any previously granted permissions.
+    #[serde(default)]
+    permissions: Vec<Permission>,
+}
+
+/// Set an account's role and explicit permissions. Requires the admin role.
+#[utoipa::path(
+    put,
+    path = "/admin/roles/{accountId}",
+    tag = "admin",
+    params(
+        ("accountId" = i64, Path),
+    ),
+    request_body = PutRolesQ,
+    responses(
+        (status = 200, description = "Success.", body = AccountRoles),
+        (status = 400, description = "Bad request.", body = ErrorWrap),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 404, description = "No such account.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn put_roles(
+    account_id: i64,
+    _account: AccountSession,
+    q: PutRolesQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let updated = sqlx::query("update account set role = $2 where id = $1")
+            .bind(account_id)
+            .bind(q.role)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        if updated == 0 {
+            return Ok(None);
+        }
+        sqlx::query("delete from account_permission where account_id = $1")
+            .bind(account_id)
+            .execute(&mut tx)
+            .await?;
+        for permission in &q.permissions {
+            sqlx::query(
+                "
+insert into account_permission (account_id, permission)
+values ($1, $2)
+on conflict do nothing
+                ",
+            )
+            .bind(account_id)
+            .bind(permission)
+            .execute(&mut tx)
+            .await?;
+        }
+        let roles = sqlx::query_as::<_, AccountRoles>(
+            "
+select
+    a.id,
+    a.email,
+    a.role,
+    array_remove(array_agg(p.permission::text order by p.permission), null) as permissions
+from account a
+left join account_permission p
+    on p.account_id = a.id
+where a.id = $1
+group by a.id
+            ",
+        )
+        .bind(account_id)
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+        eyre::Result::<_>::Ok(Some(roles))
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to update roles: {:?}", e);
+        ApiError::Internal
+    })?;
+    match result {
+        Some(roles) => Ok(json(&roles).into_response()),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+const MAX_ACCOUNTS_LIMIT: i64 = 100;
+const DEFAULT_ACCOUNTS_LIMIT: i64 = 20;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct AccountsQ {
+    /// Matches part of the email or display name, ignoring case, or the exact id.
+    q: Option<String>,
+    #[param(inline)]
+    status: Option<AccountStatus>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    /// Only accounts older than the one with this id, to page through the list.
+    before: Option<i64>,
+    #[param(default = 20, maximum = 100)]
+    limit: Option<i64>,
+}
+
+#[derive(sqlx::FromRow)]
+struct AccountSummaryRow {
+    id: i64,
+    email: String,
+    display_name: Option<String>,
+    kind: AccountKind,
+    role: Role,
+    status: AccountStatus,
+    rate_limit_tier: RateLimitTier,
+    created_at: DateTime<Utc>,
+    signals: i64,
+    sessions: i64,
+    tokens: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSummary {
+    id: i64,
+    email: String,
+    display_name: Option<String>,
+    kind: AccountKind,
+    role: Role,
+    status: AccountStatus,
+    rate_limit_tier: RateLimitTier,
+    created_at: Timestamp,
+    signals: i64,
+    /// Sessions that haven't expired.
+    sessions: i64,
+    /// Personal API tokens.
+    tokens: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSummaries {
+    accounts: Vec<AccountSummary>,
+}
+
+/// Search accounts, newest first. Requires the user-moderation permission.
+///
+/// Deleted accounts are left out.
+#[utoipa::path(
+    get,
+    path = "/admin/accounts",
+    tag = "admin",
+    params(AccountsQ),
+    responses(
+        (status = 200, description = "Success.", body = AccountSummaries),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_accounts(
+    account: AccountSession,
+    q: AccountsQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let search = q.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+    let rows = sqlx::query_as::<_, AccountSummaryRow>(
+        "
+select
+    a.id,
+    a.email,
+    a.display_name,
+    a.kind,
+    a.role,
+    a.status,
+    a.rate_limit_tier,
+    a.created_at,
+    (select count(1) from signal where account_id = a.id) as signals,
+    (
+        select count(1)
+        from session
+        where account_id = a.id and expires_at > now() and last_used_at + idle_timeout > now()
+    ) as sessions,
+    (select count(1) from token where account_id = a.id) as tokens
+from account a
+where a.id <> 0 and a.deleted_at is null
+    and (
+        $1::text is null
+        or strpos(lower(a.email), lower($1)) > 0
+        or strpos(lower(a.display_name), lower($1)) > 0
+        or a.id::text = $1
+    )
+    and ($2::account_status is null or a.status = $2)
+    and ($3::timestamptz is null or a.created_at >= $3)
+    and ($4::timestamptz is null or a.created_at < $4)
+    and ($5::bigint is null or a.id < $5)
+order by a.id desc
+limit $6
+        ",
+    )
+    .bind(search)
+    .bind(q.status)
+    .bind(q.created_after)
+    .bind(q.created_before)
+    .bind(q.before)
+    .bind(
+        q.limit
+            .unwrap_or(DEFAULT_ACCOUNTS_LIMIT)
+            .clamp(0, MAX_ACCOUNTS_LIMIT),
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to search accounts: {:?}", e);
+        ApiError::Internal
+    })?;
+    let tz = account.tz();
+    let accounts = rows
+        .into_iter()
+        .map(|r| AccountSummary {
+            id: r.id,
+            email: r.email,
+            display_name: r.display_name,
+            kind: r.kind,
+            role: r.role,
+            status: r.status,
+            rate_limit_tier: r.rate_limit_tier,
+            created_at: Timestamp::new(r.created_at, tz),
+            signals: r.signals,
+            sessions: r.sessions,
+            tokens: r.tokens,
+        })
+        .collect();
+    Ok(json(&AccountSummaries { accounts }).into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeAccountsQ {
+    /// The account that's kept.
+    into: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeResult {
+    into: i64,
+    /// Signals moved over, including those that replaced one of the kept account's.
+    signals_moved: u64,
+    /// Fic and tag pairs both accounts signalled for or against.
+    conflicts: i64,
+}
+
+/// Merge an account into another. Requires the admin role.
+///
+/// Moves the account's signals to the other account and closes it as if its owner deleted it, which
+/// also ends its sessions, all at once. Where both accounts signalled the same tag on the same fic,
+/// the more recently updated signal wins.
+#[utoipa::path(
+    post,
+    path = "/admin/accounts/{accountId}/merge",
+    tag = "admin",
+    params(
+        ("accountId" = i64, Path, description = "The account that's closed."),
+    ),
+    request_body = MergeAccountsQ,
+    responses(
+        (status = 200, description = "Success.", body = MergeResult),
+        (
+            status = 400,
+            description = "Bad request, e.g. merging an account into itself.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (
+            status = 404,
+            description = "Either account doesn't exist or was deleted.",
+            body = ErrorWrap,
+        ),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn merge_accounts(
+    account_id: i64,
+    _account: AccountSession,
+    q: MergeAccountsQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    if account_id == q.into {
+        return Err(ApiError::BadRequest(
+            "cannot merge an account into itself".into(),
+        ));
+    }
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let found = sqlx::query_scalar::<_, i64>(
+            "
+select id from account
+where id = any(array[$1, $2]) and id <> 0 and deleted_at is null
+order by id
+for update
+            ",
+        )
+        .bind(account_id)
+        .bind(q.into)
+        .fetch_all(&mut tx)
+        .await?;
+        if found.len() < 2 {
+            return Ok(None);
+        }
+        let conflicts = sqlx::query_scalar::<_, i64>(
+            "
+select count(1)
+from signal s
+join signal t
+    on t.account_id = $2 and t.url = s.url and t.tag_canonical = s.tag_canonical
+where s.account_id = $1
+            ",
+        )
+        .bind(account_id)
+        .bind(q.into)
+        .fetch_one(&mut tx)
+        .await?;
+        let signals_moved = sqlx::query(
+            "
+insert into signal (account_id, url, tag, tag_canonical, signal, created_at, updated_at)
+select $2, url, tag, tag_canonical, signal, created_at, updated_at
+from signal
+where account_id = $1
+on conflict (account_id, url, tag_canonical) do update
+set tag = excluded.tag, signal = excluded.signal, updated_at = excluded.updated_at
+where excluded.updated_at > signal.updated_at
+            ",
+        )
+        .bind(account_id)
+        .bind(q.into)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+        close_account(&mut tx, account_id, DeletedSignals::Delete).await?;
+        tx.commit().await?;
+        eyre::Result::<_>::Ok(Some(MergeResult {
+            into: q.into,
+            signals_moved,
+            conflicts,
+        }))
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to merge accounts: {:?}", e);
+        ApiError::Internal
+    })?;
+    match result {
+        Some(result) => Ok(json(&result).into_response()),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_accounts, merge_accounts, get_roles, put_roles))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_accounts = warp::path!("v1" / "admin" / "accounts")
+        .and(warp::get())
+        .and(ctx.require_permission(Permission::UserModeration))
+        .and(warp::query::<AccountsQ>())
+        .and(ctx.pool())
+        .then(get_accounts)
+        .and_then(reject);
+    let merge_accounts = warp::path!("v1" / "admin" / "accounts" / i64 / "merge")
+        .and(warp::post())
+        .and(ctx.require_role(Role::Admin))
+        .and(json_body::<MergeAccountsQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(merge_accounts)
+        .and_then(reject);
+    let get_roles = warp::path!("v1" / "admin" / "roles")
+        .and(warp::get())
+        .and(ctx.require_role(Role::Admin))
+        .and(ctx.pool())
+        .then(get_roles)
+        .and_then(reject);
+    let put_roles = warp::path!("v1" / "admin" / "roles" / i64)
+        .and(warp::put())
+        .and(ctx.require_role(Role::Admin))
+        .and(json_body::<PutRolesQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(put_roles)
+        .and_then(reject);
+    get_accounts
+        .or(merge_accounts)
+        .or(get_roles)
+        .or(put_roles)
+        .map(Reply::into_response)
+        .boxed()
+}
@@ -0,0 +1,136 @@
+use eyre::{eyre, WrapErr};
+use futures::future::BoxFuture;
+use futures::FutureExt as _;
+
+use crate::fichub::{parse_timestamp, FicStatus, Meta};
+use crate::metadata::{between, unescape, MetadataProvider};
+
+/// Reads metadata straight off AO3 work pages, for when fichub is down.
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl Client {
+    /// `base_url` is e.g. `https://archiveofourown.org`, without a trailing slash.
+    pub fn new(base_url: String, timeout: std::time::Duration) -> eyre::Result<Self> {
+        let http = reqwest::Client::builder()
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .timeout(timeout)
+            .build()
+            .wrap_err("failed to build http client")?;
+        Ok(Self { http, base_url })
+    }
+
+    async fn work(&self, url: &str) -> eyre::Result<Meta> {
+        let id = work_id(url).ok_or_else(|| eyre!("{} is not an AO3 work", url))?;
+        let page = self
+            .http
+            .get(format!("{}/works/{}", self.base_url, id))
+            // Otherwise works rated explicit only show a warning.
+            .query(&[("view_adult", "true")])
+            .send()
+            .await
+            .wrap_err("failed to reach AO3")?
+            .error_for_status()
+            .wrap_err("AO3 answered with an error status")?
+            .text()
+            .await
+            .wrap_err("failed to read AO3 work page")?;
+        let title = between(&page, r#"<h2 class="title heading">"#, "</h2>")
+            .ok_or_else(|| eyre!("no title on AO3 work page of {}", url))?;
+        let author_link = between(&page, r#"rel="author""#, "</a>");
+        let author = author_link
+            .and_then(|a| a.split_once('>'))
+            .map(|(_, name)| unescape(name.trim()));
+        // Pseuds of the same user are the same author.
+        let author_id = author_link
+            .and_then(|a| between(a, "/users/", "/"))
+            .map(|user| format!("ao3-{}", user));
+        // `Part 2 of <a href="/series/123">Title</a>`, for the first of the series the work is in.
+        let series = between(&page, r#"<span class="position">"#, "</span>");
+        let series_link = series.and_then(|s| between(s, r#"<a href="/series/"#, "</a>"));
+        let series_id = series_link
+            .and_then(|a| a.split_once('"'))
+            .map(|(id, _)| format!("ao3-{}", id));
+        let series_title = series_link
+            .and_then(|a| a.split_once('>'))
+            .map(|(_, title)| unescape(title.trim()));
+        let series_position = series
+            .and_then(|s| between(s, "Part ", " of"))
+            .and_then(|p| p.trim().parse().ok());
+        let words = between(&page, r#"<dd class="words">"#, "</dd>")
+            .and_then(|w| w.trim().replace(',', "").parse().ok());
+        // `5/10`, or `5/?` while the author hasn't said how many chapters there will be.
+        let chapters = between(&page, r#"<dd class="chapters">"#, "</dd>")
+            .and_then(|c| c.trim().split_once('/'));
+        let status = chapters.map(|(posted, planned)| {
+            if posted == planned {
+                FicStatus::Complete
+            } else {
+                FicStatus::Ongoing
+            }
+        });
+        let fandoms = between(&page, r#"<dd class="fandom tags">"#, "</dd>")
+            .map(|tags| {
+                tags.split(r#"<a class="tag""#)
+                    .skip(1)
+                    .filter_map(|tag| between(tag, ">", "</a>"))
+                    .map(|name| unescape(name.trim()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // Only works with more than one chapter have a last-updated date.
+        let updated = between(&page, r#"<dd class="status">"#, "</dd>")
+            .or_else(|| between(&page, r#"<dd class="published">"#, "</dd>"))
+            .and_then(|d| parse_timestamp(d.trim()));
+        Ok(Meta {
+            id: format!("ao3-{}", id),
+            title: unescape(title.trim()),
+            source: format!("https://archiveofourown.org/works/{}", id),
+            site: Some("Archive of Our Own".to_string()),
+            author,
+            author_id,
+            series: series_title,
+            series_id,
+            series_position,
+            words,
+            chapters: chapters.and_then(|(posted, _)| posted.parse().ok()),
+            status,
+            fandoms,
+            updated,
+        })
+    }
+}
+
+impl MetadataProvider for Client {
+    fn name(&self) -> &'static str {
+        "ao3"
+    }
+
+    fn handles(&self, url: &str) -> bool {
+        work_id(url).is_some()
+    }
+
+    fn meta<'a>(&'a self, url: &'a str) -> BoxFuture<'a, eyre::Result<Meta>> {
+        self.work(url).boxed()
+    }
+}
+
+/// The id of the work at `url`, e.g. `123` for
+/// `https://archiveofourown.org/works/123/chapters/456`.
+fn work_id(url: &str) -> Option<u64> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let rest = rest.strip_prefix("www.").unwrap_or(rest);
+    let rest = rest.strip_prefix("archiveofourown.org/works/")?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
@@ -0,0 +1,410 @@
+use base64ct::Encoding as _;
+use chrono::{DateTime, Utc};
+use http::{Response, StatusCode};
+use hyper::Body;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use sqlx::{Postgres, Transaction};
+use tap::prelude::*;
+use utoipa::{OpenApi, ToSchema};
+use warp::{
+    reply::{json, with_status},
+    Filter, Rejection, Reply,
+};
+
+use crate::context::Context;
+use crate::httputil::{cached, json_body, reject, ApiError, CachePolicy, Empty, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::{optional_authenticate, session_cookie_value, AccountSession, SessionPolicy};
+use crate::DB;
+
+/// Tokens are sent as `Authorization: Bearer ficai_<base64url>`, the prefix making them easy to
+/// recognize, e.g. by secret scanners.
+const TOKEN_PREFIX: &str = "ficai_";
+const TOKEN_BYTES: usize = 32;
+const MAX_TOKENS_PER_ACCOUNT: i64 = 20;
+const MAX_NAME_LENGTH: usize = 256;
+
+/// What a token may be used for. Managing the account itself always takes a session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+#[schema(as = TokenScope)]
+pub enum Scope {
+    /// Reading signals, the feed and fic metadata.
+    Read,
+    /// Adding and removing signals.
+    WriteSignals,
+}
+
+impl Scope {
+    /// As stored in `token.scopes`.
+    fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::WriteSignals => "write-signals",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "read" => Some(Scope::Read),
+            "write-signals" => Some(Scope::WriteSignals),
+            _ => None,
+        }
+    }
+}
+
+fn hash(token: &[u8]) -> Vec<u8> {
+    Sha256::digest(token).to_vec()
+}
+
+/// Like [`optional_authenticate`], but also accepts a personal API token that has `scope`. A
+/// token that isn't valid for `scope` is rejected with [`ApiError::Forbidden`] rather than
+/// ignored, so that scripts notice.
+///
+/// Accounts authenticated by token have no session, so handlers must not touch
+/// `AccountSession`'s session.
+pub fn optional_authenticate_scoped(
+    db: DB,
+    policy: &'static SessionPolicy,
+    scope: Scope,
+) -> impl Filter<Extract = (Option<AccountSession>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(optional_authenticate(db.clone(), policy))
+        .then(
+            move |authorization: Option<String>, session: Option<AccountSession>| {
+                let db = db.clone();
+                async move {
+                    let authorization = match authorization {
+                        Some(authorization) => authorization,
+                        None => return Ok(session),
+                    };
+                    let token = authorization
+                        .strip_prefix("Bearer ")
+                        .and_then(|t| t.trim().strip_prefix(TOKEN_PREFIX))
+                        .and_then(|t| base64ct::Base64UrlUnpadded::decode_vec(t).ok())
+                        .ok_or(ApiError::Forbidden)?;
+                    // Marks the token as used, at most once a minute to spare the writes.
+                    let account = sqlx::query_as::<_, AccountSession>(
+                        "
+with touched as (
+    update token set last_used_at = now()
+    where token_hash = $1
+        and (last_used_at is null or last_used_at < now() - interval '1 minute')
+)
+select a.id, a.email, a.display_name, a.role, a.timezone, ''::bytea as session_id
+    , a.status, a.status_reason, a.suspended_until, a.rate_limit_tier
+from token t
+join account a
+    on a.id = t.account_id
+where t.token_hash = $1 and $2 = any(t.scopes)
+                        ",
+                    )
+                    .bind(hash(&token))
+                    .bind(scope.as_str())
+                    .fetch_optional(&db)
+                    .await
+                    .map_err(|e| {
+                        eprintln!("failed to look up api token: {:?}", e);
+                        ApiError::Internal
+                    })?;
+                    match account {
+                        Some(account) => match account.restriction() {
+                            Some(restriction) => Err(ApiError::AccountRestricted(restriction)),
+                            None => Ok(Some(account)),
+                        },
+                        None => Err(ApiError::Forbidden),
+                    }
+                }
+            },
+        )
+        .and_then(reject)
+}
+
+/// Like [`crate::usermgmt::authenticate`], but also accepts a personal API token, see
+/// [`optional_authenticate_scoped`].
+pub fn authenticate_scoped(
+    db: DB,
+    policy: &'static SessionPolicy,
+    scope: Scope,
+) -> impl Filter<Extract = (AccountSession,), Error = Rejection> + Clone {
+    session_cookie_value()
+        .and(optional_authenticate_scoped(db, policy, scope))
+        .then(
+            move |cookie: Option<String>, account: Option<AccountSession>| async move {
+                account.ok_or_else(|| policy.unauthenticated(cookie.as_deref()))
+            },
+        )
+        .and_then(reject)
+}
+
+#[derive(sqlx::FromRow)]
+struct TokenRow {
+    id: i64,
+    name: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Token {
+    id: i64,
+    name: String,
+    scopes: Vec<Scope>,
+    created_at: DateTime<Utc>,
+    /// Updated at most once a minute; `null` if never used.
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<TokenRow> for Token {
+    fn from(row: TokenRow) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            scopes: row.scopes.iter().filter_map(|s| Scope::parse(s)).collect(),
+            created_at: row.created_at,
+            last_used_at: row.last_used_at,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Tokens {
+    tokens: Vec<Token>,
+}
+
+/// List the current account's personal API tokens.
+#[utoipa::path(
+    get,
+    path = "/tokens",
+    tag = "accounts",
+    responses(
+        (status = 200, description = "Success.", body = Tokens),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_tokens(account: AccountSession, pool: DB) -> Result<Response<Body>, ApiError> {
+    Ok(json(&list(account.id, &pool).await?).into_response())
+}
+
+/// The account's tokens, newest first.
+pub(crate) async fn list(account_id: i64, pool: &DB) -> Result<Tokens, ApiError> {
+    let rows = sqlx::query_as::<_, TokenRow>(
+        "
+select id, name, scopes, created_at, last_used_at
+from token
+where account_id = $1
+order by created_at desc, id desc
+        ",
+    )
+    .bind(account_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to list api tokens: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(Tokens {
+        tokens: rows.into_iter().map(Token::from).collect(),
+    })
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct CreateTokenQ {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedToken {
+    #[serde(flatten)]
+    meta: Token,
+    /// Only ever shown here; the server keeps nothing but a hash of it.
+    token: String,
+}
+
+/// Create a personal API token.
+///
+/// The token itself is only returned here. Accounts can have up to 20 tokens.
+#[utoipa::path(
+    post,
+    path = "/tokens",
+    tag = "accounts",
+    request_body = CreateTokenQ,
+    responses(
+        (status = 201, description = "Created.", body = CreatedToken),
+        (
+            status = 400,
+            description = "Bad request, e.g. no scopes, or too many tokens.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn create_token(
+    account: AccountSession,
+    q: CreateTokenQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to create api token: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let token = issue(&mut tx, account.id, &q).await?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(json(&token)
+        .pipe(|r| with_status(r, StatusCode::CREATED))
+        .into_response())
+}
+
+/// Creates a token for the account, as part of `tx`.
+pub(crate) async fn issue(
+    tx: &mut Transaction<'_, Postgres>,
+    account_id: i64,
+    q: &CreateTokenQ,
+) -> Result<CreatedToken, ApiError> {
+    let name = q.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ApiError::BadRequest(
+            format!("name must be 1 to {} characters", MAX_NAME_LENGTH).into(),
+        ));
+    }
+    if q.scopes.is_empty() {
+        return Err(ApiError::BadRequest("no scopes given".into()));
+    }
+    let mut scopes = q.scopes.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+    scopes.sort_unstable();
+    scopes.dedup();
+    let mut token = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut token);
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to create api token: {:?}", e);
+        ApiError::Internal
+    };
+    // Serializes token creation per account, so the limit holds.
+    sqlx::query("select id from account where id = $1 for update")
+        .bind(account_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_error)?;
+    let count = sqlx::query_scalar::<_, i64>("select count(1) from token where account_id = $1")
+        .bind(account_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(internal_error)?;
+    if count >= MAX_TOKENS_PER_ACCOUNT {
+        return Err(ApiError::BadRequest(
+            format!("at most {} tokens per account", MAX_TOKENS_PER_ACCOUNT).into(),
+        ));
+    }
+    let row = sqlx::query_as::<_, TokenRow>(
+        "
+insert into token (account_id, name, token_hash, scopes)
+values ($1, $2, $3, $4)
+returning id, name, scopes, created_at, last_used_at
+        ",
+    )
+    .bind(account_id)
+    .bind(name)
+    .bind(hash(&token))
+    .bind(&scopes)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(internal_error)?;
+    Ok(CreatedToken {
+        meta: row.into(),
+        token: format!(
+            "{}{}",
+            TOKEN_PREFIX,
+            base64ct::Base64UrlUnpadded::encode_string(&token)
+        ),
+    })
+}
+
+/// Revoke a personal API token.
+#[utoipa::path(
+    delete,
+    path = "/tokens/{id}",
+    tag = "accounts",
+    params(
+        ("id" = i64, Path),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 404, description = "The account has no such token.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn delete_token(
+    account: AccountSession,
+    id: i64,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    revoke(account.id, id, &pool).await
+}
+
+/// Deletes one of the account's tokens.
+pub(crate) async fn revoke(
+    account_id: i64,
+    id: i64,
+    pool: &DB,
+) -> Result<Response<Body>, ApiError> {
+    let deleted = sqlx::query("delete from token where id = $1 and account_id = $2")
+        .bind(id)
+        .bind(account_id)
+        .execute(pool)
+        .await
+        .map_err(|e| {
+            eprintln!("failed to delete api token: {:?}", e);
+            ApiError::Internal
+        })?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(json(&Empty {}).into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_tokens, create_token, delete_token))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_tokens = warp::path!("v1" / "tokens")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(get_tokens)
+        .and_then(reject);
+    let create_token = warp::path!("v1" / "tokens")
+        .and(warp::post())
+        .and(ctx.authenticate())
+        .and(json_body::<CreateTokenQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(create_token)
+        .and_then(reject);
+    let delete_token = warp::path!("v1" / "tokens" / i64)
+        .and(warp::delete())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(|id, account, pool| delete_token(account, id, pool))
+        .and_then(reject);
+    cached(
+        CachePolicy::Never,
+        &[],
+        get_tokens
+            .or(create_token)
+            .or(delete_token)
+            .map(Reply::into_response),
+    )
+    .boxed()
+}
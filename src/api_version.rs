@@ -0,0 +1,28 @@
+/// A version of the API, served under its own path prefix, e.g. `/v2/signals`. Versions share
+/// handlers and differ in their request and response types, so that v1 stays as the browser
+/// extension knows it while newer clients get better shapes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::V2];
+
+    /// The first path segment of the version's routes.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+            ApiVersion::V2 => "v2",
+        }
+    }
+
+    /// Where the version's specification is served as JSON.
+    pub fn spec_path(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "/openapi.json",
+            ApiVersion::V2 => "/v2/openapi.json",
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::fichub::{CachedMeta, FicStatus, Meta};
+use crate::httputil::{reject, ApiError, ErrorWrap, PercentDecoded};
+use crate::routes::Routes;
+use crate::signal::CombinedSignal;
+use crate::DB;
+
+/// Keeps the author of a fic that is about to be stored up to date. Authors are only known by
+/// providers that give them an id.
+pub(crate) async fn upsert(tx: &mut Transaction<'_, Postgres>, meta: &Meta) -> eyre::Result<()> {
+    let (id, name) = match (&meta.author_id, &meta.author) {
+        (Some(id), Some(name)) => (id, name),
+        _ => return Ok(()),
+    };
+    sqlx::query(
+        "
+insert into author (id, name)
+values ($1, $2)
+on conflict (id) do update set name = excluded.name
+        ",
+    )
+    .bind(id)
+    .bind(name)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorDetail {
+    id: String,
+    name: String,
+    fic_count: i64,
+    /// The tags most signalled for across the author's fics.
+    tags: Vec<CombinedSignal>,
+}
+
+const AUTHOR_TAGS_LIMIT: i64 = 20;
+
+impl AuthorDetail {
+    pub async fn get(id: &str, pool: &DB) -> eyre::Result<Option<Self>> {
+        let name = match sqlx::query_scalar::<_, String>("select name from author where id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?
+        {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let fic_ids = sqlx::query_scalar::<_, String>("select id from fic where author_id = $1")
+            .bind(id)
+            .fetch_all(pool)
+            .await?;
+        Ok(Some(Self {
+            id: id.to_string(),
+            name,
+            fic_count: fic_ids.len() as i64,
+            tags: CombinedSignal::of_fics(&fic_ids, AUTHOR_TAGS_LIMIT, pool).await?,
+        }))
+    }
+}
+
+/// Get an author and the tags signalled on their fics.
+///
+/// Authors are known once metadata of one of their fics was looked up. Signals on any URL of any of
+/// the author's fics count, once per account and fic.
+#[utoipa::path(
+    get,
+    path = "/authors/{id}",
+    tag = "fics",
+    params(
+        ("id" = String, Path, description = "As in `authorId` of fic metadata."),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = AuthorDetail),
+        (status = 404, description = "No such author is known.", body = ErrorWrap),
+    ),
+)]
+pub async fn get_author(id: String, pool: DB) -> Result<Response<Body>, ApiError> {
+    let detail = AuthorDetail::get(&id, &pool).await.map_err(|e| {
+        eprintln!("failed to get author: {:?}", e);
+        ApiError::Internal
+    })?;
+    match detail {
+        Some(detail) => Ok(json(&detail).into_response()),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuthorFicsQ {
+    #[param(default = 50, minimum = 0, maximum = 200)]
+    limit: Option<i64>,
+    /// Only fics with this status.
+    #[param(inline)]
+    status: Option<FicStatus>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorFics {
+    fics: Vec<CachedMeta>,
+}
+
+const DEFAULT_FICS_LIMIT: i64 = 50;
+const MAX_FICS_LIMIT: i64 = 200;
+
+/// Get the known fics of an author, most recently updated first.
+#[utoipa::path(
+    get,
+    path = "/authors/{id}/fics",
+    tag = "fics",
+    params(
+        ("id" = String, Path, description = "As in `authorId` of fic metadata."),
+        AuthorFicsQ,
+    ),
+    responses(
+        (status = 200, description = "Success.", body = AuthorFics),
+        (status = 404, description = "No such author is known.", body = ErrorWrap),
+    ),
+)]
+pub async fn get_author_fics(
+    id: String,
+    q: AuthorFicsQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let fics = async {
+        let exists =
+            sqlx::query_scalar::<_, bool>("select exists (select from author where id = $1)")
+                .bind(&id)
+                .fetch_one(&pool)
+                .await?;
+        if !exists {
+            return Ok(None);
+        }
+        let fics = sqlx::query_as::<_, CachedMeta>(
+            "
+select
+    f.id, f.title, f.source, f.site, f.author, f.author_id, f.series, f.series_id,
+    f.series_position, f.words, f.chapters, f.status, f.fandoms, f.updated,
+    (select max(c.fetched_at) from fic_url_cache c where c.fic_id = f.id) as fetched_at
+from fic f
+where f.author_id = $1
+    and ($3::fic_status is null or f.status = $3)
+order by f.updated desc nulls last, f.title, f.id
+limit $2
+            ",
+        )
+        .bind(&id)
+        .bind(
+            q.limit
+                .unwrap_or(DEFAULT_FICS_LIMIT)
+                .clamp(0, MAX_FICS_LIMIT),
+        )
+        .bind(q.status)
+        .fetch_all(&pool)
+        .await?;
+        eyre::Result::<_>::Ok(Some(fics))
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to get author fics: {:?}", e);
+        ApiError::Internal
+    })?;
+    match fics {
+        Some(fics) => Ok(json(&AuthorFics { fics }).into_response()),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_author, get_author_fics))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_author_fics = warp::path!("v1" / "authors" / PercentDecoded / "fics")
+        .and(warp::get())
+        .and(warp::query::<AuthorFicsQ>())
+        .and(ctx.pool())
+        .then(|id: PercentDecoded, q, pool| get_author_fics(id.0, q, pool))
+        .and_then(reject);
+    let get_author = warp::path!("v1" / "authors" / PercentDecoded)
+        .and(warp::get())
+        .and(ctx.pool())
+        .then(|id: PercentDecoded, pool| get_author(id.0, pool))
+        .and_then(reject);
+    get_author_fics
+        .or(get_author)
+        .map(Reply::into_response)
+        .boxed()
+}
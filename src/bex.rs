@@ -0,0 +1,264 @@
+use bytes::Bytes;
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use http::{Response, StatusCode};
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tap::prelude::*;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{
+    reply::{json, with_status},
+    Filter, Reply,
+};
+
+use crate::context::Context;
+use crate::httputil::{reject, reply_json, ApiError, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+/// Header carrying the hex-encoded SHA-256 of an artifact, both on upload and download.
+pub const CHECKSUM_HEADER: &str = "x-checksum-sha256";
+
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// A stored browser extension release artifact.
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = BexArtifact)]
+pub struct Artifact {
+    version: String,
+    filename: String,
+    content_type: String,
+    /// Hex encoded SHA-256 of the artifact.
+    sha256: String,
+    /// Size of the artifact in bytes.
+    size: usize,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct UploadArtifactQ {
+    /// File name offered to downloaders. Defaults to `ficai-bex-{version}.zip`.
+    filename: Option<String>,
+}
+
+/// Upload the release artifact of a browser extension version. Requires the `settings` permission.
+///
+/// The artifact should already be signed for distribution. The server verifies that the body
+/// matches the checksum given in `X-Checksum-Sha256` and replaces any previous upload for the same
+/// version.
+#[utoipa::path(
+    post,
+    path = "/admin/bex/releases/{version}/artifact",
+    tag = "bex",
+    params(
+        ("version" = String, Path),
+        UploadArtifactQ,
+        (
+            "X-Checksum-Sha256" = String,
+            Header,
+            description = "Hex encoded SHA-256 of the request body.",
+        ),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 201, description = "Stored.", body = Artifact),
+        (
+            status = 400,
+            description = "Bad request, including a checksum mismatch.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (
+            status = 413,
+            description = "The artifact is larger than the configured limit.",
+            body = ErrorWrap,
+        ),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn upload_bex_artifact(
+    version: String,
+    account: AccountSession,
+    q: UploadArtifactQ,
+    content_type: Option<String>,
+    checksum: String,
+    content: Bytes,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let expected = hex::decode(checksum.trim()).map_err(|_| {
+        ApiError::BadRequest(format!("{} must be hex encoded", CHECKSUM_HEADER).into())
+    })?;
+    let actual = Sha256::digest(&content);
+    if actual.as_slice() != expected.as_slice() {
+        return Err(ApiError::BadRequest("checksum mismatch".into()));
+    }
+
+    let artifact = Artifact {
+        filename: q
+            .filename
+            .unwrap_or_else(|| format!("ficai-bex-{}.zip", version)),
+        version,
+        content_type: content_type.unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_string()),
+        sha256: hex::encode(actual),
+        size: content.len(),
+    };
+    sqlx::query(
+        "
+insert into bex_release_artifact (version, filename, content_type, sha256, content, uploaded_by)
+values ($1, $2, $3, $4, $5, $6)
+on conflict (version) do update set
+    filename = excluded.filename,
+    content_type = excluded.content_type,
+    sha256 = excluded.sha256,
+    content = excluded.content,
+    uploaded_by = excluded.uploaded_by,
+    uploaded_at = now()
+        ",
+    )
+    .bind(&artifact.version)
+    .bind(&artifact.filename)
+    .bind(&artifact.content_type)
+    .bind(actual.as_slice())
+    .bind(&content[..])
+    .bind(account.id)
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to store bex artifact: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&artifact)
+        .pipe(|r| with_status(r, StatusCode::CREATED))
+        .into_response())
+}
+
+/// Download the release artifact of a browser extension version.
+#[utoipa::path(
+    get,
+    path = "/bex/releases/{version}/download",
+    tag = "bex",
+    params(
+        ("version" = String, Path),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "The artifact, with the content type it was uploaded with.",
+            body = Vec<u8>,
+            content_type = "application/octet-stream",
+            headers((
+                "X-Checksum-Sha256" = String,
+                description = "Hex encoded SHA-256 of the artifact."
+            )),
+        ),
+        (
+            status = 404,
+            description = "No artifact has been uploaded for this version.",
+            body = ErrorWrap,
+        ),
+    ),
+)]
+pub async fn download_bex_artifact(version: String, pool: DB) -> Result<Response<Body>, ApiError> {
+    let row = sqlx::query_as::<_, (String, String, Vec<u8>, Vec<u8>)>(
+        "
+select filename, content_type, sha256, content
+from bex_release_artifact
+where version = $1
+        ",
+    )
+    .bind(&version)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to load bex artifact: {:?}", e);
+        ApiError::Internal
+    })?;
+    let (filename, content_type, sha256, content) = match row {
+        Some(row) => row,
+        None => return Err(ApiError::NotFound),
+    };
+    Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename.replace('"', "")),
+        )
+        .header(CHECKSUM_HEADER, hex::encode(sha256))
+        .body(Body::from(content))
+        .map_err(|e| {
+            eprintln!("failed to build bex artifact response: {:?}", e);
+            ApiError::Internal
+        })
+}
+
+/// Information about a specific browser extension version.
+#[derive(Serialize, ToSchema)]
+#[schema(as = BexVersion)]
+pub struct Bex {
+    /// Whether this version of the browser extension is no longer supported.
+    retired: bool,
+    /// The latest version of the browser extension that is available.
+    #[schema(example = "v0.1.0-6e6c4b2")]
+    latest_version: String,
+}
+
+/// Get information about a specific browser extension version.
+#[utoipa::path(
+    get,
+    path = "/bex/versions/{version}",
+    operation_id = "getBexVersion",
+    tag = "bex",
+    params(
+        (
+            "version" = String,
+            Path,
+            description = "The version of the extension to retrieve information about.",
+        ),
+    ),
+    responses(
+        (status = 200, description = "Information about a specific browser version.", body = Bex),
+    ),
+)]
+pub async fn get_bex_version(v: String, _pool: DB, bex_latest_version: &str) -> eyre::Result<Bex> {
+    Ok(Bex {
+        retired: v == "v0.0.0",
+        latest_version: bex_latest_version.to_string(),
+    })
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_bex_version, upload_bex_artifact, download_bex_artifact))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_bex_version = warp::path!("v1" / "bex" / "versions" / String)
+        .and(warp::get())
+        .and(ctx.pool())
+        .then(|v, pool| get_bex_version(v, pool, ctx.bex_latest_version))
+        .and_then(reply_json);
+    let download_bex_artifact = warp::path!("v1" / "bex" / "releases" / String / "download")
+        .and(warp::get())
+        .and(ctx.pool())
+        .then(download_bex_artifact)
+        .and_then(reject);
+    let upload_bex_artifact =
+        warp::path!("v1" / "admin" / "bex" / "releases" / String / "artifact")
+            .and(warp::post())
+            .and(ctx.require_permission(Permission::Settings))
+            .and(warp::query::<UploadArtifactQ>())
+            .and(warp::header::optional::<String>("content-type"))
+            .and(warp::header::<String>(CHECKSUM_HEADER))
+            .and(warp::body::content_length_limit(ctx.bex_artifact_max_bytes))
+            .and(warp::body::bytes())
+            .and(ctx.pool())
+            .then(upload_bex_artifact)
+            .and_then(reject);
+    get_bex_version
+        .or(download_bex_artifact)
+        .or(upload_bex_artifact)
+        .map(Reply::into_response)
+        .boxed()
+}
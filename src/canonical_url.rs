@@ -0,0 +1,78 @@
+use reqwest::Url;
+
+/// Folds URLs of chapters, pages and posts of a fic into the URL of the fic itself, so signals on
+/// any of them are counted together. URLs of sites without a rule here are left as they are.
+///
+/// * AO3: `https://archiveofourown.org/works/<id>`, from `/works/<id>/chapters/<chapter>` and
+///   work URLs inside collections.
+/// * FanFiction.net and FictionPress: `https://www.fanfiction.net/s/<id>`, from
+///   `/s/<id>/<chapter>/<title>` and the mobile site.
+/// * XenForo forums such as SpaceBattles: `https://<host>/threads/<thread>/`, from
+///   `/threads/<thread>/page-<n>`, `/post-<n>`, `/threadmarks`, `/reader/` and similar.
+pub fn fold(url: &str) -> String {
+    let parsed = match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => parsed,
+        _ => return url.to_string(),
+    };
+    let host = match parsed.host_str() {
+        Some(host) => host.to_lowercase(),
+        None => return url.to_string(),
+    };
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    let segments = parsed
+        .path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let folded = match host {
+        "archiveofourown.org" => ao3(&segments),
+        "fanfiction.net" | "m.fanfiction.net" => ffn("www.fanfiction.net", &segments),
+        "fictionpress.com" | "m.fictionpress.com" => ffn("www.fictionpress.com", &segments),
+        "forums.spacebattles.com"
+        | "forums.sufficientvelocity.com"
+        | "forum.questionablequesting.com"
+        | "questionablequesting.com" => xenforo(host, &parsed, &segments),
+        _ => None,
+    };
+    folded.unwrap_or_else(|| url.to_string())
+}
+
+fn ao3(segments: &[&str]) -> Option<String> {
+    let at = segments.iter().position(|s| *s == "works")?;
+    let id = segments.get(at + 1).filter(|id| is_number(id))?;
+    Some(format!("https://archiveofourown.org/works/{}", id))
+}
+
+fn ffn(host: &str, segments: &[&str]) -> Option<String> {
+    match segments {
+        ["s", id, ..] if is_number(id) => Some(format!("https://{}/s/{}", host, id)),
+        _ => None,
+    }
+}
+
+/// Thread URLs keep their query, which the forums don't use for anything but filters of
+/// threadmark listings; the fragment (`#post-<n>`) goes.
+fn xenforo(host: &str, parsed: &Url, segments: &[&str]) -> Option<String> {
+    let thread = match segments {
+        ["threads", thread, rest @ ..] if rest.iter().all(|s| is_thread_page(s)) => thread,
+        _ => return None,
+    };
+    let mut folded = format!("https://{}/threads/{}/", host, thread);
+    if let Some(query) = parsed.query() {
+        folded.push('?');
+        folded.push_str(query);
+    }
+    Some(folded)
+}
+
+fn is_thread_page(segment: &str) -> bool {
+    matches!(
+        segment,
+        "reader" | "threadmarks" | "unread" | "latest" | "first-unread"
+    ) || ["page-", "post-"]
+        .iter()
+        .any(|prefix| segment.strip_prefix(prefix).is_some_and(is_number))
+}
+
+pub(crate) fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
@@ -0,0 +1,58 @@
+use std::net::IpAddr;
+
+use serde::Deserialize;
+
+use crate::httputil::ApiError;
+
+/// Checks the CAPTCHA that clients solved before creating an account, with hCaptcha or Cloudflare
+/// Turnstile, which share the same verification API.
+pub struct Captcha {
+    /// E.g. `https://challenges.cloudflare.com/turnstile/v0/siteverify`.
+    pub verify_url: String,
+    pub secret: String,
+    pub http: reqwest::Client,
+}
+
+#[derive(Deserialize, Debug)]
+struct VerifyResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+impl Captcha {
+    /// Fails with [`ApiError::BadRequest`] unless `token` is a solved CAPTCHA, or with
+    /// [`ApiError::BadGateway`] if the provider can't tell.
+    pub async fn verify(&self, token: Option<&str>, ip: Option<IpAddr>) -> Result<(), ApiError> {
+        let token = match token {
+            Some(token) if !token.is_empty() => token,
+            _ => return Err(ApiError::BadRequest("captcha token required".into())),
+        };
+        let ip = ip.map(|ip| ip.to_string());
+        let mut form = vec![("secret", self.secret.as_str()), ("response", token)];
+        if let Some(ip) = &ip {
+            form.push(("remoteip", ip));
+        }
+        let response = async {
+            self.http
+                .post(&self.verify_url)
+                .form(&form)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<VerifyResponse>()
+                .await
+        }
+        .await
+        .map_err(|e| {
+            eprintln!("failed to verify captcha: {:?}", e);
+            ApiError::BadGateway
+        })?;
+        if response.success {
+            Ok(())
+        } else {
+            eprintln!("captcha rejected: {:?}", response.error_codes);
+            Err(ApiError::BadRequest("captcha failed".into()))
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::fichub::{CachedMeta, FicStatus};
+use crate::httputil::{cached, reject, ApiError, CachePolicy, ErrorWrap};
+use crate::pagination;
+use crate::routes::Routes;
+use crate::tag::TagName;
+use crate::DB;
+
+const MAX_CATALOG_LIMIT: i64 = 100;
+const DEFAULT_CATALOG_LIMIT: i64 = 20;
+/// How many tags are listed per fic.
+const CATALOG_TAGS: i64 = 5;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CatalogQ {
+    cursor: Option<String>,
+    /// Maximum number of fics per page.
+    #[param(default = 20, minimum = 0, maximum = 100)]
+    limit: Option<i64>,
+    /// Only fics of this fandom, ignoring case.
+    fandom: Option<String>,
+    /// Only fics that carry this tag, i.e. have more signals for it than against it. An alias is
+    /// resolved to the tag it points to.
+    tag: Option<String>,
+    /// Only fics with this status, e.g. only complete ones.
+    #[param(inline)]
+    status: Option<FicStatus>,
+}
+
+/// Position after the last fic of a page: when it was updated (fics without an update date sort
+/// as if updated at the epoch) and its id, which together are unique.
+#[derive(Serialize, Deserialize)]
+struct CatalogCursor {
+    updated: DateTime<Utc>,
+    id: String,
+}
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CatalogTag {
+    #[serde(skip)]
+    fic_id: String,
+    tag: String,
+    signals_for: i64,
+    signals_against: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CatalogFic {
+    #[serde(flatten)]
+    meta: CachedMeta,
+    /// The tags the fic carries that most accounts signalled for.
+    tags: Vec<CatalogTag>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Catalog {
+    fics: Vec<CatalogFic>,
+    /// Pass as `cursor` to get the next page; `None` on the last page.
+    next_cursor: Option<String>,
+}
+
+/// Browse the fics whose metadata is known, most recently updated first.
+///
+/// Copies of a fic on other sites are listed once. Each fic comes with the tags it carries that
+/// most accounts signalled for. Results are paginated; pass `nextCursor` from one page as `cursor`
+/// to get the next.
+#[utoipa::path(
+    get,
+    path = "/fics",
+    tag = "fics",
+    params(CatalogQ),
+    responses(
+        (status = 200, description = "A page of fics.", body = Catalog),
+        (status = 400, description = "The cursor is invalid.", body = ErrorWrap),
+    ),
+)]
+pub async fn get_catalog(q: CatalogQ, pool: DB) -> Result<Response<Body>, ApiError> {
+    let cursor = pagination::decode::<CatalogCursor>(q.cursor.as_deref())?;
+    let limit = pagination::limit(q.limit, DEFAULT_CATALOG_LIMIT, MAX_CATALOG_LIMIT);
+    let result = async {
+        let tag = match &q.tag {
+            Some(tag) => Some(TagName::resolve(tag, &pool).await?.canonical),
+            None => None,
+        };
+        let mut fics = sqlx::query_as::<_, CachedMeta>(
+            "
+select
+    f.id, f.title, f.source, f.site, f.author, f.author_id, f.series, f.series_id,
+    f.series_position, f.words, f.chapters, f.status, f.fandoms, f.updated, c.fetched_at
+from fic f
+join lateral (
+    select max(fetched_at) as fetched_at
+    from fic_url_cache
+    where fic_id = f.id
+) c
+    on c.fetched_at is not null
+where f.canonical_id is null
+    and ($1::text is null or exists (select from unnest(f.fandoms) x where lower(x) = lower($1)))
+    and (
+        $2::text is null
+        or f.id in (
+            select u.fic_id
+            from fic_url u
+            join signal s
+                on s.url = u.url
+            where s.tag_canonical = $2
+            group by u.fic_id
+            having count(distinct s.account_id) filter (where s.signal)
+                > count(distinct s.account_id) filter (where not s.signal)
+        )
+    )
+    and (
+        $3::timestamptz is null
+        or coalesce(f.updated, 'epoch') < $3
+        or (coalesce(f.updated, 'epoch') = $3 and f.id > $4)
+    )
+    and ($6::fic_status is null or f.status = $6)
+order by coalesce(f.updated, 'epoch') desc, f.id asc
+limit $5
+            ",
+        )
+        .bind(&q.fandom)
+        .bind(&tag)
+        .bind(cursor.as_ref().map(|c| c.updated))
+        .bind(cursor.as_ref().map(|c| c.id.as_str()))
+        .bind(limit + 1)
+        .bind(q.status)
+        .fetch_all(&pool)
+        .await?;
+        let next_cursor = pagination::next_cursor(&mut fics, limit, |f| CatalogCursor {
+            updated: f.meta.updated.unwrap_or(DateTime::UNIX_EPOCH),
+            id: f.meta.id.clone(),
+        });
+        let fic_ids = fics.iter().map(|f| f.meta.id.clone()).collect::<Vec<_>>();
+        let mut tags = HashMap::<String, Vec<CatalogTag>>::new();
+        for tag in sqlx::query_as::<_, CatalogTag>(
+            "
+with tally as (
+    select
+        u.fic_id,
+        mode() within group (order by s.tag) as tag,
+        count(distinct s.account_id) filter (where s.signal) as signals_for,
+        count(distinct s.account_id) filter (where not s.signal) as signals_against
+    from fic_url u
+    join signal s
+        on s.url = u.url
+    where u.fic_id = any($1)
+    group by u.fic_id, s.tag_canonical
+),
+ranked as (
+    select
+        *,
+        row_number() over (partition by fic_id order by signals_for desc, tag) as rank
+    from tally
+    where signals_for > signals_against
+)
+select fic_id, tag, signals_for, signals_against
+from ranked
+where rank <= $2
+order by fic_id, rank
+            ",
+        )
+        .bind(&fic_ids)
+        .bind(CATALOG_TAGS)
+        .fetch_all(&pool)
+        .await?
+        {
+            tags.entry(tag.fic_id.clone()).or_default().push(tag);
+        }
+        eyre::Result::<_>::Ok(Catalog {
+            fics: fics
+                .into_iter()
+                .map(|meta| CatalogFic {
+                    tags: tags.remove(&meta.meta.id).unwrap_or_default(),
+                    meta,
+                })
+                .collect(),
+            next_cursor,
+        })
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to list fic catalog: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&result).into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_catalog))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_catalog = cached(
+        CachePolicy::Public(60),
+        &[],
+        warp::path!("v1" / "fics")
+            .and(warp::get())
+            .and(warp::query::<CatalogQ>())
+            .and(ctx.pool())
+            .then(get_catalog)
+            .and_then(reject),
+    );
+    get_catalog.map(Reply::into_response).boxed()
+}
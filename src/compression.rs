@@ -0,0 +1,138 @@
+use std::convert::Infallible;
+use std::io::Write;
+
+use http::header::{
+    HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY,
+};
+use http::{HeaderMap, Response, StatusCode};
+use hyper::body::HttpBody;
+use hyper::Body;
+use warp::Filter;
+
+/// Brotli's slower settings shrink JSON little further and cost a lot more CPU per response.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+/// A content coding that responses can be compressed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// The coding that `Accept-Encoding` weighs highest, preferring brotli on ties, or `None` if
+    /// it allows neither.
+    fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let (mut br, mut gzip, mut any) = (None, None, None);
+        for part in accept_encoding.split(',') {
+            let mut params = part.split(';');
+            let coding = params.next().unwrap_or("").trim();
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match coding.to_ascii_lowercase().as_str() {
+                "br" => br = Some(q),
+                "gzip" | "x-gzip" => gzip = Some(q),
+                "*" => any = Some(q),
+                _ => {}
+            }
+        }
+        let br = br.or(any).unwrap_or(0.0);
+        let gzip = gzip.or(any).unwrap_or(0.0);
+        if br <= 0.0 && gzip <= 0.0 {
+            None
+        } else if br >= gzip {
+            Some(Encoding::Brotli)
+        } else {
+            Some(Encoding::Gzip)
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut out = Vec::new();
+                {
+                    let mut w = brotli::CompressorWriter::new(
+                        &mut out,
+                        4096,
+                        BROTLI_QUALITY,
+                        BROTLI_WINDOW_BITS,
+                    );
+                    w.write_all(data)?;
+                }
+                Ok(out)
+            }
+            Encoding::Gzip => {
+                let mut w =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                w.write_all(data)?;
+                w.finish()
+            }
+        }
+    }
+}
+
+/// The coding the client would like responses in, if it accepts any the server offers.
+pub fn accept_encoding() -> impl Filter<Extract = (Option<Encoding>,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: HeaderMap| {
+        headers
+            .get(ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .and_then(Encoding::negotiate)
+    })
+}
+
+/// Compresses JSON responses of at least `min_bytes`. Smaller ones barely shrink, and bodies of
+/// unknown size, like streamed exports, are sent as they are.
+pub async fn compress(
+    response: Response<Body>,
+    encoding: Option<Encoding>,
+    min_bytes: u64,
+) -> Response<Body> {
+    let (mut parts, body) = response.into_parts();
+    let is_json = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    let large = body.size_hint().exact().is_some_and(|n| n >= min_bytes);
+    if !is_json || !large || parts.headers.contains_key(CONTENT_ENCODING) {
+        return Response::from_parts(parts, body);
+    }
+    // Caches must not hand a compressed response to clients that didn't ask for one.
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => return Response::from_parts(parts, body),
+    };
+    let compressed = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => encoding.encode(&bytes),
+        Err(e) => Err(std::io::Error::other(e)),
+    };
+    match compressed {
+        Ok(compressed) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.insert(
+                CONTENT_ENCODING,
+                HeaderValue::from_static(encoding.as_str()),
+            );
+            Response::from_parts(parts, Body::from(compressed))
+        }
+        Err(e) => {
+            eprintln!("failed to compress response: {:?}", e);
+            parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+            Response::from_parts(parts, Body::empty())
+        }
+    }
+}
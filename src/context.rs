@@ -0,0 +1,362 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use base64ct::Encoding as _;
+use eyre::{eyre, WrapErr};
+use warp::{Filter, Rejection};
+
+use crate::api_token::{authenticate_scoped, optional_authenticate_scoped, Scope};
+use crate::captcha::Captcha;
+use crate::config::Config;
+use crate::invite::InvitePolicy;
+use crate::ip_limit::IpLimiter;
+use crate::login_throttle::LoginThrottle;
+use crate::mail::{LogMailer, Mailer, SmtpMailer};
+use crate::metadata::{MetadataProvider, ProviderKind, Providers};
+use crate::oauth::OAuth;
+use crate::password_policy::PasswordPolicy;
+use crate::server::Listen;
+use crate::tag_policy::TagPolicy;
+use crate::url_policy::UrlPolicy;
+use crate::usermgmt::{
+    authenticate, client, optional_authenticate, require_permission, require_role, AccountSession,
+    Client, DeletedSignals, EmailChange, MagicLink, PasswordReset, Peppers, Permission, Role,
+    SessionPolicy,
+};
+use crate::write_limit::WriteLimiter;
+use crate::DB;
+
+/// What route handlers share: the database pool, and the services and settings made from the
+/// configuration. Made once at startup and leaked, so that filters can hold on to it.
+pub struct Context {
+    pub(crate) db: DB,
+    pub(crate) max_body_bytes: u64,
+    pub(crate) bex_artifact_max_bytes: u64,
+    pub(crate) peppers: &'static Peppers,
+    pub(crate) password_reset: &'static PasswordReset,
+    pub(crate) magic_link: &'static MagicLink,
+    pub(crate) email_change: &'static EmailChange,
+    pub(crate) oauth: Option<&'static OAuth>,
+    pub(crate) captcha: Option<&'static Captcha>,
+    pub(crate) domain: &'static str,
+    pub(crate) session_policy: &'static SessionPolicy,
+    pub(crate) login_throttle: &'static LoginThrottle,
+    pub(crate) write_limiter: &'static WriteLimiter,
+    pub(crate) ip_limiter: &'static IpLimiter,
+    /// Reverse proxies in front of the server, whose `X-Forwarded-For` is trusted.
+    pub(crate) trusted_proxies: usize,
+    pub(crate) password_policy: &'static PasswordPolicy,
+    pub(crate) invite_policy: &'static InvitePolicy,
+    pub(crate) account_deletion_signals: DeletedSignals,
+    pub(crate) bex_latest_version: &'static str,
+    pub(crate) tag_tombstone_days: i32,
+    pub(crate) tag_policy: &'static TagPolicy,
+    pub(crate) fic_cache: &'static crate::fichub::Cache,
+    pub(crate) url_policy: &'static UrlPolicy,
+    pub(crate) fic_batch_max_urls: usize,
+    pub(crate) fic_batch_concurrency: usize,
+    /// The background jobs that are running, see `GET v1/meta/version`.
+    pub(crate) features: &'static [&'static str],
+    pub(crate) metrics_listen: Option<SocketAddr>,
+    pub(crate) swagger_ui: bool,
+    /// Responses of at least this many bytes are compressed, if compression is on.
+    pub(crate) compression: Option<u64>,
+    /// How long a request may take, if there's a limit.
+    pub(crate) request_timeout: Option<std::time::Duration>,
+}
+
+impl Context {
+    /// Sets up the services configured in `cfg`. Doesn't start background jobs, so `features`
+    /// is left empty.
+    pub fn new(cfg: &Config, db: DB) -> eyre::Result<Self> {
+        let decode_pepper = |pepper: &str| {
+            base64ct::Base64Unpadded::decode_vec(pepper).wrap_err("pepper is not valid base64")
+        };
+        let retired_peppers = cfg
+            .pwd_retired_peppers
+            .iter()
+            .flat_map(|peppers| peppers.split(','))
+            .map(|pair| {
+                let (id, peppers) = pair
+                    .split_once(':')
+                    .ok_or_else(|| eyre!("retired peppers must be given as id:pepper"))?;
+                Ok((id.to_string(), decode_pepper(peppers)?))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        let peppers: &'static Peppers = Box::leak(Box::new(Peppers::new(
+            cfg.pwd_pepper_id.clone(),
+            decode_pepper(&cfg.pwd_pepper)?,
+            retired_peppers,
+        )?));
+
+        let mailer: &'static dyn Mailer = match &cfg.smtp_url {
+            Some(url) => Box::leak(Box::new(SmtpMailer::new(url, &cfg.mail_from)?)),
+            None => &LogMailer,
+        };
+        let password_reset: &'static PasswordReset = Box::leak(Box::new(PasswordReset {
+            mailer,
+            ttl: chrono::Duration::seconds(cfg.password_reset_ttl_secs),
+            link: cfg.password_reset_link.clone().unwrap_or_else(|| {
+                format!("https://{}/password-reset?token={{token}}", cfg.domain)
+            }),
+        }));
+        let magic_link: &'static MagicLink = Box::leak(Box::new(MagicLink {
+            mailer,
+            ttl: chrono::Duration::seconds(cfg.magic_link_ttl_secs),
+            link: cfg.magic_link.clone().unwrap_or_else(|| {
+                format!(
+                    "https://{}/v1/sessions/magic-link?token={{token}}",
+                    cfg.domain
+                )
+            }),
+            return_url: cfg
+                .magic_link_return_url
+                .clone()
+                .unwrap_or_else(|| format!("https://{}/", cfg.domain)),
+        }));
+        let email_change: &'static EmailChange = Box::leak(Box::new(EmailChange {
+            mailer,
+            ttl: chrono::Duration::seconds(cfg.email_change_ttl_secs),
+            link: cfg
+                .email_change_link
+                .clone()
+                .unwrap_or_else(|| format!("https://{}/email-change?token={{token}}", cfg.domain)),
+        }));
+        let oauth: Option<&'static OAuth> = match &cfg.oauth_issuer {
+            Some(issuer) => Some(Box::leak(Box::new(OAuth {
+                issuer: issuer.clone(),
+                client_id: cfg
+                    .oauth_client_id
+                    .clone()
+                    .ok_or_else(|| eyre!("FICAI_OAUTH_CLIENT_ID is required with an issuer"))?,
+                client_secret: cfg
+                    .oauth_client_secret
+                    .clone()
+                    .ok_or_else(|| eyre!("FICAI_OAUTH_CLIENT_SECRET is required with an issuer"))?,
+                redirect_url: cfg
+                    .oauth_redirect_url
+                    .clone()
+                    .unwrap_or_else(|| format!("https://{}/v1/oauth/callback", cfg.domain)),
+                return_url: cfg
+                    .oauth_return_url
+                    .clone()
+                    .unwrap_or_else(|| format!("https://{}/", cfg.domain)),
+                signup: cfg.oauth_signup,
+                http: reqwest::Client::builder()
+                    .timeout(std::time::Duration::from_secs(10))
+                    .build()?,
+            }))),
+            None => None,
+        };
+        let captcha: Option<&'static Captcha> = match &cfg.captcha_verify_url {
+            Some(verify_url) => Some(Box::leak(Box::new(Captcha {
+                verify_url: verify_url.clone(),
+                secret: cfg
+                    .captcha_secret
+                    .clone()
+                    .ok_or_else(|| eyre!("FICAI_CAPTCHA_SECRET is required with a verify url"))?,
+                http: reqwest::Client::builder()
+                    .timeout(std::time::Duration::from_secs(10))
+                    .build()?,
+            }))),
+            None => None,
+        };
+        let domain: &'static str = Box::leak(cfg.domain.clone().into_boxed_str());
+        let cookie_key: Option<&'static [u8]> = match &cfg.session_cookie_key {
+            Some(key) => Some(Box::leak(
+                base64ct::Base64Unpadded::decode_vec(key)
+                    .wrap_err("session cookie key is not valid base64")?
+                    .into_boxed_slice(),
+            )),
+            None => None,
+        };
+        let session_policy: &'static SessionPolicy = Box::leak(Box::new(SessionPolicy {
+            domain,
+            max_age: chrono::Duration::seconds(cfg.session_max_age_secs),
+            idle_timeout: chrono::Duration::seconds(cfg.session_idle_timeout_secs),
+            cookie_key,
+            max_sessions: cfg.max_sessions_per_account,
+        }));
+        if cfg.login_throttle_ip_failures > 0
+            && cfg.trusted_proxies == 0
+            && matches!(cfg.listen, Listen::Unix(_))
+        {
+            return Err(eyre!(
+                "clients on a unix socket have no address to throttle logins by: set \
+                 FICAI_TRUSTED_PROXIES, or FICAI_LOGIN_THROTTLE_IP_FAILURES=0"
+            ));
+        }
+        let login_throttle: &'static LoginThrottle = Box::leak(Box::new(LoginThrottle {
+            email_failures: cfg.login_throttle_email_failures,
+            ip_failures: cfg.login_throttle_ip_failures,
+            window: chrono::Duration::seconds(cfg.login_throttle_window_secs),
+            max_lockout: chrono::Duration::seconds(cfg.login_throttle_max_lockout_secs),
+        }));
+        let write_limiter: &'static WriteLimiter = Box::leak(Box::new(WriteLimiter::new(
+            cfg.write_limit_per_minute,
+            cfg.write_limit_trusted_per_minute,
+            cfg.write_limit_restricted_per_minute,
+        )));
+        let ip_limiter: &'static IpLimiter = Box::leak(Box::new(
+            IpLimiter::new(
+                cfg.ip_limit_requests,
+                std::time::Duration::from_secs(cfg.ip_limit_window_secs),
+                &cfg.ip_limit_routes,
+                cfg.trusted_proxies,
+            )
+            .wrap_err("bad IP rate limit")?,
+        ));
+        let password_policy: &'static PasswordPolicy = Box::leak(Box::new(PasswordPolicy {
+            min_length: cfg.password_min_length,
+            min_strength_bits: cfg.password_min_strength_bits,
+        }));
+        let invite_policy: &'static InvitePolicy = Box::leak(Box::new(InvitePolicy {
+            per_account: cfg.invites_per_account,
+            ttl: chrono::Duration::seconds(cfg.invite_ttl_secs),
+        }));
+        let bex_latest_version: &'static str =
+            Box::leak(cfg.bex_latest_version.clone().into_boxed_str());
+        let tag_tombstone_days = cfg.tag_tombstone_days;
+        let tag_policy: &'static TagPolicy = Box::leak(Box::new(TagPolicy {
+            // Signals are stored as `varchar(1024)`.
+            max_length: cfg.tag_max_length.min(1024),
+            allowed: cfg.tag_allowed_chars.clone(),
+            reserved_prefixes: cfg
+                .tag_reserved_prefixes
+                .iter()
+                .filter(|p| !p.is_empty())
+                .cloned()
+                .collect(),
+        }));
+
+        let mut providers: Vec<Box<dyn MetadataProvider>> = Vec::new();
+        for kind in &cfg.metadata_providers {
+            providers.push(match kind {
+                ProviderKind::Fichub => Box::new(crate::fichub::Client::new(
+                    cfg.fichub_url.trim_end_matches('/').to_string(),
+                    crate::fichub::ClientOptions {
+                        timeout: std::time::Duration::from_secs(cfg.fichub_timeout_secs),
+                        retries: cfg.fichub_retries,
+                        backoff: std::time::Duration::from_millis(200),
+                        breaker_threshold: cfg.fichub_breaker_threshold.max(1),
+                        breaker_cooldown: std::time::Duration::from_secs(
+                            cfg.fichub_breaker_cooldown_secs,
+                        ),
+                        max_rps: cfg.fichub_max_rps,
+                        max_concurrency: cfg.fichub_max_concurrency,
+                        queue_timeout: std::time::Duration::from_secs(
+                            cfg.fichub_queue_timeout_secs,
+                        ),
+                    },
+                )?),
+                ProviderKind::Ao3 => Box::new(crate::ao3::Client::new(
+                    cfg.ao3_url.trim_end_matches('/').to_string(),
+                    std::time::Duration::from_secs(cfg.fichub_timeout_secs),
+                )?),
+                ProviderKind::Opengraph => {
+                    Box::new(crate::opengraph::Client::new(crate::opengraph::Options {
+                        allowed_hosts: cfg.opengraph_allowed_hosts.clone(),
+                        timeout: std::time::Duration::from_secs(cfg.opengraph_timeout_secs),
+                        max_bytes: cfg.opengraph_max_bytes,
+                    })?)
+                }
+            });
+        }
+        let fic_cache: &'static crate::fichub::Cache =
+            Box::leak(Box::new(crate::fichub::Cache::new(
+                db.clone(),
+                Providers(providers),
+                chrono::Duration::seconds(cfg.fic_cache_ttl_secs),
+                chrono::Duration::seconds(cfg.fic_cache_stale_secs),
+                chrono::Duration::seconds(cfg.fic_cache_failure_ttl_secs),
+            )));
+        let url_policy: &'static UrlPolicy = Box::leak(Box::new(UrlPolicy {
+            unknown_sites: cfg.url_policy_unknown_sites,
+            cache: fic_cache,
+        }));
+
+        Ok(Context {
+            db,
+            max_body_bytes: cfg.max_body_bytes,
+            bex_artifact_max_bytes: cfg.bex_artifact_max_bytes,
+            peppers,
+            password_reset,
+            magic_link,
+            email_change,
+            oauth,
+            captcha,
+            domain,
+            session_policy,
+            login_throttle,
+            write_limiter,
+            ip_limiter,
+            trusted_proxies: cfg.trusted_proxies,
+            password_policy,
+            invite_policy,
+            account_deletion_signals: cfg.account_deletion_signals,
+            bex_latest_version,
+            tag_tombstone_days,
+            tag_policy,
+            fic_cache,
+            url_policy,
+            fic_batch_max_urls: cfg.fic_batch_max_urls,
+            fic_batch_concurrency: cfg.fic_batch_concurrency,
+            features: &[],
+            metrics_listen: cfg.metrics_listen,
+            swagger_ui: cfg.swagger_ui,
+            compression: cfg.compression.then_some(cfg.compression_min_bytes),
+            request_timeout: (cfg.request_timeout_secs > 0)
+                .then(|| std::time::Duration::from_secs(cfg.request_timeout_secs)),
+        })
+    }
+
+    pub fn pool(&self) -> impl Filter<Extract = (DB,), Error = Infallible> + Clone {
+        let db = self.db.clone();
+        warp::any().map(move || db.clone())
+    }
+
+    /// The client's address, resolved through `trusted_proxies`, and user agent.
+    pub fn client(&self) -> impl Filter<Extract = (Client,), Error = Rejection> + Clone {
+        client(self.trusted_proxies)
+    }
+
+    pub fn authenticate(
+        &self,
+    ) -> impl Filter<Extract = (AccountSession,), Error = Rejection> + Clone {
+        authenticate(self.db.clone(), self.session_policy)
+    }
+
+    pub fn optional_authenticate(
+        &self,
+    ) -> impl Filter<Extract = (Option<AccountSession>,), Error = Rejection> + Clone {
+        optional_authenticate(self.db.clone(), self.session_policy)
+    }
+
+    pub fn authenticate_scoped(
+        &self,
+        scope: Scope,
+    ) -> impl Filter<Extract = (AccountSession,), Error = Rejection> + Clone {
+        authenticate_scoped(self.db.clone(), self.session_policy, scope)
+    }
+
+    pub fn optional_authenticate_scoped(
+        &self,
+        scope: Scope,
+    ) -> impl Filter<Extract = (Option<AccountSession>,), Error = Rejection> + Clone {
+        optional_authenticate_scoped(self.db.clone(), self.session_policy, scope)
+    }
+
+    pub fn require_role(
+        &self,
+        role: Role,
+    ) -> impl Filter<Extract = (AccountSession,), Error = Rejection> + Clone {
+        require_role(self.db.clone(), self.session_policy, role)
+    }
+
+    pub fn require_permission(
+        &self,
+        permission: Permission,
+    ) -> impl Filter<Extract = (AccountSession,), Error = Rejection> + Clone {
+        require_permission(self.db.clone(), self.session_policy, permission)
+    }
+}
@@ -0,0 +1,106 @@
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{cached, json_body, reject, ApiError, CachePolicy, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, CONSTRAINT_VIOLATION_SQLSTATE};
+use crate::DB;
+
+const MIN_LENGTH: usize = 3;
+const MAX_LENGTH: usize = 32;
+
+/// Display names are what others see of an account, so that its email doesn't have to be shown
+/// anywhere. They're unique regardless of case, lest two accounts pass for one another.
+pub(crate) fn validate(name: &str) -> Result<(), &'static str> {
+    let length = name.chars().count();
+    if !(MIN_LENGTH..=MAX_LENGTH).contains(&length) {
+        return Err("display name must be 3 to 32 characters long");
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '_' | '-' | '.'))
+    {
+        return Err("display name may only contain letters, digits, spaces and _-.");
+    }
+    if name.starts_with(' ') || name.ends_with(' ') || name.contains("  ") {
+        return Err("display name must not have leading, trailing or repeated spaces");
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DisplayNameQ {
+    /// 3 to 32 letters, digits, spaces and `_-.`, without leading, trailing or repeated spaces.
+    /// `null` to go without one.
+    display_name: Option<String>,
+}
+
+/// Set or clear the current account's display name.
+#[utoipa::path(
+    put,
+    path = "/accounts/display-name",
+    tag = "accounts",
+    request_body = DisplayNameQ,
+    responses(
+        (status = 200, description = "The new display name.", body = DisplayNameQ),
+        (status = 400, description = "The display name breaks the rules.", body = ErrorWrap),
+        (status = 403, description = "Not logged in.", body = ErrorWrap),
+        (
+            status = 409,
+            description = "Another account already has the display name, regardless of case.",
+            body = ErrorWrap,
+        ),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn put_display_name(
+    account: AccountSession,
+    q: DisplayNameQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    if let Some(name) = &q.display_name {
+        validate(name).map_err(|e| ApiError::BadRequest(e.into()))?;
+    }
+    let result = sqlx::query("update account set display_name = $2 where id = $1")
+        .bind(account.id)
+        .bind(&q.display_name)
+        .execute(&pool)
+        .await;
+    match result {
+        Ok(_) => Ok(json(&q).into_response()),
+        Err(sqlx::Error::Database(db_err))
+            if db_err.code() == Some(CONSTRAINT_VIOLATION_SQLSTATE.into()) =>
+        {
+            Err(ApiError::DisplayNameTaken)
+        }
+        Err(e) => {
+            eprintln!("failed to set display name: {:?}", e);
+            Err(ApiError::Internal)
+        }
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(put_display_name))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let put_display_name = warp::path!("v1" / "accounts" / "display-name")
+        .and(warp::put())
+        .and(ctx.authenticate())
+        .and(json_body::<DisplayNameQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(put_display_name)
+        .and_then(reject);
+    cached(
+        CachePolicy::Never,
+        &[],
+        put_display_name.map(Reply::into_response),
+    )
+    .boxed()
+}
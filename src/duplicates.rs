@@ -0,0 +1,336 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::{DateTime, Utc};
+use http::Response;
+use hyper::Body;
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{reject, ApiError, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+/// Accounts need at least this many signals before their signals are compared.
+const MIN_SIGNALS_FOR_CORRELATION: i64 = 5;
+/// Jaccard similarity of two accounts' signals above which they are considered correlated.
+const SIGNAL_SIMILARITY_THRESHOLD: f64 = 0.8;
+/// Networks used by more accounts than this say nothing about any two of them, e.g. a carrier's
+/// NAT, or a reverse proxy recorded before `FICAI_TRUSTED_PROXIES` was set.
+const MAX_ACCOUNTS_PER_NETWORK: i64 = 10;
+
+/// Reduces an email address to the mailbox it is delivered to: lowercased, without `+suffix`
+/// sub-addressing, and without dots for providers that ignore them.
+fn normalize_email(email: &str) -> String {
+    let email = email.trim().to_lowercase();
+    let (local, domain) = match email.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return email,
+    };
+    let local = local.split('+').next().unwrap_or(local);
+    let domain = match domain {
+        "googlemail.com" => "gmail.com",
+        d => d,
+    };
+    if domain == "gmail.com" {
+        format!("{}@{}", local.replace('.', ""), domain)
+    } else {
+        format!("{}@{}", local, domain)
+    }
+}
+
+/// Finds pairs of likely duplicate accounts and replaces the stored candidates with them.
+/// Returns the number of candidate pairs found.
+pub async fn detect(pool: &DB) -> eyre::Result<usize> {
+    let mut pairs: BTreeMap<(i64, i64), BTreeSet<String>> = BTreeMap::new();
+    let mut add = |a: i64, b: i64, reason: String| {
+        let key = if a < b { (a, b) } else { (b, a) };
+        pairs.entry(key).or_default().insert(reason);
+    };
+
+    let accounts = sqlx::query_as::<_, (i64, String)>("select id, email from account")
+        .fetch_all(pool)
+        .await?;
+    let mut by_mailbox: HashMap<String, Vec<i64>> = HashMap::new();
+    for (id, email) in accounts {
+        by_mailbox
+            .entry(normalize_email(&email))
+            .or_default()
+            .push(id);
+    }
+    for (mailbox, ids) in by_mailbox {
+        for (i, a) in ids.iter().enumerate() {
+            for b in &ids[i + 1..] {
+                add(*a, *b, format!("emails normalize to {}", mailbox));
+            }
+        }
+    }
+
+    let shared_networks = sqlx::query_as::<_, (i64, i64, String)>(
+        "
+with ip as (
+    select id as account_id, created_ip as ip from account where created_ip is not null
+    union
+    select account_id, created_ip from session where created_ip is not null
+),
+net as (
+    select distinct
+        account_id,
+        network(set_masklen(ip, case when family(ip) = 4 then 24 else 64 end)) as net
+    from ip
+),
+small_net as (
+    select net
+    from net
+    group by net
+    having count(1) <= $1
+)
+select a.account_id, b.account_id, a.net::text
+from net a
+join small_net s
+    on s.net = a.net
+join net b
+    on a.net = b.net and a.account_id < b.account_id
+        ",
+    )
+    .bind(MAX_ACCOUNTS_PER_NETWORK)
+    .fetch_all(pool)
+    .await?;
+    for (a, b, net) in shared_networks {
+        add(a, b, format!("used the same network {}", net));
+    }
+
+    let correlated = sqlx::query_as::<_, (i64, i64, f64)>(
+        "
+with per_account as (
+    select account_id, count(1) as n
+    from signal
+    group by account_id
+    having count(1) >= $1
+),
+shared as (
+    select a.account_id as a_id, b.account_id as b_id, count(1) as shared
+    from signal a
+    join signal b
+        on a.url = b.url and a.tag_canonical = b.tag_canonical and a.signal = b.signal
+        and a.account_id < b.account_id
+    group by a.account_id, b.account_id
+)
+select s.a_id, s.b_id, s.shared::float8 / (pa.n + pb.n - s.shared) as similarity
+from shared s
+join per_account pa
+    on pa.account_id = s.a_id
+join per_account pb
+    on pb.account_id = s.b_id
+where s.shared::float8 / (pa.n + pb.n - s.shared) >= $2
+        ",
+    )
+    .bind(MIN_SIGNALS_FOR_CORRELATION)
+    .bind(SIGNAL_SIMILARITY_THRESHOLD)
+    .fetch_all(pool)
+    .await?;
+    for (a, b, similarity) in correlated {
+        add(
+            a,
+            b,
+            format!("{:.0}% of their signals are identical", similarity * 100.0),
+        );
+    }
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("delete from duplicate_account_candidate")
+        .execute(&mut tx)
+        .await?;
+    for ((a, b), reasons) in &pairs {
+        sqlx::query(
+            "
+insert into duplicate_account_candidate (account_id_a, account_id_b, reasons)
+values ($1, $2, $3)
+            ",
+        )
+        .bind(a)
+        .bind(b)
+        .bind(reasons.iter().cloned().collect::<Vec<String>>())
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(pairs.len())
+}
+
+/// Spawns a task that runs [`detect`] every `interval`.
+pub fn spawn_detection(pool: DB, interval: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match detect(&pool).await {
+                Ok(n) => println!("duplicate account detection: {} candidate pairs", n),
+                Err(e) => eprintln!("duplicate account detection failed: {:?}", e),
+            }
+        }
+    });
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterAccount {
+    id: i64,
+    #[schema(format = Email)]
+    email: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Cluster {
+    accounts: Vec<ClusterAccount>,
+    /// Human readable reasons why accounts in the cluster were linked.
+    reasons: Vec<String>,
+}
+
+/// Clusters of accounts that likely belong to the same person.
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = DuplicateAccountsReport)]
+pub struct Report {
+    /// When detection last ran, if any candidates were found.
+    detected_at: Option<DateTime<Utc>>,
+    clusters: Vec<Cluster>,
+}
+
+fn find(parent: &mut HashMap<i64, i64>, id: i64) -> i64 {
+    let p = *parent.entry(id).or_insert(id);
+    if p == id {
+        return id;
+    }
+    let root = find(parent, p);
+    parent.insert(id, root);
+    root
+}
+
+/// Get the latest report of likely duplicate accounts. Requires the `user-moderation` permission.
+///
+/// Accounts are linked when their emails normalize to the same mailbox, when they signed up or
+/// logged in from the same network (/24 for IPv4, /64 for IPv6), or when most of their signals are
+/// identical. Linked accounts are grouped into clusters. No action is taken automatically.
+#[utoipa::path(
+    get,
+    path = "/admin/reports/duplicate-accounts",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Success.", body = Report),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_duplicate_accounts_report(
+    _account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let rows = sqlx::query_as::<_, (i64, String, i64, String, Vec<String>, DateTime<Utc>)>(
+        "
+select c.account_id_a, a.email, c.account_id_b, b.email, c.reasons, c.detected_at
+from duplicate_account_candidate c
+join account a
+    on a.id = c.account_id_a
+join account b
+    on b.id = c.account_id_b
+order by c.account_id_a, c.account_id_b
+        ",
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to load duplicate account candidates: {:?}", e);
+        ApiError::Internal
+    })?;
+
+    let mut parent = HashMap::new();
+    let mut emails = HashMap::new();
+    let mut detected_at = None;
+    for (a, a_email, b, b_email, _, at) in &rows {
+        emails.insert(*a, a_email.clone());
+        emails.insert(*b, b_email.clone());
+        let (ra, rb) = (find(&mut parent, *a), find(&mut parent, *b));
+        parent.insert(ra.max(rb), ra.min(rb));
+        detected_at = detected_at.max(Some(*at));
+    }
+
+    let mut clusters: BTreeMap<i64, (BTreeSet<i64>, BTreeSet<String>)> = BTreeMap::new();
+    for (a, _, b, _, reasons, _) in rows {
+        let root = find(&mut parent, a);
+        let cluster = clusters.entry(root).or_default();
+        cluster.0.insert(a);
+        cluster.0.insert(b);
+        cluster.1.extend(reasons);
+    }
+
+    let report = Report {
+        detected_at,
+        clusters: clusters
+            .into_values()
+            .map(|(ids, reasons)| Cluster {
+                accounts: ids
+                    .into_iter()
+                    .map(|id| ClusterAccount {
+                        id,
+                        email: emails.remove(&id).unwrap_or_default(),
+                    })
+                    .collect(),
+                reasons: reasons.into_iter().collect(),
+            })
+            .collect(),
+    };
+    Ok(json(&report).into_response())
+}
+
+/// Run duplicate account detection now and return the new report.
+///
+/// Requires the `user-moderation` permission.
+#[utoipa::path(
+    post,
+    path = "/admin/reports/duplicate-accounts",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Success.", body = Report),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn run_duplicate_accounts_report(
+    account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    detect(&pool).await.map_err(|e| {
+        eprintln!("duplicate account detection failed: {:?}", e);
+        ApiError::Internal
+    })?;
+    get_duplicate_accounts_report(account, pool).await
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_duplicate_accounts_report, run_duplicate_accounts_report))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_duplicate_accounts_report =
+        warp::path!("v1" / "admin" / "reports" / "duplicate-accounts")
+            .and(warp::get())
+            .and(ctx.require_permission(Permission::UserModeration))
+            .and(ctx.pool())
+            .then(get_duplicate_accounts_report)
+            .and_then(reject);
+    let run_duplicate_accounts_report =
+        warp::path!("v1" / "admin" / "reports" / "duplicate-accounts")
+            .and(warp::post())
+            .and(ctx.require_permission(Permission::UserModeration))
+            .and(ctx.pool())
+            .then(run_duplicate_accounts_report)
+            .and_then(reject);
+    get_duplicate_accounts_report
+        .or(run_duplicate_accounts_report)
+        .map(Reply::into_response)
+        .boxed()
+}
@@ -0,0 +1,171 @@
+use chrono::NaiveDate;
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{Filter, Reply};
+
+use crate::context::Context;
+use crate::fichub::CachedMeta;
+use crate::httputil::{reply_json, ErrorWrap, TimeWindow};
+use crate::routes::Routes;
+use crate::signal::SYSTEM_ACCOUNT_ID;
+use crate::DB;
+
+/// Recomputes `fic_stats_daily` from scratch. Signals are counted on the day they were last
+/// changed, for the fic their URL belongs to now; signals on URLs without metadata don't count,
+/// and neither do the server's own. Returns the number of rows written.
+pub async fn rollup(pool: &DB) -> eyre::Result<u64> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("delete from fic_stats_daily")
+        .execute(&mut tx)
+        .await?;
+    let written = sqlx::query(
+        "
+insert into fic_stats_daily (fic_id, day, signals)
+select u.fic_id, (s.updated_at at time zone 'UTC')::date, count(1)
+from signal s
+join fic_url u
+    on u.url = s.url
+where s.account_id <> $1
+group by 1, 2
+        ",
+    )
+    .bind(SYSTEM_ACCOUNT_ID)
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    tx.commit().await?;
+    Ok(written)
+}
+
+/// Spawns a task that runs [`rollup`] every `interval`.
+pub fn spawn_rollup(pool: DB, interval: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = rollup(&pool).await {
+                eprintln!("fic stats rollup failed: {:?}", e);
+            }
+        }
+    });
+}
+
+const MAX_POPULAR_LIMIT: i64 = 100;
+const DEFAULT_POPULAR_LIMIT: i64 = 20;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PopularFicsQ {
+    /// How far back to go.
+    #[param(inline, default = "7d")]
+    window: Option<TimeWindow>,
+    #[param(default = 20, minimum = 0, maximum = 100)]
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PopularFic {
+    #[serde(flatten)]
+    meta: CachedMeta,
+    /// Signals added or changed during the window.
+    signals: i64,
+}
+
+impl<'r> FromRow<'r, PgRow> for PopularFic {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            meta: CachedMeta::from_row(row)?,
+            signals: row.try_get("signals")?,
+        })
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PopularFics {
+    /// First day counted.
+    since: NaiveDate,
+    fics: Vec<PopularFic>,
+}
+
+impl PopularFics {
+    /// The fics with the most signal activity in the window, which defaults to 7 days. Counts are
+    /// as of the last rollup run.
+    pub async fn get(q: PopularFicsQ, pool: &DB) -> eyre::Result<Self> {
+        let window = q.window.map_or(chrono::Duration::days(7), |w| w.0);
+        let since = (chrono::Utc::now() - window).date_naive();
+        let fics = sqlx::query_as::<_, PopularFic>(
+            "
+with activity as (
+    select fic_id, sum(signals)::bigint as signals
+    from fic_stats_daily
+    where day >= $1
+    group by fic_id
+)
+select
+    f.id, f.title, f.source, f.site, f.author, f.author_id, f.series, f.series_id,
+    f.series_position, f.words, f.chapters, f.status, f.fandoms, f.updated, c.fetched_at,
+    a.signals
+from activity a
+join fic f
+    on f.id = a.fic_id
+join lateral (
+    select max(fetched_at) as fetched_at
+    from fic_url_cache
+    where fic_id = f.id
+) c
+    on c.fetched_at is not null
+order by a.signals desc, f.id
+limit $2
+            ",
+        )
+        .bind(since)
+        .bind(
+            q.limit
+                .unwrap_or(DEFAULT_POPULAR_LIMIT)
+                .clamp(0, MAX_POPULAR_LIMIT),
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(Self { since, fics })
+    }
+}
+
+/// Get the fics with the most signal activity recently.
+///
+/// Signals added or changed during the window count, except those the server adds itself. Only fics
+/// whose metadata is known are listed. Counts are computed periodically, so the most recent signals
+/// may be missing.
+#[utoipa::path(
+    get,
+    path = "/fics/popular",
+    tag = "fics",
+    params(PopularFicsQ),
+    responses(
+        (status = 200, description = "Most active fics first.", body = PopularFics),
+        (status = 400, description = "The window is invalid.", body = ErrorWrap),
+    ),
+)]
+async fn get_popular_fics(q: PopularFicsQ, pool: DB) -> eyre::Result<PopularFics> {
+    PopularFics::get(q, &pool)
+        .await
+        .wrap_err("failed to get popular fics")
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_popular_fics))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_popular_fics = warp::path!("v1" / "fics" / "popular")
+        .and(warp::get())
+        .and(warp::query::<PopularFicsQ>())
+        .and(ctx.pool())
+        .then(get_popular_fics)
+        .and_then(reply_json);
+    get_popular_fics.map(Reply::into_response).boxed()
+}
@@ -0,0 +1,188 @@
+use chrono::{DateTime, Utc};
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{Filter, Reply};
+
+use crate::api_token::Scope;
+use crate::context::Context;
+use crate::fichub::{FicStatus, Meta};
+use crate::httputil::{reply_json, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::AccountSession;
+use crate::DB;
+
+/// What's known about a fic's progress, to tell whether it was updated.
+#[derive(Debug, sqlx::FromRow)]
+pub(crate) struct Progress {
+    chapters: Option<i32>,
+    updated: Option<DateTime<Utc>>,
+    status: Option<FicStatus>,
+}
+
+impl Progress {
+    pub(crate) async fn get(
+        tx: &mut Transaction<'_, Postgres>,
+        fic_id: &str,
+    ) -> eyre::Result<Option<Self>> {
+        Ok(sqlx::query_as::<_, Self>(
+            "select chapters, updated, status from fic where id = $1 for update",
+        )
+        .bind(fic_id)
+        .fetch_optional(&mut *tx)
+        .await?)
+    }
+}
+
+/// Records an update of `meta.id` if it has more chapters, was updated later or changed status
+/// since `before`. Providers that don't report any of these never yield updates, and a status
+/// that is no longer reported isn't a change.
+pub(crate) async fn detect(
+    tx: &mut Transaction<'_, Postgres>,
+    before: &Progress,
+    meta: &Meta,
+) -> eyre::Result<()> {
+    let more_chapters = matches!((before.chapters, meta.chapters), (Some(b), Some(a)) if a > b);
+    let updated_later = matches!((before.updated, meta.updated), (Some(b), Some(a)) if a > b);
+    let status_changed = matches!((before.status, meta.status), (Some(b), Some(a)) if a != b);
+    if !more_chapters && !updated_later && !status_changed {
+        return Ok(());
+    }
+    sqlx::query(
+        "
+insert into fic_update (fic_id, chapters_before, chapters, status_before, status, updated)
+values ($1, $2, $3, $4, $5, $6)
+        ",
+    )
+    .bind(&meta.id)
+    .bind(before.chapters)
+    .bind(meta.chapters)
+    .bind(before.status)
+    .bind(meta.status)
+    .bind(meta.updated)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct UpdatesQ {
+    #[param(default = 50, minimum = 0, maximum = 200)]
+    limit: Option<i64>,
+    /// Only return updates detected before this, to fetch the page after one ending there.
+    before: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = FicUpdate)]
+pub struct Update {
+    fic_id: String,
+    title: String,
+    source: String,
+    chapters_before: Option<i32>,
+    chapters: Option<i32>,
+    /// Differs from `status` if the update is a change of status, e.g. the fic was completed.
+    status_before: Option<FicStatus>,
+    status: Option<FicStatus>,
+    /// When the provider says the fic was updated.
+    updated: Option<DateTime<Utc>>,
+    /// When the update was noticed, which may be much later.
+    detected_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = FicUpdates)]
+pub struct Updates {
+    updates: Vec<Update>,
+}
+
+const DEFAULT_UPDATES_LIMIT: i64 = 50;
+const MAX_UPDATES_LIMIT: i64 = 200;
+
+impl Updates {
+    /// Updates of fics `uid` signalled on, under any of their URLs, most recently detected first.
+    pub async fn get(uid: i64, q: UpdatesQ, pool: &DB) -> eyre::Result<Self> {
+        Ok(Self {
+            updates: sqlx::query_as::<_, Update>(
+                "
+with tracked as (
+    select distinct u.fic_id
+    from signal s
+    join fic_url u
+        on u.url = s.url
+    where s.account_id = $1
+)
+select
+    f.id as fic_id,
+    f.title,
+    f.source,
+    up.chapters_before,
+    up.chapters,
+    up.status_before,
+    up.status,
+    up.updated,
+    up.detected_at
+from fic_update up
+join fic f
+    on f.id = up.fic_id
+where coalesce(f.canonical_id, f.id) in (select fic_id from tracked)
+    and ($2::timestamptz is null or up.detected_at < $2)
+order by up.detected_at desc, up.id desc
+limit $3
+                ",
+            )
+            .bind(uid)
+            .bind(q.before)
+            .bind(
+                q.limit
+                    .unwrap_or(DEFAULT_UPDATES_LIMIT)
+                    .clamp(0, MAX_UPDATES_LIMIT),
+            )
+            .fetch_all(pool)
+            .await?,
+        })
+    }
+}
+
+/// Get updates of fics the current account signalled on.
+///
+/// Updates are noticed when fic metadata is refreshed, e.g. by the background refresh job, and
+/// reported when a fic has more chapters or a later update date than before. Signals on any URL of
+/// a fic count. Updates are ordered by when they were noticed, most recent first; pass `detectedAt`
+/// of the last entry as `before` to get the next page.
+#[utoipa::path(
+    get,
+    path = "/updates",
+    tag = "fics",
+    params(UpdatesQ),
+    responses(
+        (status = 200, description = "Success.", body = Updates),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = []), ("bearerAuth" = [])),
+)]
+async fn get_fic_updates(account: AccountSession, q: UpdatesQ, pool: DB) -> eyre::Result<Updates> {
+    Updates::get(account.id, q, &pool)
+        .await
+        .wrap_err("failed to get fic updates")
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_fic_updates))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_fic_updates = warp::path!("v1" / "updates")
+        .and(warp::get())
+        .and(ctx.authenticate_scoped(Scope::Read))
+        .and(warp::query::<UpdatesQ>())
+        .and(ctx.pool())
+        .then(get_fic_updates)
+        .and_then(reply_json);
+    get_fic_updates.map(Reply::into_response).boxed()
+}
@@ -0,0 +1,1107 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use chrono::{DateTime, Duration, Utc};
+use eyre::{eyre, WrapErr};
+use futures::future::BoxFuture;
+use futures::{stream, FutureExt as _, StreamExt as _};
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow as _, Row as _};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::api_token::Scope;
+use crate::context::Context;
+use crate::fic_update::Progress;
+use crate::httputil::{
+    json_body, last_modified, reject, ApiError, CachePolicy, ErrorWrap, PercentDecoded,
+};
+use crate::metadata::{FailureKind, MetadataProvider, Providers, Unsupported};
+use crate::metrics;
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, Role};
+use crate::DB;
+
+/// Metadata of a fic as reported by a metadata provider.
+#[derive(Serialize, Debug, Clone, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    /// The provider's id for the fic, stable across URLs of the same fic. Other providers than
+    /// fichub prefix theirs with their name, e.g. `ao3-123`.
+    pub id: String,
+    pub title: String,
+    /// The URL the provider fetched the fic from.
+    pub source: String,
+    /// Name of the site the fic is posted on, e.g. `Archive of Our Own`, if the provider knows.
+    pub site: Option<String>,
+    pub author: Option<String>,
+    /// The provider's id for the author, see `author`.
+    pub author_id: Option<String>,
+    /// Title of the series the fic is part of, if any.
+    pub series: Option<String>,
+    /// The provider's id for the series, see `series`.
+    pub series_id: Option<String>,
+    /// Where in the series the fic is, starting at 1.
+    pub series_position: Option<i32>,
+    pub words: Option<i64>,
+    pub chapters: Option<i32>,
+    /// Null if the provider doesn't report a status this server understands.
+    pub status: Option<FicStatus>,
+    pub fandoms: Vec<String>,
+    /// When a chapter was last posted.
+    pub updated: Option<DateTime<Utc>>,
+}
+
+/// How far along a fic is. Providers that report something else, e.g. a hiatus, are mapped to
+/// the closest of these.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "fic_status", rename_all = "lowercase")]
+pub enum FicStatus {
+    Ongoing,
+    Complete,
+    Abandoned,
+}
+
+impl FicStatus {
+    /// Understands the statuses of the providers and sites they scrape; `None` for anything else.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "ongoing" | "in progress" | "in-progress" | "incomplete" | "wip" | "active"
+            | "hiatus" | "on hiatus" => Some(Self::Ongoing),
+            "complete" | "completed" | "finished" => Some(Self::Complete),
+            "abandoned" | "dead" | "discontinued" | "cancelled" | "canceled" => {
+                Some(Self::Abandoned)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct EpubResponse {
+    #[serde(default)]
+    err: i64,
+    msg: Option<String>,
+    meta: Option<FichubMeta>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FichubMeta {
+    id: String,
+    title: String,
+    source: String,
+    author: Option<String>,
+    author_id: Option<String>,
+    words: Option<i64>,
+    chapters: Option<i32>,
+    status: Option<String>,
+    updated: Option<String>,
+    /// Whatever else fichub scraped off the page, which differs from site to site.
+    raw_extended_meta: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FichubSeries {
+    id: String,
+    title: String,
+    position: Option<i32>,
+}
+
+impl From<FichubMeta> for Meta {
+    fn from(meta: FichubMeta) -> Self {
+        // Sites that have fandoms at all list them either as an array or in a single string.
+        let fandoms = match meta
+            .raw_extended_meta
+            .as_ref()
+            .and_then(|raw| raw.get("fandoms").or_else(|| raw.get("fandom")))
+        {
+            Some(serde_json::Value::Array(fandoms)) => fandoms
+                .iter()
+                .filter_map(|f| f.as_str())
+                .map(|f| f.trim().to_string())
+                .collect(),
+            Some(serde_json::Value::String(fandoms)) => fandoms
+                .split(&[',', '/'][..])
+                .map(|f| f.trim().to_string())
+                .collect(),
+            _ => Vec::new(),
+        };
+        // Only for sites that have series, as `{"id": ..., "title": ..., "position": ...}`.
+        let series = meta
+            .raw_extended_meta
+            .as_ref()
+            .and_then(|raw| raw.get("series"))
+            .and_then(|series| serde_json::from_value::<FichubSeries>(series.clone()).ok());
+        Self {
+            id: meta.id,
+            title: meta.title,
+            source: meta.source,
+            site: None,
+            author: meta.author,
+            author_id: meta.author_id,
+            series: series.as_ref().map(|s| s.title.clone()),
+            series_id: series.as_ref().map(|s| s.id.clone()),
+            series_position: series.and_then(|s| s.position),
+            words: meta.words,
+            chapters: meta.chapters,
+            status: meta.status.as_deref().and_then(FicStatus::parse),
+            fandoms: fandoms.into_iter().filter(|f| !f.is_empty()).collect(),
+            updated: meta.updated.as_deref().and_then(parse_timestamp),
+        }
+    }
+}
+
+/// Accepts RFC 3339 timestamps, and timestamps or dates without a time zone as fichub and AO3
+/// give them, which are in UTC.
+pub(crate) fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Some(t.with_timezone(&Utc));
+    }
+    if let Ok(t) = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S") {
+        return Some(t.and_utc());
+    }
+    chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|t| t.and_utc())
+}
+
+/// How hard [`Client`] tries to reach fichub.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientOptions {
+    /// For a single request, including reading the response.
+    pub timeout: std::time::Duration,
+    /// How many times a failed request is retried, waiting twice as long before each retry.
+    pub retries: u32,
+    /// Delay before the first retry.
+    pub backoff: std::time::Duration,
+    /// After this many lookups in a row failed, fichub is considered down.
+    pub breaker_threshold: u32,
+    /// How long to wait before trying fichub again once it's considered down.
+    pub breaker_cooldown: std::time::Duration,
+    /// Requests per second sent to fichub at most, over all lookups; `0` for no limit. Up to a
+    /// second's worth of requests may be sent at once.
+    pub max_rps: f64,
+    /// Requests to fichub in flight at most.
+    pub max_concurrency: usize,
+    /// How long a request may wait for its turn before the lookup fails as [`Throttled`].
+    pub queue_timeout: std::time::Duration,
+}
+
+/// Why an attempt at looking up a URL failed.
+enum Failure {
+    /// Fichub couldn't be reached or misbehaved; worth trying again.
+    Upstream(eyre::Report),
+    /// Fichub answered, but can't handle the URL.
+    Rejected(eyre::Report),
+    /// The request wasn't sent, see [`Throttled`].
+    Throttled,
+}
+
+/// The request budget for fichub was exhausted. Such lookups don't count as failures of fichub,
+/// and aren't remembered as failed either.
+#[derive(Debug)]
+pub struct Throttled;
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("too many requests to fichub already")
+    }
+}
+
+impl std::error::Error for Throttled {}
+
+/// A token bucket refilling at `max_rps`, so that bursts of lookups can't get the server banned
+/// by fichub. Tokens are taken ahead of time, so requests are sent in the order they asked.
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Stops sending requests to fichub for a while once it keeps failing, so that lookups fall back
+/// to cached data right away instead of waiting for timeouts.
+#[derive(Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    options: ClientOptions,
+    breaker: Mutex<Breaker>,
+    bucket: Mutex<Bucket>,
+    in_flight: tokio::sync::Semaphore,
+}
+
+impl Client {
+    /// `base_url` is e.g. `https://fichub.net`, without a trailing slash.
+    pub fn new(base_url: String, options: ClientOptions) -> eyre::Result<Self> {
+        let http = reqwest::Client::builder()
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .timeout(options.timeout)
+            .build()
+            .wrap_err("failed to build http client")?;
+        Ok(Self {
+            http,
+            base_url,
+            options,
+            breaker: Mutex::new(Breaker::default()),
+            bucket: Mutex::new(Bucket {
+                tokens: options.max_rps.max(1.0),
+                refilled_at: Instant::now(),
+            }),
+            in_flight: tokio::sync::Semaphore::new(options.max_concurrency.max(1)),
+        })
+    }
+
+    /// Waits for a token and a free slot for a request, for up to `queue_timeout`.
+    async fn wait_turn(&self) -> Result<tokio::sync::SemaphorePermit<'_>, Throttled> {
+        let deadline = Instant::now() + self.options.queue_timeout;
+        if self.options.max_rps > 0.0 {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let capacity = self.options.max_rps.max(1.0);
+                bucket.tokens = (bucket.tokens
+                    + (now - bucket.refilled_at).as_secs_f64() * self.options.max_rps)
+                    .min(capacity);
+                bucket.refilled_at = now;
+                let wait = std::time::Duration::from_secs_f64(
+                    (1.0 - bucket.tokens).max(0.0) / self.options.max_rps,
+                );
+                if wait > self.options.queue_timeout {
+                    return Err(Throttled);
+                }
+                bucket.tokens -= 1.0;
+                wait
+            };
+            tokio::time::sleep(wait).await;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match tokio::time::timeout(remaining, self.in_flight.acquire()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(Throttled),
+        }
+    }
+
+    async fn lookup(&self, url: &str) -> eyre::Result<Meta> {
+        if let Some(open_until) = self.breaker.lock().unwrap().open_until {
+            if Instant::now() < open_until {
+                metrics::get()
+                    .fichub_attempts
+                    .with_label_values(&["paused"])
+                    .inc();
+                return Err(eyre!("fichub is failing, not trying to look up {}", url));
+            }
+        }
+        let mut backoff = self.options.backoff;
+        let mut retries = self.options.retries;
+        loop {
+            match self.attempt(url).await {
+                Ok(meta) => {
+                    self.record(true);
+                    return Ok(meta);
+                }
+                Err(Failure::Rejected(e)) => {
+                    // Fichub itself is fine.
+                    self.record(true);
+                    return Err(e);
+                }
+                Err(Failure::Throttled) => {
+                    return Err(eyre::Report::new(Throttled)
+                        .wrap_err(format!("not looking up {} for now", url)));
+                }
+                Err(Failure::Upstream(e)) if retries == 0 => {
+                    self.record(false);
+                    return Err(e);
+                }
+                Err(Failure::Upstream(e)) => {
+                    eprintln!("retrying lookup of {} in {:?}: {:?}", url, backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    retries -= 1;
+                }
+            }
+        }
+    }
+
+    fn record(&self, success: bool) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if success {
+            *breaker = Breaker::default();
+            metrics::get().fichub_paused.set(0);
+            return;
+        }
+        breaker.consecutive_failures += 1;
+        if breaker.consecutive_failures >= self.options.breaker_threshold {
+            if breaker.open_until.is_none() {
+                eprintln!(
+                    "fichub failed {} lookups in a row, pausing lookups for {:?}",
+                    breaker.consecutive_failures, self.options.breaker_cooldown
+                );
+            }
+            breaker.open_until = Some(Instant::now() + self.options.breaker_cooldown);
+            metrics::get().fichub_paused.set(1);
+        }
+    }
+
+    async fn attempt(&self, url: &str) -> Result<Meta, Failure> {
+        let _permit = match self.wait_turn().await {
+            Ok(permit) => permit,
+            Err(Throttled) => {
+                metrics::get()
+                    .fichub_attempts
+                    .with_label_values(&["throttled"])
+                    .inc();
+                return Err(Failure::Throttled);
+            }
+        };
+        let started = Instant::now();
+        let result = self.request(url).await;
+        let outcome = match &result {
+            Ok(_) => "ok",
+            Err(Failure::Rejected(_)) => "rejected",
+            Err(_) => "error",
+        };
+        let metrics = metrics::get();
+        metrics.fichub_attempts.with_label_values(&[outcome]).inc();
+        metrics
+            .fichub_request_duration
+            .with_label_values(&[outcome])
+            .observe(started.elapsed().as_secs_f64());
+        result
+    }
+
+    async fn request(&self, url: &str) -> Result<Meta, Failure> {
+        let response = self
+            .http
+            .get(format!("{}/api/v0/epub", self.base_url))
+            .query(&[("q", url)])
+            .send()
+            .await
+            .wrap_err("failed to reach fichub")
+            .map_err(Failure::Upstream)?
+            .error_for_status()
+            .wrap_err("fichub answered with an error status")
+            .map_err(Failure::Upstream)?
+            .json::<EpubResponse>()
+            .await
+            .wrap_err("failed to parse fichub response")
+            .map_err(Failure::Upstream)?;
+        match response {
+            EpubResponse {
+                err: 0,
+                meta: Some(meta),
+                ..
+            } => Ok(meta.into()),
+            EpubResponse { msg, .. } => Err(Failure::Rejected(
+                eyre::Report::new(Unsupported(
+                    msg.unwrap_or_else(|| "no reason given".to_string()),
+                ))
+                .wrap_err(format!("fichub couldn't look up {}", url)),
+            )),
+        }
+    }
+}
+
+impl MetadataProvider for Client {
+    fn name(&self) -> &'static str {
+        "fichub"
+    }
+
+    fn handles(&self, _url: &str) -> bool {
+        true
+    }
+
+    fn meta<'a>(&'a self, url: &'a str) -> BoxFuture<'a, eyre::Result<Meta>> {
+        self.lookup(url).boxed()
+    }
+}
+
+/// Cached metadata along with when it was fetched.
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = FicMeta)]
+pub struct CachedMeta {
+    #[serde(flatten)]
+    pub meta: Meta,
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl<'r> sqlx::FromRow<'r, PgRow> for CachedMeta {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            meta: Meta::from_row(row)?,
+            fetched_at: row.try_get("fetched_at")?,
+        })
+    }
+}
+
+/// A failed lookup, remembered for a while so that asking again doesn't wait for providers to
+/// fail again.
+#[derive(Serialize, Debug, Clone, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupFailure {
+    /// `unsupported` if no provider has metadata for the URL, `unavailable` if providers failed.
+    #[schema(inline)]
+    pub kind: FailureKind,
+    /// Meant for clients, e.g. fichub's reason for not supporting a URL.
+    pub reason: String,
+    pub failed_at: DateTime<Utc>,
+    /// Until then, the URL isn't looked up again.
+    pub retry_after: DateTime<Utc>,
+}
+
+impl fmt::Display for LookupFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "metadata lookup failed: {}", self.reason)
+    }
+}
+
+impl std::error::Error for LookupFailure {}
+
+/// Whatever metadata of `url` is in the database, however old.
+pub async fn cached(url: &str, pool: &DB) -> eyre::Result<Option<CachedMeta>> {
+    Ok(sqlx::query_as::<_, CachedMeta>(
+        "
+select
+    f.id, f.title, f.source, f.site, f.author, f.author_id, f.series, f.series_id,
+    f.series_position, f.words, f.chapters, f.status, f.fandoms, f.updated, c.fetched_at
+from fic_url_cache c
+join fic f
+    on f.id = c.fic_id
+where c.url = $1
+        ",
+    )
+    .bind(url)
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// Whatever metadata of the fic with `id` is in the database, as fetched last under any URL.
+pub async fn cached_fic(id: &str, pool: &DB) -> eyre::Result<Option<CachedMeta>> {
+    Ok(sqlx::query_as::<_, CachedMeta>(
+        "
+select
+    f.id, f.title, f.source, f.site, f.author, f.author_id, f.series, f.series_id,
+    f.series_position, f.words, f.chapters, f.status, f.fandoms, f.updated, c.fetched_at
+from fic f
+join lateral (
+    select max(fetched_at) as fetched_at
+    from fic_url_cache
+    where fic_id = f.id
+) c
+    on true
+where f.id = $1
+        ",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn fic_exists(id: &str, pool: &DB) -> eyre::Result<bool> {
+    Ok(
+        sqlx::query_scalar::<_, bool>("select exists (select from fic where id = $1)")
+            .bind(id)
+            .fetch_one(pool)
+            .await?,
+    )
+}
+
+/// Looks up fic metadata by URL, going to the providers only when the database has nothing recent.
+///
+/// Entries younger than `ttl` are served as-is. Entries younger than `ttl + stale` are served
+/// as well, but refreshed in the background. Older entries are refreshed before answering, and
+/// only served if the providers fail. Failed lookups aren't repeated for `failure_ttl`.
+pub struct Cache {
+    pool: DB,
+    providers: Providers,
+    ttl: Duration,
+    stale: Duration,
+    failure_ttl: Duration,
+    /// URLs being refreshed in the background, so concurrent lookups don't pile up on providers.
+    refreshing: Mutex<HashSet<String>>,
+}
+
+impl Cache {
+    pub fn new(
+        pool: DB,
+        providers: Providers,
+        ttl: Duration,
+        stale: Duration,
+        failure_ttl: Duration,
+    ) -> Self {
+        Self {
+            pool,
+            providers,
+            ttl,
+            stale,
+            failure_ttl,
+            refreshing: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub async fn get(&'static self, url: &str) -> eyre::Result<CachedMeta> {
+        let cached = cached(url, &self.pool).await?;
+        self.resolve(url, cached).await
+    }
+
+    /// Like [`Cache::get`] for many URLs at once, in the same order. Cached entries are served
+    /// right away, while at most `concurrency` URLs are looked up at a time.
+    pub async fn get_many(
+        &'static self,
+        urls: &[String],
+        concurrency: usize,
+    ) -> eyre::Result<Vec<(String, eyre::Result<CachedMeta>)>> {
+        let mut cached = sqlx::query(
+            "
+select
+    c.url,
+    f.id, f.title, f.source, f.site, f.author, f.author_id, f.series, f.series_id,
+    f.series_position, f.words, f.chapters, f.status, f.fandoms, f.updated, c.fetched_at
+from fic_url_cache c
+join fic f
+    on f.id = c.fic_id
+where c.url = any($1)
+            ",
+        )
+        .bind(urls)
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(|row| Ok((row.try_get::<String, _>("url")?, CachedMeta::from_row(row)?)))
+        .collect::<Result<HashMap<String, CachedMeta>, sqlx::Error>>()?;
+        let entries = urls
+            .iter()
+            .map(|url| (url.clone(), cached.remove(url)))
+            .collect::<Vec<_>>();
+        Ok(stream::iter(entries)
+            .map(|(url, cached)| async move {
+                let meta = self.resolve(&url, cached).await;
+                (url, meta)
+            })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await)
+    }
+
+    async fn resolve(
+        &'static self,
+        url: &str,
+        cached: Option<CachedMeta>,
+    ) -> eyre::Result<CachedMeta> {
+        let age = cached.as_ref().map(|c| Utc::now() - c.fetched_at);
+        let lookup = |result| {
+            metrics::get()
+                .metadata_cache_lookups
+                .with_label_values(&[result])
+                .inc();
+        };
+        match (cached, age) {
+            (Some(cached), Some(age)) if age < self.ttl => {
+                lookup("hit");
+                Ok(cached)
+            }
+            (Some(cached), Some(age)) if age < self.ttl + self.stale => {
+                lookup("stale");
+                self.revalidate(url);
+                Ok(cached)
+            }
+            (cached, _) => {
+                if let Some(failure) = self.recent_failure(url).await? {
+                    lookup("failure");
+                    return cached.ok_or_else(|| failure.into());
+                }
+                lookup("miss");
+                match self.refresh(url).await {
+                    Ok(fresh) => Ok(fresh),
+                    Err(e) => cached.ok_or(e),
+                }
+            }
+        }
+    }
+
+    async fn recent_failure(&self, url: &str) -> eyre::Result<Option<LookupFailure>> {
+        Ok(sqlx::query_as::<_, LookupFailure>(
+            "
+select
+    failure as kind,
+    failure_reason as reason,
+    failed_at,
+    failed_at + $2 * interval '1 second' as retry_after
+from fic_url_cache
+where url = $1 and failed_at > now() - $2 * interval '1 second'
+            ",
+        )
+        .bind(url)
+        .bind(self.failure_ttl.num_seconds() as f64)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Remembers that looking up `url` failed with `e`, keeping metadata fetched earlier. The
+    /// returned error carries a [`LookupFailure`].
+    async fn record_failure(&self, url: &str, e: eyre::Report) -> eyre::Report {
+        let kind = FailureKind::of(&e);
+        let reason = match kind {
+            FailureKind::Unsupported => e
+                .chain()
+                .find_map(|e| e.downcast_ref::<Unsupported>())
+                .map(|u| u.0.clone())
+                .unwrap_or_default(),
+            FailureKind::Unavailable => "metadata providers are unavailable".to_string(),
+        };
+        let failure = sqlx::query_as::<_, LookupFailure>(
+            "
+insert into fic_url_cache (url, failure, failure_reason, failed_at)
+values ($1, $2, $3, now())
+on conflict (url) do update set
+    failure = excluded.failure,
+    failure_reason = excluded.failure_reason,
+    failed_at = excluded.failed_at
+returning
+    failure as kind,
+    failure_reason as reason,
+    failed_at,
+    failed_at + $4 * interval '1 second' as retry_after
+            ",
+        )
+        .bind(url)
+        .bind(kind)
+        .bind(&reason)
+        .bind(self.failure_ttl.num_seconds() as f64)
+        .fetch_one(&self.pool)
+        .await;
+        match failure {
+            Ok(failure) => e.wrap_err(failure),
+            Err(db) => {
+                eprintln!("failed to record failed lookup of {}: {:?}", url, db);
+                e
+            }
+        }
+    }
+
+    /// Fetches `url` from the providers and stores the result.
+    pub async fn refresh(&self, url: &str) -> eyre::Result<CachedMeta> {
+        let meta = match self.providers.meta(url).await {
+            Ok(meta) => meta,
+            Err(e) if e.chain().any(|e| e.is::<Throttled>()) => return Err(e),
+            Err(e) => return Err(self.record_failure(url, e).await),
+        };
+        let mut tx = self.pool.begin().await?;
+        let before = Progress::get(&mut tx, &meta.id).await?;
+        crate::author::upsert(&mut tx, &meta).await?;
+        crate::series::upsert(&mut tx, &meta).await?;
+        sqlx::query(
+            "
+insert into fic (
+    id, title, source, site, author, author_id, series, series_id, series_position, words,
+    chapters, status, fandoms, updated
+)
+values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+on conflict (id) do update set
+    title = excluded.title,
+    source = excluded.source,
+    site = excluded.site,
+    author = excluded.author,
+    author_id = excluded.author_id,
+    series = excluded.series,
+    series_id = excluded.series_id,
+    series_position = excluded.series_position,
+    words = excluded.words,
+    chapters = excluded.chapters,
+    status = excluded.status,
+    fandoms = excluded.fandoms,
+    updated = excluded.updated,
+    fetched_at = now()
+            ",
+        )
+        .bind(&meta.id)
+        .bind(&meta.title)
+        .bind(&meta.source)
+        .bind(&meta.site)
+        .bind(&meta.author)
+        .bind(&meta.author_id)
+        .bind(&meta.series)
+        .bind(&meta.series_id)
+        .bind(meta.series_position)
+        .bind(meta.words)
+        .bind(meta.chapters)
+        .bind(meta.status)
+        .bind(&meta.fandoms)
+        .bind(meta.updated)
+        .execute(&mut tx)
+        .await?;
+        if let Some(before) = before {
+            crate::fic_update::detect(&mut tx, &before, &meta).await?;
+        }
+        // The same fic may be known to providers under several ids, e.g. when it's cross-posted
+        // to several sites. The first one seen stands for all of them.
+        let fic_id = sqlx::query_scalar::<_, String>(
+            "
+update fic set canonical_id = coalesce(
+    canonical_id,
+    (
+        select coalesce(other.canonical_id, other.id)
+        from fic other
+        where other.id <> fic.id
+            and lower(other.title) = lower(fic.title)
+            and lower(other.author) = lower(fic.author)
+        order by other.fetched_at
+        limit 1
+    )
+)
+where id = $1
+returning coalesce(canonical_id, id)
+            ",
+        )
+        .bind(&meta.id)
+        .fetch_one(&mut tx)
+        .await?;
+        // Signals are stored under folded URLs, which must lead to the fic as well.
+        sqlx::query(
+            "
+insert into fic_url (url, fic_id)
+select distinct unnest($1::text[]), $2
+on conflict (url) do update set fic_id = excluded.fic_id
+            ",
+        )
+        .bind(&[url.to_string(), crate::canonical_url::fold(url)][..])
+        .bind(&fic_id)
+        .execute(&mut tx)
+        .await?;
+        crate::signal::Signal::set_fandoms(
+            &mut tx,
+            &crate::canonical_url::fold(url),
+            &meta.fandoms,
+        )
+        .await?;
+        let fetched_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+            "
+insert into fic_url_cache (url, fic_id)
+values ($1, $2)
+on conflict (url) do update set
+    fic_id = excluded.fic_id,
+    fetched_at = now(),
+    failure = null,
+    failure_reason = null,
+    failed_at = null
+returning fetched_at
+            ",
+        )
+        .bind(url)
+        .bind(&meta.id)
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(CachedMeta { meta, fetched_at })
+    }
+
+    fn revalidate(&'static self, url: &str) {
+        if !self.refreshing.lock().unwrap().insert(url.to_string()) {
+            return;
+        }
+        let url = url.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = self.refresh(&url).await {
+                eprintln!("failed to refresh metadata of {}: {:?}", url, e);
+            }
+            self.refreshing.lock().unwrap().remove(&url);
+        });
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct FicMetaQ {
+    url: String,
+}
+
+/// Look up metadata of the fic at a URL.
+///
+/// Metadata comes from the providers in `FICAI_METADATA_PROVIDERS`, e.g. fichub, falling back to
+/// the next one when a provider fails, and is cached. Cached entries older than
+/// `FICAI_FIC_CACHE_TTL_SECS` are still served for another `FICAI_FIC_CACHE_STALE_SECS` while they
+/// are refreshed in the background; after that they are only served if no provider can be reached.
+#[utoipa::path(
+    get,
+    path = "/fics/meta",
+    tag = "fics",
+    params(FicMetaQ),
+    responses(
+        (status = 200, description = "Success.", body = CachedMeta),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (
+            status = 502,
+            description = "No provider could be reached or knows the URL, and nothing is cached. \
+                `failure` tells which, and until when the URL won't be looked up again.",
+            body = ErrorWrap,
+        ),
+    ),
+    security(("cookieAuth" = []), ("bearerAuth" = [])),
+)]
+pub async fn get_fic_meta(
+    q: FicMetaQ,
+    _account: AccountSession,
+    cache: &'static Cache,
+) -> Result<Response<Body>, ApiError> {
+    let meta = cache.get(&q.url).await.map_err(|e| {
+        eprintln!("failed to get fic metadata: {:?}", e);
+        unavailable(&e)
+    })?;
+    let mut response = json(&meta).into_response();
+    last_modified(&mut response, meta.fetched_at);
+    Ok(response)
+}
+
+fn unavailable(e: &eyre::Report) -> ApiError {
+    match e.downcast_ref::<LookupFailure>() {
+        Some(failure) => ApiError::MetadataUnavailable(failure.clone()),
+        None => ApiError::BadGateway,
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldChange {
+    field: String,
+    before: serde_json::Value,
+    after: serde_json::Value,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ForcedRefresh {
+    before: CachedMeta,
+    after: CachedMeta,
+    /// Metadata fields that differ between `before` and `after`, by name.
+    changes: Vec<FieldChange>,
+}
+
+/// Look up a fic's metadata again right away. Requires the admin role.
+///
+/// Bypasses the cache, e.g. after a fic changed its title or the metadata was wrong, and looks the
+/// fic up under the URL it was most recently looked up with. Answers with the metadata before and
+/// after, and which fields changed.
+#[utoipa::path(
+    post,
+    path = "/admin/fics/{id}/refresh",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "As in `id` of fic metadata."),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = ForcedRefresh),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 404, description = "No fic with this id is known.", body = ErrorWrap),
+        (
+            status = 502,
+            description = "The fic couldn't be looked up again; its metadata is left as it was.",
+            body = ErrorWrap,
+        ),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn refresh_fic(
+    _account: AccountSession,
+    id: String,
+    cache: &'static Cache,
+) -> Result<Response<Body>, ApiError> {
+    let before = async {
+        let before = match cached_fic(&id, &cache.pool).await? {
+            Some(before) => before,
+            None => return Ok(None),
+        };
+        let url = sqlx::query_scalar::<_, String>(
+            "
+select url
+from fic_url_cache
+where fic_id = $1
+order by fetched_at desc
+limit 1
+            ",
+        )
+        .bind(&id)
+        .fetch_optional(&cache.pool)
+        .await?
+        .unwrap_or_else(|| before.meta.source.clone());
+        eyre::Result::<_>::Ok(Some((before, url)))
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to get fic to refresh: {:?}", e);
+        ApiError::Internal
+    })?;
+    let (before, url) = before.ok_or(ApiError::NotFound)?;
+    let after = cache.refresh(&url).await.map_err(|e| {
+        eprintln!("failed to refresh fic metadata of {}: {:?}", url, e);
+        unavailable(&e)
+    })?;
+    let changes = match (
+        serde_json::to_value(&before.meta),
+        serde_json::to_value(&after.meta),
+    ) {
+        (Ok(serde_json::Value::Object(b)), Ok(serde_json::Value::Object(mut a))) => b
+            .into_iter()
+            .filter_map(|(field, before)| {
+                let after = a.remove(&field)?;
+                (before != after).then_some(FieldChange {
+                    field,
+                    before,
+                    after,
+                })
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    Ok(json(&ForcedRefresh {
+        before,
+        after,
+        changes,
+    })
+    .into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FicMetaBatchQ {
+    urls: Vec<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FicMetaBatchEntry {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<CachedMeta>,
+    /// Set instead of `meta` when no metadata could be found.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Why, if it's known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure: Option<LookupFailure>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FicMetaBatch {
+    fics: Vec<FicMetaBatchEntry>,
+}
+
+/// Look up metadata of many fics at once.
+///
+/// Like `GET /fics/meta` for each URL, answered in the same order. Repeated URLs are answered once.
+/// At most `FICAI_FIC_BATCH_MAX_URLS` URLs are accepted.
+#[utoipa::path(
+    post,
+    path = "/fics/batch",
+    tag = "fics",
+    request_body = FicMetaBatchQ,
+    responses(
+        (
+            status = 200,
+            description = "Success, even if some URLs couldn't be looked up.",
+            body = FicMetaBatch,
+        ),
+        (status = 400, description = "Too many URLs.", body = ErrorWrap),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = []), ("bearerAuth" = [])),
+)]
+pub async fn get_fic_meta_batch(
+    _account: AccountSession,
+    mut q: FicMetaBatchQ,
+    cache: &'static Cache,
+    max_urls: usize,
+    concurrency: usize,
+) -> Result<Response<Body>, ApiError> {
+    let mut seen = HashSet::new();
+    q.urls.retain(|url| seen.insert(url.clone()));
+    if q.urls.len() > max_urls {
+        return Err(ApiError::BadRequest(
+            format!("at most {} urls can be looked up at once", max_urls).into(),
+        ));
+    }
+    let results = cache.get_many(&q.urls, concurrency).await.map_err(|e| {
+        eprintln!("failed to get fic metadata: {:?}", e);
+        ApiError::Internal
+    })?;
+    let fics = results
+        .into_iter()
+        .map(|(url, meta)| match meta {
+            Ok(meta) => FicMetaBatchEntry {
+                url,
+                meta: Some(meta),
+                error: None,
+                failure: None,
+            },
+            Err(e) => {
+                eprintln!("failed to get fic metadata: {:?}", e);
+                FicMetaBatchEntry {
+                    url,
+                    meta: None,
+                    error: Some("metadata unavailable".to_string()),
+                    failure: e.downcast_ref::<LookupFailure>().cloned(),
+                }
+            }
+        })
+        .collect();
+    Ok(json(&FicMetaBatch { fics }).into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_fic_meta, get_fic_meta_batch, refresh_fic))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_fic_meta = crate::httputil::cached(
+        CachePolicy::Private,
+        &[],
+        warp::path!("v1" / "fics" / "meta")
+            .and(warp::get())
+            .and(warp::query::<FicMetaQ>())
+            .and(ctx.authenticate_scoped(Scope::Read))
+            .then(move |q, account| get_fic_meta(q, account, ctx.fic_cache))
+            .and_then(reject),
+    );
+    let get_fic_meta_batch = warp::path!("v1" / "fics" / "batch")
+        .and(warp::post())
+        .and(ctx.authenticate_scoped(Scope::Read))
+        .and(json_body::<FicMetaBatchQ>(ctx.max_body_bytes))
+        .then(move |account, q| {
+            get_fic_meta_batch(
+                account,
+                q,
+                ctx.fic_cache,
+                ctx.fic_batch_max_urls,
+                ctx.fic_batch_concurrency,
+            )
+        })
+        .and_then(reject);
+    let refresh_fic = warp::path!("v1" / "admin" / "fics" / PercentDecoded / "refresh")
+        .and(warp::post())
+        .and(ctx.require_role(Role::Admin))
+        .then(move |id: PercentDecoded, account| refresh_fic(account, id.0, ctx.fic_cache))
+        .and_then(reject);
+    get_fic_meta
+        .or(get_fic_meta_batch)
+        .or(refresh_fic)
+        .map(Reply::into_response)
+        .boxed()
+}
@@ -1,81 +1,622 @@
 use std::borrow::Cow;
 use std::convert::Infallible;
+use std::str::FromStr;
 
-use http::StatusCode;
-use serde::Serialize;
+use base64ct::Encoding as _;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use http::header::{
+    HeaderValue, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED, RETRY_AFTER,
+    VARY,
+};
+use http::{Response, StatusCode};
+use hyper::body::HttpBody;
+use hyper::Body;
+use serde::{Deserialize as _, Serialize};
+use sha2::{Digest as _, Sha256};
+use utoipa::openapi::path::{Parameter, ParameterBuilder, ParameterIn};
+use utoipa::openapi::schema::{ObjectBuilder, Schema, Type};
+use utoipa::openapi::RefOr;
+use utoipa::{IntoParams, PartialSchema, ToSchema};
 use warp::reject::Reject;
-use warp::{Rejection, Reply};
+use warp::{Filter, Rejection, Reply};
 
-#[derive(Serialize, Debug)]
+use crate::account_status::Restriction;
+use crate::fichub::LookupFailure;
+use crate::password_policy::Problem;
+use crate::tag_policy::Violation;
+
+/// Empty object response.
+#[derive(Serialize, Debug, ToSchema)]
+#[schema(as = EmptyObject)]
 pub struct Empty {}
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
+#[schema(as = ErrorDetails)]
 pub struct Error {
+    /// Human readable.
+    #[schema(example = "bad request")]
     pub message: String,
+    /// Only for requests rejected by the tag policy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub violations: Option<Vec<Violation>>,
+    /// Only for fic metadata that couldn't be looked up.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<LookupFailure>,
+    /// Only for new passwords rejected by the password policy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problems: Option<Vec<Problem>>,
+    /// Only for suspended or banned accounts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restriction: Option<Restriction>,
+    /// Only for requests that took too long: how long they were given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
 }
 
-#[derive(Serialize, Debug)]
+/// Details about the unsuccessful fulfillment of a request; the status tells what went wrong.
+#[derive(Serialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
+#[schema(as = Error)]
 pub struct ErrorWrap {
     pub error: Error,
 }
 
-#[derive(Debug)]
-pub struct BadRequest(pub Cow<'static, str>);
-impl Reject for BadRequest {}
+/// Every way a request can fail. Handlers return it and filters reject with it, and either way
+/// it's answered with its status and an [`ErrorWrap`] body, see [`recover`].
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    BadRequest(Cow<'static, str>),
+    Forbidden,
+    /// The password was right, but the account also needs a two-factor code.
+    TwoFactorRequired,
+    /// The session cookie isn't validly signed, on a route that needs a session.
+    SessionInvalid,
+    NotFound,
+    /// Logged where it happened; clients only learn that something went wrong.
+    Internal,
+    /// An upstream service, e.g. fichub, failed and there was nothing to fall back to.
+    BadGateway,
+    /// Like `BadGateway`, for a metadata lookup that failed for a known reason.
+    MetadataUnavailable(LookupFailure),
+    /// The request took longer than the server allows, see [`crate::timeout::timeout`].
+    GatewayTimeout {
+        timeout_secs: u64,
+    },
+    AccountAlreadyExists,
+    DisplayNameTaken,
+    /// Tells clients when to try again in `Retry-After`.
+    TooManyRequests {
+        retry_after_secs: u64,
+    },
+    /// Like `TooManyRequests`, for an address over its limit, see `ip_limit`. Also tells clients
+    /// the limit in the `RateLimit-*` headers; `reset_secs` is until the current window ends.
+    RateLimited {
+        limit: u64,
+        reset_secs: u64,
+    },
+    /// Lists every violation of the tag policy.
+    TagPolicyViolation(Vec<Violation>),
+    /// Lists every problem the password policy found with a new password.
+    WeakPassword(Vec<Problem>),
+    /// Tells the reason and, for suspensions, when they end.
+    AccountRestricted(Restriction),
+    /// Tells why the URL doesn't look like a fic.
+    UrlPolicyViolation(&'static str),
+    PayloadTooLarge,
+    LengthRequired,
+    MethodNotAllowed,
+    /// A CORS request from an origin that isn't allowed.
+    OriginNotAllowed,
+}
 
-#[derive(Debug)]
-pub struct Forbidden;
-impl Reject for Forbidden {}
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_)
+            | ApiError::TagPolicyViolation(_)
+            | ApiError::WeakPassword(_) => StatusCode::BAD_REQUEST,
+            ApiError::TwoFactorRequired | ApiError::SessionInvalid => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden | ApiError::AccountRestricted(_) | ApiError::OriginNotAllowed => {
+                StatusCode::FORBIDDEN
+            }
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::AccountAlreadyExists | ApiError::DisplayNameTaken => StatusCode::CONFLICT,
+            ApiError::LengthRequired => StatusCode::LENGTH_REQUIRED,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UrlPolicyViolation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TooManyRequests { .. } | ApiError::RateLimited { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::BadGateway | ApiError::MetadataUnavailable(_) => StatusCode::BAD_GATEWAY,
+            ApiError::GatewayTimeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
 
-#[derive(Debug)]
-pub struct InternalError;
-impl Reject for InternalError {}
+    fn body(&self) -> Error {
+        let message = match self {
+            ApiError::BadRequest(message) => message.to_string(),
+            ApiError::Forbidden => "forbidden".to_string(),
+            ApiError::TwoFactorRequired => "two-factor code required".to_string(),
+            ApiError::SessionInvalid => "invalid auth cookie".to_string(),
+            ApiError::NotFound => "not found".to_string(),
+            ApiError::Internal => "internal server error".to_string(),
+            ApiError::BadGateway => "upstream unavailable".to_string(),
+            ApiError::MetadataUnavailable(_) => "metadata unavailable".to_string(),
+            ApiError::GatewayTimeout { .. } => "request timed out".to_string(),
+            ApiError::AccountAlreadyExists => "account already exists".to_string(),
+            ApiError::DisplayNameTaken => "display name taken".to_string(),
+            ApiError::TooManyRequests { .. } | ApiError::RateLimited { .. } => {
+                "too many requests".to_string()
+            }
+            ApiError::TagPolicyViolation(_) => "tag policy violated".to_string(),
+            ApiError::WeakPassword(_) => "password rejected".to_string(),
+            ApiError::AccountRestricted(restriction) => restriction.message().to_string(),
+            ApiError::UrlPolicyViolation(message) => message.to_string(),
+            ApiError::PayloadTooLarge => "payload too large".to_string(),
+            ApiError::LengthRequired => "content-length required".to_string(),
+            ApiError::MethodNotAllowed => "method not allowed".to_string(),
+            ApiError::OriginNotAllowed => "origin not allowed".to_string(),
+        };
+        Error {
+            message,
+            violations: match self {
+                ApiError::TagPolicyViolation(violations) => Some(violations.clone()),
+                _ => None,
+            },
+            failure: match self {
+                ApiError::MetadataUnavailable(failure) => Some(failure.clone()),
+                _ => None,
+            },
+            problems: match self {
+                ApiError::WeakPassword(problems) => Some(problems.clone()),
+                _ => None,
+            },
+            restriction: match self {
+                ApiError::AccountRestricted(restriction) => Some(restriction.clone()),
+                _ => None,
+            },
+            timeout_secs: match self {
+                ApiError::GatewayTimeout { timeout_secs } => Some(*timeout_secs),
+                _ => None,
+            },
+        }
+    }
+
+    /// The error a rejection stands for. Warp's own rejections are mapped to the closest
+    /// `ApiError`. Of several, a bad query wins over `Forbidden`, which wins over `NotFound`: a
+    /// path like `/v1/tags/trending` with a bad query also falls through to `/v1/tags/{tag}`, which
+    /// doesn't find a tag with that name.
+    fn of(r: &Rejection) -> Self {
+        if r.is_not_found() {
+            return ApiError::NotFound;
+        }
+        let custom = r.find::<ApiError>();
+        if let Some(e) = custom {
+            if !matches!(e, ApiError::NotFound | ApiError::Forbidden) {
+                return e.clone();
+            }
+        }
+        if r.find::<warp::reject::InvalidQuery>().is_some() {
+            eprintln!("invalid query error: {:#?}", r);
+            return ApiError::BadRequest("bad request query".into());
+        }
+        if r.find::<Refused>().is_some() {
+            return ApiError::Forbidden;
+        }
+        if let Some(e) = custom {
+            return e.clone();
+        }
+        if r.find::<warp::filters::body::BodyDeserializeError>()
+            .is_some()
+        {
+            eprintln!("body deserialization error: {:#?}", r);
+            ApiError::BadRequest("bad request body".into())
+        } else if let Some(e) = r.find::<warp::reject::MissingHeader>() {
+            ApiError::BadRequest(format!("missing header {}", e.name()).into())
+        } else if r.find::<warp::reject::PayloadTooLarge>().is_some() {
+            ApiError::PayloadTooLarge
+        } else if r.find::<warp::reject::LengthRequired>().is_some() {
+            ApiError::LengthRequired
+        } else if r.find::<warp::cors::CorsForbidden>().is_some() {
+            ApiError::OriginNotAllowed
+        } else if r.find::<warp::reject::MethodNotAllowed>().is_some() {
+            eprintln!("method not allowed rejection: {:#?}", r);
+            ApiError::MethodNotAllowed
+        } else {
+            eprintln!("uhandled rejection: {:#?}", r);
+            ApiError::Internal
+        }
+    }
+}
+
+impl Reject for ApiError {}
 
+/// Logs the error, as clients only learn that something went wrong.
+impl From<eyre::Report> for ApiError {
+    fn from(e: eyre::Report) -> Self {
+        eprintln!("error: {:?}", e);
+        ApiError::Internal
+    }
+}
+
+impl Reply for ApiError {
+    fn into_response(self) -> warp::reply::Response {
+        let status = self.status();
+        let mut response =
+            warp::reply::with_status(warp::reply::json(&ErrorWrap { error: self.body() }), status)
+                .into_response();
+        let headers = response.headers_mut();
+        match self {
+            ApiError::TooManyRequests { retry_after_secs } => {
+                headers.insert(RETRY_AFTER, retry_after_secs.into());
+            }
+            ApiError::RateLimited { limit, reset_secs } => {
+                headers.insert(RETRY_AFTER, reset_secs.into());
+                headers.insert("ratelimit-limit", limit.into());
+                headers.insert("ratelimit-remaining", 0.into());
+                headers.insert("ratelimit-reset", reset_secs.into());
+            }
+            _ => {}
+        }
+        response
+    }
+}
+
+/// Passes a handler's error on as a rejection, for `.then(handler).and_then(reject)`. Routes
+/// after the handler's still get to match, as with `Filter::and_then`, and [`recover`] answers
+/// with the error in the end.
+pub async fn reject<T>(result: Result<T, ApiError>) -> Result<T, Rejection> {
+    result.map_err(rejection)
+}
+
+/// Stands in for [`ApiError::Forbidden`] in rejections. Of several `ApiError`s, warp only finds
+/// the one of the route tried last, e.g. the `NotFound` of `/v1/tags/{tag}` for a refused
+/// `/v1/tags/export`, and the route that refused the request knows better.
 #[derive(Debug)]
-pub struct AccountAlreadyExists;
-impl Reject for AccountAlreadyExists {}
-
-pub async fn recover_custom(r: Rejection) -> Result<impl Reply, Infallible> {
-    let (status, message) = if r.is_not_found() {
-        (StatusCode::NOT_FOUND, "not found".to_string())
-    } else if let Some(BadRequest(message)) = r.find() {
-        (StatusCode::BAD_REQUEST, message.to_string())
-    } else if let Some(Forbidden {}) = r.find() {
-        (StatusCode::FORBIDDEN, "forbidden".to_string())
-    } else if let Some(InternalError {}) = r.find() {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "internal server error".to_string(),
-        )
-    } else if let Some(AccountAlreadyExists {}) = r.find() {
-        (StatusCode::CONFLICT, "account already exists".to_string())
-    } else if r
-        .find::<warp::filters::body::BodyDeserializeError>()
-        .is_some()
-    {
-        eprintln!("body deserialization error: {:#?}", r);
-        (StatusCode::BAD_REQUEST, "bad request body".to_string())
-    } else if r.find::<warp::reject::InvalidQuery>().is_some() {
-        eprintln!("invalid query error: {:#?}", r);
-        (StatusCode::BAD_REQUEST, "bad request query".to_string())
-    } else if r.find::<warp::reject::MethodNotAllowed>().is_some() {
-        eprintln!("method not allowed rejection: {:#?}", r);
-        (
-            StatusCode::METHOD_NOT_ALLOWED,
-            "method not allowed".to_string(),
-        )
-    } else {
-        eprintln!("uhandled rejection: {:#?}", r);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "internal server error".to_string(),
-        )
+struct Refused;
+
+impl Reject for Refused {}
+
+fn rejection(e: ApiError) -> Rejection {
+    match e {
+        ApiError::Forbidden => Refused.into(),
+        e => e.into(),
+    }
+}
+
+/// How clients and caches in between, e.g. a CDN, may reuse a route's responses, see [`cached`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Never stored, e.g. for anything to do with sessions.
+    Never,
+    /// Only stored by the client, which checks with the server before each reuse; for responses
+    /// that depend on who's asking.
+    Private,
+    /// Reused by anyone for up to this many seconds, then checked with the server.
+    Public(u32),
+}
+
+impl CachePolicy {
+    fn cache_control(self) -> HeaderValue {
+        match self {
+            CachePolicy::Never => HeaderValue::from_static("no-store"),
+            CachePolicy::Private => HeaderValue::from_static("private, no-cache"),
+            CachePolicy::Public(max_age) => format!("public, max-age={}", max_age)
+                .parse()
+                .expect("valid header value"),
+        }
+    }
+}
+
+/// Applies `policy` to the successful responses of `route`, with `vary` naming the request
+/// headers they depend on, e.g. `accept-language`. Those that may be stored get an `ETag` made
+/// from their body, and are answered with `304 Not Modified` if the client already has them,
+/// judging by that or by a `Last-Modified` the handler set, see [`last_modified`].
+pub fn cached<F, R>(
+    policy: CachePolicy,
+    vary: &'static [&'static str],
+    route: F,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::header::optional::<String>("if-none-match")
+        .and(warp::header::optional::<String>("if-modified-since"))
+        .and(route)
+        .then(move |if_none_match, if_modified_since, reply: R| {
+            let response = reply.into_response();
+            revalidate(policy, vary, if_none_match, if_modified_since, response)
+        })
+}
+
+async fn revalidate(
+    policy: CachePolicy,
+    vary: &[&'static str],
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>,
+    response: Response<Body>,
+) -> Response<Body> {
+    let (mut parts, body) = response.into_parts();
+    // Redirects of routes that are never cached, e.g. logging in with OAuth, aren't either.
+    let never_redirect = policy == CachePolicy::Never && parts.status.is_redirection();
+    if !parts.status.is_success() && !never_redirect {
+        return Response::from_parts(parts, body);
+    }
+    parts.headers.insert(CACHE_CONTROL, policy.cache_control());
+    for name in vary {
+        parts.headers.append(VARY, HeaderValue::from_static(name));
+    }
+    if policy == CachePolicy::Never || parts.status != StatusCode::OK {
+        return Response::from_parts(parts, body);
+    }
+    // Streamed bodies, like exports, are left alone rather than buffered to hash them.
+    if body.size_hint().exact().is_none() {
+        return Response::from_parts(parts, body);
+    }
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("failed to read response to cache: {:?}", e);
+            parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+            return Response::from_parts(parts, Body::empty());
+        }
     };
+    // Weak, as compression changes the bytes sent but not what they mean.
+    let etag = format!(
+        "W/\"{}\"",
+        base64ct::Base64UrlUnpadded::encode_string(&Sha256::digest(&bytes)[..16])
+    );
+    let not_modified = match (if_none_match, if_modified_since) {
+        (Some(if_none_match), _) => if_none_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag.trim_start_matches("W/")
+        }),
+        (None, Some(since)) => parts
+            .headers
+            .get(LAST_MODIFIED)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+            .zip(DateTime::parse_from_rfc2822(&since).ok())
+            .is_some_and(|(modified, since)| modified <= since),
+        (None, None) => false,
+    };
+    if let Ok(etag) = etag.parse() {
+        parts.headers.insert(ETAG, etag);
+    }
+    if not_modified {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(CONTENT_LENGTH);
+        parts.headers.remove(CONTENT_TYPE);
+        return Response::from_parts(parts, Body::empty());
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// Tells clients when what `response` describes last changed, for routes that are [`cached`].
+pub fn last_modified(response: &mut Response<Body>, at: DateTime<Utc>) {
+    let value = at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    if let Ok(value) = value.parse() {
+        response.headers_mut().insert(LAST_MODIFIED, value);
+    }
+}
+
+/// A point in time as both a UTC instant and a string formatted for display in the viewer's time
+/// zone, so that clients don't each need their own formatting logic.
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Timestamp {
+    utc: DateTime<Utc>,
+    /// As `YYYY-MM-DD HH:MM TZ`.
+    #[schema(example = "2022-09-05 14:54 CEST")]
+    display: String,
+}
+
+impl Timestamp {
+    pub fn new(at: DateTime<Utc>, tz: Tz) -> Self {
+        Self {
+            utc: at,
+            display: at
+                .with_timezone(&tz)
+                .format("%Y-%m-%d %H:%M %Z")
+                .to_string(),
+        }
+    }
+}
+
+/// A path segment with percent-encoding removed, for path parameters that may contain spaces or
+/// other characters that clients have to escape (e.g. tag names).
+#[derive(Debug)]
+pub struct PercentDecoded(pub String);
+
+impl FromStr for PercentDecoded {
+    type Err = std::str::Utf8Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        percent_encoding::percent_decode_str(s)
+            .decode_utf8()
+            .map(|s| Self(s.into_owned()))
+    }
+}
+
+/// A length of time given as a number followed by a unit, e.g. `24h`, `7d` or `2w`.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeWindow(pub chrono::Duration);
+
+impl FromStr for TimeWindow {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const MAX_DAYS: i64 = 366;
+        const FORMAT: &str = "time window must be a number followed by a unit";
+        let unit = s.chars().last().ok_or(FORMAT)?;
+        let n = s[..s.len() - unit.len_utf8()]
+            .parse::<i64>()
+            .map_err(|_| FORMAT)?;
+        if n <= 0 || n > MAX_DAYS * 24 {
+            return Err("time window out of range");
+        }
+        let d = match unit {
+            'h' => chrono::Duration::hours(n),
+            'd' => chrono::Duration::days(n),
+            'w' => chrono::Duration::weeks(n),
+            _ => return Err("time window unit must be one of h, d, w"),
+        };
+        if d > chrono::Duration::days(MAX_DAYS) {
+            return Err("time window out of range");
+        }
+        Ok(Self(d))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TimeWindow {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl PartialSchema for TimeWindow {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .description(Some(
+                "A number followed by `h` (hours), `d` (days) or `w` (weeks). At most a year.",
+            ))
+            .pattern(Some("^[0-9]+[hdw]$"))
+            .examples(["7d"])
+            .into()
+    }
+}
+
+impl ToSchema for TimeWindow {}
+
+/// Language ranges from an `Accept-Language` header, most preferred first and lowercased. Each
+/// range is followed by its primary language, so that `de-CH` falls back to `de`. Malformed
+/// entries are skipped rather than rejected.
+#[derive(Debug, Default, Clone)]
+pub struct AcceptLanguage(pub Vec<String>);
+
+impl FromStr for AcceptLanguage {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ranges: Vec<(String, f32)> = s
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let lang = parts.next()?.to_lowercase();
+                if lang.is_empty()
+                    || lang.len() > 35
+                    || !lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                {
+                    return None;
+                }
+                let q = parts
+                    .find_map(|p| p.strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+                (q > 0.0).then_some((lang, q))
+            })
+            .collect();
+        // Stable, so ranges with equal weight keep their order.
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let mut langs = Vec::new();
+        for (lang, _) in ranges {
+            let primary = lang.split('-').next().unwrap_or_default().to_string();
+            for l in [lang, primary] {
+                if !langs.contains(&l) {
+                    langs.push(l);
+                }
+            }
+        }
+        Ok(Self(langs))
+    }
+}
+
+impl IntoParams for AcceptLanguage {
+    fn into_params(_: impl Fn() -> Option<ParameterIn>) -> Vec<Parameter> {
+        vec![ParameterBuilder::new()
+            .name("Accept-Language")
+            .parameter_in(ParameterIn::Header)
+            .description(Some(
+                "Preferred languages for tag labels. A regional variant falls back to its \
+                language.",
+            ))
+            .schema(Some(String::schema()))
+            .example(Some("de-CH, en;q=0.5".into()))
+            .build()]
+    }
+}
+
+/// Deserializes a comma-separated list, e.g. `?include=a,b`. A missing parameter needs
+/// `#[serde(default)]`.
+pub fn comma_separated<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    let s = String::deserialize(d)?;
+    s.split(',')
+        .filter(|part| !part.is_empty())
+        .map(|part| T::deserialize(serde::de::value::StrDeserializer::<D::Error>::new(part)))
+        .collect()
+}
+
+/// All values of a query parameter that may be repeated, e.g. `?tag=a&tag=b`, which
+/// `warp::query` can't deserialize into a `Vec`.
+pub fn query_list(
+    name: &'static str,
+) -> impl Filter<Extract = (Vec<String>,), Error = Infallible> + Clone {
+    fn decode(s: &str) -> String {
+        percent_encoding::percent_decode_str(&s.replace('+', " "))
+            .decode_utf8_lossy()
+            .into_owned()
+    }
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .map(move |raw: String| {
+            raw.split('&')
+                .filter_map(|pair| {
+                    let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+                    (decode(k) == name).then(|| decode(v))
+                })
+                .collect()
+        })
+}
+
+/// A JSON request body of at most `max_bytes`, rejecting larger ones with a 413 before reading
+/// them. Needs a `Content-Length`, like `warp::body::content_length_limit`.
+pub fn json_body<T>(max_bytes: u64) -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: serde::de::DeserializeOwned + Send,
+{
+    warp::body::content_length_limit(max_bytes).and(warp::body::json::<T>())
+}
+
+/// Answers with the error a rejection stands for, see [`ApiError`].
+pub async fn recover(r: Rejection) -> Result<warp::reply::Response, Infallible> {
+    Ok(ApiError::of(&r).into_response())
+}
+
+/// Answers with `val` as JSON, or rejects with its error.
+pub async fn reply_json<T: Serialize, E: Into<ApiError>>(
+    val: Result<T, E>,
+) -> Result<Response<Body>, Rejection> {
+    match val {
+        Ok(val) => Ok(warp::reply::json(&val).into_response()),
+        Err(e) => Err(rejection(e.into())),
+    }
+}
 
-    let json = warp::reply::json(&ErrorWrap {
-        error: Error { message },
-    });
-    Ok(warp::reply::with_status(json, status))
+/// The client's preferred languages; none if it didn't send an `Accept-Language` header.
+pub fn accept_language() -> impl Filter<Extract = (AcceptLanguage,), Error = Infallible> + Clone {
+    warp::header::optional::<AcceptLanguage>("accept-language")
+        .map(Option::unwrap_or_default)
+        .recover(|_| async { Ok::<_, Infallible>(AcceptLanguage::default()) })
+        .unify()
 }
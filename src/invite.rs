@@ -0,0 +1,304 @@
+use chrono::{DateTime, Utc};
+use http::{Response, StatusCode};
+use hyper::Body;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+use tap::prelude::*;
+use utoipa::{OpenApi, ToSchema};
+use warp::{
+    reply::{json, with_status},
+    Filter, Reply,
+};
+
+use crate::context::Context;
+use crate::httputil::{cached, json_body, reject, ApiError, CachePolicy, Empty, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+const CODE_BYTES: usize = 10;
+
+/// Who may invite whom. Accounts with the user moderation permission may mint invites of any size
+/// and validity; everyone else gets single-use ones that expire after `ttl`, a few at a time.
+pub struct InvitePolicy {
+    /// How many unused, unexpired invites an account without the user moderation permission may
+    /// have at once. 0 keeps them from inviting anyone.
+    pub per_account: i64,
+    pub ttl: chrono::Duration,
+}
+
+/// Uses up one use of the invite with `code`, returning its id to attribute the new account to,
+/// or `None` if there's no such invite or it's used up or expired. Done in the transaction that
+/// creates the account, so the use is only counted if that succeeds.
+pub(crate) async fn redeem(
+    tx: &mut Transaction<'_, Postgres>,
+    code: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        "
+update invite set uses = uses + 1
+where code = $1
+    and (max_uses is null or uses < max_uses)
+    and (expires_at is null or expires_at > now())
+returning id
+        ",
+    )
+    .bind(code.trim())
+    .fetch_optional(&mut *tx)
+    .await
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Invite {
+    id: i64,
+    #[schema(example = "mzxw-6ytb-oi3d-qmbr")]
+    code: String,
+    /// `null` for no limit.
+    max_uses: Option<i32>,
+    /// The number of accounts created with it.
+    uses: i32,
+    /// `null` if it never expires.
+    expires_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Invites {
+    invites: Vec<Invite>,
+}
+
+/// List the invites the current account minted, newest first.
+#[utoipa::path(
+    get,
+    path = "/invites",
+    tag = "accounts",
+    responses(
+        (status = 200, description = "Success.", body = Invites),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_invites(account: AccountSession, pool: DB) -> Result<Response<Body>, ApiError> {
+    let invites = sqlx::query_as::<_, Invite>(
+        "
+select id, code, max_uses, uses, expires_at, created_at
+from invite
+where created_by = $1
+order by created_at desc, id desc
+        ",
+    )
+    .bind(account.id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to list invites: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&Invites { invites }).into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateInviteQ {
+    /// How many accounts may be created with it.
+    #[serde(default)]
+    #[schema(default = 1)]
+    max_uses: Option<i32>,
+    /// Defaults to `FICAI_INVITE_TTL_SECS`.
+    #[serde(default)]
+    valid_for_secs: Option<i64>,
+}
+
+/// Mint an invite code for someone to create an account with.
+///
+/// Accounts without the `user-moderation` permission may only mint single-use invites, valid for 14
+/// days at most by default, and only have a few unused ones at once.
+#[utoipa::path(
+    post,
+    path = "/invites",
+    tag = "accounts",
+    request_body = CreateInviteQ,
+    responses(
+        (status = 201, description = "Created.", body = Invite),
+        (
+            status = 400,
+            description = "Bad request, e.g. the invite is larger than allowed, or there are too \
+                many unused ones.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn create_invite(
+    account: AccountSession,
+    q: CreateInviteQ,
+    pool: DB,
+    policy: &InvitePolicy,
+) -> Result<Response<Body>, ApiError> {
+    let max_uses = q.max_uses.unwrap_or(1);
+    let valid_for = q
+        .valid_for_secs
+        .map_or(policy.ttl, chrono::Duration::seconds);
+    if max_uses < 1 || valid_for <= chrono::Duration::zero() {
+        return Err(ApiError::BadRequest(
+            "maxUses and validForSecs must be positive".into(),
+        ));
+    }
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to create invite: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let granted = sqlx::query_scalar::<_, bool>(
+        "select exists(select 1 from account_permission where account_id = $1 and permission = $2)",
+    )
+    .bind(account.id)
+    .bind(Permission::UserModeration)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(internal_error)?;
+    if !(granted || Permission::UserModeration.implied_by(account.role)) {
+        if max_uses != 1 || valid_for > policy.ttl {
+            return Err(ApiError::BadRequest(
+                format!(
+                    "invites must be single-use and valid for at most {} seconds",
+                    policy.ttl.num_seconds()
+                )
+                .into(),
+            ));
+        }
+        // Serializes minting per account, so the limit holds.
+        sqlx::query("select id from account where id = $1 for update")
+            .bind(account.id)
+            .execute(&mut tx)
+            .await
+            .map_err(internal_error)?;
+        let outstanding = sqlx::query_scalar::<_, i64>(
+            "
+select count(1)
+from invite
+where created_by = $1
+    and (max_uses is null or uses < max_uses)
+    and (expires_at is null or expires_at > now())
+            ",
+        )
+        .bind(account.id)
+        .fetch_one(&mut tx)
+        .await
+        .map_err(internal_error)?;
+        if outstanding >= policy.per_account {
+            return Err(ApiError::BadRequest(
+                format!("at most {} unused invites per account", policy.per_account).into(),
+            ));
+        }
+    }
+    let mut code = [0u8; CODE_BYTES];
+    OsRng.fill_bytes(&mut code);
+    let code = data_encoding::BASE32_NOPAD
+        .encode(&code)
+        .to_ascii_lowercase()
+        .as_bytes()
+        .chunks(4)
+        .map(|c| std::str::from_utf8(c).unwrap())
+        .collect::<Vec<_>>()
+        .join("-");
+    let invite = sqlx::query_as::<_, Invite>(
+        "
+insert into invite (code, created_by, max_uses, expires_at)
+values ($1, $2, $3, now() + $4 * interval '1 second')
+returning id, code, max_uses, uses, expires_at, created_at
+        ",
+    )
+    .bind(code)
+    .bind(account.id)
+    .bind(max_uses)
+    .bind(valid_for.num_seconds() as f64)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(json(&invite)
+        .pipe(|r| with_status(r, StatusCode::CREATED))
+        .into_response())
+}
+
+// Expires the invite rather than deleting it, keeping it around for the accounts it created.
+/// Revoke an invite, so that no more accounts can be created with it.
+#[utoipa::path(
+    delete,
+    path = "/invites/{id}",
+    tag = "accounts",
+    params(
+        ("id" = i64, Path),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 404, description = "The account minted no such invite.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn revoke_invite(
+    account: AccountSession,
+    id: i64,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let revoked = sqlx::query(
+        "
+update invite set expires_at = least(expires_at, now())
+where id = $1 and created_by = $2
+        ",
+    )
+    .bind(id)
+    .bind(account.id)
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to revoke invite: {:?}", e);
+        ApiError::Internal
+    })?
+    .rows_affected();
+    if revoked == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(json(&Empty {}).into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_invites, create_invite, revoke_invite))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_invites = warp::path!("v1" / "invites")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(get_invites)
+        .and_then(reject);
+    let create_invite = warp::path!("v1" / "invites")
+        .and(warp::post())
+        .and(ctx.authenticate())
+        .and(json_body::<CreateInviteQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |account, q, pool| create_invite(account, q, pool, ctx.invite_policy))
+        .and_then(reject);
+    let revoke_invite = warp::path!("v1" / "invites" / i64)
+        .and(warp::delete())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(|id, account, pool| revoke_invite(account, id, pool))
+        .and_then(reject);
+    cached(
+        CachePolicy::Never,
+        &[],
+        get_invites
+            .or(create_invite)
+            .or(revoke_invite)
+            .map(Reply::into_response),
+    )
+    .boxed()
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use eyre::{eyre, WrapErr};
+use warp::path::FullPath;
+use warp::{Filter, Rejection};
+
+use crate::httputil::{reject, ApiError};
+
+/// Windows of addresses that haven't made requests in a while are dropped once there are this
+/// many, at most once per window.
+const MAX_IDLE_WINDOWS: usize = 100_000;
+
+/// Requests counted for an address in the current and the previous window.
+struct Window {
+    started_at: Instant,
+    current: u64,
+    previous: u64,
+}
+
+struct Windows {
+    /// By address and the route with a limit of its own, if any.
+    by_client: HashMap<(IpAddr, Option<String>), Window>,
+    /// Until when idle windows are kept however many there are, lest every request go through
+    /// all of them while they're all in use.
+    next_sweep: Instant,
+}
+
+/// Limits how many requests each client address makes, across all routes, to keep abusive
+/// clients from hogging the server. Routes can have limits of their own, which are counted
+/// separately. Requests are counted in a sliding window, estimated from the counts of the
+/// current and the previous fixed window. Counts are kept in memory, like [`WriteLimiter`]'s.
+///
+/// [`WriteLimiter`]: crate::write_limit::WriteLimiter
+pub struct IpLimiter {
+    /// Requests per window on routes without a limit of their own, or 0 for no limit.
+    pub limit: u64,
+    pub window: Duration,
+    /// Limits of routes, as counted by `metrics`, e.g. `/v1/sessions`, or 0 for no limit.
+    routes: HashMap<String, u64>,
+    /// How many reverse proxies in front of the server append to `X-Forwarded-For`. Without
+    /// any, the header is ignored, as clients could make it up.
+    trusted_proxies: usize,
+    windows: Mutex<Windows>,
+}
+
+impl IpLimiter {
+    /// `routes` are given as `route=limit`, e.g. `/v1/sessions=20`.
+    pub fn new(
+        limit: u64,
+        window: Duration,
+        routes: &[String],
+        trusted_proxies: usize,
+    ) -> eyre::Result<Self> {
+        let routes = routes
+            .iter()
+            .map(|r| {
+                let (route, limit) = r
+                    .rsplit_once('=')
+                    .ok_or_else(|| eyre!("route limits must be given as route=limit"))?;
+                let limit = limit
+                    .parse::<u64>()
+                    .wrap_err_with(|| format!("bad limit for {}", route))?;
+                Ok((route.to_string(), limit))
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Self {
+            limit,
+            window: window.max(Duration::from_secs(1)),
+            routes,
+            trusted_proxies,
+            windows: Mutex::new(Windows {
+                by_client: HashMap::new(),
+                next_sweep: Instant::now(),
+            }),
+        })
+    }
+
+    /// Counts a request from `ip` to `path`, or fails with [`ApiError::RateLimited`] if the
+    /// address made too many already.
+    fn check(&self, ip: IpAddr, path: &str) -> Result<(), ApiError> {
+        let route = crate::metrics::route(path);
+        let (limit, route) = match self.routes.get(&route) {
+            Some(limit) => (*limit, Some(route)),
+            None => (self.limit, None),
+        };
+        if limit == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        if windows.by_client.len() >= MAX_IDLE_WINDOWS && now >= windows.next_sweep {
+            windows
+                .by_client
+                .retain(|_, w| now - w.started_at < 2 * self.window);
+            windows.next_sweep = now + self.window;
+        }
+        let window = windows.by_client.entry((ip, route)).or_insert(Window {
+            started_at: now,
+            current: 0,
+            previous: 0,
+        });
+        let elapsed = now - window.started_at;
+        if elapsed >= 2 * self.window {
+            *window = Window {
+                started_at: now,
+                current: 0,
+                previous: 0,
+            };
+        } else if elapsed >= self.window {
+            window.previous = window.current;
+            window.current = 0;
+            window.started_at += self.window;
+        }
+        let into_window = (now - window.started_at).as_secs_f64() / self.window.as_secs_f64();
+        let estimate = window.previous as f64 * (1.0 - into_window) + window.current as f64;
+        if estimate + 1.0 > limit as f64 {
+            let reset_secs = (self.window - (now - window.started_at))
+                .as_secs_f64()
+                .ceil() as u64;
+            return Err(ApiError::RateLimited {
+                limit,
+                reset_secs: reset_secs.max(1),
+            });
+        }
+        window.current += 1;
+        Ok(())
+    }
+
+    /// Passes requests on unless their address made too many already. Requests without an
+    /// address aren't limited.
+    pub fn filter(&'static self) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        client_ip(self.trusted_proxies)
+            .and(warp::path::full())
+            .then(move |ip: Option<IpAddr>, path: FullPath| async move {
+                match ip {
+                    Some(ip) => self.check(ip, path.as_str()),
+                    None => Ok(()),
+                }
+            })
+            .and_then(reject)
+            .untuple_one()
+    }
+}
+
+/// The client's address: the one `trusted_proxies` down `X-Forwarded-For` from the end, which
+/// the proxy closest to the client appended, or else the peer's. `None` on a unix socket
+/// without a trusted proxy in front.
+pub fn client_ip(
+    trusted_proxies: usize,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = Rejection> + Clone {
+    crate::timeout::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .map(
+            move |remote: Option<SocketAddr>, forwarded_for: Option<String>| {
+                if trusted_proxies > 0 {
+                    let forwarded = forwarded_for.as_deref().and_then(|f| {
+                        f.rsplit(',')
+                            .nth(trusted_proxies - 1)?
+                            .trim()
+                            .parse::<IpAddr>()
+                            .ok()
+                    });
+                    if forwarded.is_some() {
+                        return forwarded;
+                    }
+                }
+                remote.map(|r| r.ip())
+            },
+        )
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use rand_core::{OsRng, RngCore};
+
+use crate::fichub::Cache;
+use crate::DB;
+
+/// Settings of the fic metadata refresh job.
+#[derive(Debug, Clone, Copy)]
+pub struct FicRefresh {
+    /// Time between runs, before jitter.
+    pub interval: Duration,
+    /// Cache entries older than this are refreshed.
+    pub max_age: chrono::Duration,
+    /// Most URLs refreshed per run, so a run never hammers fichub for long.
+    pub batch_size: i64,
+}
+
+/// Up to a tenth of `interval`, so that instances started together don't all hit fichub at once.
+fn jitter(interval: Duration) -> Duration {
+    let max = interval.as_millis() as u64 / 10;
+    if max == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(OsRng.next_u64() % max)
+}
+
+/// Periodically re-fetches fic metadata whose cache entries are older than `settings.max_age`,
+/// oldest first, so that lookups by users rarely have to wait for fichub.
+pub fn spawn_fic_refresh(pool: DB, cache: &'static Cache, settings: FicRefresh) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(jitter(settings.interval)).await;
+            match refresh_stale_fics(&pool, cache, settings).await {
+                Ok((0, 0)) => {}
+                Ok((refreshed, failed)) => println!(
+                    "fic metadata refresh: refreshed {}, failed {}",
+                    refreshed, failed
+                ),
+                Err(e) => eprintln!("fic metadata refresh failed: {:?}", e),
+            }
+            tokio::time::sleep(settings.interval).await;
+        }
+    });
+}
+
+/// Returns how many URLs were refreshed and how many failed.
+async fn refresh_stale_fics(
+    pool: &DB,
+    cache: &Cache,
+    settings: FicRefresh,
+) -> eyre::Result<(usize, usize)> {
+    let urls = sqlx::query_scalar::<_, String>(
+        "
+select url
+from fic_url_cache
+where fic_id is not null and fetched_at < now() - $1 * interval '1 second'
+order by fetched_at
+limit $2
+        ",
+    )
+    .bind(settings.max_age.num_seconds() as f64)
+    .bind(settings.batch_size)
+    .fetch_all(pool)
+    .await?;
+    let (mut refreshed, mut failed) = (0, 0);
+    for url in urls {
+        match cache.refresh(&url).await {
+            Ok(_) => refreshed += 1,
+            Err(e) => {
+                eprintln!("failed to refresh metadata of {}: {:?}", url, e);
+                failed += 1;
+            }
+        }
+    }
+    Ok((refreshed, failed))
+}
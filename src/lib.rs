@@ -0,0 +1,182 @@
+use eyre::{eyre, WrapErr};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use warp::Filter as _;
+
+use crate::api_version::ApiVersion;
+use crate::httputil::recover;
+
+mod account_event;
+mod account_export;
+mod account_stats;
+mod account_status;
+mod admin;
+mod ao3;
+mod api_token;
+mod api_version;
+mod author;
+mod bex;
+mod canonical_url;
+mod captcha;
+mod catalog;
+mod compression;
+mod config;
+mod context;
+mod display_name;
+mod duplicates;
+mod fic_stats;
+mod fic_update;
+mod fichub;
+mod httputil;
+mod invite;
+mod ip_limit;
+mod jobs;
+mod login_throttle;
+mod mail;
+mod meta;
+mod metadata;
+mod metrics;
+mod migrate;
+mod oauth;
+mod openapi;
+mod opengraph;
+mod pagination;
+mod password_policy;
+mod preferences;
+mod profile;
+mod routes;
+mod series;
+mod server;
+mod service_account;
+mod signal;
+mod tag;
+mod tag_category_proposal;
+mod tag_export;
+mod tag_implication;
+mod tag_policy;
+mod tag_presentation;
+mod tag_proposal;
+mod tag_review;
+mod tag_search;
+mod tag_stats;
+mod tag_subscription;
+mod tag_translation;
+mod timeout;
+mod totp;
+mod url_policy;
+mod usermgmt;
+mod v2;
+mod write_limit;
+
+pub use crate::config::Config;
+pub use crate::context::Context;
+pub use crate::routes::{routes, Routes};
+
+pub type DB = sqlx::PgPool;
+
+/// Runs the server as configured in `cfg`, until it's told to shut down.
+pub async fn run(cfg: Config) -> eyre::Result<()> {
+    let cors = crate::server::cors(&cfg)?;
+    let tls = crate::server::tls(&cfg)?;
+    for version in ApiVersion::ALL {
+        crate::openapi::spec(version)
+            .wrap_err_with(|| format!("bad specification of {}", version.prefix()))?;
+    }
+
+    let conn_opt = PgConnectOptions::new()
+        .host(&cfg.db_host)
+        .port(cfg.db_port)
+        .username(&cfg.db_username)
+        .password(&cfg.db_password)
+        .database(&cfg.db_database)
+        // todo: sqlx doesn't support target_session_attrs (at time of writing), find another way
+        // .options([("target_session_attrs", "read-write")])
+        ;
+    // todo: error handling
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect_with(conn_opt)
+        .await
+        .map_err(|e| eyre!("failed to connect to database: {:?}", e))?;
+    crate::migrate::migrate(&pool)
+        .await
+        .wrap_err("failed to migrate the database schema")?;
+
+    let mut ctx = Context::new(&cfg, pool.clone())?;
+    let offending_tags = crate::tag_policy::audit(&pool, ctx.tag_policy, cfg.tag_policy_cleanup)
+        .await
+        .wrap_err("failed to check existing tags against the tag policy")?;
+    if !offending_tags.is_empty() {
+        println!(
+            "{} {} tags violating the tag policy: {:?}",
+            if cfg.tag_policy_cleanup {
+                "deleted"
+            } else {
+                "found"
+            },
+            offending_tags.len(),
+            offending_tags
+        );
+    }
+
+    // Background jobs can be turned off by setting their interval to 0.
+    let mut features = Vec::new();
+    if cfg.tag_inference_interval_secs > 0 {
+        crate::tag::spawn_category_inference(
+            pool.clone(),
+            std::time::Duration::from_secs(cfg.tag_inference_interval_secs),
+        );
+        features.push("tag-category-inference");
+    }
+    if cfg.duplicate_detection_interval_secs > 0 {
+        crate::duplicates::spawn_detection(
+            pool.clone(),
+            std::time::Duration::from_secs(cfg.duplicate_detection_interval_secs),
+        );
+        features.push("duplicate-account-detection");
+    }
+    if cfg.tag_stats_interval_secs > 0 {
+        crate::tag_stats::spawn_rollup(
+            pool.clone(),
+            std::time::Duration::from_secs(cfg.tag_stats_interval_secs),
+        );
+        features.push("tag-stats-rollup");
+    }
+    if cfg.fic_stats_interval_secs > 0 {
+        crate::fic_stats::spawn_rollup(
+            pool.clone(),
+            std::time::Duration::from_secs(cfg.fic_stats_interval_secs),
+        );
+        features.push("fic-stats-rollup");
+    }
+    if cfg.fic_refresh_interval_secs > 0 {
+        crate::jobs::spawn_fic_refresh(
+            pool.clone(),
+            ctx.fic_cache,
+            crate::jobs::FicRefresh {
+                interval: std::time::Duration::from_secs(cfg.fic_refresh_interval_secs),
+                max_age: chrono::Duration::seconds(cfg.fic_refresh_max_age_secs),
+                batch_size: cfg.fic_refresh_batch_size,
+            },
+        );
+        features.push("fic-metadata-refresh");
+    }
+    ctx.features = Box::leak(features.into_boxed_slice());
+    crate::meta::log_startup(&pool, ctx.features).await;
+    let ctx: &'static Context = Box::leak(Box::new(ctx));
+
+    if let Some(listen) = cfg.metrics_listen {
+        crate::metrics::spawn_listener(listen, pool.clone())?;
+    }
+
+    let routes = routes(ctx);
+    // Outside of `recover`, so that errors carry CORS headers too and browsers let
+    // clients read them.
+    let grace = std::time::Duration::from_secs(cfg.shutdown_grace_secs);
+    match cors {
+        Some(cors) => {
+            let routes = routes.with(cors).recover(recover);
+            crate::server::serve(routes, cfg.listen, cfg.listen_mode, tls, grace, pool).await
+        }
+        None => crate::server::serve(routes, cfg.listen, cfg.listen_mode, tls, grace, pool).await,
+    }
+}
@@ -0,0 +1,119 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+
+use crate::httputil::ApiError;
+use crate::DB;
+
+/// Slows down password guessing by locking out logins after repeated failures, both for the email
+/// tried and for the address trying it. Past the allowed number of failures, every further one
+/// doubles the lockout, up to `max_lockout`. Failures are counted by email rather than account, so
+/// the lockout doesn't give away which emails have one.
+///
+/// Addresses are the client's, see [`crate::ip_limit::client_ip`]. Logins without one, e.g. on a
+/// unix socket behind a proxy that didn't send `X-Forwarded-For`, are only throttled by email.
+pub struct LoginThrottle {
+    /// Failures allowed per email before locking out, or 0 for no limit.
+    pub email_failures: i64,
+    /// Failures allowed per address before locking out, or 0 for no limit. Higher than for emails,
+    /// since many people may share an address.
+    pub ip_failures: i64,
+    /// How long failures are remembered.
+    pub window: chrono::Duration,
+    pub max_lockout: chrono::Duration,
+}
+
+impl LoginThrottle {
+    /// Until when `failures` lock logins out, the last of them being at `last`.
+    fn locked_until(
+        &self,
+        allowed: i64,
+        failures: i64,
+        last: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        if allowed == 0 || failures < allowed {
+            return None;
+        }
+        let doublings = (failures - allowed).min(30) as u32;
+        let lockout = chrono::Duration::seconds(1 << doublings).min(self.max_lockout);
+        Some(last + lockout)
+    }
+
+    /// Fails with [`ApiError::TooManyRequests`] while logins with `email` or from `ip` are
+    /// locked out.
+    pub async fn check(&self, email: &str, ip: Option<IpAddr>, pool: &DB) -> Result<(), ApiError> {
+        let (email_failures, email_last, ip_failures, ip_last) =
+            sqlx::query_as::<_, (i64, Option<DateTime<Utc>>, i64, Option<DateTime<Utc>>)>(
+                "
+select e.n, e.last, i.n, i.last
+from (
+    select count(1) as n, max(failed_at) as last
+    from login_failure
+    where email = $1 and failed_at > $3
+) e
+cross join (
+    select count(1) as n, max(failed_at) as last
+    from login_failure
+    where ip = $2::inet and failed_at > $3
+) i
+                ",
+            )
+            .bind(email)
+            .bind(ip.map(|ip| ip.to_string()))
+            .bind(Utc::now() - self.window)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| {
+                eprintln!("failed to check login failures: {:?}", e);
+                ApiError::Internal
+            })?;
+        let locked_until = [
+            email_last
+                .and_then(|last| self.locked_until(self.email_failures, email_failures, last)),
+            ip_last.and_then(|last| self.locked_until(self.ip_failures, ip_failures, last)),
+        ]
+        .into_iter()
+        .flatten()
+        .max();
+        match locked_until {
+            Some(until) if until > Utc::now() => Err(ApiError::TooManyRequests {
+                retry_after_secs: (until - Utc::now()).num_seconds().max(0) as u64 + 1,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts a failed login, forgetting failures that are out of the window by now.
+    pub async fn record_failure(&self, email: &str, ip: Option<IpAddr>, pool: &DB) {
+        if ip.is_none() && self.ip_failures > 0 {
+            eprintln!("failed login without a client address, only throttled by email");
+        }
+        let result = async {
+            sqlx::query("delete from login_failure where failed_at <= $1")
+                .bind(Utc::now() - self.window)
+                .execute(pool)
+                .await?;
+            sqlx::query("insert into login_failure (email, ip) values ($1, $2::inet)")
+                .bind(email)
+                .bind(ip.map(|ip| ip.to_string()))
+                .execute(pool)
+                .await
+        }
+        .await;
+        if let Err(e) = result {
+            eprintln!("failed to record login failure: {:?}", e);
+        }
+    }
+
+    /// Forgets the failures of `email` once a login with it succeeds. Those of the address are
+    /// kept, lest an attacker reset them by logging into an account of their own.
+    pub async fn clear(&self, email: &str, pool: &DB) {
+        if let Err(e) = sqlx::query("delete from login_failure where email = $1")
+            .bind(email)
+            .execute(pool)
+            .await
+        {
+            eprintln!("failed to clear login failures: {:?}", e);
+        }
+    }
+}
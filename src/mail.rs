@@ -0,0 +1,151 @@
+use eyre::WrapErr;
+use futures::future::{BoxFuture, FutureExt as _};
+use lettre::message::Mailbox;
+use lettre::{AsyncSmtpTransport, AsyncTransport as _, Message, Tokio1Executor};
+
+/// Sends email to account holders, e.g. password reset links.
+pub trait Mailer: Send + Sync {
+    fn send<'a>(
+        &'a self,
+        to: &'a str,
+        subject: &'a str,
+        body: String,
+    ) -> BoxFuture<'a, eyre::Result<()>>;
+}
+
+/// Sends email through an SMTP server, see `FICAI_SMTP_URL`.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(url: &str, from: &str) -> eyre::Result<Self> {
+        Ok(Self {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+                .wrap_err("invalid SMTP url")?
+                .build(),
+            from: from.parse().wrap_err("invalid sender address")?,
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send<'a>(
+        &'a self,
+        to: &'a str,
+        subject: &'a str,
+        body: String,
+    ) -> BoxFuture<'a, eyre::Result<()>> {
+        async move {
+            let message = Message::builder()
+                .from(self.from.clone())
+                .to(to.parse().wrap_err("invalid recipient address")?)
+                .subject(subject)
+                .body(body)?;
+            self.transport.send(message).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Prints email to stdout instead of sending it, for development without an SMTP server.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send<'a>(
+        &'a self,
+        to: &'a str,
+        subject: &'a str,
+        body: String,
+    ) -> BoxFuture<'a, eyre::Result<()>> {
+        println!("mail to {}: {}\n{}", to, subject, body);
+        futures::future::ready(Ok(())).boxed()
+    }
+}
+
+/// Every email the server sends, so that their wording lives in one place.
+#[derive(Debug)]
+pub enum Template {
+    PasswordReset {
+        link: String,
+        valid_for: chrono::Duration,
+    },
+    MagicLink {
+        link: String,
+        valid_for: chrono::Duration,
+    },
+    /// Sent to the new address, to verify it.
+    ConfirmEmailChange {
+        old_email: String,
+        link: String,
+        valid_for: chrono::Duration,
+    },
+    /// Sent to the old address once the change went through.
+    EmailChanged { new_email: String },
+}
+
+const SIGNATURE: &str = "\n\n-- \nFic.AI";
+
+impl Template {
+    /// The subject and plain text body.
+    pub fn render(&self) -> (&'static str, String) {
+        let (subject, body) = match self {
+            Self::PasswordReset { link, valid_for } => (
+                "Reset your Fic.AI password",
+                format!(
+                    "Someone asked to reset the password of your Fic.AI account. To choose a new \
+                     password, open this link within {} minutes:\n\n{}\n\nIf that wasn't you, you \
+                     can ignore this email.",
+                    valid_for.num_minutes(),
+                    link
+                ),
+            ),
+            Self::MagicLink { link, valid_for } => (
+                "Log in to Fic.AI",
+                format!(
+                    "Someone asked for a link to log into your Fic.AI account. To log in, open \
+                     this link within {} minutes:\n\n{}\n\nThe link works once. If that wasn't \
+                     you, you can ignore this email.",
+                    valid_for.num_minutes(),
+                    link
+                ),
+            ),
+            Self::ConfirmEmailChange {
+                old_email,
+                link,
+                valid_for,
+            } => (
+                "Confirm your new Fic.AI email",
+                format!(
+                    "Someone asked to change the email of the Fic.AI account {} to this address. \
+                     To confirm, open this link within {} hours:\n\n{}\n\nIf that wasn't you, you \
+                     can ignore this email.",
+                    old_email,
+                    valid_for.num_hours(),
+                    link
+                ),
+            ),
+            Self::EmailChanged { new_email } => (
+                "Your Fic.AI email was changed",
+                format!(
+                    "The email of your Fic.AI account was changed to {}. If that wasn't you, \
+                     please contact us right away.",
+                    new_email
+                ),
+            ),
+        };
+        (subject, body + SIGNATURE)
+    }
+}
+
+/// Sends `template` without waiting for the mail server, logging failures.
+pub(crate) fn send_in_background(mailer: &'static dyn Mailer, to: String, template: Template) {
+    tokio::spawn(async move {
+        let (subject, body) = template.render();
+        if let Err(e) = mailer.send(&to, subject, body).await {
+            eprintln!("failed to send email \"{}\": {:?}", subject, e);
+        }
+    });
+}
@@ -0,0 +1,169 @@
+use chrono::{DateTime, TimeZone, Utc};
+use http::{Response, StatusCode};
+use hyper::Body;
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+use warp::{
+    reply::{json, with_status},
+    Filter, Reply,
+};
+
+use crate::context::Context;
+use crate::httputil::{reject, reply_json, ApiError, Empty};
+use crate::routes::Routes;
+use crate::DB;
+
+/// Version of `schema.sql` this build expects. Bump it together with the row inserted into
+/// `schema_version` whenever the schema changes, and add the change to `migrate::migrate`.
+pub const SCHEMA_VERSION: i32 = 42;
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Version {
+    /// Version of the server package.
+    version: &'static str,
+    /// Commit the server was built from, or `unknown`.
+    git_commit: &'static str,
+    built_at: DateTime<Utc>,
+    /// Schema version recorded in the database, which may lag behind `expected_schema_version`
+    /// if migrations weren't applied.
+    schema_version: Option<i32>,
+    expected_schema_version: i32,
+    /// Optional features that are enabled, such as background jobs.
+    #[schema(value_type = Vec<String>)]
+    features: &'static [&'static str],
+}
+
+pub fn built_at() -> DateTime<Utc> {
+    Utc.timestamp_opt(env!("FICAI_BUILT_AT").parse::<i64>().unwrap_or_default(), 0)
+        .single()
+        .unwrap_or_else(|| Utc.timestamp_opt(0, 0).unwrap())
+}
+
+pub async fn schema_version(pool: &DB) -> eyre::Result<Option<i32>> {
+    Ok(
+        sqlx::query_scalar::<_, Option<i32>>("select max(version) from schema_version")
+            .fetch_one(pool)
+            .await?,
+    )
+}
+
+/// Logs what is running, so that logs attached to bug reports identify the build, and warns if
+/// the database schema doesn't match it.
+pub async fn log_startup(pool: &DB, features: &[&str]) {
+    println!(
+        "ficai-signals-server {} (commit {}, built {})",
+        env!("CARGO_PKG_VERSION"),
+        env!("FICAI_GIT_COMMIT"),
+        built_at().to_rfc3339(),
+    );
+    println!("enabled features: {}", features.join(", "));
+    match schema_version(pool).await {
+        Ok(Some(v)) if v == SCHEMA_VERSION => println!("schema version: {}", v),
+        Ok(v) => eprintln!(
+            "warning: database schema version is {:?}, but this build expects {}",
+            v, SCHEMA_VERSION
+        ),
+        Err(e) => eprintln!("warning: failed to read database schema version: {:?}", e),
+    }
+}
+
+/// Describe the running build, to be included in bug reports.
+#[utoipa::path(
+    get,
+    path = "/meta/version",
+    tag = "meta",
+    responses(
+        (status = 200, description = "Build information.", body = Version),
+    ),
+)]
+pub async fn get_version(pool: DB, features: &'static [&'static str]) -> eyre::Result<Version> {
+    Ok(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("FICAI_GIT_COMMIT"),
+        built_at: built_at(),
+        schema_version: schema_version(&pool).await?,
+        expected_schema_version: SCHEMA_VERSION,
+        features,
+    })
+}
+
+/// How long `GET /readyz` waits for the database before giving up on it.
+const READY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+    ready: bool,
+    database_reachable: bool,
+    schema_version: Option<i32>,
+    expected_schema_version: i32,
+}
+
+/// Answers as long as the process does, for liveness probes.
+pub async fn get_healthz() -> Result<Response<Body>, ApiError> {
+    Ok(json(&Empty {}).into_response())
+}
+
+/// Whether requests can be served: the database answers and has the schema this build expects.
+/// Answers with `503 Service Unavailable` otherwise, for readiness probes.
+pub async fn get_readyz(pool: DB) -> Result<Response<Body>, ApiError> {
+    let check = async {
+        sqlx::query("select 1").execute(&pool).await?;
+        schema_version(&pool).await
+    };
+    let (database_reachable, schema_version) =
+        match tokio::time::timeout(READY_TIMEOUT, check).await {
+            Ok(Ok(version)) => (true, version),
+            Ok(Err(e)) => {
+                eprintln!("readiness check failed: {:?}", e);
+                (false, None)
+            }
+            Err(_) => {
+                eprintln!("readiness check timed out after {:?}", READY_TIMEOUT);
+                (false, None)
+            }
+        };
+    let ready = database_reachable && schema_version == Some(SCHEMA_VERSION);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(with_status(
+        json(&Readiness {
+            ready,
+            database_reachable,
+            schema_version,
+            expected_schema_version: SCHEMA_VERSION,
+        }),
+        status,
+    )
+    .into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_version))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_version = warp::path!("v1" / "meta" / "version")
+        .and(warp::get())
+        .and(ctx.pool())
+        .then(move |pool| get_version(pool, ctx.features))
+        .and_then(reply_json);
+    let get_healthz = warp::path!("healthz")
+        .and(warp::get())
+        .then(get_healthz)
+        .and_then(reject);
+    let get_readyz = warp::path!("readyz")
+        .and(warp::get())
+        .and(ctx.pool())
+        .then(get_readyz)
+        .and_then(reject);
+    get_version
+        .or(get_healthz)
+        .or(get_readyz)
+        .map(Reply::into_response)
+        .boxed()
+}
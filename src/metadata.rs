@@ -0,0 +1,131 @@
+use std::fmt;
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::fichub::{Meta, Throttled};
+use crate::metrics;
+
+/// A source of fic metadata.
+pub trait MetadataProvider: Send + Sync {
+    /// Used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Whether `url` is worth asking this provider about at all.
+    fn handles(&self, url: &str) -> bool;
+
+    fn meta<'a>(&'a self, url: &'a str) -> BoxFuture<'a, eyre::Result<Meta>>;
+}
+
+/// The providers that can be configured, see `FICAI_METADATA_PROVIDERS`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProviderKind {
+    Fichub,
+    Ao3,
+    Opengraph,
+}
+
+/// A provider found out that it has no metadata for a URL, as opposed to failing to find out.
+/// The message is meant for clients.
+#[derive(Debug)]
+pub struct Unsupported(pub String);
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// Why no metadata could be found for a URL.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "lookup_failure", rename_all = "lowercase")]
+pub enum FailureKind {
+    /// No provider has metadata for the URL.
+    Unsupported,
+    /// Providers failed, so trying again later may help.
+    Unavailable,
+}
+
+impl FailureKind {
+    pub fn of(e: &eyre::Report) -> Self {
+        if e.chain().any(|e| e.is::<Unsupported>()) {
+            Self::Unsupported
+        } else {
+            Self::Unavailable
+        }
+    }
+}
+
+/// Asks each provider that handles a URL in turn, until one of them knows it.
+pub struct Providers(pub Vec<Box<dyn MetadataProvider>>);
+
+impl Providers {
+    /// Fails as [`Unsupported`] only if every provider that handles `url` said so.
+    pub async fn meta(&self, url: &str) -> eyre::Result<Meta> {
+        let mut errors: Vec<eyre::Report> = Vec::new();
+        for provider in self.0.iter().filter(|p| p.handles(url)) {
+            if let Some(e) = errors.last() {
+                eprintln!("falling back to {} for {}: {:?}", provider.name(), url, e);
+            }
+            let started = Instant::now();
+            let result = provider.meta(url).await;
+            let outcome = match &result {
+                Ok(_) => "ok",
+                Err(e) if e.chain().any(|e| e.is::<Throttled>()) => "throttled",
+                Err(e) => match FailureKind::of(e) {
+                    FailureKind::Unsupported => "unsupported",
+                    FailureKind::Unavailable => "error",
+                },
+            };
+            let labels = [provider.name(), outcome];
+            let metrics = metrics::get();
+            metrics
+                .metadata_fetch_duration
+                .with_label_values(&labels)
+                .observe(started.elapsed().as_secs_f64());
+            if outcome != "ok" {
+                metrics
+                    .metadata_fetch_errors
+                    .with_label_values(&labels)
+                    .inc();
+            }
+            match result {
+                Ok(meta) => return Ok(meta),
+                Err(e) => errors.push(e.wrap_err(format!("{} lookup failed", provider.name()))),
+            }
+        }
+        let unavailable = errors
+            .iter()
+            .rposition(|e| FailureKind::of(e) == FailureKind::Unavailable);
+        Err(match (unavailable, errors.pop()) {
+            (Some(i), _) if i < errors.len() => errors.swap_remove(i),
+            (_, Some(last)) => last,
+            (_, None) => eyre::Report::new(Unsupported(
+                "no metadata provider handles the url".to_string(),
+            )),
+        })
+    }
+}
+
+/// The text between the first `start` in `haystack` and the `end` after it.
+pub(crate) fn between<'a>(haystack: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let from = haystack.find(start)? + start.len();
+    let len = haystack[from..].find(end)?;
+    Some(&haystack[from..from + len])
+}
+
+/// Undoes the escaping HTML pages commonly apply to text.
+pub(crate) fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,255 @@
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+
+use eyre::WrapErr;
+use http::header::CONTENT_TYPE;
+use http::Response;
+use hyper::Body;
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use warp::{Filter, Rejection, Reply};
+
+use crate::api_version::ApiVersion;
+use crate::context::Context;
+use crate::httputil::{reject, ApiError};
+use crate::routes::Routes;
+use crate::DB;
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: [f64; 10] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// What is exported, for scraping by Prometheus.
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub db_pool_connections: IntGaugeVec,
+    pub fichub_attempts: IntCounterVec,
+    pub fichub_request_duration: HistogramVec,
+    pub fichub_paused: IntGauge,
+    pub metadata_fetch_duration: HistogramVec,
+    pub metadata_fetch_errors: IntCounterVec,
+    pub metadata_cache_lookups: IntCounterVec,
+}
+
+fn counter(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    IntCounterVec::new(Opts::new(name, help), labels).unwrap()
+}
+
+fn histogram(name: &str, help: &str, labels: &[&str]) -> HistogramVec {
+    HistogramVec::new(
+        HistogramOpts::new(name, help).buckets(BUCKETS.to_vec()),
+        labels,
+    )
+    .unwrap()
+}
+
+fn gauge(name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    IntGaugeVec::new(Opts::new(name, help), labels).unwrap()
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let metrics = Metrics {
+            registry: Registry::new(),
+            http_requests: counter(
+                "ficai_http_requests_total",
+                "Requests by route, method and status. Routes are as documented in the OpenAPI spec.",
+                &["route", "method", "status"],
+            ),
+            http_request_duration: histogram(
+                "ficai_http_request_duration_seconds",
+                "Time taken to answer requests, by route and method.",
+                &["route", "method"],
+            ),
+            db_pool_connections: gauge(
+                "ficai_db_pool_connections",
+                "Database connections by state, as of the scrape.",
+                &["state"],
+            ),
+            fichub_attempts: counter(
+                "ficai_fichub_attempts_total",
+                "Attempts to look up a URL on fichub, by outcome; throttled and paused ones never reach it.",
+                &["outcome"],
+            ),
+            fichub_request_duration: histogram(
+                "ficai_fichub_request_duration_seconds",
+                "Time taken by requests to fichub, by outcome.",
+                &["outcome"],
+            ),
+            fichub_paused: IntGauge::new(
+                "ficai_fichub_paused",
+                "1 from when fichub failed too many lookups in a row until one succeeds again.",
+            )
+            .unwrap(),
+            metadata_fetch_duration: histogram(
+                "ficai_metadata_fetch_duration_seconds",
+                "Time taken by metadata providers to look up a URL, by provider and outcome.",
+                &["provider", "outcome"],
+            ),
+            metadata_fetch_errors: counter(
+                "ficai_metadata_fetch_errors_total",
+                "Failed lookups by metadata provider and outcome.",
+                &["provider", "outcome"],
+            ),
+            metadata_cache_lookups: counter(
+                "ficai_metadata_cache_lookups_total",
+                "Fic metadata lookups by how the cache answered them.",
+                &["result"],
+            ),
+        };
+        let collectors: [Box<dyn Collector>; 9] = [
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.http_request_duration.clone()),
+            Box::new(metrics.db_pool_connections.clone()),
+            Box::new(metrics.fichub_attempts.clone()),
+            Box::new(metrics.fichub_request_duration.clone()),
+            Box::new(metrics.fichub_paused.clone()),
+            Box::new(metrics.metadata_fetch_duration.clone()),
+            Box::new(metrics.metadata_fetch_errors.clone()),
+            Box::new(metrics.metadata_cache_lookups.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+}
+
+pub fn get() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+/// The routes of each API version as documented, split into segments, e.g. `["tags", "{tag}"]`.
+fn documented_routes(version: ApiVersion) -> &'static [Vec<&'static str>] {
+    static ROUTES: OnceLock<Vec<Vec<Vec<&'static str>>>> = OnceLock::new();
+    let i = ApiVersion::ALL.iter().position(|v| *v == version).unwrap();
+    &ROUTES.get_or_init(|| {
+        ApiVersion::ALL
+            .iter()
+            .map(|v| {
+                // Checked at startup, see `run`.
+                let paths = crate::openapi::spec(*v)
+                    .ok()
+                    .and_then(|spec| spec["paths"].as_object());
+                paths
+                    .into_iter()
+                    .flat_map(|paths| paths.keys())
+                    .map(|path| path.trim_start_matches('/').split('/').collect())
+                    .collect()
+            })
+            .collect()
+    })[i]
+}
+
+/// The documented route a request path belongs to, e.g. `/v1/tags/{tag}` for `/v1/tags/worm`,
+/// so that requests are counted per route rather than per URL. Of the routes that match, the one
+/// with the fewest parameters wins, as `/v1/tags/search` isn't a tag. Paths that match none are
+/// `other`, lest made up ones add time series without end.
+pub fn route(path: &str) -> String {
+    if ["/metrics", "/healthz", "/readyz", "/docs"].contains(&path)
+        || ApiVersion::ALL.iter().any(|v| v.spec_path() == path)
+    {
+        return path.to_string();
+    }
+    let version = ApiVersion::ALL.into_iter().find_map(|v| {
+        let rest = path
+            .strip_prefix('/')?
+            .strip_prefix(v.prefix())?
+            .strip_prefix('/')?;
+        Some((v, rest))
+    });
+    let (version, segments) = match version {
+        Some((version, rest)) => (version, rest.split('/').collect::<Vec<_>>()),
+        None => return "other".to_string(),
+    };
+    documented_routes(version)
+        .iter()
+        .filter(|route| {
+            route.len() == segments.len()
+                && route
+                    .iter()
+                    .zip(&segments)
+                    .all(|(r, s)| r.starts_with('{') || r == s)
+        })
+        .min_by_key(|route| route.iter().filter(|r| r.starts_with('{')).count())
+        .map_or_else(
+            || "other".to_string(),
+            |route| format!("/{}/{}", version.prefix(), route.join("/")),
+        )
+}
+
+/// Counts a request once it was answered, for `warp::log::custom`.
+pub fn record_request(info: warp::log::Info<'_>) {
+    let route = route(info.path());
+    let method = match info.method().as_str() {
+        method @ ("GET" | "HEAD" | "POST" | "PUT" | "PATCH" | "DELETE" | "OPTIONS") => method,
+        _ => "other",
+    };
+    let metrics = get();
+    metrics
+        .http_requests
+        .with_label_values(&[&route, method, info.status().as_str()])
+        .inc();
+    metrics
+        .http_request_duration
+        .with_label_values(&[&route, method])
+        .observe(info.elapsed().as_secs_f64());
+}
+
+pub async fn get_metrics(pool: DB) -> Result<Response<Body>, ApiError> {
+    let metrics = get();
+    let idle = pool.num_idle() as u32;
+    let connections = &metrics.db_pool_connections;
+    connections.with_label_values(&["idle"]).set(idle.into());
+    connections
+        .with_label_values(&["in-use"])
+        .set(pool.size().saturating_sub(idle).into());
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder
+        .encode(&metrics.registry.gather(), &mut body)
+        .wrap_err("failed to encode metrics")?;
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, encoder.format_type())
+        .body(Body::from(body))
+        .unwrap())
+}
+
+fn get_metrics_route(
+    db: DB,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .and(warp::any().map(move || db.clone()))
+        .then(get_metrics)
+        .and_then(reject)
+}
+
+/// Serves `GET /metrics` on its own listener instead of alongside the API, so that it can be
+/// kept private. Fails if `listen` can't be bound.
+pub fn spawn_listener(listen: SocketAddr, db: DB) -> eyre::Result<()> {
+    let (_, server) = warp::serve(get_metrics_route(db))
+        .try_bind_ephemeral(listen)
+        .wrap_err_with(|| format!("failed to listen on {} for metrics", listen))?;
+    tokio::spawn(server);
+    Ok(())
+}
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    // Only on one listener or the other.
+    let get_metrics = warp::any()
+        .and_then(move || async move {
+            match ctx.metrics_listen {
+                Some(_) => Err(warp::reject::not_found()),
+                None => Ok(()),
+            }
+        })
+        .untuple_one()
+        .and(get_metrics_route(ctx.db.clone()));
+    get_metrics.map(Reply::into_response).boxed()
+}
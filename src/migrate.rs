@@ -0,0 +1,321 @@
+use sqlx::{Postgres, Transaction};
+
+use crate::meta::SCHEMA_VERSION;
+use crate::DB;
+
+/// The oldest schema version there are migrations from. Older databases have to be brought up to
+/// it by hand.
+const FIRST_MIGRATED_VERSION: i32 = 2;
+
+/// Brings a database made from an older `schema.sql` up to [`SCHEMA_VERSION`], one version at a
+/// time, all in one transaction. Databases that are up to date, newer, or older than
+/// [`FIRST_MIGRATED_VERSION`] are left alone, for `meta::log_startup` to warn about; so are the
+/// versions on the way from the first one without a migration.
+pub async fn migrate(pool: &DB) -> eyre::Result<()> {
+    let mut tx = pool.begin().await?;
+    // Lest several servers starting at once migrate the same database.
+    sqlx::query("lock table schema_version in exclusive mode")
+        .execute(&mut tx)
+        .await?;
+    let mut version =
+        match sqlx::query_scalar::<_, Option<i32>>("select max(version) from schema_version")
+            .fetch_one(&mut tx)
+            .await?
+        {
+            Some(v) if (FIRST_MIGRATED_VERSION..SCHEMA_VERSION).contains(&v) => v,
+            _ => return Ok(()),
+        };
+    while version < SCHEMA_VERSION {
+        match version {
+            2 => canonicalize_tags(&mut tx).await?,
+            39 => fold_urls(&mut tx).await?,
+            40 => add_tag_documents(&mut tx).await?,
+            41 => add_category_proposal_status(&mut tx).await?,
+            _ => break,
+        }
+        version += 1;
+        sqlx::query("insert into schema_version (version) values ($1)")
+            .bind(version)
+            .execute(&mut tx)
+            .await?;
+        println!("migrated schema to version {}", version);
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Tables keyed by tag, other than `signal`: the column holding it, the rest of the key, and which
+/// of the rows that collide once canonicalized to prefer, before the one whose tag already was.
+const TAG_KEYED_TABLES: [(&str, &str, &str, &str); 5] = [
+    ("tag_meta", "tag", "", ""),
+    ("tag_category_proposal", "tag", "", "r.created_at desc,"),
+    ("tag_alias", "alias", "", ""),
+    ("blocked_tag", "tag", "r.account_id,", ""),
+    ("tag_tombstone", "tag", "", "r.expires_at desc,"),
+];
+
+/// Groups signals by `tag::canonicalize`d tag, and stores tags in that form in the tables keyed by
+/// them. Where an account signalled a fic for several tags that are the same once canonicalized,
+/// the latest signal wins. Elsewhere, see [`TAG_KEYED_TABLES`]; descriptions and categories the
+/// winning `tag_meta` row lacks are taken from the others.
+async fn canonicalize_tags(tx: &mut Transaction<'_, Postgres>) -> eyre::Result<()> {
+    let (tags, canonical): (Vec<String>, Vec<String>) = sqlx::query_scalar::<_, String>(
+        "
+select tag from signal
+union select tag from tag_meta
+union select tag from tag_category_proposal
+union select alias from tag_alias
+union select tag from tag_alias
+union select tag from blocked_tag
+union select tag from tag_tombstone
+union select successor from tag_tombstone where successor is not null
+        ",
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|tag| {
+        let canonical = crate::tag::canonicalize(&tag);
+        (tag, canonical)
+    })
+    .unzip();
+    sqlx::query(
+        "
+create temporary table tag_canonical_map (
+    tag varchar(1024) primary key
+  , canonical varchar(1024) not null
+) on commit drop
+        ",
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("insert into tag_canonical_map select * from unnest($1::text[], $2::text[])")
+        .bind(&tags)
+        .bind(&canonical)
+        .execute(&mut *tx)
+        .await?;
+
+    for statement in [
+        "alter table signal add column tag_canonical varchar(1024)",
+        "
+update signal s set tag_canonical = m.canonical
+from tag_canonical_map m
+where m.tag = s.tag
+        ",
+        "
+update signal s set created_at = d.created_at
+from (
+    select account_id, url, tag_canonical, min(created_at) as created_at
+    from signal
+    group by account_id, url, tag_canonical
+    having count(*) > 1
+) d
+where d.account_id = s.account_id and d.url = s.url and d.tag_canonical = s.tag_canonical
+        ",
+    ] {
+        sqlx::query(statement).execute(&mut *tx).await?;
+    }
+    let merged = sqlx::query(
+        "
+delete from signal s
+using signal o
+where o.account_id = s.account_id
+    and o.url = s.url
+    and o.tag_canonical = s.tag_canonical
+    and (o.updated_at, o.tag) > (s.updated_at, s.tag)
+        ",
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    for statement in [
+        "
+alter table signal
+    alter column tag_canonical set not null,
+    drop constraint signal_pkey,
+    add primary key (account_id, url, tag_canonical)
+        ",
+        "drop index signal_tag_prefix_idx",
+        "drop index signal_tag_trgm_idx",
+        "create index signal_tag_canonical_idx on signal (tag_canonical)",
+        "create index signal_tag_prefix_idx on signal (tag_canonical text_pattern_ops)",
+        "create index signal_tag_trgm_idx on signal using gin (tag_canonical gin_trgm_ops)",
+        "
+update tag_meta t set
+    description = coalesce(t.description, (
+        select o.description
+        from tag_meta o
+        join tag_canonical_map n
+            on n.tag = o.tag
+        where n.canonical = m.canonical and o.description is not null
+        order by o.tag <> n.canonical, o.tag
+        limit 1
+    )),
+    category = coalesce(t.category, (
+        select o.category
+        from tag_meta o
+        join tag_canonical_map n
+            on n.tag = o.tag
+        where n.canonical = m.canonical and o.category is not null
+        order by o.tag <> n.canonical, o.tag
+        limit 1
+    ))
+from tag_canonical_map m
+where m.tag = t.tag
+        ",
+    ] {
+        sqlx::query(statement).execute(&mut *tx).await?;
+    }
+
+    for (table, column, rest_of_key, preference) in TAG_KEYED_TABLES {
+        sqlx::query(&format!(
+            "
+delete from {table}
+where ctid not in (
+    select distinct on ({rest_of_key} m.canonical) r.ctid
+    from {table} r
+    join tag_canonical_map m
+        on m.tag = r.{column}
+    order by {rest_of_key} m.canonical, {preference} r.{column} <> m.canonical, r.{column}
+)
+            "
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "
+update {table} t set {column} = m.canonical
+from tag_canonical_map m
+where m.tag = t.{column} and t.{column} <> m.canonical
+            "
+        ))
+        .execute(&mut *tx)
+        .await?;
+    }
+    for statement in [
+        "
+update tag_alias a set tag = m.canonical
+from tag_canonical_map m
+where m.tag = a.tag
+        ",
+        // Aliases that differed from their tag only in case or normalization.
+        "delete from tag_alias where alias = tag",
+        "alter table tag_tombstone add column successor_canonical varchar(1024)",
+        "
+update tag_tombstone t set successor_canonical = m.canonical
+from tag_canonical_map m
+where m.tag = t.successor
+        ",
+    ] {
+        sqlx::query(statement).execute(&mut *tx).await?;
+    }
+    println!(
+        "canonicalized {} tags, merging {} signals",
+        tags.iter().zip(&canonical).filter(|(t, c)| t != c).count(),
+        merged
+    );
+    Ok(())
+}
+
+/// Moves signals stored under URLs of chapters or pages to the URL of the fic, see
+/// `canonical_url::fold`. Where an account signalled for a tag on several of them, the latest
+/// signal wins. URLs that lead to a fic also lead there folded, as they do for new lookups.
+async fn fold_urls(tx: &mut Transaction<'_, Postgres>) -> eyre::Result<()> {
+    let (urls, folded): (Vec<String>, Vec<String>) =
+        sqlx::query_scalar::<_, String>("select url from signal union select url from fic_url")
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .filter_map(|url| {
+                let folded = crate::canonical_url::fold(&url);
+                (folded != url).then_some((url, folded))
+            })
+            .unzip();
+    let moved = sqlx::query(
+        "
+insert into signal (account_id, url, tag, tag_canonical, signal, created_at, updated_at)
+select distinct on (s.account_id, f.folded, s.tag_canonical)
+    s.account_id, f.folded, s.tag, s.tag_canonical, s.signal, s.created_at, s.updated_at
+from unnest($1::text[], $2::text[]) as f(url, folded)
+join signal s
+    on s.url = f.url
+order by s.account_id, f.folded, s.tag_canonical, s.updated_at desc
+on conflict (account_id, url, tag_canonical) do update set
+    signal = case
+        when excluded.updated_at > signal.updated_at then excluded.signal
+        else signal.signal
+    end,
+    created_at = least(signal.created_at, excluded.created_at),
+    updated_at = greatest(signal.updated_at, excluded.updated_at)
+        ",
+    )
+    .bind(&urls)
+    .bind(&folded)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    sqlx::query("delete from signal where url = any($1)")
+        .bind(&urls)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "
+insert into fic_url (url, fic_id)
+select distinct on (f.folded) f.folded, u.fic_id
+from unnest($1::text[], $2::text[]) as f(url, folded)
+join fic_url u
+    on u.url = f.url
+order by f.folded, u.url
+on conflict (url) do nothing
+        ",
+    )
+    .bind(&urls)
+    .bind(&folded)
+    .execute(&mut *tx)
+    .await?;
+    println!(
+        "folded {} URLs of chapters or pages, moving {} signals",
+        urls.len(),
+        moved
+    );
+    Ok(())
+}
+
+/// Stores the documents tag search matches against, see `tag_search::TagSearch::get`, and indexes
+/// them.
+async fn add_tag_documents(tx: &mut Transaction<'_, Postgres>) -> eyre::Result<()> {
+    for statement in [
+        "
+alter table signal add column tag_document tsvector not null
+    generated always as (setweight(to_tsvector('english', tag_canonical), 'A')) stored
+        ",
+        "create index signal_tag_document_idx on signal using gin (tag_document)",
+        "
+alter table tag_meta add column document tsvector not null generated always as (
+    setweight(to_tsvector('english', tag), 'A')
+        || setweight(to_tsvector('english', coalesce(description, '')), 'B')
+) stored
+        ",
+        "create index tag_meta_document_idx on tag_meta using gin (document)",
+    ] {
+        sqlx::query(statement).execute(&mut *tx).await?;
+    }
+    println!("added documents for tag search");
+    Ok(())
+}
+
+/// Keeps decided category proposals, so that the inference job doesn't propose them again.
+async fn add_category_proposal_status(tx: &mut Transaction<'_, Postgres>) -> eyre::Result<()> {
+    sqlx::query(
+        "
+alter table tag_category_proposal
+    add column status proposal_status not null default 'open',
+    add column decided_by bigint references account(id),
+    add column decided_at timestamptz
+        ",
+    )
+    .execute(&mut *tx)
+    .await?;
+    println!("added status to tag category proposals");
+    Ok(())
+}
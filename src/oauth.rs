@@ -0,0 +1,387 @@
+use base64ct::Encoding as _;
+use http::header::{LOCATION, SET_COOKIE};
+use http::{Response, StatusCode};
+use hyper::Body;
+use rand_core::{OsRng, RngCore};
+use reqwest::Url;
+use serde::Deserialize;
+use tap::prelude::*;
+use utoipa::{IntoParams, OpenApi};
+use warp::{Filter, Reply};
+
+use crate::account_event::{record as record_event, EventKind};
+use crate::context::Context;
+use crate::httputil::{cached, reject, ApiError, CachePolicy, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, Client, SessionPolicy};
+use crate::DB;
+
+/// Holds the `state` of a login in progress, tying the callback to the browser that started it.
+const STATE_COOKIE_NAME: &str = "FicAiOAuthState";
+const STATE_BYTES: usize = 16;
+/// How long a login may take at the provider.
+const STATE_MAX_AGE_SECS: i64 = 600;
+
+/// Logging in through an OpenID Connect provider, with the authorization code flow. The provider's
+/// endpoints are discovered from the issuer for every login, so that they can change without a
+/// restart.
+pub struct OAuth {
+    /// E.g. `https://accounts.google.com`.
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Where the provider sends the browser back to, i.e. `v1/oauth/callback`.
+    pub redirect_url: String,
+    /// Where the browser ends up once logged in.
+    pub return_url: String,
+    /// Whether logging in creates an account for emails that don't have one yet.
+    pub signup: bool,
+    pub http: reqwest::Client,
+}
+
+#[derive(Deserialize, Debug)]
+struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct UserInfo {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+}
+
+impl OAuth {
+    async fn discover(&self) -> eyre::Result<Discovery> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        );
+        Ok(self
+            .http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// Trades the authorization code for the identity it stands for.
+    async fn user_info(&self, code: &str) -> eyre::Result<UserInfo> {
+        let discovery = self.discover().await?;
+        let token = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_url),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TokenResponse>()
+            .await?;
+        Ok(self
+            .http
+            .get(&discovery.userinfo_endpoint)
+            .bearer_auth(token.access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+fn state_cookie(value: String, domain: &str, max_age_secs: i64) -> cookie::Cookie<'_> {
+    cookie::Cookie::build(STATE_COOKIE_NAME, value)
+        .domain(domain)
+        .path("/v1/oauth")
+        .secure(true)
+        .http_only(true)
+        // Sent along when the provider redirects back, which is a top-level navigation.
+        .same_site(cookie::SameSite::Lax)
+        .max_age(cookie::time::Duration::seconds(max_age_secs))
+        .finish()
+}
+
+pub fn state_cookie_value(
+) -> impl warp::Filter<Extract = (Option<String>,), Error = std::convert::Infallible> + Clone {
+    warp::cookie::optional(STATE_COOKIE_NAME)
+}
+
+/// Log in through the configured OpenID Connect provider.
+///
+/// Redirects the browser to the provider, which sends it back to `/oauth/callback`.
+#[utoipa::path(
+    get,
+    path = "/oauth/login",
+    tag = "sessions",
+    responses(
+        (
+            status = 302,
+            description = "Redirect to the provider's authorization endpoint.",
+            headers(
+                ("Location" = String),
+                (
+                    "Set-Cookie" = String,
+                    description = "Includes a cookie named `FicAiOAuthState`, tying the callback \
+                        to this browser."
+                ),
+            ),
+        ),
+        (
+            status = 404,
+            description = "Logging in through a provider is not configured.",
+            body = ErrorWrap,
+        ),
+        (status = 502, description = "The provider could not be reached.", body = ErrorWrap),
+    ),
+)]
+pub async fn oauth_login(
+    oauth: Option<&'static OAuth>,
+    policy: &SessionPolicy,
+) -> Result<Response<Body>, ApiError> {
+    let oauth = oauth.ok_or(ApiError::NotFound)?;
+    let discovery = oauth.discover().await.map_err(|e| {
+        eprintln!("failed to discover the oauth provider: {:?}", e);
+        ApiError::BadGateway
+    })?;
+    let mut state = [0u8; STATE_BYTES];
+    OsRng.fill_bytes(&mut state);
+    let state = base64ct::Base64UrlUnpadded::encode_string(&state);
+    let mut url = Url::parse(&discovery.authorization_endpoint).map_err(|e| {
+        eprintln!("invalid oauth authorization endpoint: {:?}", e);
+        ApiError::BadGateway
+    })?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &oauth.client_id)
+        .append_pair("redirect_uri", &oauth.redirect_url)
+        .append_pair("scope", "openid email")
+        .append_pair("state", &state);
+    Ok(Response::builder()
+        .status(StatusCode::FOUND)
+        .header(LOCATION, url.as_str())
+        .header(
+            SET_COOKIE,
+            state_cookie(state, policy.domain, STATE_MAX_AGE_SECS).to_string(),
+        )
+        .body(Body::empty())
+        .unwrap())
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CallbackQ {
+    code: Option<String>,
+    state: Option<String>,
+    /// Set instead of `code` when the provider didn't log the user in.
+    error: Option<String>,
+}
+
+/// Finish logging in through the OpenID Connect provider.
+///
+/// The provider's identity is matched to an account it was logged in with before, or else to the
+/// account with its email if the provider verified it, linking the two. Without either, an account
+/// without a password is created if signups through the provider are enabled. Accounts with
+/// two-factor authentication can't log in this way, as it would bypass their second factor.
+#[utoipa::path(
+    get,
+    path = "/oauth/callback",
+    tag = "sessions",
+    params(CallbackQ),
+    responses(
+        (
+            status = 302,
+            description = "Success, redirect to the configured return URL. A session cookie \
+                named `FicAiSession` will be returned.",
+            headers(
+                ("Location" = String),
+                ("Set-Cookie" = String, description = "Includes session cookie."),
+            ),
+        ),
+        (
+            status = 400,
+            description = "Bad request, e.g. the state doesn't match, or the provider refused \
+                the login.",
+            body = ErrorWrap,
+        ),
+        (
+            status = 403,
+            description = "The email isn't verified by the provider, or has no account and \
+                signups are disabled, or the account has two-factor authentication enabled.",
+            body = ErrorWrap,
+        ),
+        (
+            status = 404,
+            description = "Logging in through a provider is not configured.",
+            body = ErrorWrap,
+        ),
+        (
+            status = 502,
+            description = "The provider could not be reached, or refused the code.",
+            body = ErrorWrap,
+        ),
+    ),
+)]
+pub async fn oauth_callback(
+    q: CallbackQ,
+    state: Option<String>,
+    client: Client,
+    pool: DB,
+    oauth: Option<&'static OAuth>,
+    policy: &SessionPolicy,
+) -> Result<Response<Body>, ApiError> {
+    let oauth = oauth.ok_or(ApiError::NotFound)?;
+    if let Some(error) = q.error {
+        return Err(ApiError::BadRequest(
+            format!("provider refused the login: {}", error).into(),
+        ));
+    }
+    match (&q.state, &state) {
+        (Some(q_state), Some(state)) if q_state == state => {}
+        _ => return Err(ApiError::BadRequest("login state mismatch".into())),
+    }
+    let code = q
+        .code
+        .ok_or_else(|| ApiError::BadRequest("missing code".into()))?;
+    let info = oauth.user_info(&code).await.map_err(|e| {
+        eprintln!("failed to log in with the oauth provider: {:?}", e);
+        ApiError::BadGateway
+    })?;
+
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to log in with oauth: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let linked = sqlx::query_scalar::<_, i64>(
+        "select account_id from oauth_identity where issuer = $1 and subject = $2",
+    )
+    .bind(&oauth.issuer)
+    .bind(&info.sub)
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(internal_error)?;
+    let account_id = match linked {
+        Some(account_id) => account_id,
+        None => {
+            // Emails the provider doesn't vouch for could be anyone's.
+            let email = match info.email {
+                Some(email) if info.email_verified => email,
+                _ => return Err(ApiError::Forbidden),
+            };
+            let existing = sqlx::query_scalar::<_, i64>(
+                "select id from account where email = $1 and deleted_at is null",
+            )
+            .bind(&email)
+            .fetch_optional(&mut tx)
+            .await
+            .map_err(internal_error)?;
+            let account_id = match existing {
+                Some(account_id) => account_id,
+                None if oauth.signup => {
+                    // Without a password, until one is set through a password reset.
+                    sqlx::query_scalar::<_, i64>(
+                        "
+insert into account (email, password_hash, created_ip)
+values ($1, '', $2::inet)
+returning id
+                        ",
+                    )
+                    .bind(&email)
+                    .bind(client.ip().map(|ip| ip.to_string()))
+                    .fetch_one(&mut tx)
+                    .await
+                    .map_err(internal_error)?
+                }
+                None => return Err(ApiError::Forbidden),
+            };
+            sqlx::query(
+                "insert into oauth_identity (issuer, subject, account_id) values ($1, $2, $3)",
+            )
+            .bind(&oauth.issuer)
+            .bind(&info.sub)
+            .bind(account_id)
+            .execute(&mut tx)
+            .await
+            .map_err(internal_error)?;
+            account_id
+        }
+    };
+    // Before committing, so that the identity isn't linked either.
+    if crate::totp::is_enabled(account_id, &mut tx)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err(ApiError::Forbidden);
+    }
+    tx.commit().await.map_err(internal_error)?;
+    crate::account_status::check(account_id, &pool).await?;
+    record_event(account_id, EventKind::OauthLogin, &client, &pool).await;
+
+    let session = AccountSession::create(account_id, &client, policy, &pool)
+        .await
+        .map_err(|e| {
+            eprintln!("{:?}", e);
+            ApiError::Internal
+        })?;
+    Ok(Response::builder()
+        .status(StatusCode::FOUND)
+        .header(LOCATION, &oauth.return_url)
+        .body(Body::empty())
+        .unwrap()
+        .tap_mut(|r| {
+            let headers = r.headers_mut();
+            for cookie in [
+                session.to_cookie(policy).to_string(),
+                state_cookie(String::new(), policy.domain, 0)
+                    .tap_mut(|c| c.make_removal())
+                    .to_string(),
+            ] {
+                if let Ok(value) = cookie.parse() {
+                    headers.append(SET_COOKIE, value);
+                }
+            }
+        }))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(oauth_login, oauth_callback))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let oauth_login = warp::path!("v1" / "oauth" / "login")
+        .and(warp::get())
+        .then(move || oauth_login(ctx.oauth, ctx.session_policy))
+        .and_then(reject);
+    let oauth_callback = warp::path!("v1" / "oauth" / "callback")
+        .and(warp::get())
+        .and(warp::query::<CallbackQ>())
+        .and(state_cookie_value())
+        .and(ctx.client())
+        .and(ctx.pool())
+        .then(move |q, state, client, pool| {
+            oauth_callback(q, state, client, pool, ctx.oauth, ctx.session_policy)
+        })
+        .and_then(reject);
+    cached(
+        CachePolicy::Never,
+        &[],
+        oauth_login.or(oauth_callback).map(Reply::into_response),
+    )
+    .boxed()
+}
@@ -0,0 +1,252 @@
+use std::sync::OnceLock;
+
+use eyre::{bail, eyre};
+use http::header::CONTENT_TYPE;
+use http::Response;
+use hyper::Body;
+use utoipa::openapi;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use warp::{reply::json, Filter, Reply};
+
+use crate::api_version::ApiVersion;
+use crate::context::Context;
+use crate::httputil::{cached, reject, ApiError, CachePolicy};
+use crate::routes::Routes;
+
+/// Loads Swagger UI from a CDN, so that the server needn't ship its assets. It offers the
+/// specification of every version, see [`ApiVersion::spec_path`].
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Fic.AI Signals API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-standalone-preset.js"></script>
+  <script>
+    SwaggerUIBundle({
+      urls: [
+        { url: "/openapi.json", name: "v1" },
+        { url: "/v2/openapi.json", name: "v2" },
+      ],
+      dom_id: "#swagger-ui",
+      presets: [SwaggerUIBundle.presets.apis, SwaggerUIStandalonePreset],
+      layout: "StandaloneLayout",
+    });
+  </script>
+</body>
+</html>
+"##;
+
+/// Documents the routes of a module, see e.g. [`crate::signal::Api`].
+type Doc = fn() -> openapi::OpenApi;
+
+/// What the routes of version 1 share. Each module documents its own routes and the types they
+/// take and answer with, see [`V1_MODULES`].
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Fic.AI Signals",
+        description = "Any route may answer with `429 Too Many Requests` once a client address \
+            made too many requests, telling when to try again in `Retry-After` and the limit in \
+            `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`.\n\n\
+            Any route may answer with `504 Gateway Timeout` if the request took longer than the \
+            server allows, e.g. waiting on fichub.\n\n\
+            Responses that may be cached say so in `Cache-Control` and carry an `ETag`. Sending it \
+            back in `If-None-Match` gets `304 Not Modified` with no body if the response would be \
+            the same."
+    ),
+    servers((url = "https://fic.ai/v1")),
+    modifiers(&SecuritySchemes)
+)]
+struct V1;
+
+/// The modules with routes of version 1, in the order of `routes::MODULES`.
+const V1_MODULES: &[Doc] = &[
+    crate::usermgmt::Api::openapi,
+    crate::display_name::Api::openapi,
+    crate::account_event::Api::openapi,
+    crate::account_stats::Api::openapi,
+    crate::account_export::Api::openapi,
+    crate::totp::Api::openapi,
+    crate::oauth::Api::openapi,
+    crate::invite::Api::openapi,
+    crate::api_token::Api::openapi,
+    crate::profile::Api::openapi,
+    crate::preferences::Api::openapi,
+    crate::signal::Api::openapi,
+    crate::tag_subscription::Api::openapi,
+    crate::tag_proposal::Api::openapi,
+    crate::tag_category_proposal::Api::openapi,
+    crate::tag_review::Api::openapi,
+    crate::tag_search::Api::openapi,
+    crate::tag_export::Api::openapi,
+    crate::tag_presentation::Api::openapi,
+    crate::tag_translation::Api::openapi,
+    crate::tag_stats::Api::openapi,
+    crate::tag::Api::openapi,
+    crate::fichub::Api::openapi,
+    crate::catalog::Api::openapi,
+    crate::fic_stats::Api::openapi,
+    crate::fic_update::Api::openapi,
+    crate::author::Api::openapi,
+    crate::series::Api::openapi,
+    crate::meta::Api::openapi,
+    crate::bex::Api::openapi,
+    crate::duplicates::Api::openapi,
+    crate::account_status::Api::openapi,
+    crate::admin::Api::openapi,
+    crate::write_limit::Api::openapi,
+    crate::service_account::Api::openapi,
+    crate::tag_implication::Api::openapi,
+];
+
+/// Like [`V1`], for version 2.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Fic.AI Signals",
+        description = "Version 2 of the API, for the routes whose responses changed since version \
+            1. Every other route is served by version 1 only, see `/openapi.json`, and version 1 \
+            stays as it is for the browser extension. Like in version 1, any route may answer with \
+            `429 Too Many Requests` once a client address made too many requests."
+    ),
+    servers((url = "https://fic.ai/v2")),
+    modifiers(&SecuritySchemes)
+)]
+struct V2;
+
+const V2_MODULES: &[Doc] = &[crate::v2::Api::openapi];
+
+/// How routes that need an account take it, see `security` of each route.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, doc: &mut openapi::OpenApi) {
+        let components = doc.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "cookieAuth",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("FicAiSession"))),
+        );
+        components.add_security_scheme(
+            "bearerAuth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some(
+                        "A personal API token, see `/tokens`, as \
+                        `Authorization: Bearer ficai_...`. Tokens with the `read` scope can read \
+                        signals, the feed and fic metadata; `write-signals` is needed to change \
+                        signals.",
+                    ))
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// The specification of `version`, put together from the modules that serve it.
+fn document(version: ApiVersion) -> openapi::OpenApi {
+    let (mut doc, modules) = match version {
+        ApiVersion::V1 => (V1::openapi(), V1_MODULES),
+        ApiVersion::V2 => (V2::openapi(), V2_MODULES),
+    };
+    for module in modules {
+        doc.merge(module());
+    }
+    doc
+}
+
+/// Fails on references to schemas that aren't in `spec`, e.g. of a type only named in a parameter.
+fn check_refs(spec: &serde_json::Value, value: &serde_json::Value) -> eyre::Result<()> {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let Some(serde_json::Value::String(reference)) = map.get("$ref") {
+                let name = reference
+                    .strip_prefix("#/components/schemas/")
+                    .ok_or_else(|| eyre!("unexpected reference {}", reference))?;
+                if spec["components"]["schemas"].get(name).is_none() {
+                    bail!("no schema {}", name);
+                }
+            }
+            map.values().try_for_each(|v| check_refs(spec, v))
+        }
+        serde_json::Value::Array(values) => values.iter().try_for_each(|v| check_refs(spec, v)),
+        _ => Ok(()),
+    }
+}
+
+/// The specifications of all versions as JSON, put together once.
+fn specs() -> eyre::Result<&'static [serde_json::Value]> {
+    static JSON: OnceLock<Vec<serde_json::Value>> = OnceLock::new();
+    if let Some(specs) = JSON.get() {
+        return Ok(specs);
+    }
+    let specs = ApiVersion::ALL
+        .iter()
+        .map(|v| {
+            let spec = serde_json::to_value(document(*v))?;
+            check_refs(&spec, &spec)?;
+            Ok(spec)
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    Ok(JSON.get_or_init(|| specs))
+}
+
+/// The specification of `version` as JSON.
+pub fn spec(version: ApiVersion) -> eyre::Result<&'static serde_json::Value> {
+    let i = ApiVersion::ALL.iter().position(|v| *v == version).unwrap();
+    Ok(&specs()?[i])
+}
+
+pub async fn get_openapi_json(version: ApiVersion) -> Result<Response<Body>, ApiError> {
+    // Parsed at startup already, see `main`.
+    let spec = spec(version).map_err(|_| ApiError::Internal)?;
+    Ok(json(spec).into_response())
+}
+
+pub async fn get_swagger_ui() -> Result<Response<Body>, ApiError> {
+    Ok(
+        warp::reply::with_header(SWAGGER_UI, CONTENT_TYPE, "text/html; charset=utf-8")
+            .into_response(),
+    )
+}
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_openapi_json_v1 = cached(
+        CachePolicy::Public(3600),
+        &[],
+        warp::path!("openapi.json")
+            .and(warp::get())
+            .then(|| get_openapi_json(ApiVersion::V1))
+            .and_then(reject),
+    );
+    let get_openapi_json_v2 = cached(
+        CachePolicy::Public(3600),
+        &[],
+        warp::path!("v2" / "openapi.json")
+            .and(warp::get())
+            .then(|| get_openapi_json(ApiVersion::V2))
+            .and_then(reject),
+    );
+    let get_swagger_ui = warp::path!("docs")
+        .and(warp::get())
+        .and_then(move || async move {
+            match ctx.swagger_ui {
+                true => Ok(()),
+                false => Err(warp::reject::not_found()),
+            }
+        })
+        .untuple_one()
+        .then(get_swagger_ui)
+        .and_then(reject);
+    get_openapi_json_v1
+        .or(get_openapi_json_v2)
+        .or(get_swagger_ui)
+        .map(Reply::into_response)
+        .boxed()
+}
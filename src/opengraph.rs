@@ -0,0 +1,169 @@
+use eyre::{eyre, WrapErr};
+use futures::future::BoxFuture;
+use futures::FutureExt as _;
+use http::header::CONTENT_TYPE;
+use sha2::{Digest, Sha256};
+
+use crate::fichub::Meta;
+use crate::metadata::{between, unescape, MetadataProvider, Unsupported};
+
+/// Where pages may be fetched from, and how much of them.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Hosts whose pages may be fetched, subdomains included, e.g. `fanfiction.net`.
+    pub allowed_hosts: Vec<String>,
+    /// For the whole page, redirects included.
+    pub timeout: std::time::Duration,
+    /// Pages are read up to this size, and given up on if they're larger.
+    pub max_bytes: usize,
+}
+
+fn allowed(allowed_hosts: &[String], url: &reqwest::Url) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host.to_lowercase(),
+        None => return false,
+    };
+    matches!(url.scheme(), "http" | "https")
+        && allowed_hosts.iter().any(|allowed| {
+            host == *allowed
+                || host
+                    .strip_suffix(allowed.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+}
+
+/// Reads the title a page declares for link previews (`og:title`), as a last resort for sites
+/// that other providers don't know.
+pub struct Client {
+    http: reqwest::Client,
+    options: Options,
+}
+
+impl Client {
+    pub fn new(mut options: Options) -> eyre::Result<Self> {
+        options.allowed_hosts = options
+            .allowed_hosts
+            .iter()
+            .map(|host| host.trim().to_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        let allowed_hosts = options.allowed_hosts.clone();
+        let http = reqwest::Client::builder()
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .timeout(options.timeout)
+            // Redirects must not lead off the allowlist.
+            .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= 5 {
+                    attempt.error("too many redirects")
+                } else if allowed(&allowed_hosts, attempt.url()) {
+                    attempt.follow()
+                } else {
+                    attempt.stop()
+                }
+            }))
+            .build()
+            .wrap_err("failed to build http client")?;
+        Ok(Self { http, options })
+    }
+
+    async fn page(&self, url: &str) -> eyre::Result<Meta> {
+        let mut response = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .wrap_err("failed to fetch page")?
+            .error_for_status()
+            .wrap_err("page answered with an error status")?;
+        if response.status().is_redirection() {
+            return Err(eyre!("{} redirects off the allowed hosts", url));
+        }
+        let is_html = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .is_some_and(|t| t.starts_with("text/html"));
+        if !is_html {
+            return Err(Unsupported("not an html page".to_string()).into());
+        }
+        let mut page = Vec::new();
+        while let Some(chunk) = response.chunk().await.wrap_err("failed to read page")? {
+            if page.len() + chunk.len() > self.options.max_bytes {
+                return Err(eyre!(
+                    "{} is larger than {} bytes",
+                    url,
+                    self.options.max_bytes
+                ));
+            }
+            page.extend_from_slice(&chunk);
+        }
+        let page = String::from_utf8_lossy(&page);
+        let title = meta_property(&page, "og:title")
+            .or_else(|| between(&page, "<title>", "</title>").map(|t| unescape(t.trim())))
+            .filter(|t| !t.is_empty())
+            .ok_or_else(|| Unsupported("no title on the page".to_string()))?;
+        let source = response.url().to_string();
+        Ok(Meta {
+            id: format!(
+                "page-{}",
+                hex::encode(&Sha256::digest(source.as_bytes())[..8])
+            ),
+            title,
+            source,
+            site: meta_property(&page, "og:site_name"),
+            author: None,
+            author_id: None,
+            series: None,
+            series_id: None,
+            series_position: None,
+            words: None,
+            chapters: None,
+            status: None,
+            fandoms: Vec::new(),
+            updated: None,
+        })
+    }
+}
+
+impl MetadataProvider for Client {
+    fn name(&self) -> &'static str {
+        "opengraph"
+    }
+
+    fn handles(&self, url: &str) -> bool {
+        reqwest::Url::parse(url).is_ok_and(|url| allowed(&self.options.allowed_hosts, &url))
+    }
+
+    fn meta<'a>(&'a self, url: &'a str) -> BoxFuture<'a, eyre::Result<Meta>> {
+        self.page(url).boxed()
+    }
+}
+
+/// The `content` of the first `<meta property="...">` tag for `property`.
+fn meta_property(page: &str, property: &str) -> Option<String> {
+    page.split("<meta").skip(1).find_map(|tag| {
+        let tag = &tag[..tag.find('>')?];
+        (attribute(tag, "property")? == property)
+            .then(|| attribute(tag, "content"))
+            .flatten()
+            .map(|content| unescape(content.trim()))
+    })
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    loop {
+        let at = rest.find(name)?;
+        let preceded_by_space = rest[..at].ends_with(char::is_whitespace);
+        rest = &rest[at + name.len()..];
+        let value = rest.trim_start().strip_prefix('=').map(str::trim_start);
+        if let (true, Some(value)) = (preceded_by_space, value) {
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            return value[1..].split(quote).next();
+        }
+    }
+}
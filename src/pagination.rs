@@ -0,0 +1,44 @@
+use base64ct::Encoding as _;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::httputil::ApiError;
+
+/// The `limit` a client asked for, clamped to `0..=max`, or `default` if it didn't ask.
+pub fn limit(limit: Option<i64>, default: i64, max: i64) -> i64 {
+    limit.unwrap_or(default).clamp(0, max)
+}
+
+/// Encodes a position in a list, e.g. the sort key and id of the last item of a page, as an
+/// opaque cursor. Clients are only meant to pass it back as `cursor`.
+pub fn encode<C: Serialize>(cursor: &C) -> String {
+    let raw = serde_json::to_vec(cursor).expect("cursors are plain data");
+    base64ct::Base64UrlUnpadded::encode_string(&raw)
+}
+
+/// Decodes the `cursor` a client passed, failing with [`ApiError::BadRequest`] if it isn't one
+/// that [`encode`] made for `C`.
+pub fn decode<C: DeserializeOwned>(cursor: Option<&str>) -> Result<Option<C>, ApiError> {
+    cursor
+        .map(|cursor| {
+            base64ct::Base64UrlUnpadded::decode_vec(cursor)
+                .ok()
+                .and_then(|raw| serde_json::from_slice(&raw).ok())
+                .ok_or_else(|| ApiError::BadRequest("invalid cursor".into()))
+        })
+        .transpose()
+}
+
+/// Cuts `items`, fetched with a limit of `limit + 1` to tell whether there are more, down to a
+/// page of `limit`. Returns the `nextCursor` of the page, made from its last item by `cursor`, or
+/// `None` on the last page.
+pub fn next_cursor<T, C: Serialize>(
+    items: &mut Vec<T>,
+    limit: i64,
+    cursor: impl FnOnce(&T) -> C,
+) -> Option<String> {
+    if items.len() as i64 <= limit {
+        return None;
+    }
+    items.truncate(limit as usize);
+    items.last().map(|item| encode(&cursor(item)))
+}
@@ -0,0 +1,153 @@
+use crate::httputil::ApiError;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Longer passwords are rejected rather than hashed, since hashing takes time proportional to
+/// their length.
+const MAX_LENGTH: usize = 1024;
+
+/// Compared with the password stripped of trailing digits and punctuation, case-insensitively.
+const COMMON_PASSWORDS: &[&str] = &[
+    "password",
+    "passw0rd",
+    "qwerty",
+    "qwertyuiop",
+    "asdfgh",
+    "letmein",
+    "welcome",
+    "iloveyou",
+    "admin",
+    "monkey",
+    "dragon",
+    "football",
+    "baseball",
+    "sunshine",
+    "princess",
+    "trustno",
+    "master",
+    "shadow",
+    "superman",
+    "starwars",
+    "whatever",
+    "fanfiction",
+    "fanfic",
+];
+
+/// Rules for new passwords, checked on signup, password change and reset. Strength is estimated
+/// as the number of guesses a brute force over the character classes used would take, not
+/// counting characters that repeat or continue a sequence, e.g. `aaaa` or `1234`.
+#[derive(Debug)]
+pub struct PasswordPolicy {
+    /// In characters.
+    pub min_length: usize,
+    /// In bits; 0 to only check the length.
+    pub min_strength_bits: f64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    TooShort,
+    TooLong,
+    TooWeak,
+    Common,
+    ContainsEmail,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = PasswordProblem)]
+pub struct Problem {
+    #[schema(inline)]
+    rule: Rule,
+    /// Human readable, suggesting how to fix the password.
+    message: String,
+}
+
+/// See [`PasswordPolicy`].
+fn strength_bits(password: &str) -> f64 {
+    let (mut lower, mut upper, mut digit, mut ascii, mut other) =
+        (false, false, false, false, false);
+    let mut effective_length = 0;
+    let mut previous: Option<char> = None;
+    for c in password.chars() {
+        match c {
+            'a'..='z' => lower = true,
+            'A'..='Z' => upper = true,
+            '0'..='9' => digit = true,
+            c if c.is_ascii() => ascii = true,
+            _ => other = true,
+        }
+        let continues = previous.is_some_and(|p| (c as i64 - p as i64).abs() <= 1);
+        if !continues {
+            effective_length += 1;
+        }
+        previous = Some(c);
+    }
+    let pool = [
+        (lower, 26),
+        (upper, 26),
+        (digit, 10),
+        (ascii, 33),
+        (other, 100),
+    ]
+    .into_iter()
+    .filter(|(used, _)| *used)
+    .map(|(_, size)| size)
+    .sum::<u32>();
+    if pool == 0 {
+        return 0.0;
+    }
+    effective_length as f64 * f64::from(pool).log2()
+}
+
+impl PasswordPolicy {
+    /// `email` is that of the account, if known.
+    pub fn problems(&self, password: &str, email: Option<&str>) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        let mut problem = |rule, message: String| problems.push(Problem { rule, message });
+
+        let length = password.chars().count();
+        if length < self.min_length {
+            problem(
+                Rule::TooShort,
+                format!("password is shorter than {} characters", self.min_length),
+            );
+        }
+        if length > MAX_LENGTH {
+            problem(
+                Rule::TooLong,
+                format!("password is longer than {} characters", MAX_LENGTH),
+            );
+        }
+        let lowercase = password.to_lowercase();
+        let stem =
+            lowercase.trim_end_matches(|c: char| c.is_ascii_digit() || c.is_ascii_punctuation());
+        if COMMON_PASSWORDS.contains(&stem) {
+            problem(Rule::Common, "password is too common".into());
+        } else if self.min_strength_bits > 0.0 && strength_bits(password) < self.min_strength_bits {
+            problem(
+                Rule::TooWeak,
+                "password is too easy to guess; make it longer or mix in other kinds of characters"
+                    .into(),
+            );
+        }
+        let local_part = email
+            .and_then(|e| e.split('@').next())
+            .map(str::to_lowercase)
+            .unwrap_or_default();
+        if local_part.chars().count() >= 3 && lowercase.contains(&local_part) {
+            problem(Rule::ContainsEmail, "password contains the email".into());
+        }
+        problems
+    }
+
+    pub fn check(&self, password: &str, email: Option<&str>) -> Result<(), ApiError> {
+        let problems = self.problems(password, email);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::WeakPassword(problems))
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use chrono_tz::Tz;
+use eyre::WrapErr;
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{json_body, reject, reply_json, ApiError, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::AccountSession;
+use crate::DB;
+
+/// Tags omitted from signal listings for an account. Tags are stored lowercased and blocking one
+/// blocks all its spellings.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockedTags {
+    tags: Vec<String>,
+}
+
+impl BlockedTags {
+    pub async fn get(uid: i64, pool: &DB) -> eyre::Result<Self> {
+        Ok(Self {
+            tags: sqlx::query_scalar::<_, String>(
+                "select tag from blocked_tag where account_id = $1 order by tag",
+            )
+            .bind(uid)
+            .fetch_all(pool)
+            .await?,
+        })
+    }
+
+    /// Replaces the account's blocked tags with `self`. Tags are stored in canonical form, so
+    /// blocking a tag also blocks its other spellings.
+    pub async fn set(&self, uid: i64, pool: &DB) -> eyre::Result<()> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("delete from blocked_tag where account_id = $1")
+            .bind(uid)
+            .execute(&mut tx)
+            .await?;
+        for tag in &self.tags {
+            sqlx::query(
+                "
+insert into blocked_tag (account_id, tag)
+values ($1, $2)
+on conflict do nothing
+                ",
+            )
+            .bind(uid)
+            .bind(crate::tag::canonicalize(tag))
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// A time zone preference.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TimezoneQ {
+    /// IANA time zone name.
+    #[schema(example = "Europe/Berlin")]
+    timezone: String,
+}
+
+/// Get the time zone used to format timestamps for the current account.
+#[utoipa::path(
+    get,
+    path = "/preferences/timezone",
+    tag = "preferences",
+    responses(
+        (status = 200, description = "Success.", body = TimezoneQ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_timezone(account: AccountSession) -> Result<Response<Body>, ApiError> {
+    Ok(json(&TimezoneQ {
+        timezone: account.timezone,
+    })
+    .into_response())
+}
+
+/// Set the time zone used to format timestamps for the current account.
+#[utoipa::path(
+    put,
+    path = "/preferences/timezone",
+    tag = "preferences",
+    request_body = TimezoneQ,
+    responses(
+        (status = 200, description = "Success.", body = TimezoneQ),
+        (
+            status = 400,
+            description = "Bad request, including an unknown time zone.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn put_timezone(
+    account: AccountSession,
+    q: TimezoneQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let tz: Tz = q
+        .timezone
+        .parse()
+        .map_err(|_| ApiError::BadRequest("unknown time zone".into()))?;
+    sqlx::query("update account set timezone = $2 where id = $1")
+        .bind(account.id)
+        .bind(tz.name())
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("failed to set time zone: {:?}", e);
+            ApiError::Internal
+        })?;
+    Ok(json(&TimezoneQ {
+        timezone: tz.name().to_string(),
+    })
+    .into_response())
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfilePrivacyQ {
+    /// Whether others see the account's stats on its profile, see `/users/{id}`. Off by default.
+    show_stats: bool,
+}
+
+/// Get what the current account's public profile shows.
+#[utoipa::path(
+    get,
+    path = "/preferences/profile",
+    tag = "preferences",
+    responses(
+        (status = 200, description = "Success.", body = ProfilePrivacyQ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_profile_privacy(
+    account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let show_stats =
+        sqlx::query_scalar::<_, bool>("select profile_stats_public from account where id = $1")
+            .bind(account.id)
+            .fetch_one(&pool)
+            .await
+            .map_err(|e| {
+                eprintln!("failed to get profile privacy: {:?}", e);
+                ApiError::Internal
+            })?;
+    Ok(json(&ProfilePrivacyQ { show_stats }).into_response())
+}
+
+/// Set what the current account's public profile shows.
+#[utoipa::path(
+    put,
+    path = "/preferences/profile",
+    tag = "preferences",
+    request_body = ProfilePrivacyQ,
+    responses(
+        (status = 200, description = "Success.", body = ProfilePrivacyQ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn put_profile_privacy(
+    account: AccountSession,
+    q: ProfilePrivacyQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    sqlx::query("update account set profile_stats_public = $2 where id = $1")
+        .bind(account.id)
+        .bind(q.show_stats)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("failed to set profile privacy: {:?}", e);
+            ApiError::Internal
+        })?;
+    Ok(json(&q).into_response())
+}
+
+/// How clients show fics with tags that carry a content warning.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum NsfwHandling {
+    #[default]
+    Show,
+    Blur,
+    Hide,
+}
+
+/// Settings the extension syncs across browsers. Kept as JSON on the account, so that adding one
+/// doesn't take a schema change; missing ones take their defaults.
+#[derive(Serialize, Deserialize, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SyncedPreferences {
+    /// Tags with fewer votes than this are hidden.
+    min_votes: u32,
+    #[schema(inline)]
+    nsfw: NsfwHandling,
+}
+
+/// Client settings synced across browsers.
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Preferences {
+    #[serde(flatten)]
+    synced: SyncedPreferences,
+    /// See `/preferences/blocked-tags`.
+    blocked_tags: Vec<String>,
+}
+
+impl Preferences {
+    pub(crate) async fn get(uid: i64, pool: &DB) -> eyre::Result<Self> {
+        let synced =
+            sqlx::query_scalar::<_, String>("select preferences::text from account where id = $1")
+                .bind(uid)
+                .fetch_one(pool)
+                .await?;
+        Ok(Self {
+            synced: serde_json::from_str(&synced)?,
+            blocked_tags: BlockedTags::get(uid, pool).await?.tags,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PutPreferencesQ {
+    #[serde(flatten)]
+    synced: SyncedPreferences,
+    /// Left as they are if omitted.
+    #[serde(default)]
+    blocked_tags: Option<Vec<String>>,
+}
+
+/// Get the current account's synced client settings.
+#[utoipa::path(
+    get,
+    path = "/preferences",
+    tag = "preferences",
+    responses(
+        (status = 200, description = "Success.", body = Preferences),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_preferences(
+    account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let preferences = Preferences::get(account.id, &pool).await.map_err(|e| {
+        eprintln!("failed to get preferences: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&preferences).into_response())
+}
+
+/// Replace the current account's synced client settings.
+///
+/// Settings that are omitted are reset to their defaults, except for `blockedTags`, which are left
+/// as they are.
+#[utoipa::path(
+    put,
+    path = "/preferences",
+    tag = "preferences",
+    request_body = PutPreferencesQ,
+    responses(
+        (status = 200, description = "Success.", body = Preferences),
+        (status = 400, description = "Bad request, e.g. a negative `minVotes`.", body = ErrorWrap),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn put_preferences(
+    account: AccountSession,
+    q: PutPreferencesQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let result = async {
+        sqlx::query("update account set preferences = $2::jsonb where id = $1")
+            .bind(account.id)
+            .bind(serde_json::to_string(&q.synced)?)
+            .execute(&pool)
+            .await?;
+        if let Some(tags) = q.blocked_tags {
+            BlockedTags { tags }.set(account.id, &pool).await?;
+        }
+        Preferences::get(account.id, &pool).await
+    }
+    .await;
+    match result {
+        Ok(preferences) => Ok(json(&preferences).into_response()),
+        Err(e) => {
+            eprintln!("failed to set preferences: {:?}", e);
+            Err(ApiError::Internal)
+        }
+    }
+}
+
+/// Get the tags the current account never wants to see.
+#[utoipa::path(
+    get,
+    path = "/preferences/blocked-tags",
+    tag = "preferences",
+    responses(
+        (status = 200, description = "Success.", body = BlockedTags),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+async fn get_blocked_tags(account: AccountSession, pool: DB) -> eyre::Result<BlockedTags> {
+    BlockedTags::get(account.id, &pool)
+        .await
+        .wrap_err("failed to get blocked tags")
+}
+
+/// Replace the tags the current account never wants to see.
+#[utoipa::path(
+    put,
+    path = "/preferences/blocked-tags",
+    tag = "preferences",
+    request_body = BlockedTags,
+    responses(
+        (status = 200, description = "Success.", body = BlockedTags),
+        (status = 400, description = "Bad request.", body = ErrorWrap),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+async fn put_blocked_tags(
+    account: AccountSession,
+    q: BlockedTags,
+    pool: DB,
+) -> eyre::Result<BlockedTags> {
+    q.set(account.id, &pool)
+        .await
+        .wrap_err("failed to set blocked tags")?;
+    BlockedTags::get(account.id, &pool)
+        .await
+        .wrap_err("failed to get blocked tags")
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_preferences,
+    put_preferences,
+    get_blocked_tags,
+    put_blocked_tags,
+    get_timezone,
+    put_timezone,
+    get_profile_privacy,
+    put_profile_privacy,
+))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_preferences = warp::path!("v1" / "preferences")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(get_preferences)
+        .and_then(reject);
+    let put_preferences = warp::path!("v1" / "preferences")
+        .and(warp::put())
+        .and(ctx.authenticate())
+        .and(json_body::<PutPreferencesQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(put_preferences)
+        .and_then(reject);
+    let get_blocked_tags = warp::path!("v1" / "preferences" / "blocked-tags")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(get_blocked_tags)
+        .and_then(reply_json);
+    let put_blocked_tags = warp::path!("v1" / "preferences" / "blocked-tags")
+        .and(warp::put())
+        .and(ctx.authenticate())
+        .and(json_body::<BlockedTags>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(put_blocked_tags)
+        .and_then(reply_json);
+    let get_timezone = warp::path!("v1" / "preferences" / "timezone")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .then(get_timezone)
+        .and_then(reject);
+    let put_timezone = warp::path!("v1" / "preferences" / "timezone")
+        .and(warp::put())
+        .and(ctx.authenticate())
+        .and(json_body::<TimezoneQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(put_timezone)
+        .and_then(reject);
+    let get_profile_privacy = warp::path!("v1" / "preferences" / "profile")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(get_profile_privacy)
+        .and_then(reject);
+    let put_profile_privacy = warp::path!("v1" / "preferences" / "profile")
+        .and(warp::put())
+        .and(ctx.authenticate())
+        .and(json_body::<ProfilePrivacyQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(put_profile_privacy)
+        .and_then(reject);
+    get_preferences
+        .or(put_preferences)
+        .or(get_blocked_tags)
+        .or(put_blocked_tags)
+        .or(get_timezone)
+        .or(put_timezone)
+        .or(get_profile_privacy)
+        .or(put_profile_privacy)
+        .map(Reply::into_response)
+        .boxed()
+}
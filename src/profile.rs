@@ -0,0 +1,151 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use http::Response;
+use hyper::Body;
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{cached, reject, ApiError, CachePolicy, ErrorWrap, Timestamp};
+use crate::routes::Routes;
+use crate::usermgmt::AccountSession;
+use crate::DB;
+
+const FAVORITE_TAGS: i64 = 5;
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FavoriteTag {
+    tag: String,
+    /// How many fics the account signalled it for.
+    fics: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileStats {
+    /// Fics the account signalled any tag for or against.
+    fics_tagged: i64,
+    /// The tags the account signalled for on the most fics, most first.
+    favorite_tags: Vec<FavoriteTag>,
+}
+
+/// What anyone may see of an account. Never includes its email.
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    id: i64,
+    display_name: Option<String>,
+    joined_at: Timestamp,
+    /// `null` unless the account opted into showing them, see `/preferences/profile`.
+    stats: Option<ProfileStats>,
+}
+
+impl Profile {
+    /// `None` for deleted accounts as well as unknown ones. Stats are always included for the
+    /// account itself, so that it can see what it would show.
+    async fn get(id: i64, viewer: Option<i64>, tz: Tz, pool: &DB) -> eyre::Result<Option<Self>> {
+        let row = sqlx::query_as::<_, (Option<String>, DateTime<Utc>, bool)>(
+            "
+select display_name, created_at, profile_stats_public
+from account
+where id = $1 and id <> 0 and deleted_at is null
+            ",
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+        let (display_name, created_at, stats_public) = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let stats = if stats_public || viewer == Some(id) {
+            let fics_tagged = sqlx::query_scalar::<_, i64>(
+                "select count(distinct url) from signal where account_id = $1",
+            )
+            .bind(id)
+            .fetch_one(pool)
+            .await?;
+            let favorite_tags = sqlx::query_as::<_, FavoriteTag>(
+                "
+select tag_canonical as tag, count(1) as fics
+from signal
+where account_id = $1 and signal
+group by tag_canonical
+order by fics desc, tag
+limit $2
+                ",
+            )
+            .bind(id)
+            .bind(FAVORITE_TAGS)
+            .fetch_all(pool)
+            .await?;
+            Some(ProfileStats {
+                fics_tagged,
+                favorite_tags,
+            })
+        } else {
+            None
+        };
+        Ok(Some(Self {
+            id,
+            display_name,
+            joined_at: Timestamp::new(created_at, tz),
+            stats,
+        }))
+    }
+}
+
+/// Get an account's public profile.
+///
+/// Never includes the account's email. Stats are only included if the account opted into showing
+/// them, or to the account itself.
+#[utoipa::path(
+    get,
+    path = "/users/{id}",
+    tag = "accounts",
+    params(
+        ("id" = i64, Path),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = Profile),
+        (status = 404, description = "No such account, or it was deleted.", body = ErrorWrap),
+    ),
+)]
+pub async fn get_profile(
+    id: i64,
+    account: Option<AccountSession>,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let tz = account.as_ref().map_or(Tz::UTC, |a| a.tz());
+    let profile = Profile::get(id, account.map(|a| a.id), tz, &pool)
+        .await
+        .map_err(|e| {
+            eprintln!("failed to get profile: {:?}", e);
+            ApiError::Internal
+        })?;
+    match profile {
+        Some(profile) => Ok(json(&profile).into_response()),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_profile))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_profile = warp::path!("v1" / "users" / i64)
+        .and(warp::get())
+        .and(ctx.optional_authenticate())
+        .and(ctx.pool())
+        .then(get_profile)
+        .and_then(reject);
+    cached(
+        CachePolicy::Never,
+        &[],
+        get_profile.map(Reply::into_response),
+    )
+    .boxed()
+}
@@ -0,0 +1,94 @@
+use std::convert::Infallible;
+
+use http::Response;
+use hyper::Body;
+use warp::filters::BoxedFilter;
+use warp::Filter;
+
+use crate::context::Context;
+use crate::httputil::recover;
+
+/// The routes of a module, boxed to keep the filter types (and compile times) manageable.
+pub type Routes = BoxedFilter<(Response<Body>,)>;
+
+/// Builds the routes of a module, see e.g. [`crate::signal::routes`].
+type Builder = fn(&'static Context) -> Routes;
+
+/// Every module that serves routes, in the order they're tried. Where paths overlap, the more
+/// specific route has to come first, e.g. `tag_search`'s `/v1/tags/search` before `tag`'s
+/// `/v1/tags/{tag}`.
+const MODULES: &[Builder] = &[
+    crate::usermgmt::routes,
+    crate::display_name::routes,
+    crate::account_event::routes,
+    crate::account_stats::routes,
+    crate::account_export::routes,
+    crate::totp::routes,
+    crate::oauth::routes,
+    crate::invite::routes,
+    crate::api_token::routes,
+    crate::profile::routes,
+    crate::preferences::routes,
+    crate::signal::routes,
+    crate::tag_subscription::routes,
+    crate::tag_proposal::routes,
+    crate::tag_category_proposal::routes,
+    crate::tag_review::routes,
+    crate::tag_search::routes,
+    crate::tag_export::routes,
+    crate::tag_presentation::routes,
+    crate::tag_translation::routes,
+    crate::tag_stats::routes,
+    crate::tag::routes,
+    crate::fichub::routes,
+    crate::catalog::routes,
+    crate::fic_stats::routes,
+    crate::fic_update::routes,
+    crate::author::routes,
+    crate::series::routes,
+    crate::meta::routes,
+    crate::metrics::routes,
+    crate::openapi::routes,
+    crate::bex::routes,
+    crate::duplicates::routes,
+    crate::account_status::routes,
+    crate::admin::routes,
+    crate::write_limit::routes,
+    crate::service_account::routes,
+    crate::tag_implication::routes,
+    crate::v2::routes,
+];
+
+/// The whole API: the routes of every module, behind the IP rate limit and the request timeout,
+/// with session cookies renewed, responses compressed and errors answered as JSON. CORS is up to
+/// the caller, as it depends on where the API is served from.
+pub fn routes(
+    ctx: &'static Context,
+) -> impl Filter<Extract = (Response<Body>,), Error = Infallible> + Clone {
+    let api = MODULES
+        .iter()
+        .map(|routes| routes(ctx))
+        .reduce(|api, routes| api.or(routes).unify().boxed())
+        .expect("there are modules");
+    let api = match ctx.request_timeout {
+        Some(timeout) => crate::timeout::timeout(timeout, api.recover(recover).unify()).boxed(),
+        None => api,
+    };
+    ctx.ip_limiter
+        .filter()
+        .and(crate::usermgmt::session_cookie_value())
+        .and(crate::compression::accept_encoding())
+        .and(api)
+        .then(move |cookie, encoding, response| async move {
+            let response =
+                crate::usermgmt::renew_session_cookie(cookie, response, ctx.session_policy);
+            match ctx.compression {
+                Some(min_bytes) => {
+                    crate::compression::compress(response, encoding, min_bytes).await
+                }
+                None => response,
+            }
+        })
+        .recover(recover)
+        .unify()
+}
@@ -0,0 +1,121 @@
+use http::Response;
+use hyper::Body;
+use serde::Serialize;
+use sqlx::{Postgres, Transaction};
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::fichub::{CachedMeta, Meta};
+use crate::httputil::{reject, ApiError, ErrorWrap, PercentDecoded};
+use crate::routes::Routes;
+use crate::signal::CombinedSignal;
+use crate::DB;
+
+/// Keeps the series of a fic that is about to be stored up to date. Like authors, series are
+/// only known by providers that give them an id.
+pub(crate) async fn upsert(tx: &mut Transaction<'_, Postgres>, meta: &Meta) -> eyre::Result<()> {
+    let (id, title) = match (&meta.series_id, &meta.series) {
+        (Some(id), Some(title)) => (id, title),
+        _ => return Ok(()),
+    };
+    sqlx::query(
+        "
+insert into series (id, title)
+values ($1, $2)
+on conflict (id) do update set title = excluded.title
+        ",
+    )
+    .bind(id)
+    .bind(title)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SeriesDetail {
+    id: String,
+    title: String,
+    /// The known fics of the series, in reading order.
+    fics: Vec<CachedMeta>,
+    /// The tags most signalled for across the series.
+    tags: Vec<CombinedSignal>,
+}
+
+const SERIES_TAGS_LIMIT: i64 = 20;
+
+impl SeriesDetail {
+    pub async fn get(id: &str, pool: &DB) -> eyre::Result<Option<Self>> {
+        let title = match sqlx::query_scalar::<_, String>("select title from series where id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?
+        {
+            Some(title) => title,
+            None => return Ok(None),
+        };
+        let fics = sqlx::query_as::<_, CachedMeta>(
+            "
+select
+    f.id, f.title, f.source, f.site, f.author, f.author_id, f.series, f.series_id,
+    f.series_position, f.words, f.chapters, f.status, f.fandoms, f.updated,
+    (select max(c.fetched_at) from fic_url_cache c where c.fic_id = f.id) as fetched_at
+from fic f
+where f.series_id = $1
+order by f.series_position nulls last, f.title, f.id
+            ",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+        let fic_ids = fics.iter().map(|f| f.meta.id.clone()).collect::<Vec<_>>();
+        Ok(Some(Self {
+            id: id.to_string(),
+            title,
+            fics,
+            tags: CombinedSignal::of_fics(&fic_ids, SERIES_TAGS_LIMIT, pool).await?,
+        }))
+    }
+}
+
+/// Get a series with its fics and the tags signalled on them.
+///
+/// Series are known once metadata of one of their fics was looked up, and list only the fics looked
+/// up so far. Signals on any URL of any fic of the series count, once per account.
+#[utoipa::path(
+    get,
+    path = "/series/{id}",
+    tag = "fics",
+    params(
+        ("id" = String, Path, description = "As in `seriesId` of fic metadata."),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = SeriesDetail),
+        (status = 404, description = "No such series is known.", body = ErrorWrap),
+    ),
+)]
+pub async fn get_series(id: String, pool: DB) -> Result<Response<Body>, ApiError> {
+    let detail = SeriesDetail::get(&id, &pool).await.map_err(|e| {
+        eprintln!("failed to get series: {:?}", e);
+        ApiError::Internal
+    })?;
+    match detail {
+        Some(detail) => Ok(json(&detail).into_response()),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_series))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_series = warp::path!("v1" / "series" / PercentDecoded)
+        .and(warp::get())
+        .and(ctx.pool())
+        .then(|id: PercentDecoded, pool| get_series(id.0, pool))
+        .and_then(reject);
+    get_series.map(Reply::into_response).boxed()
+}
@@ -0,0 +1,382 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::{DirBuilderExt as _, FileTypeExt as _, PermissionsExt as _};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+use eyre::{eyre, WrapErr};
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt as _, StreamExt as _};
+use hyper::server::accept;
+use hyper::service::{make_service_fn, service_fn, Service as _};
+use tokio::net::{TcpListener, TcpStream, UnixListener};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::time::Sleep;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use warp::{Filter, Reply};
+
+use crate::config::Config;
+use crate::timeout::RemoteAddr;
+use crate::DB;
+
+/// CORS for the configured origins, or `None` if there aren't any. Requests from other origins
+/// are turned away once there are, so the site's own origin and the extension's have to be
+/// listed as well.
+pub fn cors(cfg: &Config) -> eyre::Result<Option<warp::cors::Builder>> {
+    if cfg.cors_allowed_origins.is_empty() {
+        return Ok(None);
+    }
+    for origin in &cfg.cors_allowed_origins {
+        let uri = origin
+            .parse::<http::Uri>()
+            .wrap_err_with(|| format!("bad CORS origin {}", origin))?;
+        if uri.scheme().is_none() || uri.authority().is_none() || origin.ends_with('/') {
+            return Err(eyre!(
+                "CORS origin {} must be a scheme and host, e.g. https://fic.ai",
+                origin
+            ));
+        }
+    }
+    Ok(Some(
+        warp::cors()
+            .allow_origins(cfg.cors_allowed_origins.iter().map(String::as_str))
+            .allow_credentials(cfg.cors_allow_credentials)
+            .allow_headers(cfg.cors_allowed_headers.iter().map(String::as_str))
+            .allow_methods(["GET", "POST", "PUT", "PATCH", "DELETE"])
+            .expose_headers([
+                "etag",
+                "retry-after",
+                "ratelimit-limit",
+                "ratelimit-remaining",
+                "ratelimit-reset",
+            ])
+            .max_age(std::time::Duration::from_secs(3600)),
+    ))
+}
+
+/// A certificate chain and private key to serve HTTPS with, as read from their files.
+pub struct Tls {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    config: Arc<ServerConfig>,
+}
+
+impl Tls {
+    /// Reads the certificate chain and the key, checking that they go together.
+    fn load(cert_path: PathBuf, key_path: PathBuf) -> eyre::Result<Self> {
+        let cert = std::fs::read(&cert_path)
+            .wrap_err_with(|| format!("failed to read {}", cert_path.display()))?;
+        let key = std::fs::read(&key_path)
+            .wrap_err_with(|| format!("failed to read {}", key_path.display()))?;
+        let chain = pemfile::certs(&mut cert.as_slice())
+            .ok()
+            .filter(|chain| !chain.is_empty())
+            .ok_or_else(|| eyre!("{} is not a PEM certificate chain", cert_path.display()))?;
+        let mut keys = pemfile::pkcs8_private_keys(&mut key.as_slice()).unwrap_or_default();
+        if keys.is_empty() {
+            keys = pemfile::rsa_private_keys(&mut key.as_slice()).unwrap_or_default();
+        }
+        let private_key = keys.into_iter().next().ok_or_else(|| {
+            eyre!(
+                "{} is not a PEM PKCS#8 or RSA private key",
+                key_path.display()
+            )
+        })?;
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(chain, private_key)
+            .wrap_err("TLS certificate and key don't go together")?;
+        config.set_protocols(&["h2".into(), "http/1.1".into()]);
+        Ok(Tls {
+            cert_path,
+            key_path,
+            config: Arc::new(config),
+        })
+    }
+
+    fn reload(&self) -> eyre::Result<Self> {
+        Tls::load(self.cert_path.clone(), self.key_path.clone())
+    }
+}
+
+/// Where to serve the API: a TCP socket address, e.g. `127.0.0.1:8080`, or `unix:` followed by
+/// the path of a unix socket, e.g. `unix:/run/ficai/api.sock`.
+#[derive(Debug, Clone)]
+pub enum Listen {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for Listen {
+    type Err = std::net::AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) => Ok(Listen::Unix(path.into())),
+            None => s.parse().map(Listen::Tcp),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Listen {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Permissions of a file given in octal, e.g. `660`.
+#[derive(Debug, Clone, Copy)]
+pub struct FileMode(pub u32);
+
+impl FromStr for FileMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u32::from_str_radix(s, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .map(FileMode)
+            .ok_or("file mode must be given in octal, e.g. 660")
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for FileMode {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The certificate to serve HTTPS with, or `None` to serve plain HTTP.
+pub fn tls(cfg: &Config) -> eyre::Result<Option<Tls>> {
+    match (&cfg.tls_cert, &cfg.tls_key) {
+        (Some(_), Some(_)) if matches!(cfg.listen, Listen::Unix(_)) => {
+            Err(eyre!("TLS is only served on TCP, not on unix sockets"))
+        }
+        (Some(cert), Some(key)) => Ok(Some(Tls::load(cert.clone(), key.clone())?)),
+        (None, None) => Ok(None),
+        _ => Err(eyre!(
+            "FICAI_TLS_CERT and FICAI_TLS_KEY have to be given together"
+        )),
+    }
+}
+
+/// Serves until SIGTERM or SIGINT, then stops accepting connections and gives requests in flight
+/// `grace` to finish, and the database pool as long again to close. With `tls`, serves HTTPS.
+/// A unix socket gets `mode`, if given, and is removed again once done.
+pub async fn serve<F>(
+    routes: F,
+    listen: Listen,
+    mode: Option<FileMode>,
+    tls: Option<Tls>,
+    grace: std::time::Duration,
+    db: DB,
+) -> eyre::Result<()>
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let (signalled, mut on_signal) = watch::channel(());
+    let routes = routes.with(warp::log::custom(crate::metrics::record_request));
+    let mut server = match (&listen, tls) {
+        (Listen::Tcp(addr), Some(tls)) => serve_tls(routes, *addr, tls, on_signal)?.boxed(),
+        (Listen::Tcp(addr), None) => {
+            let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(*addr, async move {
+                let _ = on_signal.changed().await;
+            });
+            server.boxed()
+        }
+        (Listen::Unix(path), _) => {
+            let listener = bind_unix(path, mode)?;
+            let incoming = futures::stream::poll_fn(move |cx| {
+                listener
+                    .poll_accept(cx)
+                    .map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+            });
+            warp::serve(routes)
+                .serve_incoming_with_graceful_shutdown(incoming, async move {
+                    let _ = on_signal.changed().await;
+                })
+                .boxed()
+        }
+    };
+    tokio::select! {
+        _ = &mut server => {}
+        _ = shutdown_signal() => {
+            println!("shutting down, finishing requests in flight");
+            let _ = signalled.send(());
+            if tokio::time::timeout(grace, &mut server).await.is_err() {
+                eprintln!("requests still in flight after {:?}, dropping them", grace);
+            }
+        }
+    }
+    if let Listen::Unix(path) = &listen {
+        let _ = std::fs::remove_file(path);
+    }
+    if tokio::time::timeout(grace, db.close()).await.is_err() {
+        eprintln!("database connections still in use, not waiting for them");
+    }
+    Ok(())
+}
+
+/// Binds a unix socket at `path`, replacing one left behind by a previous run, but not one that
+/// a running server still accepts connections on.
+///
+/// The socket is bound in a directory only this process can enter and moved into place once it
+/// has `mode`, so that nobody gets to connect before.
+fn bind_unix(path: &Path, mode: Option<FileMode>) -> eyre::Result<UnixListener> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(eyre!("{} exists and is not a socket", path.display()));
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(eyre!("{} is in use by another server", path.display()));
+        }
+    }
+    let dir = path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(format!(".ficai.{}", std::process::id()));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+    let private = dir.join("s");
+    let bound = (|| {
+        let listener = UnixListener::bind(&private)
+            .wrap_err_with(|| format!("failed to bind to {}", private.display()))?;
+        if let Some(FileMode(mode)) = mode {
+            std::fs::set_permissions(&private, std::fs::Permissions::from_mode(mode))
+                .wrap_err_with(|| format!("failed to set the mode of {}", private.display()))?;
+        }
+        std::fs::rename(&private, path)
+            .wrap_err_with(|| format!("failed to move the socket to {}", path.display()))?;
+        Ok(listener)
+    })();
+    let _ = std::fs::remove_dir_all(&dir);
+    bound
+}
+
+/// How long clients get to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TLS handshakes in progress at once. Further connections wait in the listen backlog.
+const MAX_TLS_HANDSHAKES: usize = 256;
+
+/// Serves HTTPS until `on_signal` changes. On SIGHUP, the certificate is read again and used for
+/// the connections accepted from then on, while those already open keep theirs. If it fails to
+/// load, the current one is kept.
+///
+/// Connections are served by hyper rather than warp, which can't swap the certificate of a running
+/// server, so the client's address is handed to the routes like [`crate::timeout`] does.
+fn serve_tls<F>(
+    routes: F,
+    listen: SocketAddr,
+    mut tls: Tls,
+    mut on_signal: watch::Receiver<()>,
+) -> eyre::Result<impl Future<Output = ()>>
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let listener = std::net::TcpListener::bind(listen)
+        .and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)
+        })
+        .wrap_err_with(|| format!("failed to bind to {}", listen))?;
+    let mut hangup = signal(SignalKind::hangup()).wrap_err("failed to listen for SIGHUP")?;
+    let mut acceptor = TlsAcceptor::from(tls.config.clone());
+    let mut handshakes = FuturesUnordered::new();
+    let mut pause: Option<Pin<Box<Sleep>>> = None;
+    let incoming = futures::stream::poll_fn(move |cx| {
+        while let Poll::Ready(Some(())) = hangup.poll_recv(cx) {
+            match tls.reload() {
+                Ok(reloaded) => {
+                    tls = reloaded;
+                    acceptor = TlsAcceptor::from(tls.config.clone());
+                    println!("reloaded TLS certificate");
+                }
+                Err(e) => eprintln!(
+                    "failed to reload TLS certificate, keeping the current one: {:?}",
+                    e
+                ),
+            }
+        }
+        if pause
+            .as_mut()
+            .is_some_and(|pause| pause.as_mut().poll(cx).is_ready())
+        {
+            pause = None;
+        }
+        loop {
+            while pause.is_none() && handshakes.len() < MAX_TLS_HANDSHAKES {
+                match listener.poll_accept(cx) {
+                    Poll::Ready(Ok((stream, _))) => handshakes.push(tokio::time::timeout(
+                        TLS_HANDSHAKE_TIMEOUT,
+                        acceptor.accept(stream),
+                    )),
+                    Poll::Ready(Err(e)) => {
+                        // E.g. out of file descriptors, so give connections a moment to close, as
+                        // hyper does.
+                        eprintln!("failed to accept a connection: {:?}", e);
+                        let mut sleep = Box::pin(tokio::time::sleep(Duration::from_secs(1)));
+                        let _ = sleep.as_mut().poll(cx);
+                        pause = Some(sleep);
+                    }
+                    Poll::Pending => break,
+                }
+            }
+            match handshakes.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(Ok(stream)))) => {
+                    return Poll::Ready(Some(Ok::<_, io::Error>(stream)))
+                }
+                // The client's problem, e.g. it doesn't trust the certificate or is too slow.
+                Poll::Ready(Some(_)) => continue,
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    });
+    let service = warp::service(routes);
+    let make_service = make_service_fn(move |stream: &TlsStream<TcpStream>| {
+        let remote = stream.get_ref().0.peer_addr().ok();
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request| {
+                if let Some(remote) = remote {
+                    request.extensions_mut().insert(RemoteAddr(remote));
+                }
+                service.clone().call(request)
+            }))
+        }
+    });
+    let server = hyper::Server::builder(accept::from_stream(incoming))
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            let _ = on_signal.changed().await;
+        });
+    Ok(async move {
+        if let Err(e) = server.await {
+            eprintln!("HTTPS server failed: {:?}", e);
+        }
+    })
+}
+
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
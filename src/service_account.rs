@@ -0,0 +1,267 @@
+use http::{Response, StatusCode};
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use tap::prelude::*;
+use utoipa::{OpenApi, ToSchema};
+use warp::{
+    reply::{json, with_status},
+    Filter, Reply,
+};
+
+use crate::api_token::{CreateTokenQ, CreatedToken, Tokens};
+use crate::context::Context;
+use crate::httputil::{json_body, reject, ApiError, Empty, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, Role, CONSTRAINT_VIOLATION_SQLSTATE};
+use crate::DB;
+
+/// People log in; service accounts, for bots and community tools, only ever use API tokens that
+/// admins issue to them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "account_kind", rename_all = "lowercase")]
+pub enum AccountKind {
+    Person,
+    Service,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateServiceAccountQ {
+    /// Required, so that others can tell what's behind the account's signals.
+    display_name: String,
+    /// The account's first token.
+    token: CreateTokenQ,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceAccount {
+    id: i64,
+    display_name: String,
+    token: CreatedToken,
+}
+
+/// Create a service account for a bot or community tool, with its first API token.
+///
+/// Requires the admin role. Service accounts can't log in, reset their password or get magic links;
+/// they only ever use the tokens issued to them here, whose scopes bound what they can do. The
+/// token itself is only returned here.
+#[utoipa::path(
+    post,
+    path = "/admin/service-accounts",
+    tag = "admin",
+    request_body = CreateServiceAccountQ,
+    responses(
+        (status = 201, description = "Created.", body = ServiceAccount),
+        (
+            status = 400,
+            description = "Bad request, e.g. an invalid display name or no scopes.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 409, description = "Display name taken.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn create_service_account(
+    _account: AccountSession,
+    q: CreateServiceAccountQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    crate::display_name::validate(&q.display_name).map_err(|e| ApiError::BadRequest(e.into()))?;
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to create service account: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let id = sqlx::query_scalar::<_, i64>(
+        "
+insert into account (id, email, display_name, password_hash, kind)
+select id, 'service:' || id, $1, '', 'service'
+from (select nextval('account_id_seq') as id) new
+returning id
+        ",
+    )
+    .bind(&q.display_name)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(db_err)
+            if db_err.code() == Some(CONSTRAINT_VIOLATION_SQLSTATE.into()) =>
+        {
+            ApiError::DisplayNameTaken
+        }
+        e => internal_error(e),
+    })?;
+    let token = crate::api_token::issue(&mut tx, id, &q.token).await?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(json(&ServiceAccount {
+        id,
+        display_name: q.display_name,
+        token,
+    })
+    .pipe(|r| with_status(r, StatusCode::CREATED))
+    .into_response())
+}
+
+/// Fails with [`ApiError::NotFound`] unless `account_id` is a service account that wasn't
+/// deleted.
+async fn check(account_id: i64, pool: &DB) -> Result<(), ApiError> {
+    let found = sqlx::query_scalar::<_, i64>(
+        "select id from account where id = $1 and kind = 'service' and deleted_at is null",
+    )
+    .bind(account_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to look up service account: {:?}", e);
+        ApiError::Internal
+    })?;
+    found.map(|_| ()).ok_or(ApiError::NotFound)
+}
+
+/// List a service account's API tokens. Requires the admin role.
+#[utoipa::path(
+    get,
+    path = "/admin/service-accounts/{accountId}/tokens",
+    tag = "admin",
+    params(
+        ("accountId" = i64, Path),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = Tokens),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 404, description = "No such service account.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_service_account_tokens(
+    account_id: i64,
+    _account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    check(account_id, &pool).await?;
+    Ok(json(&crate::api_token::list(account_id, &pool).await?).into_response())
+}
+
+/// Issue another API token to a service account, e.g. to rotate one. Requires the admin role.
+///
+/// The token itself is only returned here. Accounts can have up to 20 tokens.
+#[utoipa::path(
+    post,
+    path = "/admin/service-accounts/{accountId}/tokens",
+    tag = "admin",
+    params(
+        ("accountId" = i64, Path),
+    ),
+    request_body = CreateTokenQ,
+    responses(
+        (status = 201, description = "Created.", body = CreatedToken),
+        (
+            status = 400,
+            description = "Bad request, e.g. no scopes, or too many tokens.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 404, description = "No such service account.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn create_service_account_token(
+    account_id: i64,
+    _account: AccountSession,
+    q: CreateTokenQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to create service account token: {:?}", e);
+        ApiError::Internal
+    };
+    check(account_id, &pool).await?;
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let token = crate::api_token::issue(&mut tx, account_id, &q).await?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(json(&token)
+        .pipe(|r| with_status(r, StatusCode::CREATED))
+        .into_response())
+}
+
+/// Revoke a service account's API token. Requires the admin role.
+#[utoipa::path(
+    delete,
+    path = "/admin/service-accounts/{accountId}/tokens/{id}",
+    tag = "admin",
+    params(
+        ("accountId" = i64, Path),
+        ("id" = i64, Path),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (
+            status = 404,
+            description = "No such service account, or it has no such token.",
+            body = ErrorWrap,
+        ),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn delete_service_account_token(
+    account_id: i64,
+    token_id: i64,
+    _account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    check(account_id, &pool).await?;
+    crate::api_token::revoke(account_id, token_id, &pool).await
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    create_service_account,
+    get_service_account_tokens,
+    create_service_account_token,
+    delete_service_account_token,
+))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let create_service_account = warp::path!("v1" / "admin" / "service-accounts")
+        .and(warp::post())
+        .and(ctx.require_role(Role::Admin))
+        .and(json_body::<CreateServiceAccountQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(create_service_account)
+        .and_then(reject);
+    let get_service_account_tokens =
+        warp::path!("v1" / "admin" / "service-accounts" / i64 / "tokens")
+            .and(warp::get())
+            .and(ctx.require_role(Role::Admin))
+            .and(ctx.pool())
+            .then(get_service_account_tokens)
+            .and_then(reject);
+    let create_service_account_token =
+        warp::path!("v1" / "admin" / "service-accounts" / i64 / "tokens")
+            .and(warp::post())
+            .and(ctx.require_role(Role::Admin))
+            .and(json_body::<crate::api_token::CreateTokenQ>(
+                ctx.max_body_bytes,
+            ))
+            .and(ctx.pool())
+            .then(create_service_account_token)
+            .and_then(reject);
+    let delete_service_account_token =
+        warp::path!("v1" / "admin" / "service-accounts" / i64 / "tokens" / i64)
+            .and(warp::delete())
+            .and(ctx.require_role(Role::Admin))
+            .and(ctx.pool())
+            .then(delete_service_account_token)
+            .and_then(reject);
+    create_service_account
+        .or(get_service_account_tokens)
+        .or(create_service_account_token)
+        .or(delete_service_account_token)
+        .map(Reply::into_response)
+        .boxed()
+}
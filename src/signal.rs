@@ -1,70 +1,673 @@
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use eyre::{eyre, WrapErr};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+use utoipa::openapi::path::{Parameter, ParameterBuilder, ParameterIn, ParameterStyle};
+use utoipa::{IntoParams, OpenApi, PartialSchema as _, ToSchema};
+use warp::{Filter, Rejection, Reply};
 
+use crate::api_token::Scope;
+use crate::canonical_url;
+use crate::context::Context;
+use crate::fichub::CachedMeta;
+use crate::httputil::{
+    accept_language, comma_separated, json_body, query_list, reject, reply_json, AcceptLanguage,
+    ApiError, Empty, ErrorWrap, PercentDecoded,
+};
+use crate::routes::Routes;
+use crate::tag::{canonicalize, TagName, CATEGORY_FANDOM};
+use crate::tag_presentation::WarningSeverity;
+use crate::usermgmt::AccountSession;
 use crate::DB;
 
-#[derive(Serialize, Debug, sqlx::FromRow)]
+/// The account that signals on behalf of the server rather than a user, see `schema.sql`.
+pub const SYSTEM_ACCOUNT_ID: i64 = 0;
+
+/// Prefix of the tags derived from the fandoms in fic metadata.
+const FANDOM_TAG_PREFIX: &str = "fandom:";
+
+/// A tag's signals on a fic as v1 answers with them, see `v2::Signal` for v2.
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Signal {
+    pub(crate) tag: String,
+    /// The tag's label in the client's preferred language. Omitted if it hasn't been translated
+    /// to any of the languages in `Accept-Language`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) label: Option<String>,
+    /// The current account's signal, if any.
+    pub(crate) signal: Option<bool>,
+    /// How many accounts signalled for the tag.
+    pub(crate) signals_for: i64,
+    /// How many accounts signalled against the tag.
+    pub(crate) signals_against: i64,
+    /// Whether the server signalled for the tag itself, e.g. `fandom:` tags for the fandoms in the
+    /// fic's metadata. That signal is counted in `signalsFor`.
+    pub(crate) automatic: bool,
+    /// How clients should render the tag, see `TagPresentation`. Omitted if not set, as are
+    /// `icon` and `warning`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) icon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) warning: Option<WarningSeverity>,
+    /// Only in v2.
+    #[serde(skip)]
+    pub(crate) first_signalled_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub(crate) last_signalled_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Signals {
+    pub(crate) signals: Vec<Signal>,
+    /// Only with `include=meta`, and only if the fic's metadata is cached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<CachedMeta>,
+}
+
+/// A tag's signals across several fics at once, e.g. all fics of an author.
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CombinedSignal {
     tag: String,
-    signal: Option<bool>,
     signals_for: i64,
     signals_against: i64,
+    /// How many of the fics have signals for or against the tag.
+    fic_count: i64,
 }
 
-#[derive(Serialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct Signals {
-    signals: Vec<Signal>,
+impl CombinedSignal {
+    /// The `limit` tags most signalled for on any URL of the fics, see `fic_url`. Accounts count
+    /// once per tag, however many of the fics they signalled on.
+    pub async fn of_fics(fic_ids: &[String], limit: i64, pool: &DB) -> eyre::Result<Vec<Self>> {
+        Ok(sqlx::query_as::<_, Self>(
+            "
+with fic_signal as (
+    select s.account_id, s.tag, s.tag_canonical, s.signal, u.fic_id
+    from fic f
+    join fic_url u
+        on u.fic_id = coalesce(f.canonical_id, f.id)
+    join signal s
+        on s.url = u.url
+    where f.id = any($1)
+)
+select
+    mode() within group (order by tag) as tag,
+    count(distinct account_id) filter (where signal) as signals_for,
+    count(distinct account_id) filter (where not signal) as signals_against,
+    count(distinct fic_id) as fic_count
+from fic_signal
+group by tag_canonical
+order by signals_for desc, fic_count desc, tag_canonical
+limit $2
+            ",
+        )
+        .bind(fic_ids)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?)
+    }
 }
 
 impl Signal {
+    /// Which of `tags` are in use already, by their canonical names.
+    pub(crate) async fn known_tags(tags: &[String], pool: &DB) -> eyre::Result<Vec<String>> {
+        let tags = tags.iter().map(|t| canonicalize(t)).collect::<Vec<_>>();
+        Ok(sqlx::query_scalar::<_, String>(
+            "
+select t
+from unnest($1::text[]) t
+where exists(select 1 from signal where tag_canonical = t)
+    or exists(select 1 from tag_meta where tag = t)
+            ",
+        )
+        .bind(tags)
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// A tag that has never been seen before is queued for review by moderators. Signalling for a
+    /// tag also signals for the tags it implies. Signals on chapters of a fic are stored for the
+    /// fic, see `canonical_url::fold`.
     pub async fn set(uid: i64, url: &str, tag: &str, signal: bool, pool: &DB) -> eyre::Result<()> {
+        let url = &canonical_url::fold(url);
+        let tag = TagName::resolve(tag, pool).await?;
+        let mut tx = pool.begin().await?;
+        let is_known = sqlx::query_scalar::<_, bool>(
+            "
+select exists(select 1 from signal where tag_canonical = $1)
+    or exists(select 1 from tag_meta where tag = $1)
+            ",
+        )
+        .bind(&tag.canonical)
+        .fetch_one(&mut tx)
+        .await?;
         sqlx::query(
             "
-insert into signal (account_id, url, tag, signal)
-values ($1, $2, $3, $4)
-on conflict (account_id, url, tag) do update set signal = $4
+insert into signal (account_id, url, tag, tag_canonical, signal)
+values ($1, $2, $3, $4, $5)
+on conflict (account_id, url, tag_canonical) do update set signal = $5, updated_at = now()
             ",
         )
         .bind(uid)
         .bind(url)
-        .bind(tag)
+        .bind(&tag.display)
+        .bind(&tag.canonical)
         .bind(signal)
-        .execute(pool)
+        .execute(&mut tx)
         .await?;
+        if signal {
+            crate::tag_implication::apply(&mut tx, uid, url, &tag.canonical).await?;
+        }
+        if !is_known {
+            sqlx::query(
+                "
+insert into tag_review_queue (tag, display, account_id, url)
+values ($1, $2, $3, $4)
+on conflict (tag) do nothing
+                ",
+            )
+            .bind(&tag.canonical)
+            .bind(&tag.display)
+            .bind(uid)
+            .bind(url)
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Signals for a `fandom:` tag per fandom listed in a fic's metadata, as the system account,
+    /// and withdraws those for fandoms it no longer lists. New fandom tags skip review and are
+    /// categorized right away.
+    pub(crate) async fn set_fandoms(
+        tx: &mut Transaction<'_, Postgres>,
+        url: &str,
+        fandoms: &[String],
+    ) -> eyre::Result<()> {
+        let mut tags = Vec::with_capacity(fandoms.len());
+        for fandom in fandoms {
+            tags.push(
+                TagName::resolve(&format!("{}{}", FANDOM_TAG_PREFIX, fandom), &mut *tx).await?,
+            );
+        }
+        let canonical = tags.iter().map(|t| t.canonical.clone()).collect::<Vec<_>>();
+        sqlx::query(
+            "
+delete from signal
+where account_id = $1 and url = $2 and tag_canonical like $3 || '%' and tag_canonical <> all($4)
+            ",
+        )
+        .bind(SYSTEM_ACCOUNT_ID)
+        .bind(url)
+        .bind(FANDOM_TAG_PREFIX)
+        .bind(&canonical)
+        .execute(&mut *tx)
+        .await?;
+        for tag in &tags {
+            sqlx::query(
+                "
+insert into signal (account_id, url, tag, tag_canonical, signal)
+values ($1, $2, $3, $4, true)
+on conflict (account_id, url, tag_canonical) do nothing
+                ",
+            )
+            .bind(SYSTEM_ACCOUNT_ID)
+            .bind(url)
+            .bind(&tag.display)
+            .bind(&tag.canonical)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                "
+insert into tag_meta (tag, category)
+values ($1, $2)
+on conflict (tag) do update set category = coalesce(tag_meta.category, excluded.category)
+                ",
+            )
+            .bind(&tag.canonical)
+            .bind(CATEGORY_FANDOM)
+            .execute(&mut *tx)
+            .await?;
+        }
         Ok(())
     }
 
     pub async fn erase(uid: i64, url: &str, tag: &str, pool: &DB) -> eyre::Result<()> {
-        sqlx::query("delete from signal where account_id = $1 and url = $2 and tag = $3")
+        let url = &canonical_url::fold(url);
+        let tag = TagName::resolve(tag, pool).await?;
+        sqlx::query("delete from signal where account_id = $1 and url = $2 and tag_canonical = $3")
             .bind(uid)
             .bind(url)
-            .bind(tag)
+            .bind(&tag.canonical)
             .execute(pool)
             .await?;
         Ok(())
     }
 }
 
+/// Which fic signals are asked for: by any of its URLs, or by its id in `fic`.
+#[derive(Debug, Clone)]
+pub enum FicRef {
+    Url(String),
+    Id(String),
+}
+
+/// Restricts signals to tags of some categories and/or leaves out tags of others. Uncategorized
+/// tags are only left out when categories are included explicitly.
+#[derive(Debug, Default)]
+pub struct CategoryFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+/// Documents the repeatable parameters that [`query_list`] reads, as `IntoParams` can't derive
+/// them.
+impl IntoParams for CategoryFilter {
+    fn into_params(_: impl Fn() -> Option<ParameterIn>) -> Vec<Parameter> {
+        [
+            (
+                "includeCategory",
+                "Only return tags of this category. May be repeated to include several \
+                categories; uncategorized tags are left out.",
+            ),
+            (
+                "excludeCategory",
+                "Leave out tags of this category. May be repeated.",
+            ),
+        ]
+        .into_iter()
+        .map(|(name, description)| {
+            ParameterBuilder::new()
+                .name(name)
+                .parameter_in(ParameterIn::Query)
+                .description(Some(description))
+                .schema(Some(Vec::<String>::schema()))
+                .style(Some(ParameterStyle::Form))
+                .explode(Some(true))
+                .build()
+        })
+        .collect()
+    }
+}
+
 impl Signals {
-    pub async fn get(uid: Option<i64>, url: String, pool: &DB) -> eyre::Result<Self> {
+    /// Signals on other copies of the same fic, as linked in `fic_url`, count as well.
+    pub async fn get(
+        uid: Option<i64>,
+        fic: &FicRef,
+        langs: &AcceptLanguage,
+        categories: &CategoryFilter,
+        pool: &DB,
+    ) -> eyre::Result<Self> {
+        let (url, fic_id) = match fic {
+            FicRef::Url(url) => (Some(canonical_url::fold(url)), None),
+            FicRef::Id(id) => (None, Some(id)),
+        };
         Ok(Self {
             signals: sqlx::query_as::<_, Signal>(
                 "
+with target as (
+    select fic_id
+    from fic_url
+    where url = $2
+    union
+    select coalesce(canonical_id, id)
+    from fic
+    where id = $6
+),
+urls as (
+    select $2::text as url
+    where $2 is not null
+    union
+    select url
+    from fic_url
+    where fic_id in (select fic_id from target)
+)
 select
-    tag,
-    sum(case when signal then 1 else 0 end) as signals_for,
-    sum(case when signal then 0 else 1 end) as signals_against,
-    bool_or(signal) filter (where account_id = $1) as signal
-from signal
-where url = $2
-group by tag
+    mode() within group (order by s.tag) as tag,
+    -- An account may have signalled the same tag on several copies of the fic.
+    count(distinct s.account_id) filter (where s.signal) as signals_for,
+    count(distinct s.account_id) filter (where not s.signal) as signals_against,
+    bool_or(s.signal) filter (where s.account_id = $1) as signal,
+    bool_or(s.signal and s.account_id = $7) as automatic,
+    (
+        select t.label
+        from tag_translation t
+        where t.tag = s.tag_canonical and t.locale = any($3)
+        order by array_position($3, t.locale)
+        limit 1
+    ) as label,
+    m.color,
+    m.icon,
+    m.warning,
+    min(s.created_at) as first_signalled_at,
+    max(s.updated_at) as last_signalled_at
+from signal s
+left join tag_meta m
+    on m.tag = s.tag_canonical
+where s.url in (select url from urls)
+    and s.tag_canonical not in (select tag from blocked_tag where account_id = $1)
+    and (cardinality($4::text[]) = 0 or m.category = any($4))
+    and (m.category is null or m.category <> all($5))
+group by s.tag_canonical, m.tag
     ",
             )
             .bind(uid)
             .bind(url)
+            .bind(&langs.0)
+            .bind(&categories.include)
+            .bind(&categories.exclude)
+            .bind(fic_id)
+            .bind(SYSTEM_ACCOUNT_ID)
             .fetch_all(pool)
             .await?,
+            meta: None,
+        })
+    }
+}
+
+/// Exactly one of `url` and `fic_id` is given.
+#[derive(Deserialize, Debug, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct GetSignalsQ {
+    /// The URL of the fic to retrieve signals for. URLs of chapters, pages and posts of a fic on
+    /// AO3, FanFiction.net, FictionPress and XenForo forums such as SpaceBattles are treated like
+    /// the URL of the fic itself. Either this or `ficId` is required.
+    url: Option<String>,
+    /// The id of the fic to retrieve signals for, as in `id` of fic metadata, instead of `url`.
+    /// Unknown ids have no signals.
+    fic_id: Option<String>,
+    /// From the repeatable `includeCategory` and `excludeCategory` parameters.
+    #[serde(skip)]
+    categories: CategoryFilter,
+    /// Comma-separated list of what else to answer with. `meta` adds the fic's metadata if it is
+    /// cached; it is never looked up for this.
+    #[serde(default, deserialize_with = "comma_separated")]
+    #[param(inline, style = Form, explode = false)]
+    include: Vec<SignalsInclude>,
+}
+
+/// What else `GET v1/signals` can answer with.
+#[derive(Deserialize, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum SignalsInclude {
+    /// Cached metadata of the fic, if any. Never waits for metadata to be looked up.
+    Meta,
+}
+
+/// Get signals for a fic.
+///
+/// Tags blocked by the current account are omitted. Signals on other URLs of the same fic, e.g.
+/// copies on other sites, count as well once their metadata has been looked up; each account
+/// counts at most once per tag.
+#[utoipa::path(
+    get,
+    path = "/signals",
+    tag = "signals",
+    params(GetSignalsQ, CategoryFilter, AcceptLanguage),
+    responses(
+        (status = 200, description = "Expected response to a valid request.", body = Signals),
+        (status = 400, description = "Bad request.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = []), ("bearerAuth" = []), ()),
+)]
+pub async fn get_signals(
+    account: Option<AccountSession>,
+    q: GetSignalsQ,
+    langs: AcceptLanguage,
+    pool: DB,
+) -> eyre::Result<Signals> {
+    let fic = match (q.url, q.fic_id) {
+        (Some(url), _) => FicRef::Url(url),
+        (None, Some(id)) => FicRef::Id(id),
+        (None, None) => return Err(eyre!("neither url nor fic id given")),
+    };
+    let mut signals = Signals::get(account.map(|a| a.id), &fic, &langs, &q.categories, &pool)
+        .await
+        .wrap_err("failed to get signals")?;
+    if q.include.contains(&SignalsInclude::Meta) {
+        signals.meta = match &fic {
+            FicRef::Url(url) => crate::fichub::cached(url, &pool).await,
+            FicRef::Id(id) => crate::fichub::cached_fic(id, &pool).await,
+        }
+        .wrap_err("failed to get cached fic metadata")?;
+    }
+    Ok(signals)
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchSignalsQ {
+    /// The fic to update. Like for `GET /signals`, URLs of chapters are treated like the URL of
+    /// the fic itself.
+    url: String,
+    /// Tags to signal for.
+    #[serde(default)]
+    add: Vec<String>,
+    /// Tags to signal against.
+    #[serde(default)]
+    rm: Vec<String>,
+    /// Tags to withdraw the signal on, whether it was for or against.
+    #[serde(default)]
+    erase: Vec<String>,
+}
+
+/// Update signals for a fic.
+#[utoipa::path(
+    patch,
+    path = "/signals",
+    tag = "signals",
+    request_body = PatchSignalsQ,
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (
+            status = 400,
+            description = "Bad request. If any tag violates the tag policy, nothing is changed \
+                and `violations` lists every problem. Removed tags that are in use already aren't \
+                checked.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (
+            status = 413,
+            description = "The request body is larger than the configured limit, which applies to \
+                every JSON request body.",
+            body = ErrorWrap,
+        ),
+        (
+            status = 422,
+            description = "The URL doesn't point to a fic, e.g. it's a search page, a user profile \
+                or a page of a site that isn't known to host fics. Nothing is changed. Only \
+                checked when adding or removing tags; erasing signals works on any URL.",
+            body = ErrorWrap,
+        ),
+        (
+            status = 429,
+            description = "The account wrote too many signals lately. Each tag added, removed or \
+                erased counts as one. How many it may write per minute depends on its rate limit \
+                tier; requests with more than that are refused with 400.",
+            body = ErrorWrap,
+            headers((
+                "Retry-After" = u64,
+                description = "Seconds until the account can write again."
+            )),
+        ),
+    ),
+    security(("cookieAuth" = []), ("bearerAuth" = [])),
+)]
+pub async fn patch_signals(
+    account: AccountSession,
+    q: PatchSignalsQ,
+    pool: DB,
+) -> eyre::Result<Empty> {
+    for tag in q.add {
+        println!("add {}", &tag);
+        Signal::set(account.id, &q.url, &tag, true, &pool)
+            .await
+            .wrap_err("failed to add signal")?
+    }
+
+    for tag in q.rm {
+        println!("rm {}", &tag);
+        Signal::set(account.id, &q.url, &tag, false, &pool)
+            .await
+            .wrap_err("failed to rm signal")?
+    }
+
+    for tag in q.erase {
+        println!("erase {}", &tag);
+        Signal::erase(account.id, &q.url, &tag, &pool)
+            .await
+            .wrap_err("failed to erase signal")?
+    }
+
+    println!();
+    Ok(Empty {})
+}
+
+fn get_signals_q() -> impl Filter<Extract = (GetSignalsQ,), Error = Rejection> + Clone {
+    warp::query::<GetSignalsQ>()
+        .and(query_list("includeCategory"))
+        .and(query_list("excludeCategory"))
+        .map(|mut q: GetSignalsQ, include, exclude| {
+            q.categories = CategoryFilter { include, exclude };
+            q
         })
+}
+
+/// The arguments of [`get_signals`] for `GET signals`, shared by the versions, which differ in
+/// their responses only.
+pub fn get_signals_args(
+    ctx: &'static Context,
+) -> impl Filter<
+    Extract = (Option<AccountSession>, GetSignalsQ, AcceptLanguage, DB),
+    Error = Rejection,
+> + Clone {
+    ctx.optional_authenticate_scoped(Scope::Read)
+        .and(
+            get_signals_q()
+                .then(|q: GetSignalsQ| async move {
+                    match (&q.url, &q.fic_id) {
+                        (Some(_), None) | (None, Some(_)) => Ok(q),
+                        // Like any other query without a url.
+                        (None, None) => Err(ApiError::BadRequest("bad request query".into())),
+                        (Some(_), Some(_)) => Err(ApiError::BadRequest(
+                            "only one of url and ficId can be given".into(),
+                        )),
+                    }
+                })
+                .and_then(reject),
+        )
+        .and(accept_language())
+        .and(ctx.pool())
+}
+
+/// Get signals for a fic by its id.
+///
+/// Like `GET /signals`, for clients that already know the fic's id. Signals on any URL of the fic
+/// whose metadata has been looked up count, as well as those of copies of the fic on other sites.
+#[utoipa::path(
+    get,
+    path = "/fics/{id}/signals",
+    tag = "signals",
+    params(
+        ("id" = String, Path, description = "As in `id` of fic metadata."),
+        CategoryFilter,
+        (
+            "include" = inline(Option<Vec<SignalsInclude>>),
+            Query,
+            description = "Comma-separated list of what else to answer with. `meta` adds the \
+                fic's metadata if it is cached; it is never looked up for this.",
+            style = Form,
+            explode = false,
+        ),
+        AcceptLanguage,
+    ),
+    responses(
+        (status = 200, description = "Expected response to a valid request.", body = Signals),
+        (status = 400, description = "Bad request.", body = ErrorWrap),
+        (status = 404, description = "No fic with this id is known.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = []), ("bearerAuth" = []), ()),
+)]
+async fn get_fic_signals(
+    id: PercentDecoded,
+    account: Option<AccountSession>,
+    mut q: GetSignalsQ,
+    langs: AcceptLanguage,
+    pool: DB,
+) -> Result<Signals, ApiError> {
+    let exists = crate::fichub::fic_exists(&id.0, &pool).await.map_err(|e| {
+        eprintln!("failed to look up fic: {:?}", e);
+        ApiError::Internal
+    })?;
+    if !exists {
+        return Err(ApiError::NotFound);
     }
+    q.url = None;
+    q.fic_id = Some(id.0);
+    Ok(get_signals(account, q, langs, pool).await?)
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_signals, get_fic_signals, patch_signals))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_signals_v1 = warp::path!("v1" / "signals")
+        .and(warp::get())
+        .and(get_signals_args(ctx))
+        .then(get_signals)
+        .and_then(reply_json);
+    let get_fic_signals = warp::path!("v1" / "fics" / PercentDecoded / "signals")
+        .and(warp::get())
+        .and(ctx.optional_authenticate_scoped(Scope::Read))
+        .and(get_signals_q())
+        .and(accept_language())
+        .and(ctx.pool())
+        .then(get_fic_signals)
+        .and_then(reply_json);
+    let patch_signals = warp::path!("v1" / "signals")
+        .and(warp::patch())
+        .and(ctx.authenticate_scoped(Scope::WriteSignals))
+        .and(json_body::<PatchSignalsQ>(ctx.max_body_bytes).and_then(
+            move |q: PatchSignalsQ| async move {
+                // Signalling against a tag in use is fine whatever its name, so that tags stored
+                // before the policy can still be voted down.
+                let known = Signal::known_tags(&q.rm, &ctx.db)
+                    .await
+                    .map_err(ApiError::from)?;
+                let removed = q.rm.iter().filter(|t| !known.contains(&canonicalize(t)));
+                let tags = q.add.iter().chain(removed).map(String::as_str);
+                ctx.tag_policy.check(tags, false)?;
+                // Erasing signals cleans up, so it's fine on any URL.
+                if !q.add.is_empty() || !q.rm.is_empty() {
+                    ctx.url_policy.check(&q.url).await?;
+                }
+                Ok::<_, Rejection>(q)
+            },
+        ))
+        .and_then(
+            move |account: AccountSession, q: PatchSignalsQ| async move {
+                // Each tag counts, lest batching them get around the limit.
+                let writes = q.add.len() + q.rm.len() + q.erase.len();
+                ctx.write_limiter.check(&account, writes)?;
+                Ok::<_, Rejection>((account, q))
+            },
+        )
+        .untuple_one()
+        .and(ctx.pool())
+        .then(patch_signals)
+        .and_then(reply_json);
+    get_signals_v1
+        .or(get_fic_signals)
+        .or(patch_signals)
+        .map(Reply::into_response)
+        .boxed()
 }
@@ -0,0 +1,1536 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use eyre::WrapErr;
+use http::header::LOCATION;
+use http::{Response, StatusCode};
+use hyper::Body;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{Postgres, Row as _, Transaction};
+use tap::prelude::*;
+use unicode_normalization::UnicodeNormalization;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{
+    reply::{json, with_header, with_status},
+    Filter, Reply,
+};
+
+use crate::context::Context;
+use crate::fichub::{CachedMeta, FicStatus};
+use crate::httputil::{
+    accept_language, cached, json_body, query_list, reject, reply_json, AcceptLanguage, ApiError,
+    CachePolicy, ErrorWrap, PercentDecoded, TimeWindow, Timestamp,
+};
+use crate::pagination;
+use crate::routes::Routes;
+use crate::tag_policy::TagPolicy;
+use crate::tag_presentation::WarningSeverity;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+/// Returns the form of a tag that signals are grouped by, so that e.g. "Fluff" and "fluff" count
+/// as the same tag: lowercased and NFC-normalized. Tables keyed by tag (metadata, aliases,
+/// tombstones, blocked tags) store this form as well.
+pub fn canonicalize(tag: &str) -> String {
+    tag.to_lowercase().nfc().collect()
+}
+
+/// A tag as given by a client, with aliases and translated labels resolved.
+#[derive(Debug)]
+pub struct TagName {
+    /// How the tag is displayed; for an alias, the most common display form of its target.
+    pub display: String,
+    pub canonical: String,
+}
+
+impl TagName {
+    /// A translated label only stands in for its tag as long as no tag of that name is in use.
+    pub async fn resolve<'e, E>(tag: &str, executor: E) -> eyre::Result<Self>
+    where
+        E: sqlx::Executor<'e, Database = Postgres>,
+    {
+        let canonical = canonicalize(tag);
+        let target = sqlx::query_as::<_, (String, Option<String>)>(
+            "
+select
+    target,
+    (select mode() within group (order by s.tag) from signal s where s.tag_canonical = target)
+from (
+    select 0 as priority, tag as target
+    from tag_alias
+    where alias = $1
+    union all
+    select 1, tag
+    from tag_translation
+    where label_canonical = $1
+        and not exists (select 1 from signal where tag_canonical = $1)
+) t
+order by priority, target
+limit 1
+            ",
+        )
+        .bind(&canonical)
+        .fetch_optional(executor)
+        .await?;
+        Ok(match target {
+            Some((canonical, display)) => Self {
+                display: display.unwrap_or_else(|| canonical.clone()),
+                canonical,
+            },
+            None => Self {
+                display: tag.to_string(),
+                canonical,
+            },
+        })
+    }
+}
+
+/// Description and usage statistics of a tag.
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TagDetail {
+    /// The most common of the spellings that only differ in case or Unicode normalization.
+    tag: String,
+    /// Human readable explanation of what the tag means.
+    description: Option<String>,
+    category: Option<String>,
+    /// As `#rrggbb`, lowercased.
+    color: Option<String>,
+    /// Name of an icon from the client's icon set.
+    icon: Option<String>,
+    warning: Option<WarningSeverity>,
+    /// Signals on the tag, for and against.
+    usage_count: i64,
+    /// Fics that have signals on the tag.
+    url_count: i64,
+    /// When the earliest existing signal on the tag was created.
+    first_used_at: Option<DateTime<Utc>>,
+    /// When the most recent signal on the tag was created or changed.
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+impl TagDetail {
+    /// Returns `None` if the tag has neither been used in a signal nor been given any metadata.
+    /// Spellings that differ only in case or normalization count as the same tag.
+    pub async fn get(tag: &str, pool: &DB) -> eyre::Result<Option<Self>> {
+        Ok(sqlx::query_as::<_, Self>(
+            "
+select
+    coalesce(s.display, $1) as tag,
+    m.description,
+    m.category,
+    m.color,
+    m.icon,
+    m.warning,
+    s.usage_count,
+    s.url_count,
+    s.first_used_at,
+    s.last_used_at
+from (
+    select
+        mode() within group (order by tag) as display,
+        count(1) as usage_count,
+        count(distinct url) as url_count,
+        min(created_at) as first_used_at,
+        max(updated_at) as last_used_at
+    from signal
+    where tag_canonical = $2
+) s
+left join tag_meta m
+    on m.tag = $2
+where s.usage_count > 0 or m.tag is not null
+            ",
+        )
+        .bind(tag)
+        .bind(canonicalize(tag))
+        .fetch_optional(pool)
+        .await?)
+    }
+}
+
+/// A tag that no longer exists.
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagTombstone)]
+pub struct Tombstone {
+    tag: String,
+    /// The tag that replaced this one, or `None` if it was deleted outright.
+    successor: Option<String>,
+    created_at: DateTime<Utc>,
+    /// When lookups of the tag will start answering "not found".
+    expires_at: DateTime<Utc>,
+}
+
+impl Tombstone {
+    pub async fn get(tag: &str, pool: &DB) -> eyre::Result<Option<Self>> {
+        Ok(sqlx::query_as::<_, Self>(
+            "
+select tag, successor, created_at, expires_at
+from tag_tombstone
+where tag = $1 and expires_at > now()
+            ",
+        )
+        .bind(canonicalize(tag))
+        .fetch_optional(pool)
+        .await?)
+    }
+}
+
+/// Leaves a tombstone for `tag`, which was just renamed or merged into `successor` (or deleted,
+/// if `None`), so that lookups of the old name keep resolving for `ttl_days`.
+pub(crate) async fn bury(
+    tx: &mut Transaction<'_, Postgres>,
+    tag: &str,
+    successor: Option<&str>,
+    ttl_days: i32,
+) -> eyre::Result<()> {
+    let tag = canonicalize(tag);
+    sqlx::query("delete from tag_tombstone where tag = $1 or expires_at <= now()")
+        .bind(&tag)
+        .execute(&mut *tx)
+        .await?;
+    if let Some(successor) = successor {
+        // Keep older tombstones pointing at the live tag rather than at another tombstone.
+        sqlx::query(
+            "
+update tag_tombstone set successor = $2, successor_canonical = $3
+where successor_canonical = $1
+            ",
+        )
+        .bind(&tag)
+        .bind(successor)
+        .bind(canonicalize(successor))
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query(
+        "
+insert into tag_tombstone (tag, successor, successor_canonical, expires_at)
+values ($1, $2, $3, now() + $4 * interval '1 day')
+        ",
+    )
+    .bind(&tag)
+    .bind(successor)
+    .bind(successor.map(canonicalize))
+    .bind(ttl_days)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// Get details about a single tag.
+///
+/// A tag that was recently renamed or merged away answers with `308 Permanent Redirect` to its
+/// successor, one that was recently deleted with `410 Gone`. How long these tombstones are kept is
+/// configurable.
+#[utoipa::path(
+    get,
+    path = "/tags/{tag}",
+    tag = "tags",
+    params(
+        ("tag" = String, Path, description = "The name of the tag, percent-encoded."),
+    ),
+    responses(
+        (status = 200, description = "Tag details.", body = TagDetail),
+        (
+            status = 308,
+            description = "The tag was renamed or merged into another one.",
+            body = Tombstone,
+            headers(("Location" = String, description = "Path of the successor tag.")),
+        ),
+        (
+            status = 404,
+            description = "The tag has never been used and has no metadata.",
+            body = ErrorWrap,
+        ),
+        (status = 410, description = "The tag was deleted.", body = Tombstone),
+    ),
+)]
+pub async fn get_tag(tag: String, pool: DB) -> Result<Response<Body>, ApiError> {
+    let internal_error = |e: eyre::Report| {
+        eprintln!("{:?}", e);
+        ApiError::Internal
+    };
+    if let Some(detail) = TagDetail::get(&tag, &pool).await.map_err(internal_error)? {
+        return Ok(json(&detail).into_response());
+    }
+    let tombstone = match Tombstone::get(&tag, &pool).await.map_err(internal_error)? {
+        Some(tombstone) => tombstone,
+        None => return Err(ApiError::NotFound),
+    };
+    Ok(match &tombstone.successor {
+        Some(successor) => {
+            let location = format!(
+                "/v1/tags/{}",
+                utf8_percent_encode(successor, NON_ALPHANUMERIC)
+            );
+            json(&tombstone)
+                .pipe(|r| with_status(r, StatusCode::PERMANENT_REDIRECT))
+                .pipe(|r| with_header(r, LOCATION, location))
+                .into_response()
+        }
+        None => json(&tombstone)
+            .pipe(|r| with_status(r, StatusCode::GONE))
+            .into_response(),
+    })
+}
+
+/// Outcome of moving signals from one tag to another.
+#[derive(Serialize, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagMigration)]
+pub struct Migrated {
+    /// Signals that were moved over to the target tag as-is.
+    signals_moved: u64,
+    /// Signals that were folded into an existing signal of the same account on the same fic.
+    signals_merged: u64,
+}
+
+impl Migrated {
+    pub fn signals_affected(&self) -> u64 {
+        self.signals_moved + self.signals_merged
+    }
+}
+
+/// Moves every signal on tag `from` over to tag `to`.
+///
+/// Where an account has signals on both tags for the same fic, the one that was changed most
+/// recently wins and the other is dropped. Metadata of `from` is carried over only if `to` has
+/// none of its own, and its aliases are pointed at `to`. If both only differ in spelling, just the
+/// display form is changed.
+async fn migrate(
+    tx: &mut Transaction<'_, Postgres>,
+    from: &str,
+    to: &str,
+) -> eyre::Result<Migrated> {
+    let (from_canonical, to_canonical) = (canonicalize(from), canonicalize(to));
+    if from_canonical == to_canonical {
+        let signals_moved =
+            sqlx::query("update signal set tag = $2 where tag_canonical = $1 and tag <> $2")
+                .bind(&from_canonical)
+                .bind(to)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        return Ok(Migrated {
+            signals_moved,
+            signals_merged: 0,
+        });
+    }
+
+    sqlx::query(
+        "
+update signal t
+set signal = s.signal, updated_at = s.updated_at
+from signal s
+where s.tag_canonical = $1 and t.tag_canonical = $2
+    and s.account_id = t.account_id and s.url = t.url
+    and s.updated_at > t.updated_at
+        ",
+    )
+    .bind(&from_canonical)
+    .bind(&to_canonical)
+    .execute(&mut *tx)
+    .await?;
+    let signals_merged = sqlx::query(
+        "
+delete from signal s
+using signal t
+where s.tag_canonical = $1 and t.tag_canonical = $2
+    and s.account_id = t.account_id and s.url = t.url
+        ",
+    )
+    .bind(&from_canonical)
+    .bind(&to_canonical)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    let signals_moved =
+        sqlx::query("update signal set tag = $2, tag_canonical = $3 where tag_canonical = $1")
+            .bind(&from_canonical)
+            .bind(to)
+            .bind(&to_canonical)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+    sqlx::query(
+        "
+update tag_meta set tag = $2
+where tag = $1 and not exists (select 1 from tag_meta where tag = $2)
+        ",
+    )
+    .bind(&from_canonical)
+    .bind(&to_canonical)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("delete from tag_meta where tag = $1")
+        .bind(&from_canonical)
+        .execute(&mut *tx)
+        .await?;
+    for table in ["tag_category_proposal", "tag_review_queue"] {
+        sqlx::query(&format!("delete from {} where tag = $1", table))
+            .bind(&from_canonical)
+            .execute(&mut *tx)
+            .await?;
+    }
+    crate::tag_subscription::migrate(tx, from, to).await?;
+    crate::tag_translation::migrate(tx, from, to).await?;
+    sqlx::query("update tag_alias set tag = $2 where tag = $1")
+        .bind(&from_canonical)
+        .bind(&to_canonical)
+        .execute(&mut *tx)
+        .await?;
+    // `to` may have been one of those aliases, and is a tag of its own now.
+    sqlx::query("delete from tag_alias where alias = $1 and tag = $1")
+        .bind(&to_canonical)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(Migrated {
+        signals_moved,
+        signals_merged,
+    })
+}
+
+async fn record_history(
+    tx: &mut Transaction<'_, Postgres>,
+    action: &str,
+    from: &str,
+    to: Option<&str>,
+    account_id: i64,
+    migrated: &Migrated,
+) -> eyre::Result<()> {
+    sqlx::query(
+        "
+insert into tag_history (action, from_tag, to_tag, account_id, signals_moved, signals_merged)
+values ($1, $2, $3, $4, $5, $6)
+        ",
+    )
+    .bind(action)
+    .bind(from)
+    .bind(to)
+    .bind(account_id)
+    .bind(migrated.signals_moved as i64)
+    .bind(migrated.signals_merged as i64)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+pub(crate) fn validate_migration(from: &str, to: &str) -> Result<(), ApiError> {
+    if to.is_empty() {
+        return Err(ApiError::BadRequest("empty target tag".into()));
+    }
+    if from == to {
+        return Err(ApiError::BadRequest(
+            "source and target tag are the same".into(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameTagQ {
+    /// Current name of the tag.
+    from: String,
+    /// New name of the tag. May be an existing tag, in which case the two are merged.
+    to: String,
+}
+
+/// Renames `from` to `to`, recording it in the tag history. Returns `None` if `from` has no
+/// signals.
+pub(crate) async fn rename_in(
+    tx: &mut Transaction<'_, Postgres>,
+    account_id: i64,
+    from: &str,
+    to: &str,
+    tombstone_days: i32,
+) -> eyre::Result<Option<Migrated>> {
+    let migrated = migrate(tx, from, to).await?;
+    if migrated.signals_moved == 0 && migrated.signals_merged == 0 {
+        return Ok(None);
+    }
+    record_history(tx, "rename", from, Some(to), account_id, &migrated).await?;
+    if canonicalize(from) != canonicalize(to) {
+        bury(tx, from, Some(to), tombstone_days).await?;
+    }
+    Ok(Some(migrated))
+}
+
+/// Rename a tag, migrating all existing signals. Requires the `tag-curation` permission.
+#[utoipa::path(
+    post,
+    path = "/tags/rename",
+    tag = "tags",
+    request_body = RenameTagQ,
+    responses(
+        (status = 200, description = "Success.", body = Migrated),
+        (status = 400, description = "Bad request.", body = ErrorWrap),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 404, description = "The source tag has no signals.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn rename_tag(
+    account: AccountSession,
+    q: RenameTagQ,
+    pool: DB,
+    tombstone_days: i32,
+    policy: &TagPolicy,
+) -> Result<Response<Body>, ApiError> {
+    validate_migration(&q.from, &q.to)?;
+    policy.check([q.to.as_str()], true)?;
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let migrated = rename_in(&mut tx, account.id, &q.from, &q.to, tombstone_days).await?;
+        tx.commit().await?;
+        eyre::Result::<_>::Ok(migrated)
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to rename tag: {:?}", e);
+        ApiError::Internal
+    })?;
+    match result {
+        Some(migrated) => Ok(json(&migrated).into_response()),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeTagsQ {
+    /// The tag that will be merged away and turned into an alias.
+    from: String,
+    /// The surviving tag. If this is an alias, the tag it points to is used.
+    into: String,
+}
+
+/// Merges tag `from` into tag `into` and turns `from` into an alias of `into`, so that any
+/// signals later added under the old name end up on the surviving tag.
+pub(crate) async fn merge_in(
+    tx: &mut Transaction<'_, Postgres>,
+    account_id: i64,
+    from: &str,
+    into: &str,
+    tombstone_days: i32,
+) -> eyre::Result<Result<Migrated, ApiError>> {
+    let from_canonical = canonicalize(from);
+    let is_alias =
+        sqlx::query_scalar::<_, bool>("select exists(select 1 from tag_alias where alias = $1)")
+            .bind(&from_canonical)
+            .fetch_one(&mut *tx)
+            .await?;
+    if is_alias {
+        return Ok(Err(ApiError::BadRequest(
+            "source tag is already an alias".into(),
+        )));
+    }
+    if canonicalize(into) == from_canonical {
+        return Ok(Err(ApiError::BadRequest(
+            "source and target tag are the same".into(),
+        )));
+    }
+    let into = TagName::resolve(into, &mut *tx).await?;
+    if into.canonical == from_canonical {
+        return Ok(Err(ApiError::BadRequest(
+            "target tag is an alias of the source tag".into(),
+        )));
+    }
+
+    let migrated = migrate(tx, from, &into.display).await?;
+    sqlx::query(
+        "
+insert into tag_alias (alias, tag)
+values ($1, $2)
+on conflict (alias) do update set tag = excluded.tag
+        ",
+    )
+    .bind(&from_canonical)
+    .bind(&into.canonical)
+    .execute(&mut *tx)
+    .await?;
+    record_history(
+        tx,
+        "merge",
+        from,
+        Some(&into.display),
+        account_id,
+        &migrated,
+    )
+    .await?;
+    bury(tx, from, Some(&into.display), tombstone_days).await?;
+    Ok(Ok(migrated))
+}
+
+/// Merge a tag into another one. Requires the `tag-curation` permission.
+///
+/// All signals are moved to the surviving tag. Where an account has signals on both tags for the
+/// same fic, the most recently changed of the two is kept. The merged tag becomes an alias, so
+/// signals added under its name later are stored on the surviving tag.
+#[utoipa::path(
+    post,
+    path = "/tags/merge",
+    tag = "tags",
+    request_body = MergeTagsQ,
+    responses(
+        (status = 200, description = "Success.", body = Migrated),
+        (status = 400, description = "Bad request.", body = ErrorWrap),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn merge_tags(
+    account: AccountSession,
+    q: MergeTagsQ,
+    pool: DB,
+    tombstone_days: i32,
+    policy: &TagPolicy,
+) -> Result<Response<Body>, ApiError> {
+    validate_migration(&q.from, &q.into)?;
+    policy.check([q.into.as_str()], true)?;
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let migrated = merge_in(&mut tx, account.id, &q.from, &q.into, tombstone_days).await?;
+        if migrated.is_ok() {
+            tx.commit().await?;
+        }
+        eyre::Result::<_>::Ok(migrated)
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to merge tags: {:?}", e);
+        ApiError::Internal
+    })?;
+    match result {
+        Ok(migrated) => Ok(json(&migrated).into_response()),
+        Err(bad_request) => Err(bad_request),
+    }
+}
+
+/// Deletes every signal on `tag` along with its metadata, translations and subscriptions, recording it in the tag history.
+/// Returns the number of deleted signals.
+pub(crate) async fn delete_in(
+    tx: &mut Transaction<'_, Postgres>,
+    account_id: i64,
+    tag: &str,
+    tombstone_days: i32,
+) -> eyre::Result<u64> {
+    let canonical = canonicalize(tag);
+    let deleted = sqlx::query("delete from signal where tag_canonical = $1")
+        .bind(&canonical)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    for table in [
+        "tag_meta",
+        "tag_category_proposal",
+        "tag_review_queue",
+        "tag_subscription",
+        "tag_translation",
+    ] {
+        sqlx::query(&format!("delete from {} where tag = $1", table))
+            .bind(&canonical)
+            .execute(&mut *tx)
+            .await?;
+    }
+    let migrated = Migrated {
+        signals_moved: deleted,
+        signals_merged: 0,
+    };
+    record_history(tx, "delete", tag, None, account_id, &migrated).await?;
+    bury(tx, tag, None, tombstone_days).await?;
+    Ok(deleted)
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct TagHistoryRow {
+    action: String,
+    from_tag: String,
+    to_tag: Option<String>,
+    account_id: i64,
+    signals_moved: i64,
+    signals_merged: i64,
+    created_at: DateTime<Utc>,
+}
+
+/// A rename, merge or deletion of a tag.
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TagHistoryEntry {
+    /// `rename`, `merge` or `delete`.
+    action: String,
+    from: String,
+    /// `None` for deletions.
+    to: Option<String>,
+    /// The account that made the change.
+    account_id: i64,
+    /// For deletions, the number of deleted signals.
+    signals_moved: i64,
+    signals_merged: i64,
+    created_at: Timestamp,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TagHistory {
+    history: Vec<TagHistoryEntry>,
+}
+
+impl TagHistory {
+    /// Timestamps are formatted for display in `tz`.
+    pub async fn get(tag: &str, tz: Tz, pool: &DB) -> eyre::Result<Self> {
+        let rows = sqlx::query_as::<_, TagHistoryRow>(
+            "
+select action, from_tag, to_tag, account_id, signals_moved, signals_merged, created_at
+from tag_history
+where from_tag = $1 or to_tag = $1
+order by created_at desc, id desc
+            ",
+        )
+        .bind(tag)
+        .fetch_all(pool)
+        .await?;
+        Ok(Self {
+            history: rows
+                .into_iter()
+                .map(|r| TagHistoryEntry {
+                    action: r.action,
+                    from: r.from_tag,
+                    to: r.to_tag,
+                    account_id: r.account_id,
+                    signals_moved: r.signals_moved,
+                    signals_merged: r.signals_merged,
+                    created_at: Timestamp::new(r.created_at, tz),
+                })
+                .collect(),
+        })
+    }
+}
+
+const MAX_FICS_LIMIT: i64 = 100;
+const DEFAULT_FICS_LIMIT: i64 = 20;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TagFicsQ {
+    cursor: Option<String>,
+    /// Maximum number of fics per page.
+    #[param(default = 20, minimum = 0, maximum = 100)]
+    limit: Option<i64>,
+    /// Only fics whose metadata is known and has this status.
+    #[param(inline)]
+    status: Option<FicStatus>,
+}
+
+/// Position after the last fic of a page: its score and URL, which together are unique.
+#[derive(Serialize, Deserialize)]
+struct FicsCursor {
+    score: i64,
+    url: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TagFic {
+    url: String,
+    /// Signals for the tag minus signals against it.
+    score: i64,
+    signals_for: i64,
+    signals_against: i64,
+    /// Only if the fic's metadata is cached; it is never looked up for this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<CachedMeta>,
+}
+
+impl<'r> sqlx::FromRow<'r, PgRow> for TagFic {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            url: row.try_get("url")?,
+            score: row.try_get("score")?,
+            signals_for: row.try_get("signals_for")?,
+            signals_against: row.try_get("signals_against")?,
+            meta: match row.try_get::<Option<String>, _>("id")? {
+                Some(_) => Some(CachedMeta::from_row(row)?),
+                None => None,
+            },
+        })
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TagFics {
+    /// The queried tag, after resolving aliases.
+    tag: String,
+    fics: Vec<TagFic>,
+    /// Pass as `cursor` to get the next page; `None` on the last page.
+    next_cursor: Option<String>,
+}
+
+/// Browse the fics that carry a tag, best scored first.
+///
+/// A fic carries a tag if it has more signals for the tag than against it, and comes with its
+/// metadata if that is cached. Results are paginated; pass `nextCursor` from one page as `cursor`
+/// to get the next. An alias is resolved to the tag it points to.
+#[utoipa::path(
+    get,
+    path = "/tags/{tag}/fics",
+    tag = "tags",
+    params(("tag" = String, Path, description = "The name of the tag, percent-encoded."), TagFicsQ),
+    responses(
+        (status = 200, description = "A page of fics.", body = TagFics),
+        (status = 400, description = "The cursor is invalid.", body = ErrorWrap),
+    ),
+)]
+pub async fn get_tag_fics(tag: String, q: TagFicsQ, pool: DB) -> Result<Response<Body>, ApiError> {
+    let cursor = pagination::decode::<FicsCursor>(q.cursor.as_deref())?;
+    let limit = pagination::limit(q.limit, DEFAULT_FICS_LIMIT, MAX_FICS_LIMIT);
+    let result = async {
+        let tag = TagName::resolve(&tag, &pool).await?;
+        let mut fics = sqlx::query_as::<_, TagFic>(
+            "
+with scored as (
+    select
+        url,
+        count(1) filter (where signal) as signals_for,
+        count(1) filter (where not signal) as signals_against
+    from signal
+    where tag_canonical = $1
+        and (
+            $5::fic_status is null
+            or url in (
+                select u.url
+                from fic_url u
+                join fic f
+                    on f.id = u.fic_id
+                where f.status = $5
+            )
+        )
+    group by url
+)
+select
+    s.url, s.signals_for - s.signals_against as score, s.signals_for, s.signals_against,
+    f.id, f.title, f.source, f.site, f.author, f.author_id, f.series, f.series_id,
+    f.series_position, f.words, f.chapters, f.status, f.fandoms, f.updated, c.fetched_at
+from scored s
+left join fic_url_cache c
+    on c.url = s.url
+left join fic f
+    on f.id = c.fic_id
+where s.signals_for > s.signals_against
+    and (
+        $2::bigint is null
+        or s.signals_for - s.signals_against < $2
+        or (s.signals_for - s.signals_against = $2 and s.url > $3)
+    )
+order by score desc, s.url asc
+limit $4
+            ",
+        )
+        .bind(&tag.canonical)
+        .bind(cursor.as_ref().map(|c| c.score))
+        .bind(cursor.as_ref().map(|c| c.url.as_str()))
+        .bind(limit + 1)
+        .bind(q.status)
+        .fetch_all(&pool)
+        .await?;
+        let next_cursor = pagination::next_cursor(&mut fics, limit, |f| FicsCursor {
+            score: f.score,
+            url: f.url.clone(),
+        });
+        eyre::Result::<_>::Ok(TagFics {
+            tag: tag.display,
+            fics,
+            next_cursor,
+        })
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to list fics for tag: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&result).into_response())
+}
+
+/// Upper bound on the number of related tags returned by a single request.
+const MAX_RELATED_LIMIT: i64 = 100;
+const DEFAULT_RELATED_LIMIT: i64 = 10;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RelatedTagsQ {
+    tag: String,
+    /// An optional maximum limit to the number of returned results.
+    #[param(default = 10, minimum = 0, maximum = 100)]
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RelatedTag {
+    tag: String,
+    /// Number of URLs that have positive signals for both tags.
+    url_count: i64,
+    /// Share of the queried tag's URLs that also carry this tag.
+    share: f64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RelatedTags {
+    /// The queried tag, after resolving aliases.
+    tag: String,
+    related: Vec<RelatedTag>,
+}
+
+impl RelatedTags {
+    /// Finds the tags that most often appear alongside `q.tag` on the same URLs. Only positive
+    /// signals count, and aliases are resolved before looking the tag up.
+    pub async fn get(q: RelatedTagsQ, pool: &DB) -> eyre::Result<Self> {
+        let tag = TagName::resolve(&q.tag, pool).await?;
+        let related = sqlx::query_as::<_, RelatedTag>(
+            "
+with tagged as (
+    select distinct url
+    from signal
+    where tag_canonical = $1 and signal = true
+)
+select
+    mode() within group (order by s.tag) as tag,
+    count(distinct s.url) as url_count,
+    count(distinct s.url)::float8 / (select count(1) from tagged) as share
+from signal s
+join tagged t
+    on t.url = s.url
+where s.tag_canonical <> $1 and s.signal = true
+group by s.tag_canonical
+order by url_count desc, s.tag_canonical asc
+limit $2
+            ",
+        )
+        .bind(&tag.canonical)
+        .bind(
+            q.limit
+                .unwrap_or(DEFAULT_RELATED_LIMIT)
+                .clamp(0, MAX_RELATED_LIMIT),
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(Self {
+            tag: tag.display,
+            related,
+        })
+    }
+}
+
+const MAX_TRENDING_LIMIT: i64 = 100;
+const DEFAULT_TRENDING_LIMIT: i64 = 20;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TrendingTagsQ {
+    /// Length of the window.
+    #[param(inline, default = "7d", example = "24h")]
+    window: Option<TimeWindow>,
+    /// An optional maximum limit to the number of returned results.
+    #[param(default = 20, minimum = 0, maximum = 100)]
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TrendingTag {
+    tag: String,
+    /// Signals created within the window.
+    signals: i64,
+    /// Signals created within the same length of time right before the window.
+    previous_signals: i64,
+    growth: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TrendingTags {
+    /// Start of the window.
+    since: DateTime<Utc>,
+    tags: Vec<TrendingTag>,
+}
+
+impl TrendingTags {
+    /// Ranks tags by how many more signals they received within the window than within the
+    /// window before it. The window defaults to 7 days.
+    pub async fn get(q: TrendingTagsQ, pool: &DB) -> eyre::Result<Self> {
+        let window = q.window.map_or(chrono::Duration::days(7), |w| w.0);
+        let since = Utc::now() - window;
+        let tags = sqlx::query_as::<_, TrendingTag>(
+            "
+with rollup as (
+    select
+        tag_canonical,
+        mode() within group (order by tag) as tag,
+        count(1) filter (where created_at >= $1) as signals,
+        count(1) filter (where created_at < $1) as previous_signals
+    from signal
+    where created_at >= $2
+    group by tag_canonical
+)
+select tag, signals, previous_signals, signals - previous_signals as growth
+from rollup
+where signals > previous_signals
+order by growth desc, signals desc, tag_canonical asc
+limit $3
+            ",
+        )
+        .bind(since)
+        .bind(since - window)
+        .bind(
+            q.limit
+                .unwrap_or(DEFAULT_TRENDING_LIMIT)
+                .clamp(0, MAX_TRENDING_LIMIT),
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(Self { since, tags })
+    }
+}
+
+/// Categories that the inference job knows how to propose.
+pub(crate) const CATEGORY_FANDOM: &str = "fandom";
+const CATEGORY_SHIP: &str = "ship";
+const CATEGORY_CHARACTER: &str = "character";
+const KNOWN_CATEGORIES: &[&str] = &[CATEGORY_FANDOM, CATEGORY_SHIP, CATEGORY_CHARACTER];
+
+/// A tag needs to be on at least this many fics before co-occurrence is considered meaningful.
+const INFERENCE_MIN_URLS: i64 = 3;
+/// Share of a tag's fics that must also carry a single fandom tag to count as belonging to it.
+const INFERENCE_COVERAGE: f64 = 0.8;
+
+#[derive(Debug, sqlx::FromRow)]
+struct FandomOverlap {
+    tag: String,
+    fandom: String,
+    url_count: i64,
+    fandom_url_count: i64,
+    shared: i64,
+}
+
+/// Proposes a category based only on how the tag is written.
+fn infer_category_from_name(tag: &str) -> Option<(&'static str, String)> {
+    let lower = tag.to_lowercase();
+    for category in KNOWN_CATEGORIES {
+        if lower.starts_with(&format!("{}:", category)) {
+            return Some((category, format!("tag is prefixed with \"{}:\"", category)));
+        }
+    }
+    let is_pairing = |sep: &str| {
+        let mut parts = tag.split(sep);
+        parts.clone().count() >= 2 && parts.all(|p| !p.trim().is_empty())
+    };
+    if is_pairing("/") {
+        return Some((CATEGORY_SHIP, "tag has the form \"x/y\"".to_string()));
+    }
+    if is_pairing(" x ") {
+        return Some((CATEGORY_SHIP, "tag has the form \"x x y\"".to_string()));
+    }
+    None
+}
+
+/// Proposes a category based on fichub metadata of the tag's fics: a tag named like a fandom they
+/// are listed in is that fandom.
+fn infer_category_from_meta(tag: &str, fandom: &str) -> Option<(&'static str, String)> {
+    (canonicalize(fandom) == tag).then(|| {
+        (
+            CATEGORY_FANDOM,
+            format!(
+                "fichub lists \"{}\" as a fandom of fics the tag is on",
+                fandom
+            ),
+        )
+    })
+}
+
+/// Proposes a category based on how the tag's fics overlap with the fics of a known fandom tag.
+fn infer_category_from_overlap(o: &FandomOverlap) -> Option<(&'static str, String)> {
+    let covered = o.shared as f64 / o.url_count as f64;
+    if covered < INFERENCE_COVERAGE {
+        return None;
+    }
+    let covers = o.shared as f64 / o.fandom_url_count as f64;
+    if covers >= INFERENCE_COVERAGE {
+        Some((
+            CATEGORY_FANDOM,
+            format!("almost always co-occurs with fandom \"{}\"", o.fandom),
+        ))
+    } else {
+        Some((
+            CATEGORY_CHARACTER,
+            format!("mostly appears on fics of fandom \"{}\"", o.fandom),
+        ))
+    }
+}
+
+/// Runs a single pass of category inference over uncategorized tags, queueing proposals for
+/// review, see `GET /tags/category-proposals`. Tags that had a proposal already, decided or not,
+/// are skipped. Returns the number of new proposals.
+pub async fn infer_categories(pool: &DB) -> eyre::Result<u64> {
+    let unkinded = sqlx::query_scalar::<_, String>(
+        "
+select distinct s.tag_canonical
+from signal s
+left join tag_meta m
+    on m.tag = s.tag_canonical
+left join tag_category_proposal p
+    on p.tag = s.tag_canonical
+where m.category is null and p.tag is null
+        ",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let fandoms = sqlx::query_as::<_, (String, String)>(
+        "
+select distinct s.tag_canonical, d.fandom
+from signal s
+join fic_url_cache c
+    on c.url = s.url
+join fic f
+    on f.id = c.fic_id
+cross join unnest(f.fandoms) as d(fandom)
+left join tag_meta m
+    on m.tag = s.tag_canonical
+left join tag_category_proposal p
+    on p.tag = s.tag_canonical
+where s.signal and m.category is null and p.tag is null
+        ",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let overlaps = sqlx::query_as::<_, FandomOverlap>(
+        "
+with unkinded as (
+    select s.tag_canonical as tag, count(distinct s.url) as url_count
+    from signal s
+    left join tag_meta m
+        on m.tag = s.tag_canonical
+    left join tag_category_proposal p
+        on p.tag = s.tag_canonical
+    where s.signal and m.category is null and p.tag is null
+    group by s.tag_canonical
+    having count(distinct s.url) >= $1
+),
+fandom as (
+    select s.tag_canonical as tag, count(distinct s.url) as url_count
+    from signal s
+    join tag_meta m
+        on m.tag = s.tag_canonical
+    where s.signal and m.category = $2
+    group by s.tag_canonical
+),
+overlap as (
+    select
+        u.tag,
+        f.tag as fandom,
+        u.url_count,
+        f.url_count as fandom_url_count,
+        count(distinct a.url) as shared
+    from unkinded u
+    join signal a
+        on a.tag_canonical = u.tag and a.signal
+    join signal b
+        on b.url = a.url and b.signal
+    join fandom f
+        on f.tag = b.tag_canonical
+    group by u.tag, f.tag, u.url_count, f.url_count
+)
+select distinct on (tag) tag, fandom, url_count, fandom_url_count, shared
+from overlap
+order by tag, shared desc
+        ",
+    )
+    .bind(INFERENCE_MIN_URLS)
+    .bind(CATEGORY_FANDOM)
+    .fetch_all(pool)
+    .await?;
+
+    let proposals = unkinded
+        .iter()
+        .filter_map(|tag| infer_category_from_name(tag).map(|p| (tag.as_str(), p)))
+        .chain(fandoms.iter().filter_map(|(tag, fandom)| {
+            infer_category_from_meta(tag, fandom).map(|p| (tag.as_str(), p))
+        }))
+        .chain(
+            overlaps
+                .iter()
+                .filter_map(|o| infer_category_from_overlap(o).map(|p| (o.tag.as_str(), p))),
+        );
+
+    let mut inserted = 0;
+    for (tag, (category, reason)) in proposals {
+        inserted += sqlx::query(
+            "
+insert into tag_category_proposal (tag, category, reason)
+values ($1, $2, $3)
+on conflict (tag) do nothing
+            ",
+        )
+        .bind(tag)
+        .bind(category)
+        .bind(reason)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    }
+    Ok(inserted)
+}
+
+/// Spawns a task that runs [`infer_categories`] every `interval`.
+pub fn spawn_category_inference(pool: DB, interval: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match infer_categories(&pool).await {
+                Ok(0) => {}
+                Ok(n) => println!("tag category inference: queued {} proposals", n),
+                Err(e) => eprintln!("tag category inference failed: {:?}", e),
+            }
+        }
+    });
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetTagsQ {
+    /// An optional partial tag to autocomplete.
+    q: Option<String>,
+    cursor: Option<String>,
+    /// An optional maximum limit to the number of returned results.
+    #[param(default = 1000, minimum = 0, maximum = 1000)]
+    limit: Option<i64>,
+    /// Only complete tags of this category.
+    category: Option<String>,
+    /// Tags not to complete, e.g. those already applied, from the repeatable `exclude`
+    /// parameter.
+    #[serde(skip)]
+    exclude: Vec<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Tags {
+    tags: Vec<String>,
+    /// Labels in the client's preferred language, for those tags that have been translated.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+    /// Pass as `cursor` to get the next page; `None` on the last page.
+    next_cursor: Option<String>,
+}
+
+/// Upper bound on the number of tags returned by a single autocomplete request.
+const MAX_TAGS_LIMIT: i64 = 1000;
+
+/// Position after the last tag of a page. Tags are ranked on the fly, so it's how many came
+/// before; a page may repeat or skip a tag whose popularity changed in between.
+#[derive(Serialize, Deserialize)]
+struct TagsCursor {
+    offset: i64,
+}
+
+/// Escapes `%`, `_` and the escape character itself so `s` is matched literally by `like`.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Get all known fic tags, or autocomplete a partial tag.
+///
+/// Without `q`, all tags are returned ordered by popularity. With `q`, only tags that start with or
+/// are similar to `q` are returned: prefix matches first (an exact match before anything else),
+/// then similar tags ranked by similarity weighted with popularity. Matching is case-insensitive.
+/// Labels of tags translated to the client's preferred languages are matched as well. Results are
+/// paginated; pass `nextCursor` from one page as `cursor` to get the next.
+#[utoipa::path(
+    get,
+    path = "/tags",
+    tag = "tags",
+    params(
+        GetTagsQ,
+        (
+            "exclude" = Option<Vec<String>>,
+            Query,
+            description = "A tag not to return, e.g. one the user already applied. May be \
+                repeated; matching is case-insensitive.",
+            style = Form,
+            explode = true,
+        ),
+        AcceptLanguage,
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Existing tags.",
+            body = Tags,
+        ),
+        (status = 400, description = "The cursor is invalid.", body = ErrorWrap),
+    ),
+)]
+pub async fn get_tags(q: GetTagsQ, langs: AcceptLanguage, pool: DB) -> Result<Tags, ApiError> {
+    // Excluded tags and tags outside the requested category are filtered out in the query, so they
+    // don't take up room in `limit`. See `TagsCursor` on pagination.
+    let offset =
+        pagination::decode::<TagsCursor>(q.cursor.as_deref())?.map_or(0, |c| c.offset.max(0));
+    let limit = pagination::limit(q.limit, MAX_TAGS_LIMIT, MAX_TAGS_LIMIT);
+    let query = q.q.map(|q| canonicalize(&q)).filter(|q| !q.is_empty());
+    let prefix = query.as_deref().map(|q| format!("{}%", escape_like(q)));
+    let mut rows = sqlx::query_as::<_, (String, Option<String>)>(
+        "
+with translated as (
+    select distinct on (tag) tag, label, label_canonical
+    from tag_translation
+    where locale = any($4)
+    order by tag, array_position($4, locale)
+),
+candidate as (
+    select
+        tag_canonical,
+        mode() within group (order by tag) as tag,
+        count(1) as uses,
+        $1::text is not null and (
+            tag_canonical like $2 escape '\\'
+            or tag_canonical in (select tag from translated where label_canonical like $2 escape '\\')
+        ) as prefix_match,
+        tag_canonical = $1
+            or tag_canonical in (select tag from translated where label_canonical = $1) as exact_match
+    from signal
+    where (
+            $1::text is null
+            or tag_canonical like $2 escape '\\'
+            or tag_canonical % $1
+            or tag_canonical in (select tag from translated where label_canonical like $2 escape '\\')
+        )
+        and tag_canonical <> all($5)
+        and ($6::text is null or tag_canonical in (select tag from tag_meta where category = $6))
+    group by tag_canonical
+)
+select c.tag, t.label
+from candidate c
+left join translated t
+    on t.tag = c.tag_canonical
+order by
+    prefix_match desc,
+    exact_match desc,
+    case when $1::text is null or prefix_match
+        then uses
+        else similarity(tag_canonical, $1) * ln(2 + uses)
+    end desc,
+    c.tag asc
+limit $3
+offset $7
+        ",
+    )
+    .bind(&query)
+    .bind(&prefix)
+    .bind(limit + 1)
+    .bind(&langs.0)
+    .bind(
+        q.exclude
+            .iter()
+            .map(|t| canonicalize(t))
+            .collect::<Vec<_>>(),
+    )
+    .bind(&q.category)
+    .bind(offset)
+    .fetch_all(&pool)
+    .await
+    .wrap_err("failed to query tags")?;
+    let next_cursor = pagination::next_cursor(&mut rows, limit, |_| TagsCursor {
+        offset: offset + limit,
+    });
+    let labels = rows
+        .iter()
+        .filter_map(|(tag, label)| Some((tag.clone(), label.clone()?)))
+        .collect();
+    Ok(Tags {
+        tags: rows.into_iter().map(|(tag, _)| tag).collect(),
+        labels,
+        next_cursor,
+    })
+}
+
+/// Get the tags that most often co-occur with a tag on the same fics.
+///
+/// Only positive signals are considered. Tags are ordered by the number of fics they share with the
+/// queried tag. An alias is resolved to the tag it points to.
+#[utoipa::path(
+    get,
+    path = "/tags/related",
+    tag = "tags",
+    params(RelatedTagsQ),
+    responses(
+        (status = 200, description = "Related tags.", body = RelatedTags),
+    ),
+)]
+async fn get_related_tags(q: RelatedTagsQ, pool: DB) -> eyre::Result<RelatedTags> {
+    RelatedTags::get(q, &pool)
+        .await
+        .wrap_err("failed to get related tags")
+}
+
+/// Get the tags whose number of new signals grew the most recently.
+///
+/// Tags are ranked by how many more signals they received within the window than within the window
+/// of the same length right before it. Only tags that grew are returned.
+#[utoipa::path(
+    get,
+    path = "/tags/trending",
+    tag = "tags",
+    params(TrendingTagsQ),
+    responses(
+        (status = 200, description = "Trending tags.", body = TrendingTags),
+        (status = 400, description = "The window is malformed or out of range.", body = ErrorWrap),
+    ),
+)]
+async fn get_trending_tags(q: TrendingTagsQ, pool: DB) -> eyre::Result<TrendingTags> {
+    TrendingTags::get(q, &pool)
+        .await
+        .wrap_err("failed to get trending tags")
+}
+
+/// Get renames and merges involving a tag, most recent first.
+///
+/// Timestamps are formatted for display in the current account's time zone, or UTC.
+#[utoipa::path(
+    get,
+    path = "/tags/{tag}/history",
+    tag = "tags",
+    params(
+        ("tag" = String, Path, description = "The name of the tag, percent-encoded."),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Success.",
+            body = TagHistory,
+        ),
+    ),
+    security(("cookieAuth" = []), ()),
+)]
+async fn get_tag_history(
+    tag: PercentDecoded,
+    account: Option<AccountSession>,
+    pool: DB,
+) -> eyre::Result<TagHistory> {
+    let tz = account.map_or(chrono_tz::UTC, |a| a.tz());
+    TagHistory::get(&tag.0, tz, &pool)
+        .await
+        .wrap_err("failed to get tag history")
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_tags,
+    rename_tag,
+    merge_tags,
+    get_related_tags,
+    get_trending_tags,
+    get_tag_history,
+    get_tag_fics,
+    get_tag,
+))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_tags = cached(
+        CachePolicy::Public(60),
+        &["accept-language"],
+        warp::path!("v1" / "tags")
+            .and(warp::get())
+            .and(warp::query::<GetTagsQ>().and(query_list("exclude")).map(
+                |mut q: GetTagsQ, exclude| {
+                    q.exclude = exclude;
+                    q
+                },
+            ))
+            .and(accept_language())
+            .and(ctx.pool())
+            .then(get_tags)
+            .and_then(reply_json),
+    );
+    let rename_tag = warp::path!("v1" / "tags" / "rename")
+        .and(warp::post())
+        .and(ctx.require_permission(Permission::TagCuration))
+        .and(json_body::<RenameTagQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |account, q, pool| {
+            rename_tag(account, q, pool, ctx.tag_tombstone_days, ctx.tag_policy)
+        })
+        .and_then(reject);
+    let merge_tags = warp::path!("v1" / "tags" / "merge")
+        .and(warp::post())
+        .and(ctx.require_permission(Permission::TagCuration))
+        .and(json_body::<MergeTagsQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |account, q, pool| {
+            merge_tags(account, q, pool, ctx.tag_tombstone_days, ctx.tag_policy)
+        })
+        .and_then(reject);
+    let get_related_tags = cached(
+        CachePolicy::Public(300),
+        &[],
+        warp::path!("v1" / "tags" / "related")
+            .and(warp::get())
+            .and(warp::query::<RelatedTagsQ>())
+            .and(ctx.pool())
+            .then(get_related_tags)
+            .and_then(reply_json),
+    );
+    let get_trending_tags = cached(
+        CachePolicy::Public(300),
+        &[],
+        warp::path!("v1" / "tags" / "trending")
+            .and(warp::get())
+            .and(warp::query::<TrendingTagsQ>())
+            .and(ctx.pool())
+            .then(get_trending_tags)
+            .and_then(reply_json),
+    );
+    let get_tag_history = warp::path!("v1" / "tags" / PercentDecoded / "history")
+        .and(warp::get())
+        .and(ctx.optional_authenticate())
+        .and(ctx.pool())
+        .then(get_tag_history)
+        .and_then(reply_json);
+    let get_tag_fics = cached(
+        CachePolicy::Public(60),
+        &[],
+        warp::path!("v1" / "tags" / PercentDecoded / "fics")
+            .and(warp::get())
+            .and(warp::query::<TagFicsQ>())
+            .and(ctx.pool())
+            .then(|tag: PercentDecoded, q, pool| get_tag_fics(tag.0, q, pool))
+            .and_then(reject),
+    );
+    let get_tag = warp::path!("v1" / "tags" / PercentDecoded)
+        .and(warp::get())
+        .and(ctx.pool())
+        .then(|tag: PercentDecoded, pool| get_tag(tag.0, pool))
+        .and_then(reject);
+    get_tags
+        .or(rename_tag)
+        .or(merge_tags)
+        .or(get_related_tags)
+        .or(get_trending_tags)
+        .or(get_tag_history)
+        .or(get_tag_fics)
+        .or(get_tag)
+        .map(Reply::into_response)
+        .boxed()
+}
@@ -0,0 +1,181 @@
+use chrono::{DateTime, Utc};
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{json_body, reject, ApiError, ErrorWrap, PercentDecoded};
+use crate::routes::Routes;
+use crate::tag::canonicalize;
+use crate::tag_proposal::ProposalStatus;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryProposal {
+    /// The canonical form of the tag, see `GET /tags/{tag}`.
+    tag: String,
+    category: String,
+    /// Why the inference job proposed the category.
+    reason: String,
+    status: ProposalStatus,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryProposals {
+    proposals: Vec<CategoryProposal>,
+}
+
+const SELECT_PROPOSAL: &str = "
+select tag, category, reason, status, created_at
+from tag_category_proposal
+";
+
+/// List categories the background inference job proposed for uncategorized tags, awaiting a
+/// decision.
+///
+/// Requires the `tag-curation` permission.
+#[utoipa::path(
+    get,
+    path = "/tags/category-proposals",
+    tag = "tags",
+    responses(
+        (status = 200, description = "Open proposals, oldest first.", body = CategoryProposals),
+        (status = 403, description = "The account may not curate tags.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_category_proposals(
+    _account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let proposals = sqlx::query_as::<_, CategoryProposal>(&format!(
+        "{} where status = 'open' order by created_at, tag",
+        SELECT_PROPOSAL
+    ))
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to list tag category proposals: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&CategoryProposals { proposals }).into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DecideCategoryProposalQ {
+    approve: bool,
+}
+
+/// Approve or reject an open category proposal. Requires the `tag-curation` permission.
+///
+/// Approving sets the tag's category, replacing any it got in the meantime. Either way, the job
+/// doesn't propose a category for the tag again.
+#[utoipa::path(
+    post,
+    path = "/tags/category-proposals/{tag}/decision",
+    tag = "tags",
+    params(
+        ("tag" = String, Path, description = "The name of the tag, percent-encoded."),
+    ),
+    request_body = DecideCategoryProposalQ,
+    responses(
+        (status = 200, description = "The closed proposal.", body = CategoryProposal),
+        (status = 403, description = "The account may not curate tags.", body = ErrorWrap),
+        (status = 404, description = "There is no open proposal for the tag.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn decide_category_proposal(
+    tag: String,
+    account: AccountSession,
+    q: DecideCategoryProposalQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let category = sqlx::query_scalar::<_, String>(
+            "
+update tag_category_proposal
+set status = $2, decided_by = $3, decided_at = now()
+where tag = $1 and status = 'open'
+returning category
+            ",
+        )
+        .bind(canonicalize(&tag))
+        .bind(if q.approve {
+            ProposalStatus::Approved
+        } else {
+            ProposalStatus::Rejected
+        })
+        .bind(account.id)
+        .fetch_optional(&mut tx)
+        .await?;
+        let category = match category {
+            Some(category) => category,
+            None => return Ok(None),
+        };
+        if q.approve {
+            sqlx::query(
+                "
+insert into tag_meta (tag, category)
+values ($1, $2)
+on conflict (tag) do update set category = excluded.category
+                ",
+            )
+            .bind(canonicalize(&tag))
+            .bind(&category)
+            .execute(&mut tx)
+            .await?;
+        }
+        let proposal =
+            sqlx::query_as::<_, CategoryProposal>(&format!("{} where tag = $1", SELECT_PROPOSAL))
+                .bind(canonicalize(&tag))
+                .fetch_one(&mut tx)
+                .await?;
+        tx.commit().await?;
+        eyre::Result::<_>::Ok(Some(proposal))
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to decide tag category proposal: {:?}", e);
+        ApiError::Internal
+    })?;
+    match result {
+        Some(proposal) => Ok(json(&proposal).into_response()),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_category_proposals, decide_category_proposal))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_category_proposals = warp::path!("v1" / "tags" / "category-proposals")
+        .and(warp::get())
+        .and(ctx.require_permission(Permission::TagCuration))
+        .and(ctx.pool())
+        .then(get_category_proposals)
+        .and_then(reject);
+    let decide_category_proposal =
+        warp::path!("v1" / "tags" / "category-proposals" / PercentDecoded / "decision")
+            .and(warp::post())
+            .and(ctx.require_permission(Permission::TagCuration))
+            .and(json_body::<DecideCategoryProposalQ>(ctx.max_body_bytes))
+            .and(ctx.pool())
+            .then(|tag: PercentDecoded, account, q, pool| {
+                decide_category_proposal(tag.0, account, q, pool)
+            })
+            .and_then(reject);
+    get_category_proposals
+        .or(decide_category_proposal)
+        .map(Reply::into_response)
+        .boxed()
+}
@@ -0,0 +1,125 @@
+use futures::TryStreamExt as _;
+use http::header::CONTENT_TYPE;
+use http::Response;
+use hyper::Body;
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+use warp::{Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::ErrorWrap;
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+/// One line of the export.
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedTag {
+    canonical: String,
+    /// The most common spelling.
+    tag: String,
+    /// Canonical names that were merged into this tag.
+    aliases: Vec<String>,
+    category: Option<String>,
+    signals_for: i64,
+    signals_against: i64,
+    url_count: i64,
+}
+
+/// Export every tag in use as newline-delimited JSON.
+///
+/// Meant for offline analysis and for seeding local autocomplete caches. Each line is one
+/// `ExportedTag`, ordered by canonical name. The response is streamed; if it is cut off early, the
+/// last line has no trailing newline. Requires the `data-export` permission, as the export is
+/// expensive to compute.
+#[utoipa::path(
+    get,
+    path = "/tags/export",
+    tag = "tags",
+    responses(
+        (
+            status = 200,
+            description = "Success.",
+            body = ExportedTag,
+            content_type = "application/x-ndjson",
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn export_tags(_account: AccountSession, pool: DB) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    // Rows are sent as they come out of the database, so the vocabulary is never held in memory
+    // at once.
+    tokio::spawn(async move {
+        let mut rows = sqlx::query_as::<_, ExportedTag>(
+            "
+select
+    s.tag_canonical as canonical,
+    s.tag,
+    coalesce(a.aliases, '{}') as aliases,
+    m.category,
+    s.signals_for,
+    s.signals_against,
+    s.url_count
+from (
+    select
+        tag_canonical,
+        mode() within group (order by tag) as tag,
+        count(1) filter (where signal) as signals_for,
+        count(1) filter (where not signal) as signals_against,
+        count(distinct url) as url_count
+    from signal
+    group by tag_canonical
+) s
+left join tag_meta m
+    on m.tag = s.tag_canonical
+left join (
+    select tag, array_agg(alias order by alias) as aliases
+    from tag_alias
+    group by tag
+) a
+    on a.tag = s.tag_canonical
+order by s.tag_canonical
+            ",
+        )
+        .fetch(&pool);
+        loop {
+            let tag = match rows.try_next().await {
+                Ok(Some(tag)) => tag,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("failed to export tags: {:?}", e);
+                    sender.abort();
+                    return;
+                }
+            };
+            let mut line = serde_json::to_vec(&tag).expect("tags serialize to json");
+            line.push(b'\n');
+            if sender.send_data(line.into()).await.is_err() {
+                // The client went away.
+                return;
+            }
+        }
+    });
+    let mut response = Response::new(body);
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        http::HeaderValue::from_static("application/x-ndjson"),
+    );
+    response
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(export_tags))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let export_tags = warp::path!("v1" / "tags" / "export")
+        .and(warp::get())
+        .and(ctx.require_permission(Permission::DataExport))
+        .and(ctx.pool())
+        .then(export_tags);
+    export_tags.map(Reply::into_response).boxed()
+}
@@ -0,0 +1,243 @@
+use chrono::{DateTime, Utc};
+use eyre::WrapErr;
+use http::{Response, StatusCode};
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+use tap::prelude::*;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{
+    reply::{json, with_status},
+    Filter, Reply,
+};
+
+use crate::context::Context;
+use crate::httputil::{json_body, reject, reply_json, ApiError, Empty, ErrorWrap};
+use crate::routes::Routes;
+use crate::tag::canonicalize;
+use crate::tag_policy::TagPolicy;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagImplication)]
+pub struct Implication {
+    tag: String,
+    implies: String,
+    created_by: i64,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagImplications)]
+pub struct Implications {
+    implications: Vec<Implication>,
+}
+
+impl Implications {
+    pub async fn get(pool: &DB) -> eyre::Result<Self> {
+        Ok(Self {
+            implications: sqlx::query_as::<_, Implication>(
+                "
+select tag_display as tag, implied as implies, created_by, created_at
+from tag_implication
+order by tag, implied_canonical
+                ",
+            )
+            .fetch_all(pool)
+            .await?,
+        })
+    }
+}
+
+/// Adds signals for every tag that `tag` implies, directly or through other rules, to a fic the
+/// account just signalled `tag` on. Tags the account already has a signal on, for or against,
+/// are left alone.
+pub(crate) async fn apply(
+    tx: &mut Transaction<'_, Postgres>,
+    account_id: i64,
+    url: &str,
+    tag_canonical: &str,
+) -> eyre::Result<()> {
+    sqlx::query(
+        "
+with recursive implied (tag) as (
+    select implied_canonical from tag_implication where tag = $3
+    union
+    select i.implied_canonical
+    from tag_implication i
+    join implied
+        on i.tag = implied.tag
+)
+insert into signal (account_id, url, tag, tag_canonical, signal)
+select distinct on (i.implied_canonical) $1, $2, i.implied, i.implied_canonical, true
+from tag_implication i
+join implied
+    on implied.tag = i.implied_canonical
+where i.implied_canonical <> $3
+on conflict do nothing
+        ",
+    )
+    .bind(account_id)
+    .bind(url)
+    .bind(tag_canonical)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, ToSchema, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagImplicationQ)]
+#[into_params(parameter_in = Query)]
+pub struct ImplicationQ {
+    tag: String,
+    implies: String,
+}
+
+/// Add a tag implication rule. Requires the `tag-curation` permission.
+///
+/// Only applies to signals added from now on.
+#[utoipa::path(
+    post,
+    path = "/admin/tag-implications",
+    tag = "admin",
+    request_body = ImplicationQ,
+    responses(
+        (status = 201, description = "The rule was added.", body = Implication),
+        (status = 400, description = "A tag is empty or would imply itself.", body = ErrorWrap),
+        (status = 403, description = "The account may not curate tags.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn create_tag_implication(
+    account: AccountSession,
+    q: ImplicationQ,
+    pool: DB,
+    policy: &TagPolicy,
+) -> Result<Response<Body>, ApiError> {
+    policy.check([q.tag.as_str(), q.implies.as_str()], true)?;
+    let (tag, implies) = (canonicalize(&q.tag), canonicalize(&q.implies));
+    if tag == implies {
+        return Err(ApiError::BadRequest("a tag can't imply itself".into()));
+    }
+    let implication = sqlx::query_as::<_, Implication>(
+        "
+insert into tag_implication (tag, tag_display, implied, implied_canonical, created_by)
+values ($1, $2, $3, $4, $5)
+on conflict (tag, implied_canonical) do update set
+    tag_display = excluded.tag_display,
+    implied = excluded.implied
+returning tag_display as tag, implied as implies, created_by, created_at
+        ",
+    )
+    .bind(&tag)
+    .bind(&q.tag)
+    .bind(&q.implies)
+    .bind(&implies)
+    .bind(account.id)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to create tag implication: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&implication)
+        .pipe(|r| with_status(r, StatusCode::CREATED))
+        .into_response())
+}
+
+/// Remove a tag implication rule. Requires the `tag-curation` permission.
+///
+/// Signals that were added because of the rule are kept.
+#[utoipa::path(
+    delete,
+    path = "/admin/tag-implications",
+    tag = "admin",
+    params(ImplicationQ),
+    responses(
+        (status = 200, description = "The rule was removed.", body = Empty),
+        (status = 403, description = "The account may not curate tags.", body = ErrorWrap),
+        (status = 404, description = "There is no such rule.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn delete_tag_implication(
+    _account: AccountSession,
+    q: ImplicationQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let deleted =
+        sqlx::query("delete from tag_implication where tag = $1 and implied_canonical = $2")
+            .bind(canonicalize(&q.tag))
+            .bind(canonicalize(&q.implies))
+            .execute(&pool)
+            .await
+            .map_err(|e| {
+                eprintln!("failed to delete tag implication: {:?}", e);
+                ApiError::Internal
+            })?
+            .rows_affected();
+    if deleted == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(json(&Empty {}).into_response())
+}
+
+/// List tag implication rules. Requires the `tag-curation` permission.
+///
+/// When an account signals for a tag, it also signals for every tag that tag implies, directly or
+/// through other rules, unless it already has a signal on that tag.
+#[utoipa::path(
+    get,
+    path = "/admin/tag-implications",
+    tag = "admin",
+    responses(
+        (
+            status = 200,
+            description = "All rules.",
+            body = Implications,
+        ),
+        (status = 403, description = "The account may not curate tags.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+async fn get_tag_implications(_account: AccountSession, pool: DB) -> eyre::Result<Implications> {
+    Implications::get(&pool)
+        .await
+        .wrap_err("failed to list tag implications")
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_tag_implications, create_tag_implication, delete_tag_implication))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_tag_implications = warp::path!("v1" / "admin" / "tag-implications")
+        .and(warp::get())
+        .and(ctx.require_permission(Permission::TagCuration))
+        .and(ctx.pool())
+        .then(get_tag_implications)
+        .and_then(reply_json);
+    let create_tag_implication = warp::path!("v1" / "admin" / "tag-implications")
+        .and(warp::post())
+        .and(ctx.require_permission(Permission::TagCuration))
+        .and(json_body::<ImplicationQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |account, q, pool| create_tag_implication(account, q, pool, ctx.tag_policy))
+        .and_then(reject);
+    let delete_tag_implication = warp::path!("v1" / "admin" / "tag-implications")
+        .and(warp::delete())
+        .and(ctx.require_permission(Permission::TagCuration))
+        .and(warp::query::<ImplicationQ>())
+        .and(ctx.pool())
+        .then(delete_tag_implication)
+        .and_then(reject);
+    get_tag_implications
+        .or(create_tag_implication)
+        .or(delete_tag_implication)
+        .map(Reply::into_response)
+        .boxed()
+}
@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::httputil::ApiError;
+use crate::tag::canonicalize;
+use crate::DB;
+
+/// Kinds of characters a tag may be made of. Control characters and whitespace other than plain
+/// spaces are never allowed.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CharClass {
+    Letter,
+    Digit,
+    Space,
+    /// ASCII punctuation, e.g. `:` and `/` in `ship:HP/DM`.
+    Punctuation,
+    /// Anything else, e.g. emoji or non-ASCII punctuation.
+    Other,
+}
+
+impl CharClass {
+    fn of(c: char) -> Option<Self> {
+        if c.is_control() || (c.is_whitespace() && c != ' ') {
+            None
+        } else if c == ' ' {
+            Some(Self::Space)
+        } else if c.is_alphabetic() {
+            Some(Self::Letter)
+        } else if c.is_numeric() {
+            Some(Self::Digit)
+        } else if c.is_ascii_punctuation() {
+            Some(Self::Punctuation)
+        } else {
+            Some(Self::Other)
+        }
+    }
+}
+
+/// Limits on the tags users may create. Checked whenever a tag name enters the system through
+/// the API; existing tags are only checked at startup, see [`audit`].
+#[derive(Debug)]
+pub struct TagPolicy {
+    /// In characters.
+    pub max_length: usize,
+    pub allowed: Vec<CharClass>,
+    /// Prefixes only tag curators may use, compared case-insensitively.
+    pub reserved_prefixes: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    Empty,
+    TooLong,
+    DisallowedCharacter,
+    ReservedPrefix,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagPolicyViolation)]
+pub struct Violation {
+    /// The offending tag, truncated to the maximum length.
+    tag: String,
+    #[schema(inline)]
+    rule: Rule,
+    #[schema(example = "tag is longer than 128 characters")]
+    message: String,
+}
+
+impl TagPolicy {
+    /// `curated` lifts the restriction on reserved prefixes.
+    pub fn violations(&self, tag: &str, curated: bool) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let mut violate = |rule, message: String| {
+            violations.push(Violation {
+                tag: tag.chars().take(self.max_length).collect(),
+                rule,
+                message,
+            })
+        };
+
+        if tag.trim().is_empty() {
+            violate(Rule::Empty, "tag is empty".into());
+        }
+        if tag.chars().count() > self.max_length {
+            violate(
+                Rule::TooLong,
+                format!("tag is longer than {} characters", self.max_length),
+            );
+        }
+        if let Some(c) = tag
+            .chars()
+            .find(|&c| !CharClass::of(c).is_some_and(|class| self.allowed.contains(&class)))
+        {
+            violate(
+                Rule::DisallowedCharacter,
+                format!("tag contains disallowed character {:?}", c),
+            );
+        }
+        if !curated {
+            let canonical = canonicalize(tag);
+            if let Some(prefix) = self
+                .reserved_prefixes
+                .iter()
+                .find(|p| canonical.starts_with(&canonicalize(p)))
+            {
+                violate(
+                    Rule::ReservedPrefix,
+                    format!("tag prefix {:?} is reserved", prefix),
+                );
+            }
+        }
+        violations
+    }
+
+    /// Checks all `tags` at once, so that clients see every problem in a single response.
+    pub fn check<'a>(
+        &self,
+        tags: impl IntoIterator<Item = &'a str>,
+        curated: bool,
+    ) -> Result<(), ApiError> {
+        let violations: Vec<_> = tags
+            .into_iter()
+            .flat_map(|tag| self.violations(tag, curated))
+            .collect();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::TagPolicyViolation(violations))
+        }
+    }
+}
+
+/// Finds tags that were stored before the policy was introduced or tightened, and with `cleanup`
+/// deletes their signals along with anything else keyed by them. Reserved prefixes are ignored,
+/// since curators may have used them legitimately. Returns the offending tags.
+pub async fn audit(pool: &DB, policy: &TagPolicy, cleanup: bool) -> eyre::Result<Vec<String>> {
+    let tags = sqlx::query_scalar::<_, String>("select distinct tag from signal")
+        .fetch_all(pool)
+        .await?;
+    let mut offending: Vec<_> = tags
+        .into_iter()
+        .filter(|tag| !policy.violations(tag, true).is_empty())
+        .collect();
+    offending.sort();
+    if cleanup && !offending.is_empty() {
+        let canonical: Vec<_> = offending.iter().map(|t| canonicalize(t)).collect();
+        let mut tx = pool.begin().await?;
+        sqlx::query("delete from signal where tag_canonical = any($1)")
+            .bind(&canonical)
+            .execute(&mut tx)
+            .await?;
+        for table in [
+            "tag_meta",
+            "tag_category_proposal",
+            "tag_review_queue",
+            "tag_stats_daily",
+            "tag_implication",
+            "tag_subscription",
+            "tag_translation",
+        ] {
+            sqlx::query(&format!("delete from {} where tag = any($1)", table))
+                .bind(&canonical)
+                .execute(&mut tx)
+                .await?;
+        }
+        sqlx::query("delete from tag_implication where implied_canonical = any($1)")
+            .bind(&canonical)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+    }
+    Ok(offending)
+}
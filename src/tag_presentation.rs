@@ -0,0 +1,173 @@
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Deserializer, Serialize};
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{json_body, reject, ApiError, ErrorWrap, PercentDecoded};
+use crate::routes::Routes;
+use crate::tag::TagName;
+use crate::tag_policy::TagPolicy;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+/// How strongly clients should warn about a tag, e.g. by rendering it in red. Declared from least
+/// to most severe.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, sqlx::Type, ToSchema,
+)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "warning_severity", rename_all = "lowercase")]
+pub enum WarningSeverity {
+    Mild,
+    Moderate,
+    Severe,
+}
+
+/// How clients should render a tag, kept in `tag_meta`.
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagPresentation)]
+pub struct Presentation {
+    /// As `#rrggbb`, lowercased.
+    #[schema(example = "#ff0000")]
+    color: Option<String>,
+    /// Name of an icon from the client's icon set.
+    #[schema(example = "alert-triangle")]
+    icon: Option<String>,
+    warning: Option<WarningSeverity>,
+}
+
+/// Tells a missing field (leave it alone) apart from an explicit `null` (clear it).
+fn present<'de, T: Deserialize<'de>, D: Deserializer<'de>>(
+    d: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(d).map(Some)
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchTagMetaQ {
+    /// `#rrggbb`.
+    #[serde(default, deserialize_with = "present")]
+    color: Option<Option<String>>,
+    /// Name of an icon from the client's icon set, e.g. `heart` or `alert-triangle`.
+    #[serde(default, deserialize_with = "present")]
+    icon: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    warning: Option<Option<WarningSeverity>>,
+}
+
+impl PatchTagMetaQ {
+    fn validate(&self) -> Result<(), ApiError> {
+        if let Some(Some(color)) = &self.color {
+            let valid = color.len() == 7
+                && color.starts_with('#')
+                && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+            if !valid {
+                return Err(ApiError::BadRequest(
+                    "color must be given as #rrggbb".into(),
+                ));
+            }
+        }
+        if let Some(Some(icon)) = &self.icon {
+            let valid = !icon.is_empty()
+                && icon.len() <= 64
+                && icon
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if !valid {
+                return Err(ApiError::BadRequest(
+                    "icon must be a name made of lowercase letters, digits and dashes".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Set how clients should render a tag. Requires the `tag-curation` permission.
+///
+/// Fields missing from the request are left as they are, `null` clears them.
+#[utoipa::path(
+    patch,
+    path = "/tags/{tag}/meta",
+    tag = "tags",
+    params(
+        (
+            "tag" = String,
+            Path,
+            description = "The name of the tag, percent-encoded. An alias is resolved to the tag \
+                it points to.",
+        ),
+    ),
+    request_body = PatchTagMetaQ,
+    responses(
+        (
+            status = 200,
+            description = "The tag's presentation after the update.",
+            body = Presentation,
+        ),
+        (status = 400, description = "A field is malformed.", body = ErrorWrap),
+        (status = 403, description = "The account may not curate tags.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn patch_tag_meta(
+    tag: String,
+    _account: AccountSession,
+    q: PatchTagMetaQ,
+    pool: DB,
+    policy: &TagPolicy,
+) -> Result<Response<Body>, ApiError> {
+    policy.check([tag.as_str()], true)?;
+    q.validate()?;
+    let presentation = async {
+        let tag = TagName::resolve(&tag, &pool).await?;
+        let presentation = sqlx::query_as::<_, Presentation>(
+            "
+insert into tag_meta (tag, color, icon, warning)
+values ($1, $3, $5, $7)
+on conflict (tag) do update set
+    color = case when $2 then excluded.color else tag_meta.color end,
+    icon = case when $4 then excluded.icon else tag_meta.icon end,
+    warning = case when $6 then excluded.warning else tag_meta.warning end
+returning color, icon, warning
+            ",
+        )
+        .bind(&tag.canonical)
+        .bind(q.color.is_some())
+        .bind(q.color.flatten().map(|c| c.to_lowercase()))
+        .bind(q.icon.is_some())
+        .bind(q.icon.flatten())
+        .bind(q.warning.is_some())
+        .bind(q.warning.flatten())
+        .fetch_one(&pool)
+        .await?;
+        eyre::Result::<_>::Ok(presentation)
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to update tag meta: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&presentation).into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(patch_tag_meta))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let patch_tag_meta = warp::path!("v1" / "tags" / PercentDecoded / "meta")
+        .and(warp::patch())
+        .and(ctx.require_permission(Permission::TagCuration))
+        .and(json_body::<PatchTagMetaQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |tag: PercentDecoded, account, q, pool| {
+            patch_tag_meta(tag.0, account, q, pool, ctx.tag_policy)
+        })
+        .and_then(reject);
+    patch_tag_meta.map(Reply::into_response).boxed()
+}
@@ -0,0 +1,461 @@
+use chrono::{DateTime, Utc};
+use eyre::WrapErr;
+use http::{Response, StatusCode};
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use tap::prelude::*;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{
+    reply::{json, with_status},
+    Filter, Reply,
+};
+
+use crate::context::Context;
+use crate::httputil::{json_body, reject, reply_json, ApiError, ErrorWrap};
+use crate::routes::Routes;
+use crate::tag::{canonicalize, merge_in, validate_migration};
+use crate::tag_policy::TagPolicy;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "proposal_status", rename_all = "lowercase")]
+pub enum ProposalStatus {
+    Open,
+    Approved,
+    Rejected,
+}
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagAliasProposal)]
+pub struct Proposal {
+    id: i64,
+    /// The tag that would become an alias.
+    from: String,
+    into: String,
+    /// ID of the proposing account.
+    proposed_by: i64,
+    status: ProposalStatus,
+    votes: i64,
+    /// Whether the current account voted for the proposal; `null` if not logged in.
+    voted: Option<bool>,
+    created_at: DateTime<Utc>,
+}
+
+const SELECT_PROPOSAL: &str = r#"
+select
+    p.id,
+    p.from_tag as "from",
+    p.into_tag as "into",
+    p.proposed_by,
+    p.status,
+    (select count(1) from tag_alias_proposal_vote v where v.proposal_id = p.id) as votes,
+    case when $1::bigint is not null then exists(
+        select 1 from tag_alias_proposal_vote v where v.proposal_id = p.id and v.account_id = $1
+    ) end as voted,
+    p.created_at
+from tag_alias_proposal p
+"#;
+
+impl Proposal {
+    async fn get(id: i64, viewer: Option<i64>, pool: &DB) -> eyre::Result<Option<Self>> {
+        Ok(
+            sqlx::query_as::<_, Self>(&format!("{} where p.id = $2", SELECT_PROPOSAL))
+                .bind(viewer)
+                .bind(id)
+                .fetch_optional(pool)
+                .await?,
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListProposalsQ {
+    /// Open ones by default.
+    status: Option<ProposalStatus>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Proposals {
+    proposals: Vec<Proposal>,
+}
+
+impl Proposals {
+    /// Lists proposals with the given status (open ones by default), most voted first.
+    pub async fn get(q: ListProposalsQ, viewer: Option<i64>, pool: &DB) -> eyre::Result<Self> {
+        Ok(Self {
+            proposals: sqlx::query_as::<_, Proposal>(&format!(
+                "{} where p.status = $2 order by votes desc, p.created_at, p.id",
+                SELECT_PROPOSAL
+            ))
+            .bind(viewer)
+            .bind(q.status.unwrap_or(ProposalStatus::Open))
+            .fetch_all(pool)
+            .await?,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateProposalQ {
+    /// The tag that would become an alias.
+    from: String,
+    into: String,
+}
+
+/// Propose to merge one tag into another, making it an alias.
+///
+/// The proposer's vote is counted right away. Proposing a merge that is already open adds a vote to
+/// the existing proposal instead.
+#[utoipa::path(
+    post,
+    path = "/tags/proposals",
+    tag = "tags",
+    request_body = CreateProposalQ,
+    responses(
+        (status = 200, description = "The same merge was already proposed.", body = Proposal),
+        (status = 201, description = "The proposal was created.", body = Proposal),
+        (status = 400, description = "The source and target tag are the same.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn create_tag_proposal(
+    account: AccountSession,
+    q: CreateProposalQ,
+    pool: DB,
+    policy: &TagPolicy,
+) -> Result<Response<Body>, ApiError> {
+    validate_migration(&q.from, &q.into)?;
+    policy.check([q.into.as_str()], false)?;
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let existing = sqlx::query_scalar::<_, i64>(
+            "
+select id from tag_alias_proposal
+where from_canonical = $1 and into_canonical = $2 and status = 'open'
+            ",
+        )
+        .bind(canonicalize(&q.from))
+        .bind(canonicalize(&q.into))
+        .fetch_optional(&mut tx)
+        .await?;
+        let (id, created) = match existing {
+            Some(id) => (id, false),
+            None => {
+                let id = sqlx::query_scalar::<_, i64>(
+                    "
+insert into tag_alias_proposal (from_tag, from_canonical, into_tag, into_canonical, proposed_by)
+values ($1, $2, $3, $4, $5)
+returning id
+                    ",
+                )
+                .bind(&q.from)
+                .bind(canonicalize(&q.from))
+                .bind(&q.into)
+                .bind(canonicalize(&q.into))
+                .bind(account.id)
+                .fetch_one(&mut tx)
+                .await?;
+                (id, true)
+            }
+        };
+        vote(&mut tx, id, account.id).await?;
+        tx.commit().await?;
+        let proposal = Proposal::get(id, Some(account.id), &pool).await?;
+        eyre::Result::<_>::Ok((proposal, created))
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to create tag alias proposal: {:?}", e);
+        ApiError::Internal
+    })?;
+    match result {
+        (Some(proposal), true) => Ok(json(&proposal)
+            .pipe(|r| with_status(r, StatusCode::CREATED))
+            .into_response()),
+        (Some(proposal), false) => Ok(json(&proposal).into_response()),
+        (None, _) => Err(ApiError::Internal),
+    }
+}
+
+async fn vote(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    id: i64,
+    account_id: i64,
+) -> eyre::Result<()> {
+    sqlx::query(
+        "
+insert into tag_alias_proposal_vote (proposal_id, account_id)
+values ($1, $2)
+on conflict do nothing
+        ",
+    )
+    .bind(id)
+    .bind(account_id)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// Vote for an open proposal.
+#[utoipa::path(
+    put,
+    path = "/tags/proposals/{proposalId}/vote",
+    tag = "tags",
+    params(
+        ("proposalId" = i64, Path),
+    ),
+    responses(
+        (status = 200, description = "The updated proposal.", body = Proposal),
+        (status = 404, description = "There is no open proposal with this ID.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn vote_tag_proposal(
+    id: i64,
+    account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    set_vote(id, account, true, pool).await
+}
+
+/// Withdraw a vote for an open proposal.
+#[utoipa::path(
+    delete,
+    path = "/tags/proposals/{proposalId}/vote",
+    tag = "tags",
+    params(
+        ("proposalId" = i64, Path),
+    ),
+    responses(
+        (status = 200, description = "The updated proposal.", body = Proposal),
+        (status = 404, description = "There is no open proposal with this ID.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn unvote_tag_proposal(
+    id: i64,
+    account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    set_vote(id, account, false, pool).await
+}
+
+/// Adds (`add = true`) or withdraws the current account's vote for an open proposal.
+async fn set_vote(
+    id: i64,
+    account: AccountSession,
+    add: bool,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let is_open = sqlx::query_scalar::<_, bool>(
+            "select exists(select 1 from tag_alias_proposal where id = $1 and status = 'open')",
+        )
+        .bind(id)
+        .fetch_one(&mut tx)
+        .await?;
+        if !is_open {
+            return Ok(None);
+        }
+        if add {
+            vote(&mut tx, id, account.id).await?;
+        } else {
+            sqlx::query(
+                "delete from tag_alias_proposal_vote where proposal_id = $1 and account_id = $2",
+            )
+            .bind(id)
+            .bind(account.id)
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Proposal::get(id, Some(account.id), &pool).await
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to vote on tag alias proposal: {:?}", e);
+        ApiError::Internal
+    })?;
+    match result {
+        Some(proposal) => Ok(json(&proposal).into_response()),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DecideProposalQ {
+    approve: bool,
+}
+
+/// Approve or reject an open proposal. Requires the `tag-curation` permission.
+///
+/// Approving merges the tags the same way `POST /tags/merge` does.
+#[utoipa::path(
+    post,
+    path = "/tags/proposals/{proposalId}/decision",
+    tag = "tags",
+    params(
+        ("proposalId" = i64, Path),
+    ),
+    request_body = DecideProposalQ,
+    responses(
+        (status = 200, description = "The closed proposal.", body = Proposal),
+        (
+            status = 400,
+            description = "The merge is no longer possible, e.g. because the source tag became \
+                an alias in the meantime.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "The account may not curate tags.", body = ErrorWrap),
+        (status = 404, description = "There is no open proposal with this ID.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn decide_tag_proposal(
+    id: i64,
+    account: AccountSession,
+    q: DecideProposalQ,
+    pool: DB,
+    tombstone_days: i32,
+) -> Result<Response<Body>, ApiError> {
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let tags = sqlx::query_as::<_, (String, String)>(
+            "
+select from_tag, into_tag from tag_alias_proposal
+where id = $1 and status = 'open'
+for update
+            ",
+        )
+        .bind(id)
+        .fetch_optional(&mut tx)
+        .await?;
+        let (from, into) = match tags {
+            Some(tags) => tags,
+            None => return Ok(Err(ApiError::NotFound)),
+        };
+        if q.approve {
+            if let Err(bad_request) =
+                merge_in(&mut tx, account.id, &from, &into, tombstone_days).await?
+            {
+                return Ok(Err(bad_request));
+            }
+        }
+        sqlx::query(
+            "
+update tag_alias_proposal
+set status = $2, decided_by = $3, decided_at = now()
+where id = $1
+            ",
+        )
+        .bind(id)
+        .bind(if q.approve {
+            ProposalStatus::Approved
+        } else {
+            ProposalStatus::Rejected
+        })
+        .bind(account.id)
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        eyre::Result::<_>::Ok(Ok(Proposal::get(id, Some(account.id), &pool).await?))
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to decide tag alias proposal: {:?}", e);
+        ApiError::Internal
+    })?;
+    match result? {
+        Some(proposal) => Ok(json(&proposal).into_response()),
+        None => Err(ApiError::Internal),
+    }
+}
+
+/// List proposals to merge one tag into another.
+#[utoipa::path(
+    get,
+    path = "/tags/proposals",
+    tag = "tags",
+    params(ListProposalsQ),
+    responses(
+        (
+            status = 200,
+            description = "Proposals with the requested status (open by default), most voted \
+                first.",
+            body = Proposals,
+        ),
+    ),
+    security(("cookieAuth" = []), ()),
+)]
+async fn get_tag_proposals(
+    account: Option<AccountSession>,
+    q: ListProposalsQ,
+    pool: DB,
+) -> eyre::Result<Proposals> {
+    Proposals::get(q, account.map(|a| a.id), &pool)
+        .await
+        .wrap_err("failed to list tag alias proposals")
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_tag_proposals,
+    create_tag_proposal,
+    vote_tag_proposal,
+    unvote_tag_proposal,
+    decide_tag_proposal,
+))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_tag_proposals = warp::path!("v1" / "tags" / "proposals")
+        .and(warp::get())
+        .and(ctx.optional_authenticate())
+        .and(warp::query::<ListProposalsQ>())
+        .and(ctx.pool())
+        .then(get_tag_proposals)
+        .and_then(reply_json);
+    let create_tag_proposal = warp::path!("v1" / "tags" / "proposals")
+        .and(warp::post())
+        .and(ctx.authenticate())
+        .and(json_body::<CreateProposalQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |account, q, pool| create_tag_proposal(account, q, pool, ctx.tag_policy))
+        .and_then(reject);
+    let vote_tag_proposal = warp::path!("v1" / "tags" / "proposals" / i64 / "vote")
+        .and(warp::put())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(vote_tag_proposal)
+        .and_then(reject);
+    let unvote_tag_proposal = warp::path!("v1" / "tags" / "proposals" / i64 / "vote")
+        .and(warp::delete())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(unvote_tag_proposal)
+        .and_then(reject);
+    let decide_tag_proposal = warp::path!("v1" / "tags" / "proposals" / i64 / "decision")
+        .and(warp::post())
+        .and(ctx.require_permission(Permission::TagCuration))
+        .and(json_body::<DecideProposalQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |id, account, q, pool| {
+            decide_tag_proposal(id, account, q, pool, ctx.tag_tombstone_days)
+        })
+        .and_then(reject);
+    get_tag_proposals
+        .or(create_tag_proposal)
+        .or(vote_tag_proposal)
+        .or(unvote_tag_proposal)
+        .or(decide_tag_proposal)
+        .map(Reply::into_response)
+        .boxed()
+}
@@ -0,0 +1,219 @@
+use chrono::{DateTime, Utc};
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{json_body, reject, ApiError, ErrorWrap, PercentDecoded};
+use crate::routes::Routes;
+use crate::tag::{canonicalize, delete_in, merge_in, rename_in, validate_migration};
+use crate::tag_policy::TagPolicy;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedTag {
+    tag: String,
+    usage_count: i64,
+    /// The account that used the tag first.
+    account_id: i64,
+    /// The fic the tag was first used on.
+    url: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagReviewQueue)]
+pub struct ReviewQueue {
+    tags: Vec<QueuedTag>,
+}
+
+/// List tags that were used for the first time and await review.
+///
+/// Requires the `tag-curation` permission.
+#[utoipa::path(
+    get,
+    path = "/tags/review-queue",
+    tag = "tags",
+    responses(
+        (status = 200, description = "Queued tags, oldest first.", body = ReviewQueue),
+        (status = 403, description = "The account may not curate tags.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_tag_review_queue(
+    _account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let tags = sqlx::query_as::<_, QueuedTag>(
+        "
+select
+    q.display as tag,
+    (select count(1) from signal s where s.tag_canonical = q.tag) as usage_count,
+    q.account_id,
+    q.url,
+    q.created_at
+from tag_review_queue q
+order by q.created_at, q.tag
+        ",
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to load tag review queue: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&ReviewQueue { tags }).into_response())
+}
+
+/// What a moderator decided to do with a queued tag.
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum ReviewDecision {
+    /// Keep the tag as it is.
+    Approve,
+    Rename {
+        /// The new name.
+        to: String,
+    },
+    /// Merge the tag into an existing one, keeping it as an alias.
+    Alias {
+        /// The tag to merge into.
+        into: String,
+    },
+    Delete,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewOutcome {
+    tag: String,
+    /// Signals that were renamed, merged or deleted.
+    signals_affected: u64,
+}
+
+/// Approve, rename, alias or delete a queued tag, taking it off the queue.
+///
+/// Requires the `tag-curation` permission.
+#[utoipa::path(
+    post,
+    path = "/tags/review-queue/{tag}",
+    tag = "tags",
+    params(
+        ("tag" = String, Path, description = "The name of the tag, percent-encoded."),
+    ),
+    request_body = ReviewDecision,
+    responses(
+        (
+            status = 200,
+            description = "The decision was applied.",
+            body = ReviewOutcome,
+        ),
+        (
+            status = 400,
+            description = "The decision is invalid, e.g. renaming a tag to itself.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "The account may not curate tags.", body = ErrorWrap),
+        (status = 404, description = "The tag isn't queued for review.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn review_tag(
+    tag: String,
+    account: AccountSession,
+    decision: ReviewDecision,
+    pool: DB,
+    tombstone_days: i32,
+    policy: &TagPolicy,
+) -> Result<Response<Body>, ApiError> {
+    match &decision {
+        ReviewDecision::Rename { to } => {
+            validate_migration(&tag, to)?;
+            policy.check([to.as_str()], true)?;
+        }
+        ReviewDecision::Alias { into } => {
+            validate_migration(&tag, into)?;
+            policy.check([into.as_str()], true)?;
+        }
+        ReviewDecision::Approve | ReviewDecision::Delete => {}
+    }
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let display = sqlx::query_scalar::<_, String>(
+            "delete from tag_review_queue where tag = $1 returning display",
+        )
+        .bind(canonicalize(&tag))
+        .fetch_optional(&mut tx)
+        .await?;
+        let display = match display {
+            Some(display) => display,
+            None => return Ok(Err(ApiError::NotFound)),
+        };
+        let signals_affected = match decision {
+            ReviewDecision::Approve => 0,
+            ReviewDecision::Rename { to } => {
+                rename_in(&mut tx, account.id, &display, &to, tombstone_days)
+                    .await?
+                    .map_or(0, |m| m.signals_affected())
+            }
+            ReviewDecision::Alias { into } => {
+                match merge_in(&mut tx, account.id, &display, &into, tombstone_days).await? {
+                    Ok(m) => m.signals_affected(),
+                    Err(bad_request) => return Ok(Err(bad_request)),
+                }
+            }
+            ReviewDecision::Delete => {
+                delete_in(&mut tx, account.id, &display, tombstone_days).await?
+            }
+        };
+        tx.commit().await?;
+        eyre::Result::<_>::Ok(Ok(ReviewOutcome {
+            tag: display,
+            signals_affected,
+        }))
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to review tag: {:?}", e);
+        ApiError::Internal
+    })?;
+    result.map(|outcome| json(&outcome).into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_tag_review_queue, review_tag))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_tag_review_queue = warp::path!("v1" / "tags" / "review-queue")
+        .and(warp::get())
+        .and(ctx.require_permission(Permission::TagCuration))
+        .and(ctx.pool())
+        .then(get_tag_review_queue)
+        .and_then(reject);
+    let review_tag = warp::path!("v1" / "tags" / "review-queue" / PercentDecoded)
+        .and(warp::post())
+        .and(ctx.require_permission(Permission::TagCuration))
+        .and(json_body::<ReviewDecision>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |tag: PercentDecoded, account, decision, pool| {
+            review_tag(
+                tag.0,
+                account,
+                decision,
+                pool,
+                ctx.tag_tombstone_days,
+                ctx.tag_policy,
+            )
+        })
+        .and_then(reject);
+    get_tag_review_queue
+        .or(review_tag)
+        .map(Reply::into_response)
+        .boxed()
+}
@@ -0,0 +1,133 @@
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::reply_json;
+use crate::routes::Routes;
+use crate::DB;
+
+const MAX_SEARCH_LIMIT: i64 = 100;
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchTagsQ {
+    #[param(example = "taylor alt power")]
+    q: String,
+    /// An optional maximum limit to the number of returned results.
+    #[param(default = 20, minimum = 0, maximum = 100)]
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TagSearchResult {
+    tag: String,
+    description: Option<String>,
+    rank: f32,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TagSearch {
+    tags: Vec<TagSearchResult>,
+}
+
+impl TagSearch {
+    /// Searches tag names and descriptions with Postgres full-text search, so that every word of
+    /// a query like "taylor alt power" has to appear somewhere, in any order and inflection.
+    /// Matches in the name rank above matches in the description; ties go to the more used tag.
+    ///
+    /// Candidates are found through the indexed `signal.tag_document`, which has the name, and
+    /// `tag_meta.document`, which has both, then checked against the latter where there is one, so
+    /// that e.g. `-power` also excludes tags with "power" in their description.
+    pub async fn get(q: SearchTagsQ, pool: &DB) -> eyre::Result<Self> {
+        Ok(Self {
+            tags: sqlx::query_as::<_, TagSearchResult>(
+                "
+with query as (
+    select websearch_to_tsquery('english', $1) as query
+),
+matching as (
+    select s.tag_canonical
+    from signal s, query q
+    where s.tag_document @@ q.query
+    union
+    select m.tag
+    from tag_meta m, query q
+    where m.document @@ q.query
+),
+tag as (
+    select s.tag_canonical, mode() within group (order by s.tag) as tag, count(1) as uses
+    from signal s
+    join matching using (tag_canonical)
+    group by s.tag_canonical
+),
+document as (
+    select
+        t.tag,
+        m.description,
+        t.uses,
+        coalesce(m.document, setweight(to_tsvector('english', t.tag_canonical), 'A')) as document
+    from tag t
+    left join tag_meta m
+        on m.tag = t.tag_canonical
+)
+select d.tag, d.description, ts_rank(d.document, q.query) as rank
+from document d, query q
+where d.document @@ q.query
+order by rank desc, d.uses desc, d.tag asc
+limit $2
+                ",
+            )
+            .bind(&q.q)
+            .bind(
+                q.limit
+                    .unwrap_or(DEFAULT_SEARCH_LIMIT)
+                    .clamp(0, MAX_SEARCH_LIMIT),
+            )
+            .fetch_all(pool)
+            .await?,
+        })
+    }
+}
+
+/// Search tag names and descriptions.
+///
+/// Uses full-text search: every word of the query has to appear in the tag's name or description,
+/// in any order and inflection. Supports `"quoted phrases"`, `or` and `-word` to exclude a word.
+/// Matches in the name rank above matches in the description.
+#[utoipa::path(
+    get,
+    path = "/tags/search",
+    tag = "tags",
+    params(SearchTagsQ),
+    responses(
+        (
+            status = 200,
+            description = "Matching tags, best first.",
+            body = TagSearch,
+        ),
+    ),
+)]
+async fn search_tags(q: SearchTagsQ, pool: DB) -> eyre::Result<TagSearch> {
+    TagSearch::get(q, &pool)
+        .await
+        .wrap_err("failed to search tags")
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(search_tags))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let search_tags = warp::path!("v1" / "tags" / "search")
+        .and(warp::get())
+        .and(warp::query::<SearchTagsQ>())
+        .and(ctx.pool())
+        .then(search_tags)
+        .and_then(reply_json);
+    search_tags.map(Reply::into_response).boxed()
+}
@@ -0,0 +1,175 @@
+use chrono::NaiveDate;
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{reply_json, ErrorWrap, PercentDecoded, TimeWindow};
+use crate::routes::Routes;
+use crate::tag::TagName;
+use crate::DB;
+
+/// Recomputes `tag_stats_daily` from scratch. Signals are counted on the day they were created,
+/// under the tag they are on now, so renames and merges are reflected after the next run.
+/// Returns the number of rows written.
+pub async fn rollup(pool: &DB) -> eyre::Result<u64> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("delete from tag_stats_daily")
+        .execute(&mut tx)
+        .await?;
+    let written = sqlx::query(
+        "
+insert into tag_stats_daily (tag, day, signals_for, signals_against)
+select
+    tag_canonical,
+    (created_at at time zone 'UTC')::date,
+    count(1) filter (where signal),
+    count(1) filter (where not signal)
+from signal
+group by 1, 2
+        ",
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    tx.commit().await?;
+    Ok(written)
+}
+
+/// Spawns a task that runs [`rollup`] every `interval`.
+pub fn spawn_rollup(pool: DB, interval: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = rollup(&pool).await {
+                eprintln!("tag stats rollup failed: {:?}", e);
+            }
+        }
+    });
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Bucket {
+    Day,
+    Week,
+}
+
+impl Bucket {
+    /// As understood by `date_trunc`.
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Bucket::Day => "day",
+            Bucket::Week => "week",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TagStatsQ {
+    #[param(inline, default = "day")]
+    bucket: Option<Bucket>,
+    /// How far back to go.
+    #[param(inline, default = "90d")]
+    window: Option<TimeWindow>,
+}
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsPoint {
+    /// First day of the bucket, in the time zone of the account asking. Weeks start on Monday.
+    start: NaiveDate,
+    signals_for: i64,
+    signals_against: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TagStats {
+    tag: String,
+    bucket: Bucket,
+    /// Every bucket in the window, oldest first, including those without any signals.
+    series: Vec<StatsPoint>,
+}
+
+impl TagStats {
+    /// The window defaults to 90 days. Counts are as of the last rollup run.
+    pub async fn get(tag: &str, q: TagStatsQ, pool: &DB) -> eyre::Result<Self> {
+        let tag = TagName::resolve(tag, pool).await?;
+        let bucket = q.bucket.unwrap_or(Bucket::Day);
+        let window = q.window.map_or(chrono::Duration::days(90), |w| w.0);
+        let since = (chrono::Utc::now() - window).date_naive();
+        let series = sqlx::query_as::<_, StatsPoint>(
+            "
+with bucket as (
+    select generate_series(
+        date_trunc($3, $2::date),
+        date_trunc($3, now() at time zone 'UTC'),
+        ('1 ' || $3)::interval
+    )::date as start
+)
+select
+    b.start,
+    coalesce(sum(s.signals_for), 0)::bigint as signals_for,
+    coalesce(sum(s.signals_against), 0)::bigint as signals_against
+from bucket b
+left join tag_stats_daily s
+    on s.tag = $1 and s.day >= $2
+        and date_trunc($3, s.day)::date = b.start
+group by b.start
+order by b.start
+            ",
+        )
+        .bind(&tag.canonical)
+        .bind(since)
+        .bind(bucket.as_str())
+        .fetch_all(pool)
+        .await?;
+        Ok(Self {
+            tag: tag.display,
+            bucket,
+            series,
+        })
+    }
+}
+
+/// Get the number of signals on a tag over time.
+///
+/// Signals are counted in the bucket they were created in, under the tag they are on now. Counts
+/// are computed periodically, so the most recent signals may be missing. An alias is resolved to
+/// the tag it points to.
+#[utoipa::path(
+    get,
+    path = "/tags/{tag}/stats",
+    tag = "tags",
+    params(
+        ("tag" = String, Path, description = "The name of the tag, percent-encoded."),
+        TagStatsQ,
+    ),
+    responses(
+        (status = 200, description = "Success.", body = TagStats),
+        (status = 400, description = "The bucket or window is invalid.", body = ErrorWrap),
+    ),
+)]
+async fn get_tag_stats(tag: PercentDecoded, q: TagStatsQ, pool: DB) -> eyre::Result<TagStats> {
+    TagStats::get(&tag.0, q, &pool)
+        .await
+        .wrap_err("failed to get tag stats")
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_tag_stats))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_tag_stats = warp::path!("v1" / "tags" / PercentDecoded / "stats")
+        .and(warp::get())
+        .and(warp::query::<TagStatsQ>())
+        .and(ctx.pool())
+        .then(get_tag_stats)
+        .and_then(reply_json);
+    get_tag_stats.map(Reply::into_response).boxed()
+}
@@ -0,0 +1,334 @@
+use chrono::{DateTime, Utc};
+use eyre::WrapErr;
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::api_token::Scope;
+use crate::context::Context;
+use crate::httputil::{reject, reply_json, ApiError, Empty, ErrorWrap, PercentDecoded};
+use crate::routes::Routes;
+use crate::tag::{canonicalize, TagName};
+use crate::tag_policy::TagPolicy;
+use crate::usermgmt::AccountSession;
+use crate::DB;
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagSubscription)]
+pub struct Subscription {
+    tag: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagSubscriptions)]
+pub struct Subscriptions {
+    subscriptions: Vec<Subscription>,
+}
+
+impl Subscriptions {
+    pub async fn get(uid: i64, pool: &DB) -> eyre::Result<Self> {
+        Ok(Self {
+            subscriptions: sqlx::query_as::<_, Subscription>(
+                "
+select display as tag, created_at as subscribed_at
+from tag_subscription
+where account_id = $1
+order by tag
+                ",
+            )
+            .bind(uid)
+            .fetch_all(pool)
+            .await?,
+        })
+    }
+}
+
+/// Follow a tag, so that fics tagged with it show up in the feed.
+///
+/// Following a tag that is already followed is not an error.
+#[utoipa::path(
+    put,
+    path = "/tags/{tag}/subscription",
+    tag = "tags",
+    params(
+        (
+            "tag" = String,
+            Path,
+            description = "The name of the tag, percent-encoded. An alias is resolved to the tag \
+                it points to.",
+        ),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = Subscription),
+        (status = 400, description = "The tag violates the tag policy.", body = ErrorWrap),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn subscribe_tag(
+    tag: String,
+    account: AccountSession,
+    pool: DB,
+    policy: &TagPolicy,
+) -> Result<Response<Body>, ApiError> {
+    policy.check([tag.as_str()], true)?;
+    let subscription = async {
+        let tag = TagName::resolve(&tag, &pool).await?;
+        let subscription = sqlx::query_as::<_, Subscription>(
+            "
+insert into tag_subscription (account_id, tag, display)
+values ($1, $2, $3)
+on conflict (account_id, tag) do update set display = tag_subscription.display
+returning display as tag, created_at as subscribed_at
+            ",
+        )
+        .bind(account.id)
+        .bind(&tag.canonical)
+        .bind(&tag.display)
+        .fetch_one(&pool)
+        .await?;
+        eyre::Result::<_>::Ok(subscription)
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to subscribe to tag: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&subscription).into_response())
+}
+
+/// Stop following a tag.
+#[utoipa::path(
+    delete,
+    path = "/tags/{tag}/subscription",
+    tag = "tags",
+    params(
+        (
+            "tag" = String,
+            Path,
+            description = "The name of the tag, percent-encoded. An alias is resolved to the tag \
+                it points to.",
+        ),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 404, description = "The tag isn't followed.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn unsubscribe_tag(
+    tag: String,
+    account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let deleted = async {
+        let tag = TagName::resolve(&tag, &pool).await?;
+        let deleted =
+            sqlx::query("delete from tag_subscription where account_id = $1 and tag = $2")
+                .bind(account.id)
+                .bind(&tag.canonical)
+                .execute(&pool)
+                .await?
+                .rows_affected();
+        eyre::Result::<_>::Ok(deleted)
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to unsubscribe from tag: {:?}", e);
+        ApiError::Internal
+    })?;
+    if deleted == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(json(&Empty {}).into_response())
+}
+
+/// Moves subscriptions to `from` over to `to` as part of a tag migration.
+pub(crate) async fn migrate(
+    tx: &mut Transaction<'_, Postgres>,
+    from: &str,
+    to: &str,
+) -> eyre::Result<()> {
+    sqlx::query(
+        "
+insert into tag_subscription (account_id, tag, display, created_at)
+select account_id, $2, $3, created_at
+from tag_subscription
+where tag = $1
+on conflict (account_id, tag) do nothing
+        ",
+    )
+    .bind(canonicalize(from))
+    .bind(canonicalize(to))
+    .bind(to)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("delete from tag_subscription where tag = $1")
+        .bind(canonicalize(from))
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct FeedQ {
+    #[param(default = 50, minimum = 0, maximum = 200)]
+    limit: Option<i64>,
+    /// Only return fics last tagged before this, to fetch the page after one ending there.
+    before: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedEntry {
+    url: String,
+    /// Followed tags the fic was tagged with, most signalled first.
+    tags: Vec<String>,
+    /// Signals for the followed tags, summed up.
+    signals_for: i64,
+    last_tagged_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Feed {
+    entries: Vec<FeedEntry>,
+}
+
+const DEFAULT_FEED_LIMIT: i64 = 50;
+const MAX_FEED_LIMIT: i64 = 200;
+
+impl Feed {
+    /// Fics other accounts recently signalled for with any of the tags `uid` follows, most
+    /// recently tagged first.
+    pub async fn get(uid: i64, q: FeedQ, pool: &DB) -> eyre::Result<Self> {
+        Ok(Self {
+            entries: sqlx::query_as::<_, FeedEntry>(
+                "
+with hit as (
+    select
+        s.url,
+        mode() within group (order by s.tag) as tag,
+        count(1) as signals_for,
+        max(s.created_at) as last_tagged_at
+    from signal s
+    join tag_subscription f
+        on f.tag = s.tag_canonical and f.account_id = $1
+    where s.signal and s.account_id <> $1
+    group by s.url, s.tag_canonical
+)
+select
+    url,
+    array_agg(tag order by signals_for desc, tag) as tags,
+    sum(signals_for)::bigint as signals_for,
+    max(last_tagged_at) as last_tagged_at
+from hit
+group by url
+having $2::timestamptz is null or max(last_tagged_at) < $2
+order by last_tagged_at desc, url
+limit $3
+                ",
+            )
+            .bind(uid)
+            .bind(q.before)
+            .bind(
+                q.limit
+                    .unwrap_or(DEFAULT_FEED_LIMIT)
+                    .clamp(0, MAX_FEED_LIMIT),
+            )
+            .fetch_all(pool)
+            .await?,
+        })
+    }
+}
+
+/// Get fics recently tagged with any of the tags the current account follows.
+///
+/// Only signals for a tag by other accounts count. Fics are ordered by when they were last tagged,
+/// most recent first; pass `lastTaggedAt` of the last entry as `before` to get the next page.
+#[utoipa::path(
+    get,
+    path = "/feed",
+    tag = "signals",
+    params(FeedQ),
+    responses(
+        (status = 200, description = "Success.", body = Feed),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = []), ("bearerAuth" = [])),
+)]
+async fn get_feed(account: AccountSession, q: FeedQ, pool: DB) -> eyre::Result<Feed> {
+    Feed::get(account.id, q, &pool)
+        .await
+        .wrap_err("failed to get feed")
+}
+
+/// List the tags the current account follows.
+#[utoipa::path(
+    get,
+    path = "/tags/subscriptions",
+    tag = "tags",
+    responses(
+        (
+            status = 200,
+            description = "Success.",
+            body = Subscriptions,
+        ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+async fn get_tag_subscriptions(account: AccountSession, pool: DB) -> eyre::Result<Subscriptions> {
+    Subscriptions::get(account.id, &pool)
+        .await
+        .wrap_err("failed to list tag subscriptions")
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_feed, get_tag_subscriptions, subscribe_tag, unsubscribe_tag))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_feed = warp::path!("v1" / "feed")
+        .and(warp::get())
+        .and(ctx.authenticate_scoped(Scope::Read))
+        .and(warp::query::<FeedQ>())
+        .and(ctx.pool())
+        .then(get_feed)
+        .and_then(reply_json);
+    let get_tag_subscriptions = warp::path!("v1" / "tags" / "subscriptions")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(get_tag_subscriptions)
+        .and_then(reply_json);
+    let subscribe_tag = warp::path!("v1" / "tags" / PercentDecoded / "subscription")
+        .and(warp::put())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(move |tag: PercentDecoded, account, pool| {
+            subscribe_tag(tag.0, account, pool, ctx.tag_policy)
+        })
+        .and_then(reject);
+    let unsubscribe_tag = warp::path!("v1" / "tags" / PercentDecoded / "subscription")
+        .and(warp::delete())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(|tag: PercentDecoded, account, pool| unsubscribe_tag(tag.0, account, pool))
+        .and_then(reject);
+    get_feed
+        .or(get_tag_subscriptions)
+        .or(subscribe_tag)
+        .or(unsubscribe_tag)
+        .map(Reply::into_response)
+        .boxed()
+}
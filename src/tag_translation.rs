@@ -0,0 +1,261 @@
+use eyre::WrapErr;
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{json_body, reject, reply_json, ApiError, Empty, ErrorWrap, PercentDecoded};
+use crate::routes::Routes;
+use crate::tag::{canonicalize, TagName};
+use crate::tag_policy::TagPolicy;
+use crate::usermgmt::{AccountSession, Permission};
+use crate::DB;
+
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagTranslation)]
+pub struct Translation {
+    #[schema(example = "de")]
+    locale: String,
+    label: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TagTranslations)]
+pub struct Translations {
+    translations: Vec<Translation>,
+}
+
+impl Translations {
+    pub async fn get(tag: &str, pool: &DB) -> eyre::Result<Self> {
+        let tag = TagName::resolve(tag, pool).await?;
+        Ok(Self {
+            translations: sqlx::query_as::<_, Translation>(
+                "select locale, label from tag_translation where tag = $1 order by locale",
+            )
+            .bind(&tag.canonical)
+            .fetch_all(pool)
+            .await?,
+        })
+    }
+}
+
+/// List the labels a tag is displayed with in other languages.
+#[utoipa::path(
+    get,
+    path = "/tags/{tag}/translations",
+    tag = "tags",
+    params(
+        ("tag" = String, Path, description = "The name of the tag, percent-encoded."),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Success.",
+            body = Translations,
+        ),
+    ),
+)]
+async fn get_tag_translations(tag: PercentDecoded, pool: DB) -> eyre::Result<Translations> {
+    Translations::get(&tag.0, &pool)
+        .await
+        .wrap_err("failed to get tag translations")
+}
+
+/// Accepts BCP 47 language tags like `de` or `pt-BR`, stored lowercased.
+fn parse_locale(locale: &str) -> Result<String, ApiError> {
+    let valid = !locale.is_empty()
+        && locale.len() <= 35
+        && locale
+            .split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()));
+    if !valid {
+        return Err(ApiError::BadRequest("invalid locale".into()));
+    }
+    Ok(locale.to_lowercase())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TranslationQ {
+    label: String,
+}
+
+/// Set the label of a tag in a language. Requires the `tag-curation` permission.
+///
+/// Signals on the label are stored on the tag, as long as no tag with the label's name is in use.
+#[utoipa::path(
+    put,
+    path = "/tags/{tag}/translations/{locale}",
+    tag = "tags",
+    params(
+        ("tag" = String, Path, description = "The name of the tag, percent-encoded."),
+        (
+            "locale" = String,
+            Path,
+            description = "A BCP 47 language tag, e.g. `de` or `pt-BR`. Case-insensitive.",
+        ),
+    ),
+    request_body = TranslationQ,
+    responses(
+        (status = 200, description = "Success.", body = Translation),
+        (
+            status = 400,
+            description = "The locale is invalid, or the label violates the tag policy.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "The account may not curate tags.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn put_tag_translation(
+    tag: String,
+    locale: String,
+    _account: AccountSession,
+    q: TranslationQ,
+    pool: DB,
+    policy: &TagPolicy,
+) -> Result<Response<Body>, ApiError> {
+    let locale = parse_locale(&locale)?;
+    policy.check([tag.as_str(), q.label.as_str()], true)?;
+    let translation = async {
+        let tag = TagName::resolve(&tag, &pool).await?;
+        let translation = sqlx::query_as::<_, Translation>(
+            "
+insert into tag_translation (tag, locale, label, label_canonical)
+values ($1, $2, $3, $4)
+on conflict (tag, locale) do update set
+    label = excluded.label,
+    label_canonical = excluded.label_canonical
+returning locale, label
+            ",
+        )
+        .bind(&tag.canonical)
+        .bind(&locale)
+        .bind(&q.label)
+        .bind(canonicalize(&q.label))
+        .fetch_one(&pool)
+        .await?;
+        eyre::Result::<_>::Ok(translation)
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to set tag translation: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&translation).into_response())
+}
+
+/// Remove the label of a tag in a language. Requires the `tag-curation` permission.
+#[utoipa::path(
+    delete,
+    path = "/tags/{tag}/translations/{locale}",
+    tag = "tags",
+    params(
+        ("tag" = String, Path, description = "The name of the tag, percent-encoded."),
+        (
+            "locale" = String,
+            Path,
+            description = "A BCP 47 language tag, e.g. `de` or `pt-BR`. Case-insensitive.",
+        ),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (status = 403, description = "The account may not curate tags.", body = ErrorWrap),
+        (status = 404, description = "The tag has no label in that language.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn delete_tag_translation(
+    tag: String,
+    locale: String,
+    _account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let locale = parse_locale(&locale)?;
+    let deleted = async {
+        let tag = TagName::resolve(&tag, &pool).await?;
+        let deleted = sqlx::query("delete from tag_translation where tag = $1 and locale = $2")
+            .bind(&tag.canonical)
+            .bind(&locale)
+            .execute(&pool)
+            .await?
+            .rows_affected();
+        eyre::Result::<_>::Ok(deleted)
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to delete tag translation: {:?}", e);
+        ApiError::Internal
+    })?;
+    if deleted == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(json(&Empty {}).into_response())
+}
+
+/// Moves translations of `from` over to `to` as part of a tag migration, unless `to` already
+/// has its own for the same locale.
+pub(crate) async fn migrate(
+    tx: &mut Transaction<'_, Postgres>,
+    from: &str,
+    to: &str,
+) -> eyre::Result<()> {
+    sqlx::query(
+        "
+insert into tag_translation (tag, locale, label, label_canonical)
+select $2, locale, label, label_canonical
+from tag_translation
+where tag = $1
+on conflict (tag, locale) do nothing
+        ",
+    )
+    .bind(canonicalize(from))
+    .bind(canonicalize(to))
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("delete from tag_translation where tag = $1")
+        .bind(canonicalize(from))
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_tag_translations, put_tag_translation, delete_tag_translation))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_tag_translations = warp::path!("v1" / "tags" / PercentDecoded / "translations")
+        .and(warp::get())
+        .and(ctx.pool())
+        .then(get_tag_translations)
+        .and_then(reply_json);
+    let put_tag_translation = warp::path!("v1" / "tags" / PercentDecoded / "translations" / String)
+        .and(warp::put())
+        .and(ctx.require_permission(Permission::TagCuration))
+        .and(json_body::<TranslationQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |tag: PercentDecoded, locale, account, q, pool| {
+            put_tag_translation(tag.0, locale, account, q, pool, ctx.tag_policy)
+        })
+        .and_then(reject);
+    let delete_tag_translation =
+        warp::path!("v1" / "tags" / PercentDecoded / "translations" / String)
+            .and(warp::delete())
+            .and(ctx.require_permission(Permission::TagCuration))
+            .and(ctx.pool())
+            .then(|tag: PercentDecoded, locale, account, pool| {
+                delete_tag_translation(tag.0, locale, account, pool)
+            })
+            .and_then(reject);
+    get_tag_translations
+        .or(put_tag_translation)
+        .or(delete_tag_translation)
+        .map(Reply::into_response)
+        .boxed()
+}
@@ -0,0 +1,134 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Buf;
+use futures::{Stream, TryStreamExt as _};
+use http::{HeaderMap, Method, Request, Response};
+use hyper::service::Service as _;
+use hyper::Body;
+use tokio::task::{JoinError, JoinHandle};
+use warp::path::FullPath;
+use warp::{Filter, Rejection};
+
+use crate::httputil::{reject, ApiError};
+
+/// The client's address, for routes that only see the request: behind [`timeout`], or served
+/// over HTTPS by `server::serve_tls`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RemoteAddr(pub(crate) SocketAddr);
+
+/// The client's address, like `warp::addr::remote()`, also for routes behind [`timeout`] or
+/// served over HTTPS.
+pub fn remote() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<RemoteAddr>())
+        .map(
+            |remote: Option<SocketAddr>, forwarded: Option<RemoteAddr>| {
+                forwarded.map(|RemoteAddr(addr)| addr).or(remote)
+            },
+        )
+}
+
+/// Rejects with [`ApiError::GatewayTimeout`] if `route` doesn't respond within `timeout`,
+/// dropping whatever it was waiting on, e.g. fichub or the database. Streaming the body of a
+/// response that has started isn't limited.
+///
+/// Warp can't put a deadline on a filter it's running, so the request is rebuilt and handed to
+/// `route` as a service of its own, on a task that's aborted once time is up.
+pub fn timeout<F>(
+    timeout: Duration,
+    route: F,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (Response<Body>,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    let service = warp::service(route);
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::header::headers_cloned())
+        .and(remote())
+        .and(warp::body::stream().map(into_body))
+        .then(
+            move |method: Method,
+                  path: FullPath,
+                  query: String,
+                  headers: HeaderMap,
+                  remote: Option<SocketAddr>,
+                  body: Body| {
+                let mut service = service.clone();
+                async move {
+                    let uri = if query.is_empty() {
+                        path.as_str().to_string()
+                    } else {
+                        format!("{}?{}", path.as_str(), query)
+                    };
+                    let mut request = Request::builder()
+                        .method(method.clone())
+                        .uri(uri)
+                        .body(body)
+                        .map_err(|e| {
+                            eprintln!("failed to rebuild request: {:?}", e);
+                            ApiError::Internal
+                        })?;
+                    *request.headers_mut() = headers;
+                    if let Some(remote) = remote {
+                        request.extensions_mut().insert(RemoteAddr(remote));
+                    }
+                    // Spawned rather than awaited here, as warp doesn't let one route run inside
+                    // another.
+                    let task =
+                        AbortOnDrop(tokio::spawn(async move { service.call(request).await }));
+                    match tokio::time::timeout(timeout, task).await {
+                        Ok(Ok(Ok(response))) => Ok(response),
+                        Ok(Ok(Err(infallible))) => match infallible {},
+                        Ok(Err(e)) => {
+                            eprintln!("request {} {} failed: {:?}", method, path.as_str(), e);
+                            Err(ApiError::Internal)
+                        }
+                        Err(_) => {
+                            eprintln!(
+                                "request {} {} timed out after {:?}",
+                                method,
+                                path.as_str(),
+                                timeout
+                            );
+                            Err(ApiError::GatewayTimeout {
+                                timeout_secs: timeout.as_secs(),
+                            })
+                        }
+                    }
+                }
+            },
+        )
+        .and_then(reject)
+}
+
+fn into_body<S, B>(stream: S) -> Body
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: Buf,
+{
+    Body::wrap_stream(stream.map_ok(|mut buf| buf.copy_to_bytes(buf.remaining())))
+}
+
+/// Aborts the task when dropped, i.e. when it timed out or the client went away.
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Future for AbortOnDrop<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -0,0 +1,582 @@
+use std::convert::TryInto as _;
+
+use hmac::{Hmac, Mac as _};
+use http::Response;
+use hyper::Body;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest as _, Sha256};
+use sqlx::{Postgres, Transaction};
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::account_event::{record as record_event, EventKind};
+use crate::context::Context;
+use crate::httputil::{cached, json_body, reject, ApiError, CachePolicy, Empty, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::{verify_password, AccountSession, Client, Peppers};
+use crate::DB;
+
+/// Shown as the account's provider in authenticator apps.
+const ISSUER: &str = "FicAI";
+// RFC 6238 defaults, which is all that some authenticator apps support.
+const SECRET_BYTES: usize = 20;
+const STEP_SECS: i64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_BYTES: usize = 10;
+
+/// The code for a time step, per RFC 4226 and 6238.
+fn code_at(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("hmac takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let n = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    n % 10u32.pow(DIGITS)
+}
+
+/// The time step `code` belongs to, allowing for a step of clock drift either way.
+fn matching_step(secret: &[u8], code: &str) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code = code.parse::<u32>().ok()?;
+    let step = chrono::Utc::now().timestamp() / STEP_SECS;
+    (step - 1..=step + 1).find(|&s| code_at(secret, s) == code)
+}
+
+/// Recovery codes are compared without the dashes they are shown with, and case-insensitively.
+fn recovery_code_hash(code: &str) -> Vec<u8> {
+    let normalized = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    Sha256::digest(normalized.as_bytes()).to_vec()
+}
+
+/// Replaces the account's recovery codes with new ones, returning them as shown to the user.
+async fn replace_recovery_codes(
+    tx: &mut Transaction<'_, Postgres>,
+    account_id: i64,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query("delete from totp_recovery_code where account_id = $1")
+        .bind(account_id)
+        .execute(&mut *tx)
+        .await?;
+    let mut codes = Vec::with_capacity(RECOVERY_CODES);
+    for _ in 0..RECOVERY_CODES {
+        let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+        OsRng.fill_bytes(&mut bytes);
+        let encoded = data_encoding::BASE32_NOPAD
+            .encode(&bytes)
+            .to_ascii_lowercase();
+        let code = encoded
+            .as_bytes()
+            .chunks(4)
+            .map(|c| std::str::from_utf8(c).unwrap())
+            .collect::<Vec<_>>()
+            .join("-");
+        sqlx::query("insert into totp_recovery_code (code_hash, account_id) values ($1, $2)")
+            .bind(recovery_code_hash(&code))
+            .bind(account_id)
+            .execute(&mut *tx)
+            .await?;
+        codes.push(code);
+    }
+    Ok(codes)
+}
+
+/// Checks a second factor of an account with two-factor authentication enabled: either a code
+/// that wasn't used before, or a recovery code, which is used up. `None` if it's not enabled.
+async fn check_second_factor(
+    tx: &mut Transaction<'_, Postgres>,
+    account_id: i64,
+    code: &str,
+) -> Result<Option<bool>, sqlx::Error> {
+    let row = sqlx::query_as::<_, (Vec<u8>, Option<i64>)>(
+        "
+select secret, last_used_step
+from account_totp
+where account_id = $1 and enabled_at is not null
+for update
+        ",
+    )
+    .bind(account_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let (secret, last_used_step) = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    if let Some(step) = matching_step(&secret, code) {
+        // Codes can't be replayed, e.g. by someone looking over the user's shoulder.
+        if last_used_step.is_some_and(|last| step <= last) {
+            return Ok(Some(false));
+        }
+        sqlx::query("update account_totp set last_used_step = $2 where account_id = $1")
+            .bind(account_id)
+            .bind(step)
+            .execute(&mut *tx)
+            .await?;
+        return Ok(Some(true));
+    }
+    let used =
+        sqlx::query("delete from totp_recovery_code where account_id = $1 and code_hash = $2")
+            .bind(account_id)
+            .bind(recovery_code_hash(code))
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+    Ok(Some(used > 0))
+}
+
+/// Whether the account has two-factor authentication enabled.
+pub(crate) async fn is_enabled<'e, E>(account_id: i64, executor: E) -> sqlx::Result<bool>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    Ok(sqlx::query_scalar::<_, i64>(
+        "select account_id from account_totp where account_id = $1 and enabled_at is not null",
+    )
+    .bind(account_id)
+    .fetch_optional(executor)
+    .await?
+    .is_some())
+}
+
+/// The second step of logging in with a password, for accounts that have enabled two-factor
+/// authentication; others pass regardless of `code`. Logging in through an OAuth provider is
+/// refused for them instead, see `oauth::oauth_callback`.
+pub(crate) async fn verify_login(
+    account_id: i64,
+    code: Option<&str>,
+    pool: &DB,
+) -> Result<(), ApiError> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to check two-factor code: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let enabled = is_enabled(account_id, &mut tx)
+        .await
+        .map_err(internal_error)?;
+    if !enabled {
+        return Ok(());
+    }
+    let code = code.ok_or(ApiError::TwoFactorRequired)?;
+    match check_second_factor(&mut tx, account_id, code)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(true) | None => {}
+        Some(false) => return Err(ApiError::Forbidden),
+    }
+    tx.commit().await.map_err(internal_error)?;
+    Ok(())
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TwoFactorStatus {
+    enabled: bool,
+    recovery_codes_left: i64,
+}
+
+/// Get whether two-factor authentication is enabled for the current account.
+#[utoipa::path(
+    get,
+    path = "/accounts/2fa",
+    tag = "accounts",
+    responses(
+        (status = 200, description = "Success.", body = TwoFactorStatus),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_two_factor(account: AccountSession, pool: DB) -> Result<Response<Body>, ApiError> {
+    let (enabled, recovery_codes_left) = sqlx::query_as::<_, (bool, i64)>(
+        "
+select
+    exists (select 1 from account_totp where account_id = $1 and enabled_at is not null),
+    (select count(1) from totp_recovery_code where account_id = $1)
+        ",
+    )
+    .bind(account.id)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to get two-factor status: {:?}", e);
+        ApiError::Internal
+    })?;
+    Ok(json(&TwoFactorStatus {
+        enabled,
+        recovery_codes_left,
+    })
+    .into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[schema(as = EnrollTwoFactorQ)]
+pub struct EnrollQ {
+    password: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = TwoFactorEnrollment)]
+pub struct Enrollment {
+    /// Base32, for entering into an authenticator app by hand.
+    secret: String,
+    #[schema(
+        example = "otpauth://totp/FicAI:me%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=FicAI"
+    )]
+    otpauth_url: String,
+    /// `otpauthUrl` as a QR code, for scanning with an authenticator app.
+    qr_code_svg: String,
+}
+
+/// Start enabling two-factor authentication with an authenticator app.
+///
+/// Generates a new secret, to be confirmed with a code from it, see `/accounts/2fa/confirm`.
+/// Starting over replaces the secret.
+#[utoipa::path(
+    post,
+    path = "/accounts/2fa",
+    tag = "accounts",
+    request_body = EnrollQ,
+    responses(
+        (status = 200, description = "Success.", body = Enrollment),
+        (
+            status = 400,
+            description = "Two-factor authentication is already enabled.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "Not logged in, or the password is wrong.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn enroll_two_factor(
+    account: AccountSession,
+    q: EnrollQ,
+    pool: DB,
+    peppers: &Peppers,
+) -> Result<Response<Body>, ApiError> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to enroll in two-factor authentication: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let (password_hash, enabled) = sqlx::query_as::<_, (String, bool)>(
+        "
+select a.password_hash, t.enabled_at is not null
+from account a
+left join account_totp t
+    on t.account_id = a.id
+where a.id = $1
+for update of a
+        ",
+    )
+    .bind(account.id)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(internal_error)?;
+    verify_password(&q.password, &password_hash, peppers)?;
+    if enabled {
+        return Err(ApiError::BadRequest(
+            "two-factor authentication is already enabled".into(),
+        ));
+    }
+    let mut secret = [0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut secret);
+    sqlx::query(
+        "
+insert into account_totp (account_id, secret)
+values ($1, $2)
+on conflict (account_id) do update set secret = excluded.secret, created_at = now()
+        ",
+    )
+    .bind(account.id)
+    .bind(&secret[..])
+    .execute(&mut tx)
+    .await
+    .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+
+    let secret = data_encoding::BASE32_NOPAD.encode(&secret);
+    let otpauth_url = format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        ISSUER,
+        utf8_percent_encode(&account.email, NON_ALPHANUMERIC),
+        secret,
+        ISSUER,
+        DIGITS,
+        STEP_SECS,
+    );
+    let qr_code_svg = QrCode::new(otpauth_url.as_bytes())
+        .map_err(|e| {
+            eprintln!("failed to encode otpauth url as a qr code: {:?}", e);
+            ApiError::Internal
+        })?
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+    Ok(json(&Enrollment {
+        secret,
+        otpauth_url,
+        qr_code_svg,
+    })
+    .into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[schema(as = TwoFactorCodeQ)]
+pub struct CodeQ {
+    code: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodes {
+    /// Each can be used once instead of a code. Only ever shown here; the server keeps nothing
+    /// but hashes of them.
+    recovery_codes: Vec<String>,
+}
+
+/// Enable two-factor authentication with a first code from the authenticator app.
+#[utoipa::path(
+    post,
+    path = "/accounts/2fa/confirm",
+    tag = "accounts",
+    request_body = CodeQ,
+    responses(
+        (status = 200, description = "Success.", body = RecoveryCodes),
+        (
+            status = 400,
+            description = "Two-factor authentication is not being enabled.",
+            body = ErrorWrap,
+        ),
+        (status = 403, description = "Not logged in, or the code is wrong.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn confirm_two_factor(
+    account: AccountSession,
+    q: CodeQ,
+    client: Client,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to confirm two-factor authentication: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let secret = sqlx::query_scalar::<_, Vec<u8>>(
+        "select secret from account_totp where account_id = $1 and enabled_at is null for update",
+    )
+    .bind(account.id)
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| ApiError::BadRequest("two-factor authentication is not being enabled".into()))?;
+    let step = matching_step(&secret, &q.code).ok_or(ApiError::Forbidden)?;
+    sqlx::query(
+        "update account_totp set enabled_at = now(), last_used_step = $2 where account_id = $1",
+    )
+    .bind(account.id)
+    .bind(step)
+    .execute(&mut tx)
+    .await
+    .map_err(internal_error)?;
+    // Login links would bypass the second factor, see `usermgmt::login_with_magic_link`.
+    sqlx::query("delete from magic_link where account_id = $1")
+        .bind(account.id)
+        .execute(&mut tx)
+        .await
+        .map_err(internal_error)?;
+    let recovery_codes = replace_recovery_codes(&mut tx, account.id)
+        .await
+        .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    record_event(account.id, EventKind::TwoFactorEnable, &client, &pool).await;
+    Ok(json(&RecoveryCodes { recovery_codes }).into_response())
+}
+
+/// Replace the recovery codes with new ones.
+#[utoipa::path(
+    post,
+    path = "/accounts/2fa/recovery-codes",
+    tag = "accounts",
+    request_body = CodeQ,
+    responses(
+        (status = 200, description = "Success.", body = RecoveryCodes),
+        (status = 400, description = "Two-factor authentication is not enabled.", body = ErrorWrap),
+        (status = 403, description = "Not logged in, or the code is wrong.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn regenerate_recovery_codes(
+    account: AccountSession,
+    q: CodeQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to regenerate recovery codes: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    match check_second_factor(&mut tx, account.id, &q.code)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(true) => {}
+        Some(false) => return Err(ApiError::Forbidden),
+        None => {
+            return Err(ApiError::BadRequest(
+                "two-factor authentication is not enabled".into(),
+            ))
+        }
+    }
+    let recovery_codes = replace_recovery_codes(&mut tx, account.id)
+        .await
+        .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(json(&RecoveryCodes { recovery_codes }).into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[schema(as = DisableTwoFactorQ)]
+pub struct DisableQ {
+    password: String,
+    /// A code from the authenticator app, or a recovery code.
+    code: String,
+}
+
+/// Disable two-factor authentication, or abandon enabling it.
+#[utoipa::path(
+    delete,
+    path = "/accounts/2fa",
+    tag = "accounts",
+    request_body = DisableQ,
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (
+            status = 403,
+            description = "Not logged in, or the password or code is wrong.",
+            body = ErrorWrap,
+        ),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn disable_two_factor(
+    account: AccountSession,
+    q: DisableQ,
+    client: Client,
+    pool: DB,
+    peppers: &Peppers,
+) -> Result<Response<Body>, ApiError> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to disable two-factor authentication: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let password_hash = sqlx::query_scalar::<_, String>(
+        "select password_hash from account where id = $1 for update",
+    )
+    .bind(account.id)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(internal_error)?;
+    verify_password(&q.password, &password_hash, peppers)?;
+    let enabled = match check_second_factor(&mut tx, account.id, &q.code)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(true) => true,
+        Some(false) => return Err(ApiError::Forbidden),
+        None => false,
+    };
+    for statement in [
+        "delete from totp_recovery_code where account_id = $1",
+        "delete from account_totp where account_id = $1",
+    ] {
+        sqlx::query(statement)
+            .bind(account.id)
+            .execute(&mut tx)
+            .await
+            .map_err(internal_error)?;
+    }
+    tx.commit().await.map_err(internal_error)?;
+    if enabled {
+        record_event(account.id, EventKind::TwoFactorDisable, &client, &pool).await;
+    }
+    Ok(json(&Empty {}).into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_two_factor,
+    enroll_two_factor,
+    disable_two_factor,
+    confirm_two_factor,
+    regenerate_recovery_codes,
+))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_two_factor = warp::path!("v1" / "accounts" / "2fa")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(get_two_factor)
+        .and_then(reject);
+    let enroll_two_factor = warp::path!("v1" / "accounts" / "2fa")
+        .and(warp::post())
+        .and(ctx.authenticate())
+        .and(json_body::<EnrollQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |session, q, pool| enroll_two_factor(session, q, pool, ctx.peppers))
+        .and_then(reject);
+    let confirm_two_factor = warp::path!("v1" / "accounts" / "2fa" / "confirm")
+        .and(warp::post())
+        .and(ctx.authenticate())
+        .and(json_body::<CodeQ>(ctx.max_body_bytes))
+        .and(ctx.client())
+        .and(ctx.pool())
+        .then(confirm_two_factor)
+        .and_then(reject);
+    let regenerate_recovery_codes = warp::path!("v1" / "accounts" / "2fa" / "recovery-codes")
+        .and(warp::post())
+        .and(ctx.authenticate())
+        .and(json_body::<CodeQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(regenerate_recovery_codes)
+        .and_then(reject);
+    let disable_two_factor = warp::path!("v1" / "accounts" / "2fa")
+        .and(warp::delete())
+        .and(ctx.authenticate())
+        .and(json_body::<DisableQ>(ctx.max_body_bytes))
+        .and(ctx.client())
+        .and(ctx.pool())
+        .then(move |session, q, client, pool| {
+            disable_two_factor(session, q, client, pool, ctx.peppers)
+        })
+        .and_then(reject);
+    cached(
+        CachePolicy::Never,
+        &[],
+        get_two_factor
+            .or(enroll_two_factor)
+            .or(confirm_two_factor)
+            .or(regenerate_recovery_codes)
+            .or(disable_two_factor)
+            .map(Reply::into_response),
+    )
+    .boxed()
+}
@@ -0,0 +1,96 @@
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::canonical_url::is_number;
+use crate::fichub::Cache;
+use crate::httputil::ApiError;
+use crate::metadata::FailureKind;
+
+/// What to do with URLs of sites that [`UrlPolicy`] has no rules for.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownSites {
+    Allow,
+    /// Accept them if the metadata providers know the URL.
+    Probe,
+    Reject,
+}
+
+/// Which URLs may be signalled on. On sites known to host fics, URLs must point to a fic rather
+/// than e.g. a search page or a user profile; other sites are handled as configured.
+pub struct UrlPolicy {
+    pub unknown_sites: UnknownSites,
+    /// Used to probe URLs of unknown sites.
+    pub cache: &'static Cache,
+}
+
+impl UrlPolicy {
+    pub async fn check(&self, url: &str) -> Result<(), ApiError> {
+        let reject = |message| Err(ApiError::UrlPolicyViolation(message));
+        let parsed = match Url::parse(url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => parsed,
+            _ => return reject("not a web page url"),
+        };
+        match site_rule(&parsed) {
+            Some(Ok(())) => return Ok(()),
+            Some(Err(message)) => return reject(message),
+            None => {}
+        }
+        match self.unknown_sites {
+            UnknownSites::Allow => Ok(()),
+            UnknownSites::Reject => reject("not a url of a known fic site"),
+            UnknownSites::Probe => match self.cache.get(url).await {
+                Ok(_) => Ok(()),
+                Err(e) if FailureKind::of(&e) == FailureKind::Unsupported => {
+                    reject("no fic is known at this url")
+                }
+                // Don't hold signals hostage to providers being down.
+                Err(e) => {
+                    eprintln!("failed to probe {} for the url policy: {:?}", url, e);
+                    Ok(())
+                }
+            },
+        }
+    }
+}
+
+/// Whether the URL points to a fic, for sites that are known to host fics; `None` for others.
+fn site_rule(url: &Url) -> Option<Result<(), &'static str>> {
+    let host = url.host_str()?.to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    let segments = url
+        .path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let is_fic = match host {
+        "archiveofourown.org" => segments
+            .windows(2)
+            .any(|w| w[0] == "works" && is_number(w[1])),
+        "fanfiction.net" | "m.fanfiction.net" | "fictionpress.com" | "m.fictionpress.com" => {
+            matches!(segments[..], ["s", id, ..] if is_number(id))
+        }
+        "forums.spacebattles.com"
+        | "forums.sufficientvelocity.com"
+        | "forum.questionablequesting.com"
+        | "questionablequesting.com" => matches!(segments[..], ["threads", _, ..]),
+        "royalroad.com" => matches!(segments[..], ["fiction", id, ..] if is_number(id)),
+        "fimfiction.net" => matches!(segments[..], ["story", id, ..] if is_number(id)),
+        "scribblehub.com" => matches!(segments[..], ["series", id, ..] if is_number(id)),
+        // `/story/<id>-<title>`, or `/<id>-<title>` for a single part.
+        "wattpad.com" => match segments[..] {
+            ["story", story, ..] => starts_with_number(story),
+            [part, ..] => starts_with_number(part),
+            [] => false,
+        },
+        _ => return None,
+    };
+    Some(if is_fic {
+        Ok(())
+    } else {
+        Err("not a url of a fic, e.g. a search page or a user profile")
+    })
+}
+
+fn starts_with_number(segment: &str) -> bool {
+    is_number(segment.split('-').next().unwrap_or_default())
+}
@@ -1,61 +1,464 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
 use argon2::{Argon2, PasswordHash, PasswordHasher as _, PasswordVerifier as _};
 use base64ct::Encoding as _;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use eyre::{eyre, WrapErr};
-use http::header::SET_COOKIE;
+use hmac::{Hmac, Mac as _};
+use http::header::{CACHE_CONTROL, LOCATION, SET_COOKIE};
 use http::{Response, StatusCode};
 use hyper::Body;
 use rand_core::{OsRng, RngCore};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use sqlx::{Postgres, Transaction};
 use tap::prelude::*;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 use warp::{
     reply::{json, with_header, with_status},
     Filter, Rejection, Reply,
 };
 
-use crate::httputil::{AccountAlreadyExists, BadRequest, Empty, Forbidden, InternalError};
+use crate::account_event::{record as record_event, EventKind};
+use crate::account_status::{AccountStatus, Restriction};
+use crate::captcha::Captcha;
+use crate::context::Context;
+use crate::httputil::{
+    cached, json_body, reject, ApiError, CachePolicy, Empty, ErrorWrap, Timestamp,
+};
+use crate::login_throttle::LoginThrottle;
+use crate::mail::{send_in_background, Mailer, Template};
+use crate::password_policy::PasswordPolicy;
+use crate::routes::Routes;
+use crate::write_limit::RateLimitTier;
 use crate::DB;
 
 const SESSION_COOKIE_NAME: &str = "FicAiSession";
 
-const CONSTRAINT_VIOLATION_SQLSTATE: &str = "23505";
+pub(crate) const CONSTRAINT_VIOLATION_SQLSTATE: &str = "23505";
 
 // https://cheatsheetseries.owasp.org/cheatsheets/Session_Management_Cheat_Sheet.html#session-id-length
 const SESSION_ID_BYTES: usize = 16;
 
-fn create_kdf(pepper: &[u8]) -> Argon2 {
-    use argon2::{Algorithm::Argon2id, Params, Version::V0x13};
+/// Longer user agents are cut off when stored with a session.
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// Length of the signature appended to session cookies, see [`SessionPolicy::cookie_key`].
+const COOKIE_SIGNATURE_BYTES: usize = 16;
+
+/// Length of the tokens sent by email, e.g. in password reset links.
+const MAIL_TOKEN_BYTES: usize = 32;
+
+/// The algorithm, version and parameters new hashes are made with. Each hash records those it
+/// was made with, so they can be changed: hashes made with others still verify, and are replaced
+/// on the next login, see [`needs_rehash`].
+const KDF_ALGORITHM: argon2::Algorithm = argon2::Algorithm::Argon2id;
+const KDF_VERSION: argon2::Version = argon2::Version::V0x13;
+
+/// `pepper_id` is recorded in the hash as its key id, see [`Peppers`].
+fn kdf_params(pepper_id: &str) -> argon2::Params {
     // https://cheatsheetseries.owasp.org/cheatsheets/Password_Storage_Cheat_Sheet.html#argon2id
-    let params =
-        Params::new(37 * 1024, 1, 1, Some(32)).expect("failed to assemble Argon2 parameters");
-    Argon2::new_with_secret(pepper, Argon2id, V0x13, params).expect("failed to initialize Argon2")
+    let mut builder = argon2::ParamsBuilder::new();
+    builder
+        .m_cost(37 * 1024)
+        .and_then(|b| b.t_cost(1))
+        .and_then(|b| b.p_cost(1))
+        .and_then(|b| b.output_len(32))
+        .and_then(|b| b.keyid(pepper_id.as_bytes()))
+        .expect("failed to assemble Argon2 parameters");
+    builder
+        .params()
+        .expect("failed to assemble Argon2 parameters")
+}
+
+fn create_kdf<'a>(pepper_id: &str, pepper: &'a [u8]) -> Argon2<'a> {
+    Argon2::new_with_secret(pepper, KDF_ALGORITHM, KDF_VERSION, kdf_params(pepper_id))
+        .expect("failed to initialize Argon2")
+}
+
+/// Secrets mixed into password hashes, see `FICAI_PWD_PEPPER`. Each hash records the id of the
+/// pepper it was made with as its Argon2 key id, so that the pepper can be rotated: hashes made
+/// with a retired one still verify as long as it stays configured, and are replaced with ones
+/// made with the current pepper on the next login. Hashes without a key id were made before
+/// peppers had ids, and belong to the pepper whose id is empty.
+pub struct Peppers {
+    current_id: String,
+    by_id: HashMap<String, Vec<u8>>,
+}
+
+impl Peppers {
+    /// Ids are stored in every hash, so they're kept short.
+    pub const MAX_ID_LENGTH: usize = argon2::Params::MAX_KEYID_LEN;
+
+    pub fn new(
+        current_id: String,
+        current: Vec<u8>,
+        retired: impl IntoIterator<Item = (String, Vec<u8>)>,
+    ) -> eyre::Result<Self> {
+        let mut by_id = HashMap::new();
+        for (id, pepper) in std::iter::once((current_id.clone(), current)).chain(retired) {
+            if id.len() > Self::MAX_ID_LENGTH {
+                return Err(eyre!(
+                    "pepper id {:?} is longer than {} bytes",
+                    id,
+                    Self::MAX_ID_LENGTH
+                ));
+            }
+            if by_id.insert(id.clone(), pepper).is_some() {
+                return Err(eyre!("pepper id {:?} is used more than once", id));
+            }
+        }
+        Ok(Self { current_id, by_id })
+    }
+
+    fn current_kdf(&self) -> Argon2<'_> {
+        create_kdf(&self.current_id, &self.by_id[&self.current_id])
+    }
+
+    /// The KDF to verify `hash` with, or `None` if its pepper isn't configured (anymore).
+    fn kdf_for(&self, hash: &PasswordHash) -> Option<Argon2<'_>> {
+        let params = argon2::Params::try_from(hash).ok()?;
+        let id = std::str::from_utf8(params.keyid()).ok()?;
+        self.by_id
+            .get_key_value(id)
+            .map(|(id, pepper)| create_kdf(id, pepper))
+    }
 }
 
-#[derive(Serialize, Debug, sqlx::FromRow)]
+/// Whether `hash` was made with another algorithm, version, parameters or pepper than new hashes
+/// are.
+fn needs_rehash(hash: &str, peppers: &Peppers) -> bool {
+    let hash = match PasswordHash::new(hash) {
+        Ok(hash) => hash,
+        Err(_) => return false,
+    };
+    hash.algorithm != KDF_ALGORITHM.ident()
+        || hash.version != Some(KDF_VERSION.into())
+        || argon2::Params::try_from(&hash).ok() != Some(kdf_params(&peppers.current_id))
+}
+
+fn hash_password(password: &str, peppers: &Peppers) -> String {
+    let salt = argon2::password_hash::SaltString::generate(OsRng);
+    peppers
+        .current_kdf()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("failed to hash password")
+        .to_string()
+}
+
+/// Stands in for the hash of accounts that don't exist or have no password, so that verifying
+/// against it takes as long as against a real one.
+fn dummy_hash(peppers: &Peppers) -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password("", peppers))
+}
+
+/// Fails with [`ApiError::Forbidden`] unless `password` matches `hash`. Accounts without a
+/// password, e.g. created by logging in through OAuth, have an empty hash that nothing matches;
+/// it's checked against a dummy hash anyway, so that the time taken doesn't tell them apart. The
+/// same goes for hashes made with a pepper that's no longer configured.
+pub(crate) fn verify_password(
+    password: &str,
+    hash: &str,
+    peppers: &Peppers,
+) -> Result<(), ApiError> {
+    let hash = match hash {
+        "" => None,
+        hash => Some(PasswordHash::new(hash).map_err(|_| ApiError::Internal)?),
+    };
+    let kdf = hash.as_ref().and_then(|hash| peppers.kdf_for(hash));
+    if hash.is_some() && kdf.is_none() {
+        eprintln!("password hash made with an unknown pepper");
+    }
+    let (kdf, hash, matchable) = match (kdf, hash) {
+        (Some(kdf), Some(hash)) => (kdf, hash, true),
+        _ => (
+            peppers.current_kdf(),
+            PasswordHash::new(dummy_hash(peppers)).map_err(|_| ApiError::Internal)?,
+            false,
+        ),
+    };
+    match kdf.verify_password(password.as_bytes(), &hash) {
+        Ok(_) if matchable => Ok(()),
+        Ok(_) => Err(ApiError::Forbidden),
+        Err(argon2::password_hash::Error::Password) => Err(ApiError::Forbidden),
+        Err(e) => {
+            eprintln!("{:?}", e);
+            Err(ApiError::Forbidden)
+        }
+    }
+}
+
+/// What an account is allowed to do beyond managing its own signals. Moderators have the
+/// `tag-curation` permission, admins have every permission.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, sqlx::Type, ToSchema,
+)]
 #[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "account_role", rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+/// A single administrative capability.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(type_name = "permission", rename_all = "kebab-case")]
+pub enum Permission {
+    TagCuration,
+    UserModeration,
+    DataExport,
+    Settings,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 4] = [
+        Permission::TagCuration,
+        Permission::UserModeration,
+        Permission::DataExport,
+        Permission::Settings,
+    ];
+
+    /// Whether every account with `role` has this permission, regardless of explicit grants.
+    pub fn implied_by(self, role: Role) -> bool {
+        match role {
+            Role::Admin => true,
+            Role::Moderator => self == Permission::TagCuration,
+            Role::User => false,
+        }
+    }
+}
+
+/// How long sessions last, and where their cookie is valid.
+#[derive(Debug)]
+pub struct SessionPolicy {
+    pub domain: &'static str,
+    /// Sessions end this long after logging in, however active they are.
+    pub max_age: chrono::Duration,
+    /// Sessions end after being unused for this long.
+    pub idle_timeout: chrono::Duration,
+    /// Signs session cookies, so that made up or tampered ones are turned away without asking the
+    /// database.
+    pub cookie_key: Option<&'static [u8]>,
+    /// How many sessions an account may have at once, or 0 for no limit. Logging in past it
+    /// ends the oldest ones.
+    pub max_sessions: i64,
+}
+
+impl SessionPolicy {
+    fn cookie_signature(&self, key: &[u8], session_id: &[u8]) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(key)
+            .expect("hmac takes keys of any length")
+            .chain_update(SESSION_COOKIE_NAME)
+            .chain_update(session_id)
+    }
+
+    /// The session id, followed by a truncated HMAC of it if there's a key.
+    fn cookie_value(&self, session_id: &[u8]) -> String {
+        let mut value = base64ct::Base64Unpadded::encode_string(session_id);
+        if let Some(key) = self.cookie_key {
+            let signature = self
+                .cookie_signature(key, session_id)
+                .finalize()
+                .into_bytes();
+            value.push('.');
+            value.push_str(&base64ct::Base64Unpadded::encode_string(
+                &signature[..COOKIE_SIGNATURE_BYTES],
+            ));
+        }
+        value
+    }
+
+    /// Why a route that needs a session got none, given its session cookie.
+    pub(crate) fn unauthenticated(&self, cookie: Option<&str>) -> ApiError {
+        match cookie {
+            Some(cookie) if self.session_id(cookie).is_none() => ApiError::SessionInvalid,
+            _ => ApiError::Forbidden,
+        }
+    }
+
+    /// The session id in a cookie, or `None` if the cookie is malformed or its signature doesn't
+    /// match. Unsigned cookies are rejected once there's a key.
+    fn session_id(&self, value: &str) -> Option<Vec<u8>> {
+        let key = match self.cookie_key {
+            Some(key) => key,
+            None => return base64ct::Base64Unpadded::decode_vec(value).ok(),
+        };
+        let (session_id, signature) = value.split_once('.')?;
+        let session_id = base64ct::Base64Unpadded::decode_vec(session_id).ok()?;
+        let signature = base64ct::Base64Unpadded::decode_vec(signature).ok()?;
+        if signature.len() != COOKIE_SIGNATURE_BYTES {
+            return None;
+        }
+        self.cookie_signature(key, &session_id)
+            .verify_truncated_left(&signature)
+            .ok()?;
+        Some(session_id)
+    }
+}
+
+/// Who is logging in, as recorded with their session.
+#[derive(Debug)]
+pub struct Client {
+    ip: Option<IpAddr>,
+    user_agent: Option<String>,
+}
+
+impl Client {
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.ip
+    }
+
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+}
+
+pub fn client(
+    trusted_proxies: usize,
+) -> impl Filter<Extract = (Client,), Error = Rejection> + Clone {
+    crate::ip_limit::client_ip(trusted_proxies)
+        .and(warp::header::optional::<String>("user-agent"))
+        .map(|ip: Option<IpAddr>, user_agent: Option<String>| Client {
+            ip,
+            user_agent: user_agent
+                .map(|ua| ua.chars().take(MAX_USER_AGENT_LENGTH).collect::<String>()),
+        })
+}
+
+/// Information about an account.
+#[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = Account)]
 pub struct AccountSession {
+    /// The unique account id.
     pub id: i64,
-    email: String,
+    /// Only told to the account itself, see [`get_session_account`]; everywhere else the display
+    /// name stands in for it.
+    #[serde(skip_serializing)]
+    pub email: String,
+    /// What others see of the account instead of its email, unique regardless of case. `null` if
+    /// it hasn't set one.
+    pub display_name: Option<String>,
+    pub role: Role,
+    /// IANA time zone name used to format timestamps for display.
+    #[schema(example = "Europe/Berlin")]
+    pub timezone: String,
     #[serde(skip_serializing)]
     session_id: Vec<u8>,
+    #[serde(skip_serializing)]
+    status: AccountStatus,
+    #[serde(skip_serializing)]
+    status_reason: Option<String>,
+    #[serde(skip_serializing)]
+    suspended_until: Option<DateTime<Utc>>,
+    #[serde(skip_serializing)]
+    rate_limit_tier: RateLimitTier,
 }
 
 impl AccountSession {
-    async fn create(id: i64, email: String, db: &DB) -> eyre::Result<Self> {
+    pub(crate) async fn create(
+        id: i64,
+        client: &Client,
+        policy: &SessionPolicy,
+        db: &DB,
+    ) -> eyre::Result<Self> {
+        let (
+            email,
+            display_name,
+            role,
+            timezone,
+            status,
+            status_reason,
+            suspended_until,
+            rate_limit_tier,
+        ) = sqlx::query_as::<
+            _,
+            (
+                String,
+                Option<String>,
+                Role,
+                String,
+                AccountStatus,
+                Option<String>,
+                Option<DateTime<Utc>>,
+                RateLimitTier,
+            ),
+        >(
+            "
+select email, display_name, role, timezone, status, status_reason, suspended_until,
+    rate_limit_tier
+from account
+where id = $1
+                ",
+        )
+        .bind(id)
+        .fetch_one(db)
+        .await
+        .wrap_err("failed to get the account")?;
+        // Expired sessions are only ever rejected, so this is as good a time as any to clean up.
+        sqlx::query(
+            "
+delete from session
+where account_id = $1 and (expires_at < now() or last_used_at + idle_timeout < now())
+            ",
+        )
+        .bind(id)
+        .execute(db)
+        .await
+        .wrap_err("failed to delete expired sessions")?;
         let mut session_id = [0u8; SESSION_ID_BYTES];
         for _ in 0..3 {
             OsRng.fill_bytes(&mut session_id);
-            let insert_result = sqlx::query("insert into session (id, account_id) values ($1, $2)")
-                .bind(&session_id[..])
-                .bind(id)
-                .execute(db)
-                .await;
+            let insert_result = sqlx::query(
+                "
+insert into session (id, account_id, created_ip, user_agent, expires_at, idle_timeout)
+values ($1, $2, $3::inet, $4, now() + $5 * interval '1 second', $6 * interval '1 second')
+                ",
+            )
+            .bind(&session_id[..])
+            .bind(id)
+            .bind(client.ip.map(|ip| ip.to_string()))
+            .bind(&client.user_agent)
+            .bind(policy.max_age.num_seconds() as f64)
+            .bind(policy.idle_timeout.num_seconds() as f64)
+            .execute(db)
+            .await;
             match insert_result {
                 Ok(_) => {
+                    if policy.max_sessions > 0 {
+                        sqlx::query(
+                            "
+delete from session
+where account_id = $1 and id not in (
+    select id from session where account_id = $1 order by created_at desc, id limit $2
+)
+                            ",
+                        )
+                        .bind(id)
+                        .bind(policy.max_sessions)
+                        .execute(db)
+                        .await
+                        .wrap_err("failed to delete the oldest sessions")?;
+                    }
                     return Ok(Self {
                         id,
                         email,
+                        display_name,
+                        role,
+                        timezone,
                         session_id: session_id.to_vec(),
-                    })
+                        status,
+                        status_reason,
+                        suspended_until,
+                        rate_limit_tier,
+                    });
                 }
                 Err(sqlx::Error::Database(db_err))
                     if db_err.code() == Some(CONSTRAINT_VIOLATION_SQLSTATE.into()) =>
@@ -68,148 +471,401 @@ impl AccountSession {
         Err(eyre!("failed to generate a new session id in 3 attempts"))
     }
 
-    fn cookie_value(&self) -> String {
-        base64ct::Base64Unpadded::encode_string(&self.session_id)
+    /// The account's preferred time zone for displaying timestamps.
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
     }
 
-    fn to_cookie<'a>(&self, domain: &'a str) -> cookie::Cookie<'a> {
-        cookie::Cookie::build(SESSION_COOKIE_NAME, self.cookie_value())
-            .domain(domain)
-            .path("/")
-            .secure(true)
-            .http_only(true)
-            .permanent()
-            .finish()
+    pub(crate) fn rate_limit_tier(&self) -> RateLimitTier {
+        self.rate_limit_tier
+    }
+
+    /// Why the account may not be used right now, if it's suspended or banned.
+    pub(crate) fn restriction(&self) -> Option<Restriction> {
+        Restriction::of(
+            self.status,
+            self.status_reason.clone(),
+            self.suspended_until,
+        )
+    }
+
+    pub(crate) fn to_cookie(&self, policy: &SessionPolicy) -> cookie::Cookie<'static> {
+        session_cookie(
+            policy.cookie_value(&self.session_id),
+            policy.domain,
+            policy.idle_timeout,
+        )
     }
 
     fn to_cookie_removal<'a>(&self, domain: &'a str) -> cookie::Cookie<'a> {
-        self.to_cookie(domain).tap_mut(|c| c.make_removal())
+        session_cookie(String::new(), domain, chrono::Duration::zero())
+            .tap_mut(|c| c.make_removal())
+    }
+}
+
+/// The cookie expires when the session would if left idle; see [`renew_session_cookie`] for how
+/// it's kept alive.
+fn session_cookie(value: String, domain: &str, max_age: chrono::Duration) -> cookie::Cookie<'_> {
+    cookie::Cookie::build(SESSION_COOKIE_NAME, value)
+        .domain(domain)
+        .path("/")
+        .secure(true)
+        .http_only(true)
+        .max_age(cookie::time::Duration::seconds(max_age.num_seconds()))
+        .finish()
+}
+
+/// The session cookie as sent with the request, if any.
+pub fn session_cookie_value() -> impl Filter<Extract = (Option<String>,), Error = Infallible> + Clone
+{
+    warp::cookie::optional(SESSION_COOKIE_NAME)
+}
+
+/// Sends the session cookie back with a fresh expiry on successful responses, so that browsers
+/// keep it for as long as the session is in use. Responses that set the cookie themselves, i.e.
+/// logging in or out, are left alone, and so are those that caches may hand to anyone. Cookies
+/// that aren't validly signed are cleared instead, whatever the response.
+pub fn renew_session_cookie(
+    cookie: Option<String>,
+    mut response: Response<Body>,
+    policy: &SessionPolicy,
+) -> Response<Body> {
+    let cookie = match cookie {
+        Some(cookie) => cookie,
+        None => return response,
+    };
+    if policy.session_id(&cookie).is_none() {
+        if !response.headers().contains_key(SET_COOKIE) {
+            let removal = session_cookie(String::new(), policy.domain, chrono::Duration::zero())
+                .tap_mut(|c| c.make_removal())
+                .to_string();
+            if let Ok(value) = removal.parse() {
+                response.headers_mut().insert(SET_COOKIE, value);
+            }
+        }
+        return response;
+    }
+    let public = response
+        .headers()
+        .get(CACHE_CONTROL)
+        .is_some_and(|v| v.as_bytes().starts_with(b"public"));
+    if !response.status().is_success() || response.headers().contains_key(SET_COOKIE) || public {
+        return response;
     }
+    let renewed = session_cookie(cookie, policy.domain, policy.idle_timeout).to_string();
+    if let Ok(value) = renewed.parse() {
+        response.headers_mut().insert(SET_COOKIE, value);
+    }
+    response
 }
 
-#[derive(Deserialize, Debug)]
+/// Request body to create an account.
+#[derive(Deserialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateAccountQ {
+    /// Account email. Must be unique.
+    #[schema(format = Email)]
     email: String,
+    /// Account password.
+    #[schema(min_length = 8)]
     password: String,
-    beta_key: String,
+    /// Code of an invite that isn't used up or expired. Also accepted as `betaKey`, its name
+    /// during the beta.
+    #[serde(alias = "betaKey")]
+    invite_code: String,
+    /// The token of a solved hCaptcha or Turnstile CAPTCHA. Required if the server is configured
+    /// to check one.
+    #[serde(default)]
+    captcha_token: Option<String>,
 }
 
+/// Register a new account.
+#[utoipa::path(
+    post,
+    path = "/accounts",
+    tag = "accounts",
+    request_body = CreateAccountQ,
+    responses(
+        (
+            status = 201,
+            description = "Created. A session cookie named `FicAiSession` will be returned.",
+            body = AccountSession,
+            headers(("Set-Cookie" = String, description = "Includes session cookie.")),
+        ),
+        (
+            status = 400,
+            description = "Bad request, e.g. an invalid invite code, a password rejected by the \
+                password policy, or a missing or failed CAPTCHA.",
+            body = ErrorWrap,
+        ),
+        (status = 502, description = "The CAPTCHA provider couldn't be reached.", body = ErrorWrap),
+    ),
+)]
 pub async fn create_account(
     q: CreateAccountQ,
+    client: Client,
     pool: DB,
-    pepper: &[u8],
-    domain: &str,
-    beta_key: &str,
-) -> Result<Response<Body>, Rejection> {
-    if q.beta_key != beta_key {
-        return Err(warp::reject::custom(BadRequest("invalid beta key".into())));
-    }
-    let hash = {
-        let kdf = create_kdf(pepper);
-        let salt = argon2::password_hash::SaltString::generate(OsRng);
-        kdf.hash_password(q.password.as_bytes(), &salt)
-            .expect("failed to hash password")
-            .to_string()
+    peppers: &Peppers,
+    policy: &SessionPolicy,
+    password_policy: &PasswordPolicy,
+    captcha: Option<&Captcha>,
+) -> Result<Response<Body>, ApiError> {
+    password_policy.check(&q.password, Some(&q.email))?;
+    if let Some(captcha) = captcha {
+        captcha
+            .verify(q.captcha_token.as_deref(), client.ip)
+            .await?;
+    }
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("{:?}", e);
+        ApiError::Internal
     };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let invite_id = crate::invite::redeem(&mut tx, &q.invite_code)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| ApiError::BadRequest("invalid invite code".into()))?;
+    let hash = hash_password(&q.password, peppers);
     let row = sqlx::query_scalar::<_, i64>(
-        "insert into account (email, password_hash) values ($1, $2) returning id",
+        "
+insert into account (email, password_hash, created_ip, invite_id)
+values ($1, $2, $3::inet, $4)
+returning id
+        ",
     )
     .bind(&q.email)
     .bind(hash)
-    .fetch_one(&pool)
+    .bind(client.ip.map(|ip| ip.to_string()))
+    .bind(invite_id)
+    .fetch_one(&mut tx)
     .await;
     let uid = match row {
         Ok(uid) => uid,
         Err(sqlx::Error::Database(db_err))
             if db_err.code() == Some(CONSTRAINT_VIOLATION_SQLSTATE.into()) =>
         {
-            return Err(warp::reject::custom(AccountAlreadyExists))
+            return Err(ApiError::AccountAlreadyExists)
         }
         Err(e) => {
             eprintln!("{:?}", e);
-            return Err(warp::reject::custom(InternalError));
+            return Err(ApiError::Internal);
         }
     };
+    tx.commit().await.map_err(internal_error)?;
 
-    let session = AccountSession::create(uid, q.email, &pool)
+    let session = AccountSession::create(uid, &client, policy, &pool)
         .await
         .map_err(|e| {
             eprintln!("{:?}", e);
-            warp::reject::custom(InternalError)
+            ApiError::Internal
         })?;
-    let session_id_cookie = session.to_cookie(domain).to_string();
+    let session_id_cookie = session.to_cookie(policy).to_string();
     Ok(json(&session)
         .pipe(|r| with_status(r, StatusCode::CREATED))
         .pipe(|r| with_header(r, SET_COOKIE, session_id_cookie))
         .into_response())
 }
 
-#[derive(Deserialize, Debug)]
+/// Request body to create session (log in).
+#[derive(Deserialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateSessionQ {
+    /// Account email.
+    #[schema(format = Email)]
     email: String,
+    /// Account password.
     password: String,
+    /// A code from the authenticator app, or a recovery code. Required for accounts with
+    /// two-factor authentication enabled.
+    #[serde(default)]
+    two_factor_code: Option<String>,
 }
 
+/// Create a new session for an existing account (log in).
+///
+/// Accounts have a limited number of sessions at once, see `FICAI_MAX_SESSIONS_PER_ACCOUNT`; going
+/// over it ends the oldest ones.
+#[utoipa::path(
+    post,
+    path = "/sessions",
+    tag = "sessions",
+    request_body = CreateSessionQ,
+    responses(
+        (
+            status = 200,
+            description = "Success. A session cookie named `FicAiSession` will be returned.",
+            body = AccountSession,
+            headers(("Set-Cookie" = String, description = "Includes session cookie.")),
+        ),
+        (status = 400, description = "Bad request.", body = ErrorWrap),
+        (
+            status = 401,
+            description = "The account has two-factor authentication enabled, and \
+                `twoFactorCode` is missing.",
+            body = ErrorWrap,
+        ),
+        (
+            status = 403,
+            description = "Forbidden, e.g. the password or the two-factor code is wrong.",
+            body = ErrorWrap,
+        ),
+        (
+            status = 429,
+            description = "Too many failed logins with the email, or from the client's address. \
+                Every further failure locks logging in out for longer.",
+            body = ErrorWrap,
+            headers((
+                "Retry-After" = u64,
+                description = "Seconds until logging in is possible again."
+            )),
+        ),
+    ),
+)]
 pub async fn create_session(
     q: CreateSessionQ,
+    client: Client,
     db: DB,
-    pepper: &[u8],
-    domain: &str,
-) -> Result<Response<Body>, Rejection> {
-    let row = sqlx::query_as::<_, (i64, String)>(
-        "select id, password_hash from account where email = $1",
-    )
-    .bind(&q.email)
-    .fetch_optional(&db)
-    .await
-    .map_err(|e| {
-        eprintln!("{:?}", e);
-        warp::reject::custom(InternalError)
-    })?;
-    let (uid, db_hash_string) = match row {
-        Some(row) => row,
-        None => return Err(warp::reject::custom(Forbidden)),
-    };
-    let db_hash =
-        PasswordHash::new(&db_hash_string).map_err(|_| warp::reject::custom(InternalError))?;
-    match create_kdf(pepper).verify_password(q.password.as_bytes(), &db_hash) {
-        Ok(_) => {}
-        Err(argon2::password_hash::Error::Password) => return Err(warp::reject::custom(Forbidden)),
-        Err(e) => {
+    peppers: &Peppers,
+    policy: &SessionPolicy,
+    throttle: &LoginThrottle,
+) -> Result<Response<Body>, ApiError> {
+    throttle.check(&q.email, client.ip(), &db).await?;
+    let verified = async {
+        let row = sqlx::query_as::<_, (i64, String)>(
+            "
+select id, password_hash from account where email = $1 and kind = 'person' and deleted_at is null
+            ",
+        )
+        .bind(&q.email)
+        .fetch_optional(&db)
+        .await
+        .map_err(|e| {
             eprintln!("{:?}", e);
-            return Err(warp::reject::custom(Forbidden));
+            ApiError::Internal
+        })?;
+        // Unknown emails fail the same way as wrong passwords, and take as long, lest either
+        // give away which emails have an account.
+        let (uid, db_hash_string) = match row {
+            Some((uid, hash)) => (Some(uid), hash),
+            None => (None, String::new()),
+        };
+        verify_password(&q.password, &db_hash_string, peppers)?;
+        let uid = uid.ok_or(ApiError::Forbidden)?;
+        crate::totp::verify_login(uid, q.two_factor_code.as_deref(), &db).await?;
+        Ok::<_, ApiError>((uid, db_hash_string))
+    }
+    .await;
+    let (uid, db_hash_string) = match verified {
+        Ok(verified) => verified,
+        Err(r) => {
+            if matches!(r, ApiError::Forbidden) {
+                throttle.record_failure(&q.email, client.ip(), &db).await;
+            }
+            return Err(r);
+        }
+    };
+    throttle.clear(&q.email, &db).await;
+    crate::account_status::check(uid, &db).await?;
+    if needs_rehash(&db_hash_string, peppers) {
+        // Only if the password wasn't changed in the meantime.
+        if let Err(e) = sqlx::query(
+            "update account set password_hash = $3 where id = $1 and password_hash = $2",
+        )
+        .bind(uid)
+        .bind(&db_hash_string)
+        .bind(hash_password(&q.password, peppers))
+        .execute(&db)
+        .await
+        {
+            eprintln!("failed to rehash password: {:?}", e);
         }
     }
-    let session = AccountSession::create(uid, q.email, &db)
+    record_event(uid, EventKind::Login, &client, &db).await;
+    let session = AccountSession::create(uid, &client, policy, &db)
         .await
         .map_err(|e| {
             eprintln!("{:?}", e);
-            warp::reject::custom(InternalError)
+            ApiError::Internal
         })?;
-    let session_id_cookie = session.to_cookie(domain).to_string();
+    let session_id_cookie = session.to_cookie(policy).to_string();
     Ok(json(&session)
         .pipe(|r| with_header(r, SET_COOKIE, session_id_cookie))
         .into_response())
 }
 
-pub async fn get_session_account(account: AccountSession) -> Result<Response<Body>, Rejection> {
-    Ok(json(&account).into_response())
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionAccount {
+    #[serde(flatten)]
+    account: AccountSession,
+    /// The email associated with this account. Must be unique.
+    #[schema(format = Email)]
+    email: String,
+    /// Everything the account may do beyond what every account can, whether granted explicitly or
+    /// implied by its role, so that clients know which admin tooling to offer.
+    permissions: Vec<Permission>,
+}
+
+/// Get current account info.
+#[utoipa::path(
+    get,
+    path = "/sessions",
+    tag = "sessions",
+    responses(
+        (status = 200, description = "Success.", body = SessionAccount),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_session_account(
+    account: AccountSession,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let granted = sqlx::query_scalar::<_, Permission>(
+        "select permission from account_permission where account_id = $1",
+    )
+    .bind(account.id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to get account permissions: {:?}", e);
+        ApiError::Internal
+    })?;
+    let permissions = Permission::ALL
+        .into_iter()
+        .filter(|p| p.implied_by(account.role) || granted.contains(p))
+        .collect();
+    Ok(json(&SessionAccount {
+        email: account.email.clone(),
+        account,
+        permissions,
+    })
+    .into_response())
 }
 
+/// Delete a session (log out).
+#[utoipa::path(
+    delete,
+    path = "/sessions",
+    tag = "sessions",
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
 pub async fn delete_session(
     session: AccountSession,
     pool: DB,
     domain: &str,
-) -> Result<Response<Body>, Rejection> {
+) -> Result<Response<Body>, ApiError> {
     let rows_affected = sqlx::query("delete from session where id = $1")
         .bind(&session.session_id)
         .execute(&pool)
         .await
         .map_err(|e| {
             eprintln!("error deleting session: {:#?}", e);
-            warp::reject::custom(InternalError)
+            ApiError::Internal
         })?
         .rows_affected();
     if 1 == rows_affected {
@@ -219,48 +875,1180 @@ pub async fn delete_session(
     } else {
         // This may mean the account was deleted in between validating their session and getting to
         // this point, which means the current request is racing against a delete.
-        Err(warp::reject::custom(InternalError))
+        Err(ApiError::Internal)
+    }
+}
+
+/// Identifies a session in listings without giving away the session id, which would let anyone
+/// who sees it log in.
+fn session_handle(session_id: &[u8]) -> String {
+    base64ct::Base64UrlUnpadded::encode_string(&Sha256::digest(session_id))
+}
+
+#[derive(sqlx::FromRow)]
+struct SessionRow {
+    id: Vec<u8>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    last_used_at: chrono::DateTime<chrono::Utc>,
+    created_ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    /// Pass to `DELETE /sessions/{id}` to log the session out.
+    id: String,
+    created_at: Timestamp,
+    /// Updated at most once a minute.
+    last_used_at: Timestamp,
+    /// The address of the client that logged in.
+    created_ip: Option<String>,
+    user_agent: Option<String>,
+    /// Whether this is the session making the request.
+    current: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Sessions {
+    sessions: Vec<SessionInfo>,
+}
+
+/// List the sessions of the current account.
+///
+/// Most recently used first.
+#[utoipa::path(
+    get,
+    path = "/sessions/all",
+    tag = "sessions",
+    responses(
+        (status = 200, description = "Success.", body = Sessions),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn get_sessions(session: AccountSession, pool: DB) -> Result<Response<Body>, ApiError> {
+    let rows = sqlx::query_as::<_, SessionRow>(
+        "
+select id, created_at, last_used_at, host(created_ip) as created_ip, user_agent
+from session
+where account_id = $1 and expires_at > now() and last_used_at + idle_timeout > now()
+order by last_used_at desc, created_at desc
+        ",
+    )
+    .bind(session.id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to list sessions: {:?}", e);
+        ApiError::Internal
+    })?;
+    let tz = session.tz();
+    let sessions = rows
+        .into_iter()
+        .map(|r| SessionInfo {
+            id: session_handle(&r.id),
+            created_at: Timestamp::new(r.created_at, tz),
+            last_used_at: Timestamp::new(r.last_used_at, tz),
+            created_ip: r.created_ip,
+            user_agent: r.user_agent,
+            current: r.id == session.session_id,
+        })
+        .collect();
+    Ok(json(&Sessions { sessions }).into_response())
+}
+
+/// Log out a session of the current account.
+///
+/// Revoking the current session also removes its cookie.
+#[utoipa::path(
+    delete,
+    path = "/sessions/{id}",
+    tag = "sessions",
+    params(
+        ("id" = String, Path, description = "The `id` from `/sessions/all`."),
+    ),
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 404, description = "The account has no such session.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn revoke_session(
+    session: AccountSession,
+    handle: String,
+    client: Client,
+    pool: DB,
+    domain: &str,
+) -> Result<Response<Body>, ApiError> {
+    let revoked = sqlx::query_scalar::<_, Vec<u8>>(
+        "
+delete from session
+where account_id = $1 and sha256(id) = $2
+returning id
+        ",
+    )
+    .bind(session.id)
+    .bind(base64ct::Base64UrlUnpadded::decode_vec(&handle).unwrap_or_default())
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to revoke session: {:?}", e);
+        ApiError::Internal
+    })?
+    .ok_or(ApiError::NotFound)?;
+    record_event(session.id, EventKind::SessionRevoke, &client, &pool).await;
+    if revoked == session.session_id {
+        Ok(json(&Empty {})
+            .pipe(|r| with_header(r, SET_COOKIE, session.to_cookie_removal(domain).to_string()))
+            .into_response())
+    } else {
+        Ok(json(&Empty {}).into_response())
     }
 }
 
+/// Log out every session of the current account but the current one.
+#[utoipa::path(
+    delete,
+    path = "/sessions/others",
+    tag = "sessions",
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn revoke_other_sessions(
+    session: AccountSession,
+    client: Client,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    sqlx::query("delete from session where account_id = $1 and id <> $2")
+        .bind(session.id)
+        .bind(&session.session_id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("failed to revoke sessions: {:?}", e);
+            ApiError::Internal
+        })?;
+    record_event(session.id, EventKind::OtherSessionsRevoke, &client, &pool).await;
+    Ok(json(&Empty {}).into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePasswordQ {
+    current_password: String,
+    new_password: String,
+}
+
+/// Change the current account's password.
+///
+/// Every other session of the account is logged out.
+#[utoipa::path(
+    post,
+    path = "/accounts/password",
+    tag = "accounts",
+    request_body = ChangePasswordQ,
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (
+            status = 400,
+            description = "Bad request, e.g. the new password is rejected by the password \
+                policy.",
+            body = ErrorWrap,
+        ),
+        (
+            status = 403,
+            description = "Not logged in, or the current password is wrong.",
+            body = ErrorWrap,
+        ),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn change_password(
+    session: AccountSession,
+    q: ChangePasswordQ,
+    client: Client,
+    pool: DB,
+    peppers: &Peppers,
+    password_policy: &PasswordPolicy,
+) -> Result<Response<Body>, ApiError> {
+    password_policy.check(&q.new_password, Some(&session.email))?;
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to change password: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let db_hash_string = sqlx::query_scalar::<_, String>(
+        "select password_hash from account where id = $1 for update",
+    )
+    .bind(session.id)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(internal_error)?;
+    verify_password(&q.current_password, &db_hash_string, peppers)?;
+    sqlx::query("update account set password_hash = $2 where id = $1")
+        .bind(session.id)
+        .bind(hash_password(&q.new_password, peppers))
+        .execute(&mut tx)
+        .await
+        .map_err(internal_error)?;
+    sqlx::query("delete from session where account_id = $1 and id <> $2")
+        .bind(session.id)
+        .bind(&session.session_id)
+        .execute(&mut tx)
+        .await
+        .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    record_event(session.id, EventKind::PasswordChange, &client, &pool).await;
+    Ok(json(&Empty {}).into_response())
+}
+
+/// What happens to the signals of deleted accounts.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeletedSignals {
+    Delete,
+    /// Keep them, counted as an anonymous account's.
+    Detach,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteAccountQ {
+    password: String,
+}
+
+/// Delete the current account.
+///
+/// Logs out every session and removes what identifies the account holder. Depending on the server's
+/// configuration, the account's signals are deleted or kept anonymously.
+#[utoipa::path(
+    delete,
+    path = "/accounts",
+    tag = "accounts",
+    request_body = DeleteAccountQ,
+    responses(
+        (status = 200, description = "Success. The session cookie is removed.", body = Empty),
+        (status = 403, description = "Not logged in, or the password is wrong.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn delete_account(
+    session: AccountSession,
+    q: DeleteAccountQ,
+    pool: DB,
+    peppers: &Peppers,
+    domain: &str,
+    signals: DeletedSignals,
+) -> Result<Response<Body>, ApiError> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to delete account: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let db_hash_string = sqlx::query_scalar::<_, String>(
+        "select password_hash from account where id = $1 for update",
+    )
+    .bind(session.id)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(internal_error)?;
+    verify_password(&q.password, &db_hash_string, peppers)?;
+    close_account(&mut tx, session.id, signals)
+        .await
+        .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(json(&Empty {})
+        .pipe(|r| with_header(r, SET_COOKIE, session.to_cookie_removal(domain).to_string()))
+        .into_response())
+}
+
+/// Deletes what belongs to the account and anonymizes what's left of it, ending its sessions.
+pub(crate) async fn close_account(
+    tx: &mut Transaction<'_, Postgres>,
+    account_id: i64,
+    signals: DeletedSignals,
+) -> Result<(), sqlx::Error> {
+    let mut purged = vec![
+        "delete from session where account_id = $1",
+        "delete from token where account_id = $1",
+        "delete from oauth_identity where account_id = $1",
+        "delete from account_event where account_id = $1",
+        "delete from totp_recovery_code where account_id = $1",
+        "delete from account_totp where account_id = $1",
+        "delete from password_reset where account_id = $1",
+        "delete from magic_link where account_id = $1",
+        "delete from email_change where account_id = $1",
+        "update invite set expires_at = least(expires_at, now()) where created_by = $1",
+        "delete from account_permission where account_id = $1",
+        "delete from tag_subscription where account_id = $1",
+        "delete from blocked_tag where account_id = $1",
+        "delete from tag_alias_proposal_vote where account_id = $1",
+        "delete from duplicate_account_candidate where $1 in (account_id_a, account_id_b)",
+    ];
+    if signals == DeletedSignals::Delete {
+        purged.push("delete from signal where account_id = $1");
+    }
+    for query in purged {
+        sqlx::query(query)
+            .bind(account_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query(
+        "
+update account
+set email = 'deleted:' || id, display_name = null, password_hash = '', role = 'user',
+    created_ip = null, timezone = 'UTC', preferences = '{}', rate_limit_tier = 'normal',
+    deleted_at = now()
+where id = $1
+        ",
+    )
+    .bind(account_id)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// A random token to send by email, and the hash it is stored as.
+fn generate_mail_token() -> (String, Vec<u8>) {
+    let mut token = [0u8; MAIL_TOKEN_BYTES];
+    OsRng.fill_bytes(&mut token);
+    (
+        base64ct::Base64UrlUnpadded::encode_string(&token),
+        Sha256::digest(token).to_vec(),
+    )
+}
+
+/// The hash of a token from [`generate_mail_token`], or `None` if it can't be one.
+fn mail_token_hash(token: &str) -> Option<Vec<u8>> {
+    let token = base64ct::Base64UrlUnpadded::decode_vec(token).ok()?;
+    Some(Sha256::digest(token).to_vec())
+}
+
+pub struct PasswordReset {
+    pub mailer: &'static dyn Mailer,
+    /// How long a reset link stays valid.
+    pub ttl: chrono::Duration,
+    /// Where reset links point to, with `{token}` standing in for the token.
+    pub link: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestPasswordResetQ {
+    email: String,
+}
+
+/// Email a link to reset an account's password.
+///
+/// Answers the same whether or not an account with the email exists. The link is valid for an hour
+/// by default.
+#[utoipa::path(
+    post,
+    path = "/accounts/password-reset",
+    tag = "accounts",
+    request_body = RequestPasswordResetQ,
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+    ),
+)]
+pub async fn request_password_reset(
+    q: RequestPasswordResetQ,
+    pool: DB,
+    reset: &'static PasswordReset,
+) -> Result<Response<Body>, ApiError> {
+    let (token, token_hash) = generate_mail_token();
+    let created = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query("delete from password_reset where expires_at < now()")
+            .execute(&mut tx)
+            .await?;
+        let created = sqlx::query(
+            "
+insert into password_reset (token_hash, account_id, expires_at)
+select $1, id, now() + $3 * interval '1 second'
+from account
+where email = $2 and kind = 'person'
+            ",
+        )
+        .bind(&token_hash)
+        .bind(&q.email)
+        .bind(reset.ttl.num_seconds() as f64)
+        .execute(&mut tx)
+        .await?
+        .rows_affected()
+            > 0;
+        tx.commit().await?;
+        eyre::Result::<_>::Ok(created)
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to create password reset token: {:?}", e);
+        ApiError::Internal
+    })?;
+    if created {
+        // Sent in the background, so that answering doesn't take longer for existing accounts.
+        send_in_background(
+            reset.mailer,
+            q.email,
+            Template::PasswordReset {
+                link: reset.link.replace("{token}", &token),
+                valid_for: reset.ttl,
+            },
+        );
+    }
+    Ok(json(&Empty {}).into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmPasswordResetQ {
+    token: String,
+    new_password: String,
+}
+
+/// Set a new password with a token from a password reset email.
+///
+/// Tokens can only be used once. Every session of the account is logged out.
+#[utoipa::path(
+    post,
+    path = "/accounts/password-reset/confirm",
+    tag = "accounts",
+    request_body = ConfirmPasswordResetQ,
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (
+            status = 400,
+            description = "Bad request, e.g. the token is invalid or expired, or the new \
+                password is rejected by the password policy.",
+            body = ErrorWrap,
+        ),
+    ),
+)]
+pub async fn confirm_password_reset(
+    q: ConfirmPasswordResetQ,
+    client: Client,
+    pool: DB,
+    peppers: &Peppers,
+    password_policy: &PasswordPolicy,
+) -> Result<Response<Body>, ApiError> {
+    // Checked before using up the token, without the email, which isn't known until then.
+    password_policy.check(&q.new_password, None)?;
+    let invalid_token = || ApiError::BadRequest("invalid or expired token".into());
+    let token_hash = mail_token_hash(&q.token).ok_or_else(invalid_token)?;
+    let reset = async {
+        let mut tx = pool.begin().await?;
+        let account_id = sqlx::query_scalar::<_, i64>(
+            "
+delete from password_reset
+where token_hash = $1 and expires_at > now()
+returning account_id
+            ",
+        )
+        .bind(&token_hash)
+        .fetch_optional(&mut tx)
+        .await?;
+        let account_id = match account_id {
+            Some(account_id) => account_id,
+            None => return Ok(None),
+        };
+        sqlx::query("update account set password_hash = $2 where id = $1")
+            .bind(account_id)
+            .bind(hash_password(&q.new_password, peppers))
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("delete from session where account_id = $1")
+            .bind(account_id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("delete from password_reset where account_id = $1")
+            .bind(account_id)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        eyre::Result::<_>::Ok(Some(account_id))
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to reset password: {:?}", e);
+        ApiError::Internal
+    })?;
+    match reset {
+        Some(account_id) => {
+            record_event(account_id, EventKind::PasswordReset, &client, &pool).await;
+            Ok(json(&Empty {}).into_response())
+        }
+        None => Err(invalid_token()),
+    }
+}
+
+pub struct MagicLink {
+    pub mailer: &'static dyn Mailer,
+    /// How long a login link stays valid.
+    pub ttl: chrono::Duration,
+    /// Where login links point to, with `{token}` standing in for the token.
+    pub link: String,
+    /// Where visiting a login link leads once logged in.
+    pub return_url: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestMagicLinkQ {
+    email: String,
+}
+
+/// Email a link that logs into an account without its password.
+///
+/// Answers the same whether or not an account with the email exists. Accounts with two-factor
+/// authentication don't get a link, since it would bypass it. The link is valid for 15 minutes by
+/// default.
+#[utoipa::path(
+    post,
+    path = "/sessions/magic-link",
+    tag = "sessions",
+    request_body = RequestMagicLinkQ,
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+    ),
+)]
+pub async fn request_magic_link(
+    q: RequestMagicLinkQ,
+    pool: DB,
+    magic_link: &'static MagicLink,
+) -> Result<Response<Body>, ApiError> {
+    let (token, token_hash) = generate_mail_token();
+    let created = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query("delete from magic_link where expires_at < now()")
+            .execute(&mut tx)
+            .await?;
+        let created = sqlx::query(
+            "
+insert into magic_link (token_hash, account_id, expires_at)
+select $1, a.id, now() + $3 * interval '1 second'
+from account a
+where a.email = $2 and a.kind = 'person'
+    and not exists (
+        select from account_totp t where t.account_id = a.id and t.enabled_at is not null
+    )
+            ",
+        )
+        .bind(&token_hash)
+        .bind(&q.email)
+        .bind(magic_link.ttl.num_seconds() as f64)
+        .execute(&mut tx)
+        .await?
+        .rows_affected()
+            > 0;
+        tx.commit().await?;
+        eyre::Result::<_>::Ok(created)
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to create magic link token: {:?}", e);
+        ApiError::Internal
+    })?;
+    if created {
+        // Sent in the background, so that answering doesn't take longer for existing accounts.
+        send_in_background(
+            magic_link.mailer,
+            q.email,
+            Template::MagicLink {
+                link: magic_link.link.replace("{token}", &token),
+                valid_for: magic_link.ttl,
+            },
+        );
+    }
+    Ok(json(&Empty {}).into_response())
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MagicLinkQ {
+    token: String,
+}
+
+/// Log in with a token from a magic link email.
+///
+/// What the emailed link points to. Tokens can only be used once; using one voids the account's
+/// others.
+#[utoipa::path(
+    get,
+    path = "/sessions/magic-link",
+    tag = "sessions",
+    params(MagicLinkQ),
+    responses(
+        (
+            status = 302,
+            description = "Logged in; redirects to the site.",
+            headers(("Set-Cookie" = String)),
+        ),
+        (status = 400, description = "The token is invalid, expired or used up.", body = ErrorWrap),
+        (status = 403, description = "The account is suspended or banned.", body = ErrorWrap),
+    ),
+)]
+pub async fn login_with_magic_link(
+    q: MagicLinkQ,
+    client: Client,
+    pool: DB,
+    policy: &SessionPolicy,
+    magic_link: &MagicLink,
+) -> Result<Response<Body>, ApiError> {
+    let invalid_token = || ApiError::BadRequest("invalid or expired token".into());
+    let token_hash = mail_token_hash(&q.token).ok_or_else(invalid_token)?;
+    let account_id = async {
+        let mut tx = pool.begin().await?;
+        let account_id = sqlx::query_scalar::<_, i64>(
+            "
+delete from magic_link m
+where m.token_hash = $1 and m.expires_at > now()
+    and not exists (
+        select from account_totp t where t.account_id = m.account_id and t.enabled_at is not null
+    )
+returning m.account_id
+            ",
+        )
+        .bind(&token_hash)
+        .fetch_optional(&mut tx)
+        .await?;
+        if let Some(account_id) = account_id {
+            sqlx::query("delete from magic_link where account_id = $1")
+                .bind(account_id)
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+        eyre::Result::<_>::Ok(account_id)
+    }
+    .await
+    .map_err(|e| {
+        eprintln!("failed to use magic link token: {:?}", e);
+        ApiError::Internal
+    })?
+    .ok_or_else(invalid_token)?;
+    crate::account_status::check(account_id, &pool).await?;
+    record_event(account_id, EventKind::MagicLinkLogin, &client, &pool).await;
+    let session = AccountSession::create(account_id, &client, policy, &pool)
+        .await
+        .map_err(|e| {
+            eprintln!("{:?}", e);
+            ApiError::Internal
+        })?;
+    Ok(Response::builder()
+        .status(StatusCode::FOUND)
+        .header(LOCATION, &magic_link.return_url)
+        .header(SET_COOKIE, session.to_cookie(policy).to_string())
+        .body(Body::empty())
+        .unwrap())
+}
+
+pub struct EmailChange {
+    pub mailer: &'static dyn Mailer,
+    /// How long a confirmation link stays valid.
+    pub ttl: chrono::Duration,
+    /// Where confirmation links point to, with `{token}` standing in for the token.
+    pub link: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeEmailQ {
+    current_password: String,
+    new_email: String,
+}
+
+/// Change the current account's email.
+///
+/// Sends a confirmation link to the new address; the email only changes once the link is used, see
+/// `/accounts/email/confirm`. Asking again replaces the pending change.
+#[utoipa::path(
+    post,
+    path = "/accounts/email",
+    tag = "accounts",
+    request_body = ChangeEmailQ,
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (
+            status = 400,
+            description = "Bad request, e.g. the new email is empty or the current one.",
+            body = ErrorWrap,
+        ),
+        (
+            status = 403,
+            description = "Not logged in, or the current password is wrong.",
+            body = ErrorWrap,
+        ),
+        (
+            status = 409,
+            description = "Another account already has the new email.",
+            body = ErrorWrap,
+        ),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn change_email(
+    session: AccountSession,
+    q: ChangeEmailQ,
+    pool: DB,
+    peppers: &Peppers,
+    change: &'static EmailChange,
+) -> Result<Response<Body>, ApiError> {
+    let new_email = q.new_email.trim().to_string();
+    if new_email.is_empty() {
+        return Err(ApiError::BadRequest("new email is empty".into()));
+    }
+    if new_email == session.email {
+        return Err(ApiError::BadRequest("new email is the current one".into()));
+    }
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to request email change: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let db_hash_string =
+        sqlx::query_scalar::<_, String>("select password_hash from account where id = $1")
+            .bind(session.id)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(internal_error)?;
+    verify_password(&q.current_password, &db_hash_string, peppers)?;
+    // Checked again on confirmation, but most conflicts are better caught before sending mail.
+    let taken =
+        sqlx::query_scalar::<_, bool>("select exists(select from account where email = $1)")
+            .bind(&new_email)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(internal_error)?;
+    if taken {
+        return Err(ApiError::AccountAlreadyExists);
+    }
+    let (token, token_hash) = generate_mail_token();
+    sqlx::query("delete from email_change where account_id = $1 or expires_at < now()")
+        .bind(session.id)
+        .execute(&mut tx)
+        .await
+        .map_err(internal_error)?;
+    sqlx::query(
+        "
+insert into email_change (token_hash, account_id, new_email, expires_at)
+values ($1, $2, $3, now() + $4 * interval '1 second')
+        ",
+    )
+    .bind(&token_hash)
+    .bind(session.id)
+    .bind(&new_email)
+    .bind(change.ttl.num_seconds() as f64)
+    .execute(&mut tx)
+    .await
+    .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+
+    send_in_background(
+        change.mailer,
+        new_email,
+        Template::ConfirmEmailChange {
+            old_email: session.email,
+            link: change.link.replace("{token}", &token),
+            valid_for: change.ttl,
+        },
+    );
+    Ok(json(&Empty {}).into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmEmailChangeQ {
+    token: String,
+}
+
+/// Confirm an email change with the token sent to the new address.
+///
+/// Tokens can only be used once. The old address is notified of the change.
+#[utoipa::path(
+    post,
+    path = "/accounts/email/confirm",
+    tag = "accounts",
+    request_body = ConfirmEmailChangeQ,
+    responses(
+        (status = 200, description = "Success.", body = Empty),
+        (
+            status = 400,
+            description = "Bad request, e.g. the token is invalid or expired.",
+            body = ErrorWrap,
+        ),
+        (
+            status = 409,
+            description = "Another account took the new email in the meantime.",
+            body = ErrorWrap,
+        ),
+    ),
+)]
+pub async fn confirm_email_change(
+    q: ConfirmEmailChangeQ,
+    client: Client,
+    pool: DB,
+    change: &'static EmailChange,
+) -> Result<Response<Body>, ApiError> {
+    let invalid_token = || ApiError::BadRequest("invalid or expired token".into());
+    let token_hash = mail_token_hash(&q.token).ok_or_else(invalid_token)?;
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("failed to change email: {:?}", e);
+        ApiError::Internal
+    };
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let (account_id, new_email) = sqlx::query_as::<_, (i64, String)>(
+        "
+delete from email_change
+where token_hash = $1 and expires_at > now()
+returning account_id, new_email
+        ",
+    )
+    .bind(&token_hash)
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(invalid_token)?;
+    let old_email =
+        sqlx::query_scalar::<_, String>("select email from account where id = $1 for update")
+            .bind(account_id)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(internal_error)?;
+    let updated = sqlx::query("update account set email = $2 where id = $1")
+        .bind(account_id)
+        .bind(&new_email)
+        .execute(&mut tx)
+        .await;
+    match updated {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(db_err))
+            if db_err.code() == Some(CONSTRAINT_VIOLATION_SQLSTATE.into()) =>
+        {
+            return Err(ApiError::AccountAlreadyExists)
+        }
+        Err(e) => return Err(internal_error(e)),
+    }
+    tx.commit().await.map_err(internal_error)?;
+    record_event(account_id, EventKind::EmailChange, &client, &pool).await;
+
+    send_in_background(
+        change.mailer,
+        old_email,
+        Template::EmailChanged { new_email },
+    );
+    Ok(json(&Empty {}).into_response())
+}
+
 pub fn optional_authenticate(
     db: DB,
+    policy: &'static SessionPolicy,
 ) -> impl Filter<Extract = (Option<AccountSession>,), Error = Rejection> + Clone {
-    warp::cookie::optional(SESSION_COOKIE_NAME).and_then(move |cookie: Option<String>| {
-        let db = db.clone();
-        async move {
-            let cookie = match cookie {
-                Some(cookie) => cookie,
-                None => return Ok(None),
-            };
-            let cookie = base64ct::Base64Unpadded::decode_vec(&cookie)
-                .map_err(|_| warp::reject::custom(BadRequest("invalid auth cookie".into())))?;
-
-            let row = sqlx::query_as::<_, AccountSession>(
-                r#"
-                select a.id, a.email
-                    , s.id as session_id
+    warp::cookie::optional(SESSION_COOKIE_NAME)
+        .then(move |cookie: Option<String>| {
+            let db = db.clone();
+            async move {
+                let cookie = match cookie {
+                    Some(cookie) => cookie,
+                    None => return Ok(None),
+                };
+                // Unsigned or badly signed cookies count as no session; `renew_session_cookie`
+                // clears them.
+                let cookie = match policy.session_id(&cookie) {
+                    Some(session_id) => session_id,
+                    None => return Ok(None),
+                };
+
+                // Marks the session as used, at most once a minute to spare the writes. Expired
+                // sessions are treated like unknown ones.
+                let row = sqlx::query_as::<_, AccountSession>(
+                    r#"
+                with touched as (
+                    update session set last_used_at = now()
+                    where id = $1 and last_used_at < now() - interval '1 minute'
+                        and expires_at > now() and last_used_at + idle_timeout > now()
+                )
+                select a.id, a.email, a.display_name, a.role, a.timezone
+                    , s.id as session_id, a.status, a.status_reason, a.suspended_until
+                    , a.rate_limit_tier
                 from session s
                 join account a
                     on a.id = s.account_id
-                where s.id = $1"#,
-            )
-            .bind(&cookie)
-            .fetch_optional(&db)
-            .await;
-            match row {
-                Ok(account_session) => Ok(account_session),
-                Err(e) => {
+                where s.id = $1
+                    and s.expires_at > now() and s.last_used_at + s.idle_timeout > now()"#,
+                )
+                .bind(&cookie)
+                .fetch_optional(&db)
+                .await;
+                match row {
+                    Ok(Some(account_session)) => match account_session.restriction() {
+                        Some(restriction) => Err(ApiError::AccountRestricted(restriction)),
+                        None => Ok(Some(account_session)),
+                    },
+                    Ok(None) => Ok(None),
+                    Err(e) => {
+                        eprintln!("{:?}", e);
+                        Err(ApiError::Internal)
+                    }
+                }
+            }
+        })
+        .and_then(reject)
+}
+
+pub fn authenticate(
+    db: DB,
+    policy: &'static SessionPolicy,
+) -> impl Filter<Extract = (AccountSession,), Error = Rejection> + Clone {
+    session_cookie_value()
+        .and(optional_authenticate(db, policy))
+        .then(
+            move |cookie: Option<String>, account_session: Option<AccountSession>| async move {
+                account_session.ok_or_else(|| policy.unauthenticated(cookie.as_deref()))
+            },
+        )
+        .and_then(reject)
+}
+
+/// Like [`authenticate`], but additionally rejects accounts whose role is below `role`.
+pub fn require_role(
+    db: DB,
+    policy: &'static SessionPolicy,
+    role: Role,
+) -> impl Filter<Extract = (AccountSession,), Error = Rejection> + Clone {
+    authenticate(db, policy)
+        .then(move |account_session: AccountSession| async move {
+            if account_session.role >= role {
+                Ok(account_session)
+            } else {
+                Err(ApiError::Forbidden)
+            }
+        })
+        .and_then(reject)
+}
+
+/// Like [`authenticate`], but additionally rejects accounts that neither have `permission` granted
+/// explicitly nor implied by their role.
+pub fn require_permission(
+    db: DB,
+    policy: &'static SessionPolicy,
+    permission: Permission,
+) -> impl Filter<Extract = (AccountSession,), Error = Rejection> + Clone {
+    authenticate(db.clone(), policy)
+        .then(move |account_session: AccountSession| {
+            let db = db.clone();
+            async move {
+                if permission.implied_by(account_session.role) {
+                    return Ok(account_session);
+                }
+                let granted = sqlx::query_scalar::<_, bool>(
+                    "
+select exists(
+    select 1 from account_permission where account_id = $1 and permission = $2
+)",
+                )
+                .bind(account_session.id)
+                .bind(permission)
+                .fetch_one(&db)
+                .await
+                .map_err(|e| {
                     eprintln!("{:?}", e);
-                    Err(warp::reject::custom(InternalError))
+                    ApiError::Internal
+                })?;
+                if granted {
+                    Ok(account_session)
+                } else {
+                    Err(ApiError::Forbidden)
                 }
             }
-        }
-    })
+        })
+        .and_then(reject)
 }
 
-pub fn authenticate(db: DB) -> impl Filter<Extract = (AccountSession,), Error = Rejection> + Clone {
-    optional_authenticate(db).and_then(|account_session: Option<AccountSession>| async {
-        account_session.ok_or_else(|| warp::reject::custom(Forbidden))
-    })
+#[derive(OpenApi)]
+#[openapi(paths(
+    create_account,
+    delete_account,
+    change_password,
+    change_email,
+    confirm_email_change,
+    request_password_reset,
+    confirm_password_reset,
+    create_session,
+    get_session_account,
+    delete_session,
+    request_magic_link,
+    login_with_magic_link,
+    get_sessions,
+    revoke_other_sessions,
+    revoke_session,
+))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let create_account = warp::path!("v1" / "accounts")
+        .and(warp::post())
+        .and(json_body::<CreateAccountQ>(ctx.max_body_bytes))
+        .and(ctx.client())
+        .and(ctx.pool())
+        .then(move |q, client, pool| {
+            create_account(
+                q,
+                client,
+                pool,
+                ctx.peppers,
+                ctx.session_policy,
+                ctx.password_policy,
+                ctx.captcha,
+            )
+        })
+        .and_then(reject);
+    let create_session = warp::path!("v1" / "sessions")
+        .and(warp::post())
+        .and(json_body::<CreateSessionQ>(ctx.max_body_bytes))
+        .and(ctx.client())
+        .and(ctx.pool())
+        .then(move |q, client, pool| {
+            create_session(
+                q,
+                client,
+                pool,
+                ctx.peppers,
+                ctx.session_policy,
+                ctx.login_throttle,
+            )
+        })
+        .and_then(reject);
+    let change_password = warp::path!("v1" / "accounts" / "password")
+        .and(warp::post())
+        .and(ctx.authenticate())
+        .and(json_body::<ChangePasswordQ>(ctx.max_body_bytes))
+        .and(ctx.client())
+        .and(ctx.pool())
+        .then(move |session, q, client, pool| {
+            change_password(session, q, client, pool, ctx.peppers, ctx.password_policy)
+        })
+        .and_then(reject);
+    let request_password_reset = warp::path!("v1" / "accounts" / "password-reset")
+        .and(warp::post())
+        .and(json_body::<RequestPasswordResetQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |q, pool| request_password_reset(q, pool, ctx.password_reset))
+        .and_then(reject);
+    let confirm_password_reset = warp::path!("v1" / "accounts" / "password-reset" / "confirm")
+        .and(warp::post())
+        .and(json_body::<ConfirmPasswordResetQ>(ctx.max_body_bytes))
+        .and(ctx.client())
+        .and(ctx.pool())
+        .then(move |q, client, pool| {
+            confirm_password_reset(q, client, pool, ctx.peppers, ctx.password_policy)
+        })
+        .and_then(reject);
+    let request_magic_link = warp::path!("v1" / "sessions" / "magic-link")
+        .and(warp::post())
+        .and(json_body::<RequestMagicLinkQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |q, pool| request_magic_link(q, pool, ctx.magic_link))
+        .and_then(reject);
+    let login_with_magic_link = warp::path!("v1" / "sessions" / "magic-link")
+        .and(warp::get())
+        .and(warp::query::<MagicLinkQ>())
+        .and(ctx.client())
+        .and(ctx.pool())
+        .then(move |q, client, pool| {
+            login_with_magic_link(q, client, pool, ctx.session_policy, ctx.magic_link)
+        })
+        .and_then(reject);
+    let change_email = warp::path!("v1" / "accounts" / "email")
+        .and(warp::post())
+        .and(ctx.authenticate())
+        .and(json_body::<ChangeEmailQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |session, q, pool| change_email(session, q, pool, ctx.peppers, ctx.email_change))
+        .and_then(reject);
+    let confirm_email_change = warp::path!("v1" / "accounts" / "email" / "confirm")
+        .and(warp::post())
+        .and(json_body::<ConfirmEmailChangeQ>(ctx.max_body_bytes))
+        .and(ctx.client())
+        .and(ctx.pool())
+        .then(move |q, client, pool| confirm_email_change(q, client, pool, ctx.email_change))
+        .and_then(reject);
+    let delete_account = warp::path!("v1" / "accounts")
+        .and(warp::delete())
+        .and(ctx.authenticate())
+        .and(json_body::<DeleteAccountQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(move |session, q, pool| {
+            delete_account(
+                session,
+                q,
+                pool,
+                ctx.peppers,
+                ctx.domain,
+                ctx.account_deletion_signals,
+            )
+        })
+        .and_then(reject);
+    let get_session_account = warp::path!("v1" / "sessions")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(get_session_account)
+        .and_then(reject);
+    let delete_session = warp::path!("v1" / "sessions")
+        .and(warp::delete())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(move |session, pool| delete_session(session, pool, ctx.domain))
+        .and_then(reject);
+    let get_sessions = warp::path!("v1" / "sessions" / "all")
+        .and(warp::get())
+        .and(ctx.authenticate())
+        .and(ctx.pool())
+        .then(get_sessions)
+        .and_then(reject);
+    // Before `revoke_session`, which would take `others` for a session id.
+    let revoke_other_sessions = warp::path!("v1" / "sessions" / "others")
+        .and(warp::delete())
+        .and(ctx.authenticate())
+        .and(ctx.client())
+        .and(ctx.pool())
+        .then(revoke_other_sessions)
+        .and_then(reject);
+    let revoke_session = warp::path!("v1" / "sessions" / String)
+        .and(warp::delete())
+        .and(ctx.authenticate())
+        .and(ctx.client())
+        .and(ctx.pool())
+        .then(move |id, session, client, pool| {
+            revoke_session(session, id, client, pool, ctx.domain)
+        })
+        .and_then(reject);
+    cached(
+        CachePolicy::Never,
+        &[],
+        create_account
+            .or(create_session)
+            .or(change_password)
+            .or(request_password_reset)
+            .or(confirm_password_reset)
+            .or(request_magic_link)
+            .or(login_with_magic_link)
+            .or(change_email)
+            .or(confirm_email_change)
+            .or(delete_account)
+            .or(get_session_account)
+            .or(delete_session)
+            .or(get_sessions)
+            .or(revoke_other_sessions)
+            .or(revoke_session)
+            .map(Reply::into_response),
+    )
+    .boxed()
 }
@@ -0,0 +1,148 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+use warp::{Filter, Reply};
+
+use crate::context::Context;
+use crate::fichub::CachedMeta;
+use crate::httputil::{reply_json, AcceptLanguage, ErrorWrap};
+use crate::routes::Routes;
+use crate::signal::{get_signals, get_signals_args, CategoryFilter, GetSignalsQ};
+use crate::tag_presentation::WarningSeverity;
+use crate::usermgmt::AccountSession;
+use crate::DB;
+
+/// z for a 95% confidence interval.
+const SCORE_Z: f64 = 1.96;
+
+/// A tag's signals on a fic as v2 answers with them. Handlers are shared between versions and
+/// answer with v1's types, which are converted to v2's.
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Signal {
+    tag: String,
+    /// The tag's label in the client's preferred language. Omitted if it hasn't been translated
+    /// to any of the languages in `Accept-Language`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    /// The current account's signal, if any.
+    signal: Option<bool>,
+    /// How many accounts signalled for the tag.
+    signals_for: i64,
+    /// How many accounts signalled against the tag.
+    signals_against: i64,
+    /// How sure it is that the tag applies, between 0 and 1: the lower bound of the 95% Wilson
+    /// score interval of the share of signals for it. Few signals score lower than many with the
+    /// same share.
+    #[schema(example = 0.72)]
+    score: f64,
+    /// Whether the server signalled for the tag itself, e.g. `fandom:` tags for the fandoms in the
+    /// fic's metadata. That signal is counted in `signalsFor`.
+    automatic: bool,
+    /// When any account first signalled the tag on the fic.
+    first_signalled_at: DateTime<Utc>,
+    /// When any account last changed its signal on the tag on the fic.
+    last_signalled_at: DateTime<Utc>,
+    /// How clients should render the tag. Omitted if not set, as are `icon` and `warning`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    icon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<WarningSeverity>,
+}
+
+/// Unlike in v1, signals are ordered, by score and then by tag.
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Signals {
+    signals: Vec<Signal>,
+    /// Only with `include=meta`, and only if the fic's metadata is cached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<CachedMeta>,
+}
+
+/// How sure it is that the tag applies, as the lower bound of the Wilson score interval of the
+/// share of signals for it. Between 0 and 1; few signals score lower than many with the same
+/// share, so that one account alone can't put a tag on top.
+fn score(signals_for: i64, signals_against: i64) -> f64 {
+    let n = (signals_for + signals_against) as f64;
+    if n == 0.0 {
+        return 0.0;
+    }
+    let p = signals_for as f64 / n;
+    let z2 = SCORE_Z * SCORE_Z;
+    let spread = SCORE_Z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+    ((p + z2 / (2.0 * n) - spread) / (1.0 + z2 / n)).max(0.0)
+}
+
+impl From<crate::signal::Signal> for Signal {
+    fn from(s: crate::signal::Signal) -> Self {
+        Self {
+            score: score(s.signals_for, s.signals_against),
+            tag: s.tag,
+            label: s.label,
+            signal: s.signal,
+            signals_for: s.signals_for,
+            signals_against: s.signals_against,
+            automatic: s.automatic,
+            first_signalled_at: s.first_signalled_at,
+            last_signalled_at: s.last_signalled_at,
+            color: s.color,
+            icon: s.icon,
+            warning: s.warning,
+        }
+    }
+}
+
+impl From<crate::signal::Signals> for Signals {
+    fn from(s: crate::signal::Signals) -> Self {
+        let mut signals = s.signals.into_iter().map(Signal::from).collect::<Vec<_>>();
+        signals.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.tag.cmp(&b.tag)));
+        Self {
+            signals,
+            meta: s.meta,
+        }
+    }
+}
+
+/// Get signals for a fic.
+///
+/// Takes the same parameters as `GET /v1/signals`. Unlike it, signals are ordered by score,
+/// highest first, and come with when they were made.
+#[utoipa::path(
+    get,
+    path = "/signals",
+    tag = "signals",
+    params(GetSignalsQ, CategoryFilter, AcceptLanguage),
+    responses(
+        (status = 200, description = "Expected response to a valid request.", body = Signals),
+        (status = 400, description = "Bad request.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = []), ("bearerAuth" = []), ()),
+)]
+async fn get_signals_v2(
+    account: Option<AccountSession>,
+    q: GetSignalsQ,
+    langs: AcceptLanguage,
+    pool: DB,
+) -> eyre::Result<Signals> {
+    get_signals(account, q, langs, pool)
+        .await
+        .map(Signals::from)
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_signals_v2))]
+pub struct Api;
+
+/// Routes of later versions, alongside v1's. Only those that differ from v1 are here, and the
+/// specification of each version is served by `openapi`.
+pub fn routes(ctx: &'static Context) -> Routes {
+    let get_signals_v2 = warp::path!("v2" / "signals")
+        .and(warp::get())
+        .and(get_signals_args(ctx))
+        .then(get_signals_v2)
+        .and_then(reply_json);
+    get_signals_v2.map(Reply::into_response).boxed()
+}
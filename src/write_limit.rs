@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use http::Response;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+use warp::{reply::json, Filter, Reply};
+
+use crate::context::Context;
+use crate::httputil::{json_body, reject, ApiError, ErrorWrap};
+use crate::routes::Routes;
+use crate::usermgmt::{AccountSession, Role};
+use crate::DB;
+
+/// Buckets of accounts that haven't written in a while are dropped once there are this many.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+/// Which of the [`WriteLimiter`]'s limits applies to an account, set by admins.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(type_name = "rate_limit_tier", rename_all = "kebab-case")]
+pub enum RateLimitTier {
+    Normal,
+    /// Bulk importers, which legitimately write a lot more than people tagging as they read.
+    TrustedImporter,
+    /// Accounts that flooded the server with writes, without suspending them outright.
+    Restricted,
+}
+
+/// A token bucket per account, holding up to a minute's worth of writes.
+struct Bucket {
+    tier: RateLimitTier,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Limits how often each account writes signals. Counts are kept in memory, so they start over
+/// when the server restarts, and each server process counts on its own.
+pub struct WriteLimiter {
+    /// Writes per minute for `normal` accounts, or 0 for no limit.
+    pub per_minute: f64,
+    /// The same for `trusted-importer` accounts.
+    pub trusted_per_minute: f64,
+    /// The same for `restricted` accounts.
+    pub restricted_per_minute: f64,
+    buckets: Mutex<HashMap<i64, Bucket>>,
+}
+
+impl WriteLimiter {
+    pub fn new(per_minute: f64, trusted_per_minute: f64, restricted_per_minute: f64) -> Self {
+        Self {
+            per_minute,
+            trusted_per_minute,
+            restricted_per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes `writes` from the account's bucket, or fails with [`ApiError::TooManyRequests`] if it
+    /// holds fewer, or with [`ApiError::BadRequest`] if it can never hold that many.
+    pub fn check(&self, account: &AccountSession, writes: usize) -> Result<(), ApiError> {
+        let tier = account.rate_limit_tier();
+        let per_minute = match tier {
+            RateLimitTier::Normal => self.per_minute,
+            RateLimitTier::TrustedImporter => self.trusted_per_minute,
+            RateLimitTier::Restricted => self.restricted_per_minute,
+        };
+        if per_minute <= 0.0 {
+            return Ok(());
+        }
+        let per_sec = per_minute / 60.0;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_IDLE_BUCKETS {
+            buckets.retain(|_, b| (now - b.refilled_at).as_secs() < 60);
+        }
+        let capacity = per_minute.max(1.0);
+        // Even a request that writes nothing costs a write.
+        let writes = writes.max(1) as f64;
+        if writes > capacity {
+            return Err(ApiError::BadRequest(
+                "too many signals in one request".into(),
+            ));
+        }
+        let fresh = Bucket {
+            tier,
+            tokens: capacity,
+            refilled_at: now,
+        };
+        let bucket = buckets.entry(account.id).or_insert(fresh);
+        // A tier change takes effect right away, with a full bucket.
+        if bucket.tier != tier {
+            *bucket = Bucket {
+                tier,
+                tokens: capacity,
+                refilled_at: now,
+            };
+        }
+        bucket.tokens =
+            (bucket.tokens + (now - bucket.refilled_at).as_secs_f64() * per_sec).min(capacity);
+        bucket.refilled_at = now;
+        if bucket.tokens < writes {
+            return Err(ApiError::TooManyRequests {
+                retry_after_secs: ((writes - bucket.tokens) / per_sec).ceil() as u64,
+            });
+        }
+        bucket.tokens -= writes;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitTierQ {
+    tier: RateLimitTier,
+}
+
+/// Set which signal write limit applies to an account. Requires the admin role.
+#[utoipa::path(
+    put,
+    path = "/admin/accounts/{accountId}/rate-limit-tier",
+    tag = "admin",
+    params(
+        ("accountId" = i64, Path),
+    ),
+    request_body = RateLimitTierQ,
+    responses(
+        (status = 200, description = "Success.", body = RateLimitTierQ),
+        (status = 403, description = "Forbidden.", body = ErrorWrap),
+        (status = 404, description = "No such account.", body = ErrorWrap),
+    ),
+    security(("cookieAuth" = [])),
+)]
+pub async fn put_rate_limit_tier(
+    account_id: i64,
+    _account: AccountSession,
+    q: RateLimitTierQ,
+    pool: DB,
+) -> Result<Response<Body>, ApiError> {
+    let updated = sqlx::query(
+        "update account set rate_limit_tier = $2 where id = $1 and id <> 0 and deleted_at is null",
+    )
+    .bind(account_id)
+    .bind(q.tier)
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("failed to update rate limit tier: {:?}", e);
+        ApiError::Internal
+    })?
+    .rows_affected();
+    if updated == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(json(&q).into_response())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(put_rate_limit_tier))]
+pub struct Api;
+
+pub fn routes(ctx: &'static Context) -> Routes {
+    let put_rate_limit_tier = warp::path!("v1" / "admin" / "accounts" / i64 / "rate-limit-tier")
+        .and(warp::put())
+        .and(ctx.require_role(Role::Admin))
+        .and(json_body::<RateLimitTierQ>(ctx.max_body_bytes))
+        .and(ctx.pool())
+        .then(put_rate_limit_tier)
+        .and_then(reject);
+    put_rate_limit_tier.map(Reply::into_response).boxed()
+}
@@ -1,7 +1,63 @@
 #!/bin/bash
 
 source test.env
-export FICAI_LISTEN FICAI_DB_HOST FICAI_DB_PORT FICAI_DB_USERNAME FICAI_DB_PASSWORD FICAI_DB_DATABASE FICAI_PWD_PEPPER FICAI_DOMAIN FICAI_BETA_KEY FICAI_BEX_LATEST_VERSION
+export FICAI_LISTEN FICAI_DB_HOST FICAI_DB_PORT FICAI_DB_USERNAME FICAI_DB_PASSWORD FICAI_DB_DATABASE FICAI_PWD_PEPPER FICAI_DOMAIN FICAI_BEX_LATEST_VERSION
+# testTagPolicy relies on this.
+export FICAI_TAG_RESERVED_PREFIXES="system:"
+# Fic metadata comes from examples/fake_fichub.rs.
+export FAKE_FICHUB_LISTEN="127.0.0.1:8081"
+export FICAI_FICHUB_URL="http://$FAKE_FICHUB_LISTEN"
+export FICAI_AO3_URL="http://$FAKE_FICHUB_LISTEN"
+# testFicRefreshJob relies on these; entries younger than 10 days are left to testGetFicMeta.
+export FICAI_FIC_REFRESH_INTERVAL_SECS=1
+export FICAI_FIC_REFRESH_MAX_AGE_SECS=864000
+# testGetPopularFics relies on this.
+export FICAI_FIC_STATS_INTERVAL_SECS=1
+# testFichubFailures relies on these.
+export FICAI_FICHUB_TIMEOUT_SECS=1
+export FICAI_FICHUB_BREAKER_THRESHOLD=3
+export FICAI_FICHUB_BREAKER_COOLDOWN_SECS=2
+# testFicMetaFromPage relies on these.
+export FICAI_OPENGRAPH_ALLOWED_HOSTS=127.0.0.1
+export FICAI_OPENGRAPH_MAX_BYTES=65536
+# testGetFicMetaBatch relies on this.
+export FICAI_FIC_BATCH_MAX_URLS=3
+# Every test logs in from the same address; testLoginThrottle covers the limit per email.
+export FICAI_LOGIN_THROTTLE_IP_FAILURES=0
+# Lenient enough for the passwords used throughout; testPasswordPolicy relies on these.
+export FICAI_PASSWORD_MIN_LENGTH=4
+export FICAI_PASSWORD_MIN_STRENGTH_BITS=12
+# Every account is created with a solved CAPTCHA; testCaptcha covers the others.
+export FICAI_CAPTCHA_VERIFY_URL="http://$FAKE_FICHUB_LISTEN/captcha/siteverify"
+export FICAI_CAPTCHA_SECRET=captcha-secret
+# testWriteLimit relies on this.
+export FICAI_WRITE_LIMIT_RESTRICTED_PER_MINUTE=2
+# testPepperRotation relies on these. The key id doesn't go into the hash itself, so the retired
+# pepper being the same one lets the test strip key ids from hashes.
+export FICAI_PWD_PEPPER_ID=t2
+export FICAI_PWD_RETIRED_PEPPERS=":$FICAI_PWD_PEPPER"
+# testCors relies on this.
+export FICAI_CORS_ALLOWED_ORIGINS=https://app.example.com
+# testMaxSessions relies on this.
+export FICAI_MAX_SESSIONS_PER_ACCOUNT=6
+# testOpenApi relies on this.
+export FICAI_SWAGGER_UI=true
+# Every test requests from the same address; testIpLimit covers the limit of a single route, and
+# tells addresses apart by X-Forwarded-For.
+export FICAI_IP_LIMIT_REQUESTS=0
+export FICAI_IP_LIMIT_ROUTES="/healthz=0,/readyz=0,/metrics=0,/v1/bex/versions/{version}=5"
+export FICAI_TRUSTED_PROXIES=1
+# testCompression relies on this.
+export FICAI_COMPRESSION_MIN_BYTES=64
+# testSignedSessionCookie relies on this.
+export FICAI_SESSION_COOKIE_KEY=c2Vzc2lvbi1jb29raWUta2V5LWZvci10ZXN0cw
+# testOAuthLogin relies on these.
+export FICAI_OAUTH_ISSUER="http://$FAKE_FICHUB_LISTEN/oidc"
+export FICAI_OAUTH_CLIENT_ID=ficai
+export FICAI_OAUTH_CLIENT_SECRET=oidc-secret
+export FICAI_OAUTH_REDIRECT_URL="http://$FICAI_LISTEN/v1/oauth/callback"
+export FICAI_OAUTH_RETURN_URL="http://$FICAI_LISTEN/logged-in"
+export FICAI_OAUTH_SIGNUP=true
 
 TEST_TS="$( date +%s )"
 TEST_EMAIL1="${TEST_TS}.1@example.com"
@@ -9,6 +65,8 @@ TEST_EMAIL2="${TEST_TS}.2@example.com"
 TEST_URL="https://forums.sufficientvelocity.com/threads/$TEST_TS/"
 TEST_TAG="tag_${TEST_TS}"
 TEST_UID="none"
+# Inserted by oneTimeSetUp, for any number of accounts.
+TEST_INVITE="invite-${TEST_TS}"
 
 DEBUG=no
 
@@ -75,6 +133,16 @@ request_patch() {
     -X PATCH -H "Content-Type: application/json" --data-binary "$JSON"
 }
 
+sql() {
+  PGPASSWORD="$FICAI_DB_PASSWORD" psql -qtAX \
+    -h "$FICAI_DB_HOST" -p "$FICAI_DB_PORT" -U "$FICAI_DB_USERNAME" -d "$FICAI_DB_DATABASE" \
+    -c "$1"
+}
+
+set_role() {
+  sql "update account set role = '$2' where email = '$1'"
+}
+
 show_headers() {
   cat "$SHUNIT_TMPDIR/headers"
 }
@@ -143,7 +211,9 @@ assertNoTag() {
 }
 
 oneTimeSetUp() {
-  cargo build || return 1
+  cargo build --bins --examples || return 1
+  nohup "${CARGO_TARGET_DIR:-./target}/debug/examples/fake_fichub" >test-fichub.log 2>&1 &
+  echo $! >test-fichub.pid
   nohup "${CARGO_TARGET_DIR:-./target}/debug/ficai-signals-server" >test.log 2>&1 &
   echo $! >test.pid
   echo "server process pid: $(cat test.pid)"
@@ -155,6 +225,8 @@ oneTimeSetUp() {
   # todo: wait for server initialization
   sleep 1
 
+  sql "insert into invite (code) values ('$TEST_INVITE')"
+
   rm -f test.cookies
 }
 
@@ -164,8 +236,31 @@ oneTimeTearDown() {
 
   echo "taking down server process $(cat test.pid)..."
   pkill -F test.pid
+  pkill -F test-fichub.pid
+
+  rm test.pid test-fichub.pid
+}
+
+# Runs another server alongside the one under test, at 127.0.0.1:8082 unless told otherwise, with
+# settings of its own given as `VAR=value`, until stop_server. Waits until `$1/healthz` answers.
+start_server() {
+  local URL="$1"
+  shift
+  env FICAI_LISTEN=127.0.0.1:8082 FICAI_FIC_REFRESH_INTERVAL_SECS=0 FICAI_FIC_STATS_INTERVAL_SECS=0 "$@" \
+    "${CARGO_TARGET_DIR:-./target}/debug/ficai-signals-server" >"$SHUNIT_TMPDIR/server.log" 2>&1 &
+  echo $! >"$SHUNIT_TMPDIR/server.pid"
+  for i in {1..50} ; do
+    curl -sk -o /dev/null "$URL/healthz" && return 0
+    sleep 0.1s
+  done
+  fail "tired of waiting for server to start"
+  stop_server
+  return 1
+}
 
-  rm test.pid
+stop_server() {
+  pkill -F "$SHUNIT_TMPDIR/server.pid"
+  rm "$SHUNIT_TMPDIR/server.pid"
 }
 
 headers_line() {
@@ -204,28 +299,28 @@ testCreateAccountInvalidJSON() {
   assertError 'bad request body'
 }
 
-testCreateAccountInvalidBetaKey() {
+testCreateAccountInvalidInviteCode() {
   request "http://$FICAI_LISTEN/v1/accounts" \
-    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL1\",\"password\":\"pass\",\"betaKey\":\"x$FICAI_BETA_KEY\"}"
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL1\",\"password\":\"pass\",\"inviteCode\":\"x$TEST_INVITE\",\"captchaToken\":\"solved\"}"
 
   assertStatus 'HTTP/1.1 400 Bad Request'
-  assertError 'invalid beta key'
+  assertError 'invalid invite code'
   assertFalse "cookie must not be set" "grep -q FicAiSession test.cookies"
 }
 
 testCreateAccount() {
   request "http://$FICAI_LISTEN/v1/accounts" \
-    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL1\",\"password\":\"pass\",\"betaKey\":\"$FICAI_BETA_KEY\"}"
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL1\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
 
   assertStatus 'HTTP/1.1 201 Created'
-  assertEquals "$TEST_EMAIL1" "$( extractEmail )"
+  assertEquals "email is only in the account's own session info" "null" "$( extractEmail )"
   assertTrue "cookie must be set" "grep -q FicAiSession test.cookies"
   TEST_UID="$( extractUid )"
 }
 
 testCreateAccountSecondTime() {
   request "http://$FICAI_LISTEN/v1/accounts" \
-    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL1\",\"password\":\"pass\",\"betaKey\":\"$FICAI_BETA_KEY\"}"
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL1\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
   assertStatus 'HTTP/1.1 409 Conflict'
   assertError 'account already exists'
 }
@@ -258,6 +353,143 @@ testAdd() {
   assertSignal taylor true 1 0
 }
 
+testGetTagDetail() {
+  request "http://$FICAI_LISTEN/v1/tags/worm"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 'worm' "$( show_output | jq -r .tag )"
+  assertTrue "usage count" "[[ $( show_output | jq -r .usageCount ) -ge 1 ]]"
+  assertTrue "url count" "[[ $( show_output | jq -r .urlCount ) -ge 1 ]]"
+  assertNotEquals 'null' "$( show_output | jq -r .lastUsedAt )"
+}
+
+testGetTagDetailNotFound() {
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}%20missing"
+  assertStatus 'HTTP/1.1 404 Not Found'
+  assertError 'not found'
+}
+
+testSearchTags() {
+  request_patch "$TEST_URL" "+${TEST_TAG}_search"
+  sql "insert into tag_meta (tag, description) values ('${TEST_TAG}_search', 'Taylor gets an alternate power')"
+
+  request "http://$FICAI_LISTEN/v1/tags/search" \
+    -G --data-urlencode "q=${TEST_TAG} alternate powers"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_TAG}_search" "$( show_output | jq -r .tags[0].tag )"
+  assertEquals 1 "$( show_output | jq '.tags|length' )"
+
+  # paginated
+  request "http://$FICAI_LISTEN/v1/tags" -G --data-urlencode "limit=1"
+  assertStatus 'HTTP/1.1 200 OK'
+  local FIRST="$( extractFirstTag )"
+  local CURSOR="$( show_output | jq -r .nextCursor )"
+  assertNotEquals 'null' "$CURSOR"
+  request "http://$FICAI_LISTEN/v1/tags" \
+    -G --data-urlencode "limit=1" --data-urlencode "cursor=$CURSOR"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( show_output | jq '.tags|length' )"
+  assertNotEquals "$FIRST" "$( extractFirstTag )"
+
+  request "http://$FICAI_LISTEN/v1/tags" -G --data-urlencode "cursor=nonsense"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'invalid cursor'
+
+  request "http://$FICAI_LISTEN/v1/tags/search" \
+    -G --data-urlencode "q=${TEST_TAG} -power"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 0 "$( show_output | jq '.tags|length' )"
+
+  sql "delete from tag_meta where tag = '${TEST_TAG}_search'"
+  request_patch "$TEST_URL" "%${TEST_TAG}_search"
+}
+
+testGetRelatedTags() {
+  request_patch "$TEST_URL" "+${TEST_TAG}_x" "+${TEST_TAG}_y" "-${TEST_TAG}_z"
+  request "http://$FICAI_LISTEN/v1/tags/related" \
+    -G --data-urlencode "tag=${TEST_TAG}_x"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_TAG}_x" "$( show_output | jq -r .tag )"
+  local Y="$( show_output | jq -c --arg t "${TEST_TAG}_y" '.related[] | select(.tag == $t)' )"
+  assertEquals 1 "$( jq -r .urlCount <<<"$Y" )"
+  assertEquals 1 "$( jq -r .share <<<"$Y" )"
+  assertNotContains "$( show_output | jq -r .related[].tag )" "${TEST_TAG}_z"
+
+  request_patch "$TEST_URL" "%${TEST_TAG}_x" "%${TEST_TAG}_y" "%${TEST_TAG}_z"
+}
+
+testGetTagFics() {
+  request_patch "${TEST_URL}1" "+${TEST_TAG}_fics"
+  request_patch "${TEST_URL}2" "+${TEST_TAG}_fics"
+  request_patch "${TEST_URL}3" "-${TEST_TAG}_fics"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}1"
+  local ID="$( show_output | jq -r .id )"
+
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_fics/fics" \
+    -G --data-urlencode "limit=1"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_URL}1" "$( show_output | jq -r .fics[0].url )"
+  assertEquals 1 "$( show_output | jq -r .fics[0].score )"
+  # with metadata if it's cached
+  assertEquals "$ID" "$( show_output | jq -r .fics[0].meta.id )"
+  local CURSOR="$( show_output | jq -r .nextCursor )"
+  assertNotEquals 'null' "$CURSOR"
+
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_fics/fics" \
+    -G --data-urlencode "limit=1" --data-urlencode "cursor=$CURSOR"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_URL}2" "$( show_output | jq -r .fics[0].url )"
+  assertEquals 'null' "$( show_output | jq -r .fics[0].meta )"
+  assertEquals 'null' "$( show_output | jq -r .nextCursor )"
+
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_fics/fics" \
+    -G --data-urlencode "cursor=nonsense"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'invalid cursor'
+
+  request_patch "${TEST_URL}1" "%${TEST_TAG}_fics"
+  request_patch "${TEST_URL}2" "%${TEST_TAG}_fics"
+  request_patch "${TEST_URL}3" "%${TEST_TAG}_fics"
+}
+
+testGetTagStats() {
+  request "http://$FICAI_LISTEN/v1/tags/worm/stats" \
+    -G --data-urlencode "window=7d"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals day "$( show_output | jq -r .bucket )"
+  assertEquals 8 "$( show_output | jq '.series|length' )"
+  assertEquals "$( date -u +%F )" "$( show_output | jq -r .series[-1].start )"
+
+  request "http://$FICAI_LISTEN/v1/tags/worm/stats" \
+    -G --data-urlencode "window=4w" --data-urlencode "bucket=week"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( show_output | jq '[.series[].start | strptime("%Y-%m-%d") | mktime | strftime("%u")] | unique | length' )"
+
+  request "http://$FICAI_LISTEN/v1/tags/worm/stats" \
+    -G --data-urlencode "bucket=month"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+}
+
+testGetTrendingTags() {
+  request_patch "$TEST_URL" "+${TEST_TAG}_trending"
+  sql "update signal set created_at = now() - interval '2 days' where tag = '${TEST_TAG}_trending'"
+  request "http://$FICAI_LISTEN/v1/tags/trending" \
+    -G --data-urlencode "window=3d" --data-urlencode "limit=100"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( show_output | jq --arg t "${TEST_TAG}_trending" '.tags[] | select(.tag == $t) | .growth' )"
+
+  request "http://$FICAI_LISTEN/v1/tags/trending" \
+    -G --data-urlencode "window=1d" --data-urlencode "limit=100"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertNotContains "$( show_output | jq -r .tags[].tag )" "${TEST_TAG}_trending"
+
+  request "http://$FICAI_LISTEN/v1/tags/trending" \
+    -G --data-urlencode "window=7 days"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'bad request query'
+
+  request_patch "$TEST_URL" "%${TEST_TAG}_trending"
+}
+
 testGetTagsInvalidQuery() {
   request "http://$FICAI_LISTEN/v1/signals" \
     -G --data-urlencode "limit=five"
@@ -278,15 +510,34 @@ testGetTags() {
   assertTag "${TEST_TAG}"
 
   request "http://$FICAI_LISTEN/v1/tags" \
-    -G --data-urlencode "q=taylor&limit=1"
+    -G --data-urlencode "q=taylor" --data-urlencode "limit=1"
   assertStatus 'HTTP/1.1 200 OK'
   assertEquals 'taylor' "$( extractFirstTag )"
+  assertEquals 1 "$( show_output | jq '.tags|length' )"
 
   request "http://$FICAI_LISTEN/v1/tags" \
-    -G --data-urlencode "q=$TEST_TAG&limit=1"
+    -G --data-urlencode "q=$TEST_TAG" --data-urlencode "limit=1"
   assertStatus 'HTTP/1.1 200 OK'
   assertEquals "$TEST_TAG" "$( extractFirstTag )"
 
+  # case-insensitive prefix match
+  request "http://$FICAI_LISTEN/v1/tags" \
+    -G --data-urlencode "q=$( tr a-z A-Z <<<"${TEST_TAG:0:10}" )"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertTag "$TEST_TAG"
+
+  # fuzzy match
+  request "http://$FICAI_LISTEN/v1/tags" \
+    -G --data-urlencode "q=taylr"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 'taylor' "$( extractFirstTag )"
+
+  # like wildcards are matched literally
+  request "http://$FICAI_LISTEN/v1/tags" \
+    -G --data-urlencode "q=%"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertNoTag "worm"
+
   request_patch "$TEST_URL" "%${TEST_TAG}"
   request "http://$FICAI_LISTEN/v1/tags"
   assertStatus 'HTTP/1.1 200 OK'
@@ -302,6 +553,44 @@ testRm() {
   assertSignal "taylor hebert" true 1 0
 }
 
+testCanonicalTags() {
+  request_patch "$TEST_URL" "+${TEST_TAG}_Café"
+  request_patch "$TEST_URL" "-${TEST_TAG}_CAFE\u0301"
+  request_get
+  assertStatus 'HTTP/1.1 200 OK'
+  assertSignal "${TEST_TAG}_Café" false 0 1
+  assertNoSignal "${TEST_TAG}_CAFÉ"
+
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_caf%C3%A9"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_TAG}_Café" "$( show_output | jq -r .tag )"
+  assertEquals 1 "$( show_output | jq -r .usageCount )"
+
+  request_patch "$TEST_URL" "%${TEST_TAG}_café"
+  request_get
+  assertNoSignal "${TEST_TAG}_Café"
+}
+
+testBlockedTags() {
+  request "http://$FICAI_LISTEN/v1/preferences/blocked-tags" \
+    -X PUT -H "Content-Type: application/json" --data-binary '{"tags":["worm"]}'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals '{"tags":["worm"]}' "$( show_output )"
+
+  request_get
+  assertNoSignal worm
+  assertSignal "taylor hebert" true 1 0
+
+  request "http://$FICAI_LISTEN/v1/preferences/blocked-tags" \
+    -X PUT -H "Content-Type: application/json" --data-binary '{"tags":[]}'
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/preferences/blocked-tags"
+  assertEquals '{"tags":[]}' "$( show_output )"
+
+  request_get
+  assertSignal worm true 1 0
+}
+
 testCreateSessionInvalidJSON() {
   request "http://$FICAI_LISTEN/v1/sessions" \
     -X POST -H "Content-Type: application/json" --data-binary "{"
@@ -346,13 +635,26 @@ testUnauthorizedGetSignals() {
 
 testCreateSession() {
   rm test.cookies
+  local HASH="$( sql "select password_hash from account where id = $TEST_UID" )"
   request "http://$FICAI_LISTEN/v1/sessions" \
     -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL1\",\"password\":\"pass\"}"
 
   assertStatus 'HTTP/1.1 200 OK'
-  assertEquals "$TEST_EMAIL1" "$( extractEmail )"
+  assertEquals "null" "$( extractEmail )"
   assertEquals "$TEST_UID" "$( extractUid )"
   assertTrue "cookie must be set" "grep -q FicAiSession test.cookies"
+  assertEquals "hashes with current parameters are kept" \
+    "$HASH" "$( sql "select password_hash from account where id = $TEST_UID" )"
+
+  # Without its key id, the hash belongs to the retired pepper, see testPepperRotation.
+  sql "update account set password_hash = replace(password_hash, ',keyid=dDI', '') where id = $TEST_UID"
+  HASH="$( sql "select password_hash from account where id = $TEST_UID" )"
+  request "http://$FICAI_LISTEN/v1/sessions" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL1\",\"password\":\"pass\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  local REHASHED="$( sql "select password_hash from account where id = $TEST_UID" )"
+  assertNotEquals "hashes with a retired pepper are replaced" "$HASH" "$REHASHED"
+  assertContains "$REHASHED" ',keyid=dDI$'
 }
 
 testGetSignals() {
@@ -381,47 +683,2502 @@ testErase2() {
   assertNoSignal taylor
 }
 
-testCreateSessionWithWrongEmail() {
-  request "http://$FICAI_LISTEN/v1/sessions" \
-    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL2\",\"password\":\"pass\"}"
-
+testRenameTagForbidden() {
+  request "http://$FICAI_LISTEN/v1/tags/rename" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"from\":\"worm\",\"to\":\"Worm\"}"
   assertStatus 'HTTP/1.1 403 Forbidden'
   assertError 'forbidden'
 }
 
-testCreateSessionWithWrongPassword() {
-  request "http://$FICAI_LISTEN/v1/sessions" \
-    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL1\",\"password\":\"wrong pass\"}"
+testRenameTag() {
+  set_role "$TEST_EMAIL1" moderator
+  request_patch "$TEST_URL" "+${TEST_TAG}_old"
+  sql "insert into tag_alias (alias, tag) values ('${TEST_TAG}_older', '${TEST_TAG}_old')"
+  request "http://$FICAI_LISTEN/v1/tags/rename" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"from\":\"${TEST_TAG}_old\",\"to\":\"${TEST_TAG}_new\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( show_output | jq -r .signalsMoved )"
+  request_get
+  assertNoSignal "${TEST_TAG}_old"
+  assertSignal "${TEST_TAG}_new" true 1 0
+  assertEquals "aliases follow the renamed tag" \
+    "${TEST_TAG}_new" "$( sql "select tag from tag_alias where alias = '${TEST_TAG}_older'" )"
+
+  request "http://$FICAI_LISTEN/v1/tags/rename" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"from\":\"${TEST_TAG}_old\",\"to\":\"${TEST_TAG}_new\"}"
+  assertStatus 'HTTP/1.1 404 Not Found'
+
+  sql "delete from tag_alias where alias = '${TEST_TAG}_older'"
+  request_patch "$TEST_URL" "%${TEST_TAG}_new"
+  set_role "$TEST_EMAIL1" user
+}
+
+testMergeTags() {
+  set_role "$TEST_EMAIL1" moderator
+  request_patch "$TEST_URL" "-${TEST_TAG}_b"
+  request_patch "$TEST_URL" "+${TEST_TAG}_a"
+  request "http://$FICAI_LISTEN/v1/tags/merge" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"from\":\"${TEST_TAG}_a\",\"into\":\"${TEST_TAG}_b\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( show_output | jq -r .signalsMerged )"
+  request_get
+  assertNoSignal "${TEST_TAG}_a"
+  # the signal on the source tag was changed last, so it wins
+  assertSignal "${TEST_TAG}_b" true 1 0
 
+  # the merged tag is now an alias
+  request_patch "$TEST_URL" "-${TEST_TAG}_a"
+  request_get
+  assertNoSignal "${TEST_TAG}_a"
+  assertSignal "${TEST_TAG}_b" false 0 1
+
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_b/history"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals merge "$( show_output | jq -r .history[0].action )"
+  assertEquals "${TEST_TAG}_a" "$( show_output | jq -r .history[0].from )"
+  assertContains "$( show_output | jq -r .history[0].createdAt.display )" 'UTC'
+
+  # lookups of the merged tag point to the surviving one
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_a"
+  assertStatus 'HTTP/1.1 308 Permanent Redirect'
+  assertEquals "location: /v1/tags/${TEST_TAG//_/%5F}%5Fb" \
+    "$( grep -i '^location:' "$SHUNIT_TMPDIR/headers" | tr -d '\r' | sed 's/^Location:/location:/' )"
+  assertEquals "${TEST_TAG}_b" "$( show_output | jq -r .successor )"
+
+  request_patch "$TEST_URL" "%${TEST_TAG}_b"
+  set_role "$TEST_EMAIL1" user
+}
+
+testTagAliasProposals() {
+  request_patch "$TEST_URL" "+${TEST_TAG}_p1"
+  request "http://$FICAI_LISTEN/v1/tags/proposals" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"from\":\"${TEST_TAG}_p1\",\"into\":\"${TEST_TAG}_p2\"}"
+  assertStatus 'HTTP/1.1 201 Created'
+  local ID="$( show_output | jq -r .id )"
+  assertEquals 1 "$( show_output | jq -r .votes )"
+  assertEquals true "$( show_output | jq -r .voted )"
+
+  request "http://$FICAI_LISTEN/v1/tags/proposals" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"from\":\"${TEST_TAG}_p1\",\"into\":\"${TEST_TAG}_p2\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "$ID" "$( show_output | jq -r .id )"
+  assertEquals 1 "$( show_output | jq -r .votes )"
+
+  request "http://$FICAI_LISTEN/v1/tags/proposals/$ID/vote" -X DELETE
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 0 "$( show_output | jq -r .votes )"
+  request "http://$FICAI_LISTEN/v1/tags/proposals/$ID/vote" -X PUT
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( show_output | jq -r .votes )"
+
+  request "http://$FICAI_LISTEN/v1/tags/proposals"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_TAG}_p2" "$( show_output | jq -r --argjson id "$ID" '.proposals[] | select(.id == $id) | .into' )"
+
+  request "http://$FICAI_LISTEN/v1/tags/proposals/$ID/decision" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"approve":true}'
   assertStatus 'HTTP/1.1 403 Forbidden'
-  assertError 'forbidden'
+
+  set_role "$TEST_EMAIL1" moderator
+  request "http://$FICAI_LISTEN/v1/tags/proposals/$ID/decision" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"approve":true}'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals approved "$( show_output | jq -r .status )"
+  request_get
+  assertNoSignal "${TEST_TAG}_p1"
+  assertSignal "${TEST_TAG}_p2" true 1 0
+
+  request "http://$FICAI_LISTEN/v1/tags/proposals/$ID/decision" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"approve":false}'
+  assertStatus 'HTTP/1.1 404 Not Found'
+
+  request_patch "$TEST_URL" "%${TEST_TAG}_p2"
+  set_role "$TEST_EMAIL1" user
 }
 
-testUnauthorizedGetSignalsEmpty() {
-  assertTrue "cookie must be set" "grep -q FicAiSession test.cookies"
-  request "http://$FICAI_LISTEN/v1/sessions" -X DELETE
+testTagReviewQueue() {
+  request "http://$FICAI_LISTEN/v1/tags/review-queue"
+  assertStatus 'HTTP/1.1 403 Forbidden'
 
+  set_role "$TEST_EMAIL1" moderator
+  request_patch "$TEST_URL" "+${TEST_TAG}_q1" "+${TEST_TAG}_q2" "+${TEST_TAG}_q3"
+  request "http://$FICAI_LISTEN/v1/tags/review-queue"
   assertStatus 'HTTP/1.1 200 OK'
-  assertEquals "{}" "$( show_output )"
-  assertFalse "cookie must not be set" "grep -q FicAiSession test.cookies"
+  assertContains "$( show_output | jq -r .tags[].tag )" "${TEST_TAG}_q1"
+
+  request "http://$FICAI_LISTEN/v1/tags/review-queue/${TEST_TAG}_q1" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"action":"approve"}'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 0 "$( show_output | jq -r .signalsAffected )"
+
+  request "http://$FICAI_LISTEN/v1/tags/review-queue/${TEST_TAG}_q2" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"action\":\"rename\",\"to\":\"${TEST_TAG}_q1\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( show_output | jq -r .signalsAffected )"
+
+  request "http://$FICAI_LISTEN/v1/tags/review-queue/${TEST_TAG}_q3" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"action":"delete"}'
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_q3"
+  assertStatus 'HTTP/1.1 410 Gone'
+
+  request "http://$FICAI_LISTEN/v1/tags/review-queue"
+  assertNotContains "$( show_output | jq -r .tags[].tag )" "${TEST_TAG}_q"
+  request "http://$FICAI_LISTEN/v1/tags/review-queue/${TEST_TAG}_q1" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"action":"approve"}'
+  assertStatus 'HTTP/1.1 404 Not Found'
 
   request_get
+  assertSignal "${TEST_TAG}_q1" true 1 0
+  assertNoSignal "${TEST_TAG}_q3"
+
+  request_patch "$TEST_URL" "%${TEST_TAG}_q1"
+  set_role "$TEST_EMAIL1" user
+}
+
+testTagSubscriptions() {
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_followed/subscription" -X PUT
   assertStatus 'HTTP/1.1 200 OK'
-  assertNoSignal worm
-  assertNoSignal "taylor hebert"
-  assertNoSignal taylor
+  assertEquals "${TEST_TAG}_followed" "$( show_output | jq -r .tag )"
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_followed/subscription" -X PUT
+  assertStatus 'HTTP/1.1 200 OK'
+
+  request "http://$FICAI_LISTEN/v1/tags/subscriptions"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_TAG}_followed" "$( show_output | jq -r .subscriptions[0].tag )"
+
+  # only signals by other accounts show up in the feed
+  request_patch "$TEST_URL" "+${TEST_TAG}_followed"
+  request "http://$FICAI_LISTEN/v1/feed"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "[]" "$( show_output | jq -c .entries )"
+
+  local OTHER="$( sql "insert into account (email, password_hash) values ('${TEST_TS}.3@example.com', '') returning id" )"
+  sql "insert into signal (account_id, url, tag, tag_canonical, signal) values ($OTHER, '${TEST_URL}other', '${TEST_TAG}_Followed', '${TEST_TAG}_followed', true)" >/dev/null
+  request "http://$FICAI_LISTEN/v1/feed"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_URL}other" "$( show_output | jq -r .entries[0].url )"
+  assertEquals "[\"${TEST_TAG}_Followed\"]" "$( show_output | jq -c .entries[0].tags )"
+
+  request "http://$FICAI_LISTEN/v1/feed" -G --data-urlencode "before=$( show_output | jq -r .entries[0].lastTaggedAt )"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "[]" "$( show_output | jq -c .entries )"
+
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_followed/subscription" -X DELETE
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_followed/subscription" -X DELETE
+  assertStatus 'HTTP/1.1 404 Not Found'
+
+  sql "delete from signal where account_id = $OTHER; delete from account where id = $OTHER"
+  request_patch "$TEST_URL" "%${TEST_TAG}_followed"
 }
 
-testGetBex() {
-  request "http://$FICAI_LISTEN/v1/bex/versions/v0.1.0"
+testGetTagsFiltered() {
+  request_patch "$TEST_URL" "+${TEST_TAG}_f1" "+${TEST_TAG}_f2" "+${TEST_TAG}_f3"
+  sql "insert into tag_meta (tag, category) values ('${TEST_TAG}_f2', 'genre'), ('${TEST_TAG}_f3', 'genre')"
+
+  request "http://$FICAI_LISTEN/v1/tags" -G --data-urlencode "q=${TEST_TAG}_f" \
+    --data-urlencode "exclude=${TEST_TAG}_F1" --data-urlencode "exclude=${TEST_TAG}_f2"
   assertStatus 'HTTP/1.1 200 OK'
-  assertEquals false "$( extractRetired )"
-  assertEquals "${FICAI_BEX_LATEST_VERSION}" "$( extractLatestVersion )"
+  assertEquals "[\"${TEST_TAG}_f3\"]" "$( show_output | jq -c .tags )"
 
-  request "http://$FICAI_LISTEN/v1/bex/versions/v0.0.0"
+  request "http://$FICAI_LISTEN/v1/tags" -G --data-urlencode "q=${TEST_TAG}_f" \
+    --data-urlencode "category=genre" --data-urlencode "limit=1"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "[\"${TEST_TAG}_f2\"]" "$( show_output | jq -c .tags )"
+
+  sql "delete from tag_meta where tag in ('${TEST_TAG}_f2', '${TEST_TAG}_f3')"
+  request_patch "$TEST_URL" "%${TEST_TAG}_f1" "%${TEST_TAG}_f2" "%${TEST_TAG}_f3"
+}
+
+testExportTags() {
+  request_patch "$TEST_URL" "+${TEST_TAG}_Export"
+  sql "insert into tag_alias (alias, tag) values ('${TEST_TAG}_exp', '${TEST_TAG}_export')"
+
+  request "http://$FICAI_LISTEN/v1/tags/export"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  sql "insert into account_permission (account_id, permission) values ($TEST_UID, 'data-export')"
+
+  # not json, so `request` doesn't apply
+  curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/out" --cookie test.cookies \
+    "http://$FICAI_LISTEN/v1/tags/export"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 'content-type: application/x-ndjson' "$( grep content-type "$SHUNIT_TMPDIR/headers" | tr -d '\r\n' )"
+  assertTrue "every line is json" "jq -e . <$SHUNIT_TMPDIR/out >/dev/null"
+  assertEquals \
+    '{"canonical":"'"${TEST_TAG}"'_export","tag":"'"${TEST_TAG}"'_Export","aliases":["'"${TEST_TAG}"'_exp"],"category":null,"signalsFor":1,"signalsAgainst":0,"urlCount":1}' \
+    "$( jq -c --arg t "${TEST_TAG}_export" 'select(.canonical == $t)' <"$SHUNIT_TMPDIR/out" )"
+
+  sql "delete from account_permission where account_id = $TEST_UID"
+  sql "delete from tag_alias where alias = '${TEST_TAG}_exp'"
+  request_patch "$TEST_URL" "%${TEST_TAG}_Export"
+}
+
+testGetSignalsByCategory() {
+  request_patch "$TEST_URL" "+${TEST_TAG}_warn" "+${TEST_TAG}_fan" "+${TEST_TAG}_plain"
+  sql "insert into tag_meta (tag, category) values ('${TEST_TAG}_warn', 'warning'), ('${TEST_TAG}_fan', 'fandom')"
+
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$TEST_URL" \
+    --data-urlencode "excludeCategory=warning"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertNoSignal "${TEST_TAG}_warn"
+  assertSignal "${TEST_TAG}_fan" true 1 0
+  assertSignal "${TEST_TAG}_plain" true 1 0
+
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$TEST_URL" \
+    --data-urlencode "includeCategory=warning" --data-urlencode "includeCategory=fandom"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertSignal "${TEST_TAG}_warn" true 1 0
+  assertSignal "${TEST_TAG}_fan" true 1 0
+  assertNoSignal "${TEST_TAG}_plain"
+
+  sql "delete from tag_meta where tag in ('${TEST_TAG}_warn', '${TEST_TAG}_fan')"
+  request_patch "$TEST_URL" "%${TEST_TAG}_warn" "%${TEST_TAG}_fan" "%${TEST_TAG}_plain"
+}
+
+testTagPresentation() {
+  request_patch "$TEST_URL" "+${TEST_TAG}_gore"
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_gore/meta" \
+    -X PATCH -H "Content-Type: application/json" --data-binary '{"warning":"severe"}'
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  set_role "$TEST_EMAIL1" moderator
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_gore/meta" \
+    -X PATCH -H "Content-Type: application/json" --data-binary '{"color":"#FF0000","warning":"severe"}'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals '{"color":"#ff0000","icon":null,"warning":"severe"}' "$( show_output | jq -c . )"
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_gore/meta" \
+    -X PATCH -H "Content-Type: application/json" --data-binary '{"icon":"alert-triangle"}'
+  assertEquals '{"color":"#ff0000","icon":"alert-triangle","warning":"severe"}' "$( show_output | jq -c . )"
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_gore/meta" \
+    -X PATCH -H "Content-Type: application/json" --data-binary '{"color":"red"}'
+  assertStatus 'HTTP/1.1 400 Bad Request'
+
+  request_get
+  assertEquals '#ff0000 alert-triangle severe' \
+    "$( extractSignal "${TEST_TAG}_gore" | jq -r '"\(.color) \(.icon) \(.warning)"' )"
+
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_gore/meta" \
+    -X PATCH -H "Content-Type: application/json" --data-binary '{"color":null}'
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_gore"
+  assertEquals 'null severe' "$( show_output | jq -r '"\(.color) \(.warning)"' )"
+
+  set_role "$TEST_EMAIL1" user
+  request_patch "$TEST_URL" "%${TEST_TAG}_gore"
+}
+
+testTagTranslations() {
+  request_patch "$TEST_URL" "+${TEST_TAG}_love"
+  set_role "$TEST_EMAIL1" moderator
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_love/translations/DE" \
+    -X PUT -H "Content-Type: application/json" --data-binary "{\"label\":\"${TEST_TAG}_Liebe\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "de" "$( show_output | jq -r .locale )"
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_love/translations/de_DE" \
+    -X PUT -H "Content-Type: application/json" --data-binary "{\"label\":\"${TEST_TAG}_Liebe\"}"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  set_role "$TEST_EMAIL1" user
+
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$TEST_URL" \
+    -H "Accept-Language: de-CH, en;q=0.5"
+  assertEquals "${TEST_TAG}_Liebe" "$( extractSignal "${TEST_TAG}_love" | jq -r .label )"
+  request_get
+  assertEquals "null" "$( extractSignal "${TEST_TAG}_love" | jq -r .label )"
+
+  request "http://$FICAI_LISTEN/v1/tags" -G --data-urlencode "q=${TEST_TAG}_lieb" -H "Accept-Language: de"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_TAG}_love" "$( extractFirstTag )"
+  assertEquals "${TEST_TAG}_Liebe" "$( show_output | jq -r --arg t "${TEST_TAG}_love" '.labels[$t]' )"
+
+  # signalling a label signals its tag
+  request_patch "$TEST_URL" "-${TEST_TAG}_liebe"
+  request_get
+  assertSignal "${TEST_TAG}_love" false 0 1
+  assertNoSignal "${TEST_TAG}_liebe"
+
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_love/translations"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals '[{"locale":"de","label":"'"${TEST_TAG}"'_Liebe"}]' "$( show_output | jq -c .translations )"
+
+  set_role "$TEST_EMAIL1" moderator
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_love/translations/de" -X DELETE
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_love/translations/de" -X DELETE
+  assertStatus 'HTTP/1.1 404 Not Found'
+  set_role "$TEST_EMAIL1" user
+  request_patch "$TEST_URL" "%${TEST_TAG}_love"
+}
+
+testTagPolicy() {
+  LONG_TAG="${TEST_TAG}_$( printf 'a%.0s' $(seq 200) )"
+  request "http://$FICAI_LISTEN/v1/signals" -X PATCH -H "Content-Type: application/json" \
+    --data-binary "{\"url\":\"$TEST_URL\",\"add\":[\"$LONG_TAG\",\"${TEST_TAG}\\nb\",\"System:${TEST_TAG}\"]}"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertEquals "too-long disallowed-character reserved-prefix" "$( show_output | jq -r '[.error.violations[].rule] | join(" ")' )"
+
+  request_get
+  assertEquals "[]" "$( show_output | jq -c --arg t "$TEST_TAG" '[.signals[] | select(.tag | startswith($t))]' )"
+
+  # signalling against a new tag is checked too, but against one in use is fine whatever its name
+  request_patch "$TEST_URL" "-System:${TEST_TAG}"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertEquals "reserved-prefix" "$( show_output | jq -r '[.error.violations[].rule] | join(" ")' )"
+  sql "insert into signal (account_id, url, tag, tag_canonical, signal) values (0, 'https://example.com/policy/$TEST_TS', 'System:${TEST_TAG}', 'system:${TEST_TAG}', true)"
+  request_patch "$TEST_URL" "-System:${TEST_TAG}"
+  assertStatus 'HTTP/1.1 200 OK'
+  request_patch "$TEST_URL" "%System:${TEST_TAG}"
+  sql "delete from signal where tag_canonical = 'system:${TEST_TAG}'"
+
+  # curators may use reserved prefixes
+  set_role "$TEST_EMAIL1" moderator
+  request "http://$FICAI_LISTEN/v1/admin/tag-implications" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"tag\":\"${TEST_TAG}_a\",\"implies\":\"system:${TEST_TAG}\"}"
+  assertStatus 'HTTP/1.1 201 Created'
+  request "http://$FICAI_LISTEN/v1/admin/tag-implications" -X DELETE \
+    -G --data-urlencode "tag=${TEST_TAG}_a" --data-urlencode "implies=system:${TEST_TAG}"
+  assertStatus 'HTTP/1.1 200 OK'
+  set_role "$TEST_EMAIL1" user
+}
+
+testTagImplications() {
+  request "http://$FICAI_LISTEN/v1/admin/tag-implications" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"tag\":\"${TEST_TAG}_ship\",\"implies\":\"${TEST_TAG}_fandom\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  set_role "$TEST_EMAIL1" moderator
+  request "http://$FICAI_LISTEN/v1/admin/tag-implications" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"tag\":\"${TEST_TAG}_ship\",\"implies\":\"${TEST_TAG}_fandom\"}"
+  assertStatus 'HTTP/1.1 201 Created'
+  request "http://$FICAI_LISTEN/v1/admin/tag-implications" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"tag\":\"${TEST_TAG}_fandom\",\"implies\":\"${TEST_TAG}_universe\"}"
+  assertStatus 'HTTP/1.1 201 Created'
+  request "http://$FICAI_LISTEN/v1/admin/tag-implications" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"tag\":\"${TEST_TAG}_ship\",\"implies\":\"${TEST_TAG}_SHIP\"}"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+
+  request "http://$FICAI_LISTEN/v1/admin/tag-implications"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_TAG}_fandom" "$( show_output | jq -r --arg t "${TEST_TAG}_ship" '.implications[] | select(.tag == $t) | .implies' )"
+
+  # implied tags are added transitively, but never override an explicit signal
+  request_patch "$TEST_URL" "-${TEST_TAG}_universe"
+  request_patch "$TEST_URL" "+${TEST_TAG}_ship"
+  request_get
+  assertSignal "${TEST_TAG}_ship" true 1 0
+  assertSignal "${TEST_TAG}_fandom" true 1 0
+  assertSignal "${TEST_TAG}_universe" false 0 1
+
+  for PAIR in "ship fandom" "fandom universe"; do
+    set -- $PAIR
+    request "http://$FICAI_LISTEN/v1/admin/tag-implications" -X DELETE \
+      -G --data-urlencode "tag=${TEST_TAG}_$1" --data-urlencode "implies=${TEST_TAG}_$2"
+    assertStatus 'HTTP/1.1 200 OK'
+  done
+  request "http://$FICAI_LISTEN/v1/admin/tag-implications" -X DELETE \
+    -G --data-urlencode "tag=${TEST_TAG}_ship" --data-urlencode "implies=${TEST_TAG}_fandom"
+  assertStatus 'HTTP/1.1 404 Not Found'
+
+  request_patch "$TEST_URL" "%${TEST_TAG}_ship" "%${TEST_TAG}_fandom" "%${TEST_TAG}_universe"
+  set_role "$TEST_EMAIL1" user
+}
+
+testAdminRolesForbidden() {
+  request "http://$FICAI_LISTEN/v1/admin/roles"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  assertError 'forbidden'
+}
+
+testAdminRoles() {
+  set_role "$TEST_EMAIL1" admin
+  request "http://$FICAI_LISTEN/v1/sessions"
+  assertEquals '["tag-curation","user-moderation","data-export","settings"]' "$( show_output | jq -c .permissions )"
+  request "http://$FICAI_LISTEN/v1/admin/roles"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals admin "$( show_output | jq -r ".accounts[]|select(.id==$TEST_UID)|.role" )"
+
+  request "http://$FICAI_LISTEN/v1/admin/roles/$TEST_UID" \
+    -X PUT -H "Content-Type: application/json" --data-binary '{"role":"user","permissions":["tag-curation"]}'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals user "$( show_output | jq -r .role )"
+  assertEquals '["tag-curation"]' "$( show_output | jq -c .permissions )"
+  request "http://$FICAI_LISTEN/v1/sessions"
+  assertEquals '["tag-curation"]' "$( show_output | jq -c .permissions )"
+
+  # tag curation is still allowed through the explicit permission
+  request_patch "$TEST_URL" "+${TEST_TAG}_old"
+  request "http://$FICAI_LISTEN/v1/tags/rename" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"from\":\"${TEST_TAG}_old\",\"to\":\"${TEST_TAG}_new\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  request_patch "$TEST_URL" "%${TEST_TAG}_new"
+
+  request "http://$FICAI_LISTEN/v1/admin/roles"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  sql "delete from account_permission where account_id = $TEST_UID"
+}
+
+testBexArtifact() {
+  local ARTIFACT="$SHUNIT_TMPDIR/artifact.zip"
+  local VERSION="v0.0.${TEST_TS}"
+  head -c 1024 /dev/urandom >"$ARTIFACT"
+  local CHECKSUM="$( sha256sum "$ARTIFACT" | cut -d ' ' -f 1 )"
+
+  request "http://$FICAI_LISTEN/v1/admin/bex/releases/$VERSION/artifact" \
+    -X POST -H "Content-Type: application/zip" -H "X-Checksum-Sha256: $CHECKSUM" --data-binary "@$ARTIFACT"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  set_role "$TEST_EMAIL1" admin
+  request "http://$FICAI_LISTEN/v1/admin/bex/releases/$VERSION/artifact" \
+    -X POST -H "Content-Type: application/zip" -H "X-Checksum-Sha256: 00$CHECKSUM" --data-binary "@$ARTIFACT"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'checksum mismatch'
+
+  request "http://$FICAI_LISTEN/v1/admin/bex/releases/$VERSION/artifact" \
+    -X POST -H "Content-Type: application/zip" -H "X-Checksum-Sha256: $CHECKSUM" --data-binary "@$ARTIFACT"
+  assertStatus 'HTTP/1.1 201 Created'
+  assertEquals "$CHECKSUM" "$( show_output | jq -r .sha256 )"
+  set_role "$TEST_EMAIL1" user
+
+  # the artifact itself isn't json, so bypass request()
+  curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/download" \
+    "http://$FICAI_LISTEN/v1/bex/releases/$VERSION/download"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "$CHECKSUM" "$( sha256sum "$SHUNIT_TMPDIR/download" | cut -d ' ' -f 1 )"
+
+  request "http://$FICAI_LISTEN/v1/bex/releases/v0.0.0-missing/download"
+  assertStatus 'HTTP/1.1 404 Not Found'
+}
+
+testDuplicateAccountsReport() {
+  local DUP_EMAIL="${TEST_TS}.1+dup@example.com"
+  # sign up without touching the test session
+  curl -s -o /dev/null -b /dev/null -c /dev/null -H 'X-Forwarded-For: 198.51.100.7' \
+    "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$DUP_EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+
+  request "http://$FICAI_LISTEN/v1/admin/reports/duplicate-accounts" -X POST
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  set_role "$TEST_EMAIL1" admin
+  request "http://$FICAI_LISTEN/v1/admin/reports/duplicate-accounts" -X POST
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "$DUP_EMAIL" \
+    "$( show_output | jq -r ".clusters[]|select(.accounts[].id==$TEST_UID)|.accounts[]|select(.email==\"$DUP_EMAIL\")|.email" )"
+  # Signed up from elsewhere, going by the trusted proxy rather than the peer's address.
+  local REASONS="$( sql "select array_to_string(reasons, ';') from duplicate_account_candidate c join account d on d.email = '$DUP_EMAIL' where (c.account_id_a, c.account_id_b) = (least($TEST_UID, d.id), greatest($TEST_UID, d.id))" )"
+  assertContains "$REASONS" 'emails normalize'
+  assertNotContains "$REASONS" 'same network'
+  set_role "$TEST_EMAIL1" user
+}
+
+testTimezone() {
+  request "http://$FICAI_LISTEN/v1/preferences/timezone"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 'UTC' "$( show_output | jq -r .timezone )"
+
+  request "http://$FICAI_LISTEN/v1/preferences/timezone" \
+    -X PUT -H "Content-Type: application/json" --data-binary '{"timezone":"Mars/Olympus_Mons"}'
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'unknown time zone'
+
+  request "http://$FICAI_LISTEN/v1/preferences/timezone" \
+    -X PUT -H "Content-Type: application/json" --data-binary '{"timezone":"Asia/Tokyo"}'
+  assertStatus 'HTTP/1.1 200 OK'
+
+  request "http://$FICAI_LISTEN/v1/tags/${TEST_TAG}_b/history"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertContains "$( show_output | jq -r .history[0].createdAt.display )" 'JST'
+
+  request "http://$FICAI_LISTEN/v1/preferences/timezone" \
+    -X PUT -H "Content-Type: application/json" --data-binary '{"timezone":"UTC"}'
+}
+
+testGetFicMeta() {
+  local URL="${TEST_URL}meta"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "$URL" "$( show_output | jq -r .source )"
+  assertContains "$( show_output | jq -r .title )" 'Fake fic'
+  assertEquals 'Fake author' "$( show_output | jq -r .author )"
+  assertEquals 123456 "$( show_output | jq -r .words )"
+  assertEquals 12 "$( show_output | jq -r .chapters )"
+  assertEquals 'ongoing' "$( show_output | jq -r .status )"
+  assertEquals '["Worm","Pact"]' "$( show_output | jq -c .fandoms )"
+  assertEquals '2022-05-06T07:08:09Z' "$( show_output | jq -r .updated )"
+  assertNotEquals 'null' "$( show_output | jq -r .fetchedAt )"
+
+  # served from the cache the second time
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( fichub_requests "$URL" )"
+
+  # stale entries are served while they are refreshed in the background
+  sql "update fic_url_cache set fetched_at = now() - interval '2 days' where url = '$URL'"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  sleep 0.5
+  assertEquals 2 "$( fichub_requests "$URL" )"
+  assertEquals 0 "$( sql "select count(1) from fic_url_cache where url = '$URL' and fetched_at < now() - interval '1 day'" )"
+
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}error-500"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}not-found"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+}
+
+testFicRefreshJob() {
+  local URL="${TEST_URL}refresh"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( fichub_requests "$URL" )"
+
+  sql "update fic_url_cache set fetched_at = now() - interval '30 days' where url = '$URL'"
+  sleep 2.5
+  assertEquals 2 "$( fichub_requests "$URL" )"
+  assertEquals 0 "$( sql "select count(1) from fic_url_cache where url = '$URL' and fetched_at < now() - interval '1 day'" )"
+}
+
+testFichubFailures() {
+  # hanging requests time out and are retried twice
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}hang-15" \
+    --max-time 10
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  assertEquals 3 "$( fichub_requests "${TEST_URL}hang-15" )"
+
+  # the third failed lookup in a row stops further requests for a while...
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}breaker1-error-500"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}breaker2-error-500"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  assertEquals 3 "$( fichub_requests "${TEST_URL}breaker2-error-500" )"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}breaker"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  assertEquals 0 "$( fichub_requests "${TEST_URL}breaker" )"
+
+  # ...during which expired cache entries are served instead
+  sql "update fic_url_cache set fetched_at = now() - interval '20 days' where url = '${TEST_URL}meta'"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}meta"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  # the failed lookup is remembered, but other URLs are looked up again after the cooldown
+  sleep 2.1
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}breaker"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}breaker-closed"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( fichub_requests "${TEST_URL}breaker-closed" )"
+}
+
+testRequestTimeout() {
+  start_server http://127.0.0.1:8082 FICAI_REQUEST_TIMEOUT_SECS=1 FICAI_FICHUB_TIMEOUT_SECS=10 || return
+  request "http://127.0.0.1:8082/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}timeout-hang-15" \
+    --max-time 10
+  assertStatus 'HTTP/1.1 504 Gateway Timeout'
+  assertError 'request timed out'
+  assertEquals 1 "$( show_output | jq .error.timeoutSecs )"
+  stop_server
+}
+
+testTlsCertificateReload() {
+  local DIR="$SHUNIT_TMPDIR/tls"
+  local NAME
+  mkdir -p "$DIR"
+  for NAME in first second; do
+    openssl req -x509 -newkey rsa:2048 -nodes -days 1 -subj "/CN=$NAME" \
+      -addext "subjectAltName=IP:127.0.0.1" -keyout "$DIR/$NAME.key" -out "$DIR/$NAME.crt" 2>/dev/null
+  done
+  cp "$DIR/first.crt" "$DIR/server.crt"
+  cp "$DIR/first.key" "$DIR/server.key"
+  trusts() {
+    curl -s -o /dev/null -w '%{http_code}' --cacert "$DIR/$1.crt" https://127.0.0.1:8082/healthz
+  }
+  start_server https://127.0.0.1:8082 FICAI_TLS_CERT="$DIR/server.crt" FICAI_TLS_KEY="$DIR/server.key" || return
+  assertEquals 200 "$( trusts first )"
+  assertEquals 000 "$( trusts second )"
+
+  # new connections get the new certificate
+  cp "$DIR/second.crt" "$DIR/server.crt"
+  cp "$DIR/second.key" "$DIR/server.key"
+  pkill -HUP -F "$SHUNIT_TMPDIR/server.pid"
+  sleep 0.5
+  assertEquals 200 "$( trusts second )"
+  assertEquals 000 "$( trusts first )"
+
+  # one that fails to load is ignored
+  echo garbage >"$DIR/server.crt"
+  pkill -HUP -F "$SHUNIT_TMPDIR/server.pid"
+  sleep 0.5
+  assertEquals 200 "$( trusts second )"
+  assertContains "$( cat "$SHUNIT_TMPDIR/server.log" )" 'failed to reload TLS certificate, keeping the current one'
+  stop_server
+}
+
+testFichubThrottling() {
+  start_server http://127.0.0.1:8082 FICAI_FICHUB_MAX_RPS=1 FICAI_FICHUB_QUEUE_TIMEOUT_SECS=0 \
+    FICAI_FICHUB_RETRIES=0 FICAI_FICHUB_BREAKER_THRESHOLD=1 FICAI_FICHUB_BREAKER_COOLDOWN_SECS=60 || return
+  metric() {
+    curl -s http://127.0.0.1:8082/metrics | grep "^$1 " | cut -d' ' -f2
+  }
+  request "http://127.0.0.1:8082/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}throttle-1"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  # a lookup over the rate limit isn't sent, nor remembered as failed
+  request "http://127.0.0.1:8082/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}throttle-2"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  assertEquals 0 "$( sql "select count(*) from fic_url_cache where url = '${TEST_URL}throttle-2'" )"
+  assertEquals 0 "$( fichub_requests "${TEST_URL}throttle-2" )"
+  assertEquals 1 "$( metric 'ficai_fichub_attempts_total{outcome="throttled"}' )"
+  assertEquals 0 "$( metric ficai_fichub_paused )"
+
+  # a failed lookup pauses further ones
+  sleep 1.1
+  request "http://127.0.0.1:8082/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}throttle-3-error-500"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  assertEquals 1 "$( metric ficai_fichub_paused )"
+  sleep 1.1
+  request "http://127.0.0.1:8082/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}throttle-4"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  assertEquals 0 "$( fichub_requests "${TEST_URL}throttle-4" )"
+  assertEquals 1 "$( metric 'ficai_fichub_attempts_total{outcome="paused"}' )"
+  stop_server
+}
+
+testFicMetaFallback() {
+  # fichub fails, so the work page is read instead
+  local URL="https://archiveofourown.org/works/$TEST_TS/chapters/1?error-500"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "ao3-$TEST_TS" "$( show_output | jq -r .id )"
+  assertEquals "Fake AO3 work $TEST_TS & co" "$( show_output | jq -r .title )"
+  assertEquals "https://archiveofourown.org/works/$TEST_TS" "$( show_output | jq -r .source )"
+  assertEquals 'fake' "$( show_output | jq -r .author )"
+  assertEquals 'ao3-fake' "$( show_output | jq -r .authorId )"
+  assertEquals 'Fake & series' "$( show_output | jq -r .series )"
+  assertEquals 'ao3-77' "$( show_output | jq -r .seriesId )"
+  assertEquals 2 "$( show_output | jq -r .seriesPosition )"
+  assertEquals 12345 "$( show_output | jq -r .words )"
+  assertEquals 3 "$( show_output | jq -r .chapters )"
+  assertEquals 'complete' "$( show_output | jq -r .status )"
+  assertEquals '["Worm - Wildbow","Pokémon"]' "$( show_output | jq -c .fandoms )"
+  assertEquals '2021-03-04T00:00:00Z' "$( show_output | jq -r .updated )"
+  assertEquals 3 "$( fichub_requests "$URL" )"
+}
+
+testFicMirrors() {
+  # the same id for URLs on different sites...
+  local SB="https://forums.spacebattles.com/threads/$TEST_TS/?fic=$TEST_TS"
+  local SV="https://forums.sufficientvelocity.com/threads/$TEST_TS/?fic=$TEST_TS"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$SB"
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$SV"
+  assertStatus 'HTTP/1.1 200 OK'
+  request_patch "$SB" "+${TEST_TAG}_mirrored"
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$SV"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( extractSignal "${TEST_TAG}_mirrored" | jq -r .signalsFor )"
+
+  # ...or the same title and author
+  local AO3="https://archiveofourown.org/works/$TEST_TS?dup=$TEST_TS"
+  local FFN="https://www.fanfiction.net/s/$TEST_TS/1/?dup=$TEST_TS"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$AO3"
+  assertStatus 'HTTP/1.1 200 OK'
+  local AO3_ID="$( show_output | jq -r .id )"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$FFN"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertNotEquals "$AO3_ID" "$( show_output | jq -r .id )"
+  request_patch "$FFN" "+${TEST_TAG}_mirrored"
+  request_patch "$AO3" "+${TEST_TAG}_mirrored"
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$AO3"
+  assertStatus 'HTTP/1.1 200 OK'
+  # counted once per account
+  assertEquals 1 "$( extractSignal "${TEST_TAG}_mirrored" | jq -r .signalsFor )"
+  assertEquals true "$( extractSignal "${TEST_TAG}_mirrored" | jq -r .signal )"
+
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=${TEST_URL}unrelated"
+  assertEquals "[]" "$( show_output | jq -c .signals )"
+}
+
+testGetFicMetaBatch() {
+  request "http://$FICAI_LISTEN/v1/fics/batch" -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"urls\":[\"${TEST_URL}meta\",\"${TEST_URL}batch\",\"${TEST_URL}not-found\",\"${TEST_URL}batch\"]}"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 3 "$( show_output | jq -r '.fics | length' )"
+  assertEquals "${TEST_URL}meta" "$( show_output | jq -r .fics[0].url )"
+  assertEquals "${TEST_URL}meta" "$( show_output | jq -r .fics[0].meta.source )"
+  assertEquals "${TEST_URL}batch" "$( show_output | jq -r .fics[1].meta.source )"
+  assertEquals 'null' "$( show_output | jq -r .fics[1].error )"
+  assertEquals 'null' "$( show_output | jq -r .fics[2].meta )"
+  assertEquals 'metadata unavailable' "$( show_output | jq -r .fics[2].error )"
+  assertEquals 'unsupported' "$( show_output | jq -r .fics[2].failure.kind )"
+  assertEquals 1 "$( fichub_requests "${TEST_URL}batch" )"
+
+  request "http://$FICAI_LISTEN/v1/fics/batch" -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"urls\":[\"${TEST_URL}1\",\"${TEST_URL}2\",\"${TEST_URL}3\",\"${TEST_URL}4\"]}"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'at most 3 urls can be looked up at once'
+}
+
+testGetSignalsWithMeta() {
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=${TEST_URL}meta" -d include=meta
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_URL}meta" "$( show_output | jq -r .meta.source )"
+  assertEquals 'Fake author' "$( show_output | jq -r .meta.author )"
+
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=${TEST_URL}meta"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 'null' "$( show_output | jq -r .meta )"
+
+  # metadata is never looked up for this
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=${TEST_URL}uncached" -d include=meta
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 'null' "$( show_output | jq -r .meta )"
+  assertEquals 0 "$( fichub_requests "${TEST_URL}uncached" )"
+
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=${TEST_URL}meta" -d include=derp
+  assertStatus 'HTTP/1.1 400 Bad Request'
+}
+
+testGetFicUpdates() {
+  request_patch "${TEST_URL}growing" "+${TEST_TAG}"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}growing"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 11 "$( show_output | jq -r .chapters )"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}growing-untracked"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  # the refresh job notices new chapters
+  sql "update fic_url_cache set fetched_at = now() - interval '30 days' where url like '${TEST_URL}growing%'"
+  sleep 2.5
+  request "http://$FICAI_LISTEN/v1/updates"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( show_output | jq -r '.updates | length' )"
+  assertEquals "${TEST_URL}growing" "$( show_output | jq -r .updates[0].source )"
+  assertEquals 11 "$( show_output | jq -r .updates[0].chaptersBefore )"
+  assertEquals 12 "$( show_output | jq -r .updates[0].chapters )"
+  assertEquals '2022-05-02T07:08:09Z' "$( show_output | jq -r .updates[0].updated )"
+}
+
+testFicMetaFromPage() {
+  # fichub doesn't know these, so the page itself is read
+  local URL="http://$FAKE_FICHUB_LISTEN/page/$TEST_TS?not-found"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "Fake page $TEST_TS & co" "$( show_output | jq -r .title )"
+  assertEquals 'Fake Fiction' "$( show_output | jq -r .site )"
+  assertEquals "$URL" "$( show_output | jq -r .source )"
+  assertContains "$( show_output | jq -r .id )" 'page-'
+
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G \
+    --data-urlencode "url=http://$FAKE_FICHUB_LISTEN/page/huge?not-found"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G \
+    --data-urlencode "url=http://$FAKE_FICHUB_LISTEN/page/redirect?not-found"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G \
+    --data-urlencode "url=http://localhost:${FAKE_FICHUB_LISTEN#*:}/page/$TEST_TS?not-found"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+}
+
+testGetAuthor() {
+  local FIC1="${TEST_URL}author1?author=$TEST_TS"
+  local FIC2="${TEST_URL}author2?author=$TEST_TS"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$FIC1"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "author-$TEST_TS" "$( show_output | jq -r .authorId )"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$FIC2"
+  assertStatus 'HTTP/1.1 200 OK'
+  request_patch "$FIC1" "+${TEST_TAG}_by_author"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  request "http://$FICAI_LISTEN/v1/authors/author-$TEST_TS"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "Author $TEST_TS" "$( show_output | jq -r .name )"
+  assertEquals 2 "$( show_output | jq -r .ficCount )"
+  local TAG="$( show_output | jq -c ".tags[] | select(.tag == \"${TEST_TAG}_by_author\")" )"
+  assertEquals 1 "$( echo "$TAG" | jq -r .signalsFor )"
+  assertEquals 1 "$( echo "$TAG" | jq -r .ficCount )"
+
+  request "http://$FICAI_LISTEN/v1/authors/author-$TEST_TS/fics"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 2 "$( show_output | jq -r '.fics | length' )"
+  assertNotEquals null "$( show_output | jq -r .fics[0].fetchedAt )"
+
+  request "http://$FICAI_LISTEN/v1/authors/author-$TEST_TS/fics" -d limit=1 -G
+  assertEquals 1 "$( show_output | jq -r '.fics | length' )"
+
+  request "http://$FICAI_LISTEN/v1/authors/nobody-$TEST_TS"
+  assertStatus 'HTTP/1.1 404 Not Found'
+  request "http://$FICAI_LISTEN/v1/authors/nobody-$TEST_TS/fics"
+  assertStatus 'HTTP/1.1 404 Not Found'
+}
+
+testGetSeries() {
+  local PART1="${TEST_URL}series1?series=$TEST_TS&part=1"
+  local PART2="${TEST_URL}series2?series=$TEST_TS&part=2"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$PART2"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "series-$TEST_TS" "$( show_output | jq -r .seriesId )"
+  assertEquals 2 "$( show_output | jq -r .seriesPosition )"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$PART1"
+  assertStatus 'HTTP/1.1 200 OK'
+  request_patch "$PART1" "+${TEST_TAG}_in_series"
+  request_patch "$PART2" "+${TEST_TAG}_in_series"
+
+  request "http://$FICAI_LISTEN/v1/series/series-$TEST_TS"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "Series $TEST_TS" "$( show_output | jq -r .title )"
+  assertEquals "$PART1" "$( show_output | jq -r .fics[0].source )"
+  assertEquals "$PART2" "$( show_output | jq -r .fics[1].source )"
+  local TAG="$( show_output | jq -c ".tags[] | select(.tag == \"${TEST_TAG}_in_series\")" )"
+  # counted once per account
+  assertEquals 1 "$( echo "$TAG" | jq -r .signalsFor )"
+  assertEquals 2 "$( echo "$TAG" | jq -r .ficCount )"
+
+  request "http://$FICAI_LISTEN/v1/series/nothing-$TEST_TS"
+  assertStatus 'HTTP/1.1 404 Not Found'
+}
+
+testChapterUrlsFold() {
+  # not to be mixed up with fics of other tests
+  local ID="${TEST_TS}0"
+  request_patch "https://archiveofourown.org/works/$ID/chapters/5?view_adult=true#main" "+${TEST_TAG}_folded"
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=http://www.archiveofourown.org/works/$ID"
+  assertEquals "${TEST_TAG}_folded" "$( show_output | jq -r .signals[0].tag )"
+  assertEquals true "$( show_output | jq -r .signals[0].signal )"
+
+  request_patch "https://m.fanfiction.net/s/$ID/3/Some-Title" "+${TEST_TAG}_folded"
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=https://www.fanfiction.net/s/$ID/1/"
+  assertEquals "${TEST_TAG}_folded" "$( show_output | jq -r .signals[0].tag )"
+
+  request_patch "https://forums.spacebattles.com/threads/fic.$ID/page-3#post-9" "+${TEST_TAG}_folded"
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=https://forums.spacebattles.com/threads/fic.$ID/reader/"
+  assertEquals "${TEST_TAG}_folded" "$( show_output | jq -r .signals[0].tag )"
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=https://forums.spacebattles.com/threads/other.$ID/"
+  assertEquals "[]" "$( show_output | jq -c .signals )"
+
+  # erasing from another chapter erases the signal of the fic
+  request_patch "https://archiveofourown.org/works/$ID/chapters/6" "%${TEST_TAG}_folded"
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=https://archiveofourown.org/works/$ID"
+  assertEquals "[]" "$( show_output | jq -c .signals )"
+}
+
+testFicMetaFailureCache() {
+  local URL="${TEST_URL}remembered-not-found"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  assertError 'metadata unavailable'
+  assertEquals 'unsupported' "$( show_output | jq -r .error.failure.kind )"
+  assertEquals 'unsupported url' "$( show_output | jq -r .error.failure.reason )"
+  assertEquals 1 "$( fichub_requests "$URL" )"
+
+  # not asked again until the failure expires
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  assertEquals 'unsupported' "$( show_output | jq -r .error.failure.kind )"
+  assertEquals 1 "$( fichub_requests "$URL" )"
+  sql "update fic_url_cache set failed_at = now() - interval '1 hour' where url = '$URL'"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  assertEquals 2 "$( fichub_requests "$URL" )"
+
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}remembered-error-500"
+  assertStatus 'HTTP/1.1 502 Bad Gateway'
+  assertEquals 'unavailable' "$( show_output | jq -r .error.failure.kind )"
+
+  # metadata found earlier is still served
+  sql "update fic_url_cache set fetched_at = now() - interval '20 days', failure = 'unavailable', failure_reason = 'down', failed_at = now() where url = '${TEST_URL}meta'"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}meta"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_URL}meta" "$( show_output | jq -r .source )"
+  sql "update fic_url_cache set fetched_at = now(), failure = null, failure_reason = null, failed_at = null where url = '${TEST_URL}meta'"
+}
+
+testGetSignalsByFicId() {
+  local URL="${TEST_URL}by-id"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  local ID="$( show_output | jq -r .id )"
+  request_patch "$URL" "+${TEST_TAG}_by_id"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  request "http://$FICAI_LISTEN/v1/fics/$ID/signals"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals true "$( extractSignal "${TEST_TAG}_by_id" | jq -r .signal )"
+
+  request "http://$FICAI_LISTEN/v1/signals" -G -d "ficId=$ID" -d include=meta
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals true "$( extractSignal "${TEST_TAG}_by_id" | jq -r .signal )"
+  assertEquals "$URL" "$( show_output | jq -r .meta.source )"
+
+  request "http://$FICAI_LISTEN/v1/signals" -G -d "ficId=unknown-$TEST_TS"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "[]" "$( show_output | jq -c .signals )"
+  request "http://$FICAI_LISTEN/v1/fics/unknown-$TEST_TS/signals"
+  assertStatus 'HTTP/1.1 404 Not Found'
+
+  request "http://$FICAI_LISTEN/v1/signals" -G -d "ficId=$ID" --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'only one of url and ficId can be given'
+}
+
+testGetSignalsV2() {
+  local URL="${TEST_URL}v2"
+  request_patch "$URL" "+${TEST_TAG}_v2_for"
+  assertStatus 'HTTP/1.1 200 OK'
+  request_patch "$URL" "-${TEST_TAG}_v2_against"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  request "http://$FICAI_LISTEN/v2/signals" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "${TEST_TAG}_v2_for" "$( show_output | jq -r '.signals[0].tag' )"
+  assertEquals true "$( show_output | jq '.signals[0].score > .signals[1].score' )"
+  assertEquals 0 "$( show_output | jq '.signals[1].score' )"
+  assertNotNull "$( show_output | jq -r '.signals[0].firstSignalledAt // empty' )"
+  assertNotNull "$( show_output | jq -r '.signals[0].lastSignalledAt // empty' )"
+
+  # v1 stays as it was.
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals null "$( show_output | jq '.signals[0].score' )"
+
+  request "http://$FICAI_LISTEN/v2/signals"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+
+  request "http://$FICAI_LISTEN/v2/openapi.json"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals get_signals_v2 "$( show_output | jq -r '.paths["/signals"].get.operationId' )"
+}
+
+testForceFicRefresh() {
+  local URL="${TEST_URL}forced-growing"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  local ID="$( show_output | jq -r .id )"
+  local CHAPTERS="$( show_output | jq -r .chapters )"
+
+  request "http://$FICAI_LISTEN/v1/admin/fics/$ID/refresh" -X POST
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  set_role "$TEST_EMAIL1" admin
+  request "http://$FICAI_LISTEN/v1/admin/fics/$ID/refresh" -X POST
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "$CHAPTERS" "$( show_output | jq -r .before.chapters )"
+  assertEquals "$(( CHAPTERS + 1 ))" "$( show_output | jq -r .after.chapters )"
+  assertEquals '["chapters","updated"]' "$( show_output | jq -c '[.changes[].field]' )"
+  assertEquals "$CHAPTERS" "$( show_output | jq -r '.changes[0].before' )"
+  assertEquals 2 "$( fichub_requests "$URL" )"
+
+  request "http://$FICAI_LISTEN/v1/admin/fics/unknown-$TEST_TS/refresh" -X POST
+  assertStatus 'HTTP/1.1 404 Not Found'
+  set_role "$TEST_EMAIL1" user
+}
+
+testGetCatalog() {
+  local TAG="${TEST_TAG}_catalog"
+  for i in 1 2 3; do
+    request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}catalog$i"
+    assertStatus 'HTTP/1.1 200 OK'
+    request_patch "${TEST_URL}catalog$i" "+$TAG"
+  done
+
+  request "http://$FICAI_LISTEN/v1/fics" -G --data-urlencode "tag=$TAG" -d limit=2
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 2 "$( show_output | jq -r '.fics | length' )"
+  assertEquals 1 "$( show_output | jq -r ".fics[0].tags[] | select(.tag == \"$TAG\") | .signalsFor" )"
+  assertEquals '["Worm","Pact"]' "$( show_output | jq -c .fics[0].fandoms )"
+  local CURSOR="$( show_output | jq -r .nextCursor )"
+  assertNotEquals null "$CURSOR"
+  local FIRST_PAGE="$( show_output | jq -c '[.fics[].id]' )"
+
+  request "http://$FICAI_LISTEN/v1/fics" -G --data-urlencode "tag=$TAG" -d limit=2 -d "cursor=$CURSOR"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( show_output | jq -r '.fics | length' )"
+  assertEquals null "$( show_output | jq -r .nextCursor )"
+  assertNotContains "$FIRST_PAGE" "$( show_output | jq -r .fics[0].id )"
+
+  request "http://$FICAI_LISTEN/v1/fics" -G --data-urlencode "tag=$TAG" -d fandom=worm
+  assertEquals 3 "$( show_output | jq -r '.fics | length' )"
+  request "http://$FICAI_LISTEN/v1/fics" -G --data-urlencode "tag=$TAG" -d fandom=nope
+  assertEquals 0 "$( show_output | jq -r '.fics | length' )"
+
+  request "http://$FICAI_LISTEN/v1/fics" -G -d cursor=derp
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'invalid cursor'
+}
+
+testMetadataMetrics() {
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}metrics"
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}metrics"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  # not json, so `request` doesn't apply
+  curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/out" "http://$FICAI_LISTEN/metrics"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 'content-type: text/plain; version=0.0.4' "$( grep content-type "$SHUNIT_TMPDIR/headers" | tr -d '\r\n' )"
+  assertContains "$( show_output )" '# TYPE ficai_metadata_fetch_duration_seconds histogram'
+  assertContains "$( show_output )" 'ficai_metadata_fetch_duration_seconds_count{outcome="ok",provider="fichub"}'
+  assertContains "$( show_output )" 'ficai_metadata_fetch_duration_seconds_bucket{outcome="ok",provider="fichub",le="+Inf"}'
+  assertContains "$( show_output )" 'ficai_metadata_cache_lookups_total{result="hit"}'
+  assertContains "$( show_output )" 'ficai_metadata_cache_lookups_total{result="miss"}'
+  assertContains "$( show_output )" 'ficai_fichub_attempts_total{outcome="ok"}'
+  assertContains "$( show_output )" 'ficai_fichub_request_duration_seconds_count{outcome="ok"}'
+  assertContains "$( show_output )" 'ficai_db_pool_connections{state="idle"}'
+  assertContains "$( show_output )" 'ficai_db_pool_connections{state="in-use"}'
+}
+
+testBodySizeLimit() {
+  local BODY="$SHUNIT_TMPDIR/large-body.json"
+  build_patch_body "$TEST_URL" "+$( head -c 70000 /dev/zero | tr '\0' a )" > "$BODY"
+  request "http://$FICAI_LISTEN/v1/signals" \
+    -X PATCH -H "Content-Type: application/json" --data-binary "@$BODY"
+  assertStatus 'HTTP/1.1 413 Payload Too Large'
+  assertError 'payload too large'
+
+  request "http://$FICAI_LISTEN/v1/signals" -H "Transfer-Encoding: chunked" \
+    -X PATCH -H "Content-Type: application/json" --data-binary "$( build_patch_body "$TEST_URL" +chunked )"
+  assertStatus 'HTTP/1.1 411 Length Required'
+  assertError 'content-length required'
+}
+
+testCompression() {
+  request "http://$FICAI_LISTEN/v1/signals" \
+    -X PATCH -H "Content-Type: application/json" \
+    --data-binary "$( build_patch_body "$TEST_URL" +compressed-one +compressed-two +compressed-three )"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$TEST_URL" \
+    --compressed -H "Accept-Encoding: gzip, br;q=0.5"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertContains "$( show_headers )" 'content-encoding: gzip'
+  assertContains "$( show_headers )" 'vary: accept-encoding'
+  assertSignal compressed-two true 1 0
+
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$TEST_URL" --compressed
+  assertContains "$( show_headers )" 'content-encoding: br'
+
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$TEST_URL"
+  assertNotContains "$( show_headers )" 'content-encoding'
+  assertContains "$( show_headers )" 'vary: accept-encoding'
+
+  # Too small to bother.
+  request "http://$FICAI_LISTEN/healthz" --compressed
+  assertNotContains "$( show_headers )" 'content-encoding'
+}
+
+testOpenApi() {
+  request "http://$FICAI_LISTEN/openapi.json"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals '"3.1.0"' "$( show_output | jq .openapi )"
+  assertEquals patch_signals "$( show_output | jq -r '.paths["/signals"].patch.operationId' )"
+
+  curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/out" "http://$FICAI_LISTEN/docs"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertContains "$( show_headers )" 'content-type: text/html'
+  assertContains "$( show_output )" '/openapi.json'
+}
+
+testHealthAndReadiness() {
+  request "http://$FICAI_LISTEN/healthz"
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/readyz"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals true "$( show_output | jq .ready )"
+
+  local VERSION="$( sql "select max(version) from schema_version" )"
+  sql "delete from schema_version where version = $VERSION"
+  request "http://$FICAI_LISTEN/readyz"
+  sql "insert into schema_version (version) values ($VERSION)"
+  assertStatus 'HTTP/1.1 503 Service Unavailable'
+  assertEquals '{"ready":false,"databaseReachable":true}' "$( show_output | jq -c '{ready, databaseReachable}' )"
+  assertEquals "$VERSION" "$( show_output | jq .expectedSchemaVersion )"
+}
+
+testMetricsListenerInUse() {
+  # The API's own address is taken, by the server under test.
+  FICAI_METRICS_LISTEN="$FICAI_LISTEN" FICAI_LISTEN=127.0.0.1:8082 \
+    timeout 20 "${CARGO_TARGET_DIR:-./target}/debug/ficai-signals-server" >"$SHUNIT_TMPDIR/server.log" 2>&1
+  assertEquals 'exit status' 1 "$?"
+  assertContains "$( cat "$SHUNIT_TMPDIR/server.log" )" "failed to listen on $FICAI_LISTEN for metrics"
+}
+
+testRequestMetrics() {
+  request "http://$FICAI_LISTEN/v1/tags/Worm/stats"
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/tags/search" -G -d q=worm
+  curl -s -o /dev/null "http://$FICAI_LISTEN/v1/made/up/path"
+
+  curl -s -o "$SHUNIT_TMPDIR/out" "http://$FICAI_LISTEN/metrics"
+  assertContains "$( show_output )" 'ficai_http_requests_total{method="GET",route="/v1/tags/{tag}/stats",status="200"}'
+  assertContains "$( show_output )" 'ficai_http_request_duration_seconds_count{method="GET",route="/v1/tags/{tag}/stats"}'
+  # literal routes win over parameters
+  assertContains "$( show_output )" 'ficai_http_requests_total{method="GET",route="/v1/tags/search"'
+  assertContains "$( show_output )" 'ficai_http_requests_total{method="GET",route="other",status="404"}'
+  assertNotContains "$( show_output )" 'made/up'
+}
+
+testFandomTags() {
+  local URL="${TEST_URL}fandoms"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals '["fandom:Pact","fandom:Worm"]' "$( show_output | jq -c '[.signals[].tag] | sort' )"
+  assertEquals true "$( extractSignal 'fandom:Worm' | jq -r .automatic )"
+  assertEquals 1 "$( extractSignal 'fandom:Worm' | jq -r .signalsFor )"
+  assertEquals null "$( extractSignal 'fandom:Worm' | jq -r .signal )"
+  assertEquals fandom "$( sql "select category from tag_meta where tag = 'fandom:worm'" )"
+
+  # users' votes count alongside
+  request_patch "$URL" "+fandom:Worm"
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$URL"
+  assertEquals 2 "$( extractSignal 'fandom:Worm' | jq -r .signalsFor )"
+  assertEquals true "$( extractSignal 'fandom:Worm' | jq -r .signal )"
+
+  request_patch "$URL" "+${TEST_TAG}_manual"
+  request "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$URL"
+  assertEquals false "$( extractSignal "${TEST_TAG}_manual" | jq -r .automatic )"
+}
+
+testTagCategoryInference() {
+  local URL="${TEST_URL}inference"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  # fichub lists Pact as a fandom of the fic
+  request_patch "$URL" "+Pact" "+${TEST_TAG}_c1/${TEST_TAG}_c2"
+
+  start_server "http://127.0.0.1:8082" FICAI_TAG_INFERENCE_INTERVAL_SECS=1 || return
+  for i in {1..50} ; do
+    [ "$( sql "select count(1) from tag_category_proposal where tag in ('pact', '${TEST_TAG}_c1/${TEST_TAG}_c2')" )" = 2 ] && break
+    sleep 0.1s
+  done
+  stop_server
+
+  request "http://$FICAI_LISTEN/v1/tags/category-proposals"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  set_role "$TEST_EMAIL1" moderator
+  request "http://$FICAI_LISTEN/v1/tags/category-proposals"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals fandom "$( show_output | jq -r '.proposals[] | select(.tag == "pact") | .category' )"
+  assertContains "$( show_output | jq -r '.proposals[] | select(.tag == "pact") | .reason' )" 'fichub lists "Pact"'
+  assertEquals ship "$( show_output | jq -r --arg tag "${TEST_TAG}_c1/${TEST_TAG}_c2" '.proposals[] | select(.tag == $tag) | .category' )"
+
+  request "http://$FICAI_LISTEN/v1/tags/category-proposals/Pact/decision" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"approve":true}'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals approved "$( show_output | jq -r .status )"
+  assertEquals fandom "$( sql "select category from tag_meta where tag = 'pact'" )"
+
+  request "http://$FICAI_LISTEN/v1/tags/category-proposals/${TEST_TAG}_c1%2F${TEST_TAG}_c2/decision" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"approve":false}'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals rejected "$( show_output | jq -r .status )"
+  assertEquals '' "$( sql "select category from tag_meta where tag = '${TEST_TAG}_c1/${TEST_TAG}_c2'" )"
+
+  request "http://$FICAI_LISTEN/v1/tags/category-proposals"
+  assertNotContains "$( show_output | jq -r .proposals[].tag )" "${TEST_TAG}_c1"
+  request "http://$FICAI_LISTEN/v1/tags/category-proposals/${TEST_TAG}_c1%2F${TEST_TAG}_c2/decision" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"approve":true}'
+  assertStatus 'HTTP/1.1 404 Not Found'
+
+  request_patch "$URL" "%Pact" "%${TEST_TAG}_c1/${TEST_TAG}_c2"
+  sql "delete from tag_category_proposal where tag = 'pact'"
+  sql "delete from tag_meta where tag = 'pact'"
+  set_role "$TEST_EMAIL1" user
+}
+
+testFicStatus() {
+  local TAG="${TEST_TAG}_status"
+  local URL="${TEST_URL}status?fic=status$TEST_TS"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals ongoing "$( show_output | jq -r .status )"
+  request_patch "$URL" "+$TAG"
+  local ABANDONED="${TEST_URL}status?status=Abandoned"
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$ABANDONED"
+  assertEquals abandoned "$( show_output | jq -r .status )"
+  request_patch "$ABANDONED" "+$TAG"
+
+  # another copy of the same fic says it's complete now
+  request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=${TEST_URL}status-done?fic=status$TEST_TS&status=completed"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals complete "$( show_output | jq -r .status )"
+  request "http://$FICAI_LISTEN/v1/updates"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals ongoing "$( show_output | jq -r .updates[0].statusBefore )"
+  assertEquals complete "$( show_output | jq -r .updates[0].status )"
+
+  request "http://$FICAI_LISTEN/v1/fics" -G --data-urlencode "tag=$TAG" -d status=complete
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals '["complete"]' "$( show_output | jq -c '[.fics[].status]' )"
+  request "http://$FICAI_LISTEN/v1/fics" -G --data-urlencode "tag=$TAG" -d status=ongoing
+  assertEquals 0 "$( show_output | jq -r '.fics | length' )"
+
+  request "http://$FICAI_LISTEN/v1/tags/$TAG/fics" -G -d status=abandoned
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "[\"$ABANDONED\"]" "$( show_output | jq -c '[.fics[].url]' )"
+
+  request "http://$FICAI_LISTEN/v1/authors/fake-author/fics" -G -d status=derp
+  assertStatus 'HTTP/1.1 400 Bad Request'
+}
+
+testUrlPolicy() {
+  for URL in \
+    "https://archiveofourown.org/works/search?work_search%5Bquery%5D=$TEST_TS" \
+    "https://www.fanfiction.net/u/$TEST_TS/Someone" \
+    "https://forums.spacebattles.com/forums/creative-writing.18/" \
+    "https://example.com/$TEST_TS?not-found" \
+    "javascript:alert(1)"; do
+    request_patch "$URL" "+${TEST_TAG}_url_policy"
+    assertStatus 'HTTP/1.1 422 Unprocessable Entity'
+    assertNotEquals null "$( show_output | jq -r .error.message )"
+  done
+  assertEquals 0 "$( sql "select count(1) from signal where tag_canonical = '${TEST_TAG}_url_policy'" )"
+
+  # fics on known sites and unknown sites that providers know are fine
+  request_patch "https://archiveofourown.org/collections/x/works/$TEST_TS" "+${TEST_TAG}_url_policy"
+  assertStatus 'HTTP/1.1 200 OK'
+  request_patch "https://example.com/fic/$TEST_TS" "+${TEST_TAG}_url_policy"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 1 "$( fichub_requests "https://example.com/fic/$TEST_TS" )"
+
+  # erasing is always allowed
+  request_patch "https://example.com/$TEST_TS?not-found" "%${TEST_TAG}_url_policy"
+  assertStatus 'HTTP/1.1 200 OK'
+}
+
+testGetPopularFics() {
+  local HOT="${TEST_URL}popular-hot"
+  local COLD="${TEST_URL}popular-cold"
+  for URL in "$HOT" "$COLD"; do
+    request "http://$FICAI_LISTEN/v1/fics/meta" -G --data-urlencode "url=$URL"
+    assertStatus 'HTTP/1.1 200 OK'
+  done
+  request_patch "$HOT" "+${TEST_TAG}_hot1" "+${TEST_TAG}_hot2" "+${TEST_TAG}_hot3"
+  request_patch "$COLD" "+${TEST_TAG}_cold"
+  sleep 1.5
+
+  request "http://$FICAI_LISTEN/v1/fics/popular" -G -d window=30d -d limit=100
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "$( date -u -d '30 days ago' +%F )" "$( show_output | jq -r .since )"
+  # fandom tags signalled by the server don't count
+  assertEquals 3 "$( show_output | jq -r ".fics[] | select(.source == \"$HOT\") | .signals" )"
+  assertEquals 1 "$( show_output | jq -r ".fics[] | select(.source == \"$COLD\") | .signals" )"
+  assertTrue "hot before cold" "[[ $( show_output | jq "[.fics[].source] | index(\"$HOT\")" ) -lt $( show_output | jq "[.fics[].source] | index(\"$COLD\")" ) ]]"
+
+  request "http://$FICAI_LISTEN/v1/fics/popular" -G -d window=30x
+  assertStatus 'HTTP/1.1 400 Bad Request'
+}
+
+testChangePassword() {
+  local EMAIL="${TEST_TS}.1+password@example.com"
+  local JAR="$SHUNIT_TMPDIR/password.cookies"
+  local OTHER_JAR="$SHUNIT_TMPDIR/password-other.cookies"
+  # a separate account, so that the test session keeps working
+  curl -s -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"old pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  curl -s -o /dev/null -b /dev/null -c "$OTHER_JAR" "http://$FICAI_LISTEN/v1/sessions" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"old pass\"}"
+  change_password() {
+    curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/out" -b "$JAR" \
+      "http://$FICAI_LISTEN/v1/accounts/password" \
+      -X POST -H "Content-Type: application/json" --data-binary "$1"
+  }
+  session_status() {
+    curl -s -o /dev/null -w '%{http_code}' -b "$1" "http://$FICAI_LISTEN/v1/sessions"
+  }
+
+  change_password '{"currentPassword":"wrong pass","newPassword":"new pass"}'
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  change_password '{"currentPassword":"old pass","newPassword":""}'
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertEquals 200 "$( session_status "$OTHER_JAR" )"
+
+  change_password '{"currentPassword":"old pass","newPassword":"new pass"}'
+  assertStatus 'HTTP/1.1 200 OK'
+  # other sessions are logged out, the current one isn't
+  assertEquals 200 "$( session_status "$JAR" )"
+  assertEquals 403 "$( session_status "$OTHER_JAR" )"
+
+  request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"old pass\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"new pass\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+}
+
+testPasswordReset() {
+  local EMAIL="${TEST_TS}.1+reset@example.com"
+  local JAR="$SHUNIT_TMPDIR/reset.cookies"
+  curl -s -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"old pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  confirm_reset() {
+    request "http://$FICAI_LISTEN/v1/accounts/password-reset/confirm" -b /dev/null -c /dev/null \
+      -X POST -H "Content-Type: application/json" --data-binary "$1"
+  }
+
+  # unknown emails get the same answer
+  request "http://$FICAI_LISTEN/v1/accounts/password-reset" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"${TEST_TS}.nobody@example.com\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/accounts/password-reset" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  sleep 1
+  # without an SMTP server, mail goes to the server's output
+  local TOKEN="$( grep -A5 "mail to $EMAIL" test.log | grep -o 'token=[A-Za-z0-9_-]*' | tail -1 | cut -d= -f2 )"
+  assertNotEquals "" "$TOKEN"
+
+  confirm_reset '{"token":"bogus","newPassword":"new pass"}'
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  confirm_reset "{\"token\":\"$TOKEN\",\"newPassword\":\"\"}"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  confirm_reset "{\"token\":\"$TOKEN\",\"newPassword\":\"new pass\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 403 "$( curl -s -o /dev/null -w '%{http_code}' -b "$JAR" "http://$FICAI_LISTEN/v1/sessions" )"
+  # tokens only work once
+  confirm_reset "{\"token\":\"$TOKEN\",\"newPassword\":\"other pass\"}"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+
+  request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"old pass\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"new pass\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+}
+
+testChangeEmail() {
+  local EMAIL="${TEST_TS}.1+email@example.com"
+  local NEW_EMAIL="${TEST_TS}.1+email-new@example.com"
+  local JAR="$SHUNIT_TMPDIR/email.cookies"
+  curl -s -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  change_email() {
+    curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/out" -b "$JAR" \
+      "http://$FICAI_LISTEN/v1/accounts/email" \
+      -X POST -H "Content-Type: application/json" --data-binary "$1"
+  }
+  confirm_change() {
+    request "http://$FICAI_LISTEN/v1/accounts/email/confirm" -b /dev/null -c /dev/null \
+      -X POST -H "Content-Type: application/json" --data-binary "$1"
+  }
+
+  change_email "{\"currentPassword\":\"wrong pass\",\"newEmail\":\"$NEW_EMAIL\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  change_email "{\"currentPassword\":\"pass\",\"newEmail\":\"$TEST_EMAIL1\"}"
+  assertStatus 'HTTP/1.1 409 Conflict'
+  change_email "{\"currentPassword\":\"pass\",\"newEmail\":\"$NEW_EMAIL\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  sleep 1
+  local TOKEN="$( grep -A5 "mail to $NEW_EMAIL" test.log | grep -o 'token=[A-Za-z0-9_-]*' | tail -1 | cut -d= -f2 )"
+  assertNotEquals "" "$TOKEN"
+  # nothing changes before confirmation
+  assertEquals "\"$EMAIL\"" "$( curl -s -b "$JAR" "http://$FICAI_LISTEN/v1/sessions" | jq .email )"
+
+  confirm_change '{"token":"bogus"}'
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  confirm_change "{\"token\":\"$TOKEN\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "\"$NEW_EMAIL\"" "$( curl -s -b "$JAR" "http://$FICAI_LISTEN/v1/sessions" | jq .email )"
+  confirm_change "{\"token\":\"$TOKEN\"}"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  sleep 1
+  # the old address is told
+  assertContains "$( cat test.log )" "mail to $EMAIL: Your Fic.AI email was changed"
+
+  request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$NEW_EMAIL\",\"password\":\"pass\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+}
+
+testDeleteAccount() {
+  local EMAIL="${TEST_TS}.1+delete@example.com"
+  local JAR="$SHUNIT_TMPDIR/delete.cookies"
+  curl -s -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  curl -s -o /dev/null -b "$JAR" "http://$FICAI_LISTEN/v1/signals" \
+    -X PATCH -H "Content-Type: application/json" --data-binary '{"url":"https://archiveofourown.org/works/1083","add":["deleted account tag"],"rm":[]}'
+  local ACCOUNT_ID="$( sql "select id from account where email = '$EMAIL'" )"
+  assertEquals 1 "$( sql "select count(1) from signal where account_id = $ACCOUNT_ID" )"
+  delete_account() {
+    curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/out" -b "$JAR" \
+      "http://$FICAI_LISTEN/v1/accounts" \
+      -X DELETE -H "Content-Type: application/json" --data-binary "$1"
+  }
+
+  delete_account '{"password":"wrong pass"}'
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  delete_account '{"password":"pass"}'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 403 "$( curl -s -o /dev/null -w '%{http_code}' -b "$JAR" "http://$FICAI_LISTEN/v1/sessions" )"
+  assertEquals 0 "$( sql "select count(1) from signal where account_id = $ACCOUNT_ID" )"
+  assertEquals "deleted:$ACCOUNT_ID" "$( sql "select email from account where id = $ACCOUNT_ID" )"
+
+  request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  # the email can be used again
+  request "http://$FICAI_LISTEN/v1/accounts" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  assertStatus 'HTTP/1.1 201 Created'
+}
+
+testSessions() {
+  local EMAIL="${TEST_TS}.1+sessions@example.com"
+  local JAR="$SHUNIT_TMPDIR/sessions.cookies"
+  local OTHER_JAR="$SHUNIT_TMPDIR/sessions-other.cookies"
+  local THIRD_JAR="$SHUNIT_TMPDIR/sessions-third.cookies"
+  curl -s -o /dev/null -b /dev/null -c "$JAR" -A "first agent" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  for J in "$OTHER_JAR" "$THIRD_JAR"; do
+    curl -s -o /dev/null -b /dev/null -c "$J" -A "other agent" -H 'X-Forwarded-For: 203.0.113.9' \
+      "http://$FICAI_LISTEN/v1/sessions" -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\"}"
+  done
+  session_status() {
+    curl -s -o /dev/null -w '%{http_code}' -b "$1" "http://$FICAI_LISTEN/v1/sessions"
+  }
+
+  request "http://$FICAI_LISTEN/v1/sessions/all" -b "$JAR"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 3 "$( jq '.sessions | length' "$SHUNIT_TMPDIR/out" )"
+  assertEquals '"first agent"' "$( jq '.sessions[] | select(.current) | .userAgent' "$SHUNIT_TMPDIR/out" )"
+  assertEquals '"127.0.0.1"' "$( jq '.sessions[] | select(.current) | .createdIp' "$SHUNIT_TMPDIR/out" )"
+  # Through the trusted proxy.
+  assertEquals '"203.0.113.9"' "$( jq '[.sessions[] | select(.current | not)][0].createdIp' "$SHUNIT_TMPDIR/out" )"
+  local OTHER_ID="$( jq -r '[.sessions[] | select(.current | not)][0].id' "$SHUNIT_TMPDIR/out" )"
+
+  request "http://$FICAI_LISTEN/v1/sessions/bogus" -b "$JAR" -X DELETE
+  assertStatus 'HTTP/1.1 404 Not Found'
+  request "http://$FICAI_LISTEN/v1/sessions/$OTHER_ID" -b "$JAR" -X DELETE
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/sessions/all" -b "$JAR"
+  assertEquals 2 "$( jq '.sessions | length' "$SHUNIT_TMPDIR/out" )"
+
+  request "http://$FICAI_LISTEN/v1/sessions/others" -b "$JAR" -X DELETE
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 200 "$( session_status "$JAR" )"
+  assertEquals 403 "$( session_status "$OTHER_JAR" )"
+  assertEquals 403 "$( session_status "$THIRD_JAR" )"
+}
+
+testSessionExpiry() {
+  local EMAIL="${TEST_TS}.1+expiry@example.com"
+  local JAR="$SHUNIT_TMPDIR/expiry.cookies"
+  curl -s -D "$SHUNIT_TMPDIR/headers" -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  assertContains "$( cat "$SHUNIT_TMPDIR/headers" )" "Max-Age=2592000"
+  local ACCOUNT_ID="$( sql "select id from account where email = '$EMAIL'" )"
+  session_status() {
+    curl -s -D "$SHUNIT_TMPDIR/headers" -o /dev/null -w '%{http_code}' -b "$JAR" "http://$FICAI_LISTEN/v1/sessions"
+  }
+
+  # the cookie is renewed with every successful request
+  assertEquals 200 "$( session_status )"
+  assertContains "$( cat "$SHUNIT_TMPDIR/headers" )" "Max-Age=2592000"
+
+  sql "update session set last_used_at = now() - interval '31 days' where account_id = $ACCOUNT_ID" >/dev/null
+  assertEquals 403 "$( session_status )"
+  assertEquals 0 "$( grep -ci set-cookie "$SHUNIT_TMPDIR/headers" )"
+
+  sql "update session set last_used_at = now(), expires_at = now() - interval '1 second' where account_id = $ACCOUNT_ID" >/dev/null
+  assertEquals 403 "$( session_status )"
+}
+
+testApiTokens() {
+  local EMAIL="${TEST_TS}.1+tokens@example.com"
+  local JAR="$SHUNIT_TMPDIR/tokens.cookies"
+  local URL="https://archiveofourown.org/works/1086"
+  curl -s -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  with_token() {
+    local TOKEN="$1"
+    shift
+    curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/out" -b /dev/null \
+      -H "Authorization: Bearer $TOKEN" "$@"
+  }
+
+  request "http://$FICAI_LISTEN/v1/tokens" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"name":"no scopes","scopes":[]}'
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  request "http://$FICAI_LISTEN/v1/tokens" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"name":"reader","scopes":["read"]}'
+  assertStatus 'HTTP/1.1 201 Created'
+  local READ_TOKEN="$( jq -r .token "$SHUNIT_TMPDIR/out" )"
+  local READ_ID="$( jq -r .id "$SHUNIT_TMPDIR/out" )"
+  request "http://$FICAI_LISTEN/v1/tokens" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"name":"writer","scopes":["read","write-signals"]}'
+  local WRITE_TOKEN="$( jq -r .token "$SHUNIT_TMPDIR/out" )"
+
+  request "http://$FICAI_LISTEN/v1/tokens" -b "$JAR"
+  assertEquals '["writer","reader"]' "$( jq -c '[.tokens[].name]' "$SHUNIT_TMPDIR/out" )"
+  assertEquals 'null' "$( jq -c '.tokens[0].token' "$SHUNIT_TMPDIR/out" )"
+
+  local PATCH="{\"url\":\"$URL\",\"add\":[\"token tag\"],\"rm\":[]}"
+  with_token "$READ_TOKEN" "http://$FICAI_LISTEN/v1/signals" \
+    -X PATCH -H "Content-Type: application/json" --data-binary "$PATCH"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  with_token "$WRITE_TOKEN" "http://$FICAI_LISTEN/v1/signals" \
+    -X PATCH -H "Content-Type: application/json" --data-binary "$PATCH"
+  assertStatus 'HTTP/1.1 200 OK'
+  with_token "$READ_TOKEN" "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals true "$( jq '.signals[] | select(.tag == "token tag") | .signal' "$SHUNIT_TMPDIR/out" )"
+  # tokens don't manage the account
+  with_token "$WRITE_TOKEN" "http://$FICAI_LISTEN/v1/tokens"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  with_token "ficai_bogus" "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  request "http://$FICAI_LISTEN/v1/tokens/$READ_ID" -b "$JAR" -X DELETE
+  assertStatus 'HTTP/1.1 200 OK'
+  with_token "$READ_TOKEN" "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  request "http://$FICAI_LISTEN/v1/tokens/$READ_ID" -b "$JAR" -X DELETE
+  assertStatus 'HTTP/1.1 404 Not Found'
+}
+
+testServiceAccounts() {
+  local ADMIN_JAR="$SHUNIT_TMPDIR/service-admin.cookies"
+  local URL="https://archiveofourown.org/works/1087"
+  curl -s -o /dev/null -b /dev/null -c "$ADMIN_JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"service-admin-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  local BODY="{\"displayName\":\"bot ${TEST_TS}\",\"token\":{\"name\":\"reader\",\"scopes\":[\"read\"]}}"
+  with_token() {
+    local TOKEN="$1"
+    shift
+    curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/out" -b /dev/null \
+      -H "Authorization: Bearer $TOKEN" "$@"
+  }
+
+  request "http://$FICAI_LISTEN/v1/admin/service-accounts" -b "$ADMIN_JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary "$BODY"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  set_role "service-admin-${TEST_TS}@example.com" admin
+  request "http://$FICAI_LISTEN/v1/admin/service-accounts" -b "$ADMIN_JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary "$BODY"
+  assertStatus 'HTTP/1.1 201 Created'
+  local ID="$( jq .id "$SHUNIT_TMPDIR/out" )"
+  local READ_TOKEN="$( jq -r .token.token "$SHUNIT_TMPDIR/out" )"
+  assertEquals '["read"]' "$( jq -c .token.scopes "$SHUNIT_TMPDIR/out" )"
+  assertEquals service "$( sql "select kind from account where id = $ID" )"
+  request "http://$FICAI_LISTEN/v1/admin/service-accounts" -b "$ADMIN_JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary "$BODY"
+  assertStatus 'HTTP/1.1 409 Conflict'
+
+  # scopes bound what the account can do, and there's no logging in
+  with_token "$READ_TOKEN" "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 200 OK'
+  with_token "$READ_TOKEN" "http://$FICAI_LISTEN/v1/signals" \
+    -X PATCH -H "Content-Type: application/json" --data-binary "{\"url\":\"$URL\",\"add\":[\"bot tag\"],\"rm\":[]}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"service:$ID\",\"password\":\"\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  request "http://$FICAI_LISTEN/v1/admin/service-accounts/$ID/tokens" -b "$ADMIN_JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"name":"writer","scopes":["write-signals"]}'
+  assertStatus 'HTTP/1.1 201 Created'
+  local WRITE_TOKEN="$( jq -r .token "$SHUNIT_TMPDIR/out" )"
+  with_token "$WRITE_TOKEN" "http://$FICAI_LISTEN/v1/signals" \
+    -X PATCH -H "Content-Type: application/json" --data-binary "{\"url\":\"$URL\",\"add\":[\"bot tag\"],\"rm\":[]}"
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/admin/service-accounts/$ID/tokens" -b "$ADMIN_JAR"
+  assertEquals '["writer","reader"]' "$( jq -c '[.tokens[].name]' "$SHUNIT_TMPDIR/out" )"
+  local READ_ID="$( jq '.tokens[] | select(.name == "reader") | .id' "$SHUNIT_TMPDIR/out" )"
+  request "http://$FICAI_LISTEN/v1/admin/service-accounts/$ID/tokens/$READ_ID" -b "$ADMIN_JAR" -X DELETE
+  assertStatus 'HTTP/1.1 200 OK'
+  with_token "$READ_TOKEN" "http://$FICAI_LISTEN/v1/signals" -G --data-urlencode "url=$URL"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  # people aren't service accounts
+  local PERSON="$( sql "select id from account where email = 'service-admin-${TEST_TS}@example.com'" )"
+  request "http://$FICAI_LISTEN/v1/admin/service-accounts/$PERSON/tokens" -b "$ADMIN_JAR"
+  assertStatus 'HTTP/1.1 404 Not Found'
+}
+
+testOAuthLogin() {
+  local EXISTING="${TEST_TS}.1+oauth@example.com"
+  local UNVERIFIED="${TEST_TS}.1+oauth-unverified@example.com"
+  local NEW="${TEST_TS}.1+oauth-new@example.com"
+  curl -s -o /dev/null -b /dev/null -c /dev/null "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EXISTING\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  curl -s -o /dev/null -b /dev/null -c /dev/null "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$UNVERIFIED\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  # Goes through the provider as `$1`, leaving the status of the callback in `headers`.
+  oauth_login() {
+    local JAR="$SHUNIT_TMPDIR/oauth.cookies"
+    rm -f "$JAR"
+    local AUTHORIZE="$( curl -s -o /dev/null -c "$JAR" -w '%{redirect_url}' "http://$FICAI_LISTEN/v1/oauth/login" )"
+    local HINT="$( jq -rn --arg email "$1" '$email | @uri' )"
+    local CALLBACK="$( curl -s -o /dev/null -w '%{redirect_url}' "$AUTHORIZE&login_hint=$HINT" )"
+    curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/out" -b "$JAR" -c "$JAR" "$CALLBACK"
+  }
+  session_email() {
+    curl -s -b "$SHUNIT_TMPDIR/oauth.cookies" "http://$FICAI_LISTEN/v1/sessions" | jq -r .email
+  }
+
+  oauth_login "$EXISTING"
+  assertStatus 'HTTP/1.1 302 Found'
+  assertContains "$( show_headers )" "location: http://$FICAI_LISTEN/logged-in"
+  assertEquals "$EXISTING" "$( session_email )"
+  # the account can still log in with its password
+  request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EXISTING\",\"password\":\"pass\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  oauth_login "$UNVERIFIED"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  oauth_login "$NEW"
+  assertStatus 'HTTP/1.1 302 Found'
+  assertEquals "$NEW" "$( session_email )"
+  # accounts created this way have no password
+  request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$NEW\",\"password\":\"\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  # accounts with two-factor authentication neither log in nor get linked this way
+  local TWO_FACTOR="${TEST_TS}.1+oauth-2fa@example.com"
+  curl -s -o /dev/null -b /dev/null -c /dev/null "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TWO_FACTOR\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  sql "insert into account_totp (account_id, secret, enabled_at) select id, 'secret', now() from account where email in ('$EXISTING', '$TWO_FACTOR')"
+  oauth_login "$EXISTING"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  oauth_login "$TWO_FACTOR"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  assertEquals 0 "$( sql "select count(1) from oauth_identity i join account a on a.id = i.account_id where a.email = '$TWO_FACTOR'" )"
+  sql "delete from account_totp where account_id in (select id from account where email in ('$EXISTING', '$TWO_FACTOR'))"
+
+  # a callback without the state cookie of the login it belongs to is refused
+  local AUTHORIZE="$( curl -s -o /dev/null -w '%{redirect_url}' "http://$FICAI_LISTEN/v1/oauth/login" )"
+  local CALLBACK="$( curl -s -o /dev/null -w '%{redirect_url}' "$AUTHORIZE&login_hint=$EXISTING" )"
+  request "$CALLBACK" -b /dev/null -c /dev/null
+  assertStatus 'HTTP/1.1 400 Bad Request'
+}
+
+testTwoFactor() {
+  local EMAIL="${TEST_TS}.1+2fa@example.com"
+  local JAR="$SHUNIT_TMPDIR/2fa.cookies"
+  curl -s -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  # Prints the code for a base32 secret, `$2` time steps from now.
+  totp() {
+    local KEY="$( echo -n "$1" | base32 -d | od -An -tx1 | tr -d ' \n' )"
+    local STEP="$( printf '%016x' $(( $(date +%s) / 30 + ${2:-0} )) )"
+    local HASH="$( printf "$( echo -n "$STEP" | sed 's/../\\x&/g' )" \
+      | openssl dgst -sha1 -mac HMAC -macopt "hexkey:$KEY" | awk '{ print $NF }' )"
+    local OFFSET=$(( 16#${HASH:39:1} * 2 ))
+    printf '%06d' $(( (16#${HASH:$OFFSET:8} & 0x7fffffff) % 1000000 ))
+  }
+  log_in() {
+    request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+      -X POST -H "Content-Type: application/json" --data-binary "$1"
+  }
+
+  request "http://$FICAI_LISTEN/v1/accounts/2fa" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"password":"wrong"}'
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  request "http://$FICAI_LISTEN/v1/accounts/2fa" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"password":"pass"}'
+  assertStatus 'HTTP/1.1 200 OK'
+  local SECRET="$( jq -r .secret "$SHUNIT_TMPDIR/out" )"
+  assertContains "$( jq -r .otpauthUrl "$SHUNIT_TMPDIR/out" )" "secret=$SECRET"
+  assertContains "$( jq -r .qrCodeSvg "$SHUNIT_TMPDIR/out" )" '<svg'
+  # not enabled until confirmed
+  log_in "{\"email\":\"$EMAIL\",\"password\":\"pass\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  request "http://$FICAI_LISTEN/v1/accounts/2fa/confirm" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"code":"000000x"}'
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  request "http://$FICAI_LISTEN/v1/accounts/2fa/confirm" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"code\":\"$( totp "$SECRET" )\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 10 "$( jq '.recoveryCodes | length' "$SHUNIT_TMPDIR/out" )"
+  local RECOVERY1="$( jq -r '.recoveryCodes[0]' "$SHUNIT_TMPDIR/out" )"
+  local RECOVERY2="$( jq -r '.recoveryCodes[1]' "$SHUNIT_TMPDIR/out" )"
+  # stored hashed
+  assertEquals 1 "$( sql "select count(1) from totp_recovery_code where code_hash = sha256(convert_to(replace('$RECOVERY1', '-', ''), 'UTF8'))" )"
+
+  log_in "{\"email\":\"$EMAIL\",\"password\":\"pass\"}"
+  assertStatus 'HTTP/1.1 401 Unauthorized'
+  assertError 'two-factor code required'
+  log_in "{\"email\":\"$EMAIL\",\"password\":\"wrong\",\"twoFactorCode\":\"$( totp "$SECRET" 1 )\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  local CODE="$( totp "$SECRET" 1 )"
+  log_in "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"twoFactorCode\":\"$CODE\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  # codes can only be used once
+  log_in "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"twoFactorCode\":\"$CODE\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  # and so can recovery codes, which don't care about case and dashes
+  local SHOUTED="$( echo "$RECOVERY1" | tr -d - | tr a-z A-Z )"
+  log_in "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"twoFactorCode\":\"$SHOUTED\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  log_in "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"twoFactorCode\":\"$RECOVERY1\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  request "http://$FICAI_LISTEN/v1/accounts/2fa" -b "$JAR"
+  assertEquals '{"enabled":true,"recoveryCodesLeft":9}' "$( jq -c . "$SHUNIT_TMPDIR/out" )"
+  request "http://$FICAI_LISTEN/v1/accounts/2fa/recovery-codes" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"code\":\"$RECOVERY2\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  local RECOVERY3="$( jq -r '.recoveryCodes[0]' "$SHUNIT_TMPDIR/out" )"
+  request "http://$FICAI_LISTEN/v1/accounts/2fa" -b "$JAR"
+  assertEquals '{"enabled":true,"recoveryCodesLeft":10}' "$( jq -c . "$SHUNIT_TMPDIR/out" )"
+
+  request "http://$FICAI_LISTEN/v1/accounts/2fa" -b "$JAR" \
+    -X DELETE -H "Content-Type: application/json" --data-binary "{\"password\":\"wrong\",\"code\":\"$RECOVERY3\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  request "http://$FICAI_LISTEN/v1/accounts/2fa" -b "$JAR" \
+    -X DELETE -H "Content-Type: application/json" --data-binary "{\"password\":\"pass\",\"code\":\"$RECOVERY3\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+  log_in "{\"email\":\"$EMAIL\",\"password\":\"pass\"}"
+  assertStatus 'HTTP/1.1 200 OK'
+}
+
+testLoginThrottle() {
+  local EMAIL="${TEST_TS}.1+throttle@example.com"
+  curl -s -o /dev/null -b /dev/null -c /dev/null "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  log_in() {
+    request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+      -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"$1\"}"
+  }
+
+  for i in {1..4} ; do
+    log_in wrong
+    assertStatus 'HTTP/1.1 403 Forbidden'
+  done
+  # a success forgets the failures
+  log_in pass
+  assertStatus 'HTTP/1.1 200 OK'
+  for i in {1..5} ; do
+    log_in wrong
+    assertStatus 'HTTP/1.1 403 Forbidden'
+  done
+  # locked out, even with the right password
+  log_in pass
+  assertStatus 'HTTP/1.1 429 Too Many Requests'
+  assertError 'too many requests'
+  assertContains "$( show_headers )" 'retry-after: '
+  sleep 2
+  log_in wrong
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  # the lockout doubled
+  sleep 1
+  log_in pass
+  assertStatus 'HTTP/1.1 429 Too Many Requests'
+  sleep 2
+  log_in pass
+  assertStatus 'HTTP/1.1 200 OK'
+}
+
+testInvites() {
+  local EMAIL="${TEST_TS}.1+inviter@example.com"
+  local JAR="$SHUNIT_TMPDIR/invites.cookies"
+  curl -s -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  sign_up() {
+    request "http://$FICAI_LISTEN/v1/accounts" -b /dev/null -c /dev/null \
+      -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$1\",\"password\":\"pass\",\"inviteCode\":\"$2\",\"captchaToken\":\"solved\"}"
+  }
+
+  request "http://$FICAI_LISTEN/v1/invites" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"maxUses":2}'
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  request "http://$FICAI_LISTEN/v1/invites" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{}'
+  assertStatus 'HTTP/1.1 201 Created'
+  local CODE="$( jq -r .code "$SHUNIT_TMPDIR/out" )"
+  local ID="$( jq -r .id "$SHUNIT_TMPDIR/out" )"
+  assertEquals 1 "$( jq -r .maxUses "$SHUNIT_TMPDIR/out" )"
+
+  # single use, and attributed to the invite
+  sign_up "${TEST_TS}.1+invited@example.com" "$CODE"
+  assertStatus 'HTTP/1.1 201 Created'
+  assertEquals "$ID" "$( sql "select invite_id from account where email = '${TEST_TS}.1+invited@example.com'" )"
+  sign_up "${TEST_TS}.2+invited@example.com" "$CODE"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'invalid invite code'
+  request "http://$FICAI_LISTEN/v1/invites" -b "$JAR"
+  assertEquals 1 "$( jq -r ".invites[] | select(.id == $ID) | .uses" "$SHUNIT_TMPDIR/out" )"
+
+  # a failed signup doesn't use up the invite
+  request "http://$FICAI_LISTEN/v1/invites" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{}'
+  CODE="$( jq -r .code "$SHUNIT_TMPDIR/out" )"
+  ID="$( jq -r .id "$SHUNIT_TMPDIR/out" )"
+  sign_up "$EMAIL" "$CODE"
+  assertStatus 'HTTP/1.1 409 Conflict'
+  request "http://$FICAI_LISTEN/v1/invites" -b "$JAR"
+  assertEquals 0 "$( jq -r ".invites[] | select(.id == $ID) | .uses" "$SHUNIT_TMPDIR/out" )"
+  # revoked ones can't be used either
+  request "http://$FICAI_LISTEN/v1/invites/$ID" -b "$JAR" -X DELETE
+  assertStatus 'HTTP/1.1 200 OK'
+  sign_up "${TEST_TS}.3+invited@example.com" "$CODE"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+
+  for i in {1..6} ; do
+    request "http://$FICAI_LISTEN/v1/invites" -b "$JAR" \
+      -X POST -H "Content-Type: application/json" --data-binary '{}'
+  done
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'at most 5 unused invites per account'
+
+  set_role "$EMAIL" admin
+  request "http://$FICAI_LISTEN/v1/invites" -b "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"maxUses":2,"validForSecs":60}'
+  assertStatus 'HTTP/1.1 201 Created'
+  CODE="$( jq -r .code "$SHUNIT_TMPDIR/out" )"
+  sign_up "${TEST_TS}.4+invited@example.com" "$CODE"
+  assertStatus 'HTTP/1.1 201 Created'
+  sign_up "${TEST_TS}.5+invited@example.com" "$CODE"
+  assertStatus 'HTTP/1.1 201 Created'
+  sign_up "${TEST_TS}.6+invited@example.com" "$CODE"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+}
+
+testAccountEvents() {
+  local EMAIL="${TEST_TS}.1+events@example.com"
+  local JAR="$SHUNIT_TMPDIR/events.cookies"
+  curl -s -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  curl -s -o /dev/null -b /dev/null -c /dev/null -A "other device" "http://$FICAI_LISTEN/v1/sessions" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\"}"
+  # failures aren't the account's doing
+  curl -s -o /dev/null -b /dev/null -c /dev/null "http://$FICAI_LISTEN/v1/sessions" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"wrong\"}"
+  request "http://$FICAI_LISTEN/v1/accounts/password" -b "$JAR" -c "$JAR" \
+    -X POST -H "Content-Type: application/json" --data-binary '{"currentPassword":"pass","newPassword":"new pass"}'
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/sessions/others" -b "$JAR" -c "$JAR" -X DELETE
+  assertStatus 'HTTP/1.1 200 OK'
+
+  request "http://$FICAI_LISTEN/v1/accounts/events" -b "$JAR" -c "$JAR"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals '["other-sessions-revoke","password-change","login"]' "$( jq -c '[.events[].kind]' "$SHUNIT_TMPDIR/out" )"
+  assertEquals 'other device' "$( jq -r '.events[2].userAgent' "$SHUNIT_TMPDIR/out" )"
+  assertEquals '127.0.0.1' "$( jq -r '.events[2].ip' "$SHUNIT_TMPDIR/out" )"
+
+  local BEFORE="$( jq -r '.events[0].id' "$SHUNIT_TMPDIR/out" )"
+  request "http://$FICAI_LISTEN/v1/accounts/events?before=$BEFORE&limit=1" -b "$JAR" -c "$JAR"
+  assertEquals '["password-change"]' "$( jq -c '[.events[].kind]' "$SHUNIT_TMPDIR/out" )"
+
+  # only the account's own
+  local OTHER_JAR="$SHUNIT_TMPDIR/events-other.cookies"
+  curl -s -o /dev/null -b /dev/null -c "$OTHER_JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"${TEST_TS}.2+events@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  request "http://$FICAI_LISTEN/v1/accounts/events" -b "$OTHER_JAR" -c "$OTHER_JAR"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals '[]' "$( jq -c .events "$SHUNIT_TMPDIR/out" )"
+}
+
+testDisplayName() {
+  request "http://$FICAI_LISTEN/v1/accounts/display-name" \
+    -X PUT -H "Content-Type: application/json" --data-binary '{"displayName":"Taylor H."}'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "Taylor H." "$( show_output | jq -r .displayName )"
+  request "http://$FICAI_LISTEN/v1/sessions"
+  assertEquals "Taylor H." "$( show_output | jq -r .displayName )"
+  assertNotEquals "the account sees its own email" "null" "$( extractEmail )"
+
+  for name in "ab" " Taylor" "Tay  lor" "taylor@example.com"; do
+    request "http://$FICAI_LISTEN/v1/accounts/display-name" \
+      -X PUT -H "Content-Type: application/json" --data-binary "{\"displayName\":\"$name\"}"
+    assertStatus 'HTTP/1.1 400 Bad Request'
+  done
+
+  local JAR="$SHUNIT_TMPDIR/display-name.cookies"
+  curl -s -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"display-name-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  assertEquals "taken regardless of case" "409" "$(
+    curl -s -o /dev/null -w "%{http_code}" -b "$JAR" -c "$JAR" "http://$FICAI_LISTEN/v1/accounts/display-name" \
+      -X PUT -H "Content-Type: application/json" --data-binary '{"displayName":"taylor h."}'
+  )"
+
+  request "http://$FICAI_LISTEN/v1/accounts/display-name" \
+    -X PUT -H "Content-Type: application/json" --data-binary '{"displayName":null}'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "freed once cleared" "200" "$(
+    curl -s -o /dev/null -w "%{http_code}" -b "$JAR" -c "$JAR" "http://$FICAI_LISTEN/v1/accounts/display-name" \
+      -X PUT -H "Content-Type: application/json" --data-binary '{"displayName":"taylor h."}'
+  )"
+}
+
+testProfile() {
+  local JAR="$SHUNIT_TMPDIR/profile.cookies"
+  local ID
+  ID="$( curl -s -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"profile-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}" | jq .id )"
+  sql "insert into signal (account_id, url, tag, tag_canonical, signal) values
+    ($ID, 'https://example.com/profile/1', 'Worm', 'worm', true),
+    ($ID, 'https://example.com/profile/2', 'Worm', 'worm', true),
+    ($ID, 'https://example.com/profile/2', 'fluff', 'fluff', false)"
+
+  request "http://$FICAI_LISTEN/v1/users/$ID"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "$ID" "$( extractUid )"
+  assertEquals "null" "$( extractEmail )"
+  assertEquals "stats are opt-in" "null" "$( show_output | jq .stats )"
+  assertEquals "but the account sees its own" "2" "$(
+    curl -s -b "$JAR" -c "$JAR" "http://$FICAI_LISTEN/v1/users/$ID" | jq .stats.ficsTagged
+  )"
+
+  assertEquals '{"showStats":true}' "$(
+    curl -s -b "$JAR" -c "$JAR" "http://$FICAI_LISTEN/v1/preferences/profile" \
+      -X PUT -H "Content-Type: application/json" --data-binary '{"showStats":true}'
+  )"
+  request "http://$FICAI_LISTEN/v1/users/$ID"
+  assertEquals "2" "$( show_output | jq .stats.ficsTagged )"
+  assertEquals '[{"tag":"worm","fics":2}]' "$( show_output | jq -c .stats.favoriteTags )"
+
+  request "http://$FICAI_LISTEN/v1/users/0"
+  assertStatus 'HTTP/1.1 404 Not Found'
+}
+
+testPreferences() {
+  local JAR="$SHUNIT_TMPDIR/preferences.cookies"
+  curl -s -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"preferences-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  assertEquals "defaults" '{"minVotes":0,"nsfw":"show","blockedTags":[]}' "$(
+    curl -s -b "$JAR" -c "$JAR" "http://$FICAI_LISTEN/v1/preferences"
+  )"
+  assertEquals '{"minVotes":2,"nsfw":"blur","blockedTags":["worm"]}' "$(
+    curl -s -b "$JAR" -c "$JAR" "http://$FICAI_LISTEN/v1/preferences" \
+      -X PUT -H "Content-Type: application/json" \
+      --data-binary '{"minVotes":2,"nsfw":"blur","blockedTags":["Worm"]}'
+  )"
+  assertEquals "blocked tags are kept when omitted" '{"minVotes":0,"nsfw":"hide","blockedTags":["worm"]}' "$(
+    curl -s -b "$JAR" -c "$JAR" "http://$FICAI_LISTEN/v1/preferences" \
+      -X PUT -H "Content-Type: application/json" --data-binary '{"nsfw":"hide"}'
+  )"
+  assertEquals '{"tags":["worm"]}' "$(
+    curl -s -b "$JAR" -c "$JAR" "http://$FICAI_LISTEN/v1/preferences/blocked-tags"
+  )"
+  assertEquals "400" "$(
+    curl -s -o /dev/null -w "%{http_code}" -b "$JAR" -c "$JAR" "http://$FICAI_LISTEN/v1/preferences" \
+      -X PUT -H "Content-Type: application/json" --data-binary '{"minVotes":-1}'
+  )"
+}
+
+testPasswordPolicy() {
+  create_with_password() {
+    request "http://$FICAI_LISTEN/v1/accounts" -b /dev/null -c /dev/null \
+      -X POST -H "Content-Type: application/json" \
+      --data-binary "{\"email\":\"policy-${TEST_TS}@example.com\",\"password\":\"$1\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  }
+  rules() {
+    show_output | jq -c '[.error.problems[].rule]'
+  }
+
+  create_with_password "Xq9"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'password rejected'
+  assertEquals '["too-short"]' "$( rules )"
+  create_with_password "abcdefgh"
+  assertEquals "sequences don't count" '["too-weak"]' "$( rules )"
+  create_with_password "Password1!"
+  assertEquals '["common"]' "$( rules )"
+  create_with_password "x policy-$TEST_TS"
+  assertEquals '["contains-email"]' "$( rules )"
+  create_with_password "correct horse"
+  assertStatus 'HTTP/1.1 201 Created'
+}
+
+testAccountExport() {
+  local JAR="$SHUNIT_TMPDIR/export.cookies"
+  local ID
+  ID="$( curl -s -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"export-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}" | jq .id )"
+  sql "insert into signal (account_id, url, tag, tag_canonical, signal) values
+    ($ID, 'https://example.com/export/1', 'Worm', 'worm', true),
+    ($ID, 'https://example.com/export/2', 'fluff', 'fluff', false)"
+
+  request "http://$FICAI_LISTEN/v1/accounts/export" -b "$JAR" -c "$JAR"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertContains "$( show_headers )" 'content-disposition: attachment; filename="ficai-export.json"'
+  assertEquals "export-${TEST_TS}@example.com" "$( show_output | jq -r .account.email )"
+  assertEquals '"show"' "$( show_output | jq .preferences.nsfw )"
+  assertEquals '[["Worm",true],["fluff",false]]' "$( show_output | jq -c '[.signals[] | [.tag, .signal]] | sort' )"
+  assertEquals "403" "$( curl -s -o /dev/null -w "%{http_code}" -b /dev/null "http://$FICAI_LISTEN/v1/accounts/export" )"
+}
+
+testCaptcha() {
+  create_with_captcha() {
+    request "http://$FICAI_LISTEN/v1/accounts" -b /dev/null -c /dev/null \
+      -X POST -H "Content-Type: application/json" \
+      --data-binary "{\"email\":\"captcha-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\"$1}"
+  }
+
+  create_with_captcha ""
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'captcha token required'
+  create_with_captcha ',"captchaToken":"robot"'
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'captcha failed'
+  assertEquals "no account without a solved captcha" "" \
+    "$( sql "select id from account where email = 'captcha-${TEST_TS}@example.com'" )"
+  create_with_captcha ',"captchaToken":"solved-1"'
+  assertStatus 'HTTP/1.1 201 Created'
+}
+
+testAccountStatus() {
+  local MOD_JAR="$SHUNIT_TMPDIR/status-mod.cookies"
+  local JAR="$SHUNIT_TMPDIR/status.cookies"
+  local EMAIL="status-${TEST_TS}@example.com"
+  local ID
+  curl -s -o /dev/null -b /dev/null -c "$MOD_JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"status-mod-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  sql "insert into account_permission (account_id, permission)
+    select id, 'user-moderation' from account where email = 'status-mod-${TEST_TS}@example.com'"
+  ID="$( curl -s -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}" | jq .id )"
+  put_status() {
+    request "http://$FICAI_LISTEN/v1/admin/accounts/$ID/status" -b "$MOD_JAR" -c "$MOD_JAR" \
+      -X PUT -H "Content-Type: application/json" --data-binary "$1"
+  }
+
+  request "http://$FICAI_LISTEN/v1/admin/accounts/$ID/status" -b "$JAR" -c "$JAR" \
+    -X PUT -H "Content-Type: application/json" --data-binary '{"status":"banned"}'
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  put_status '{"status":"suspended","reason":"spam"}'
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'suspensions need an end in the future'
+
+  put_status '{"status":"suspended","reason":"spam","until":"2100-01-01T00:00:00Z"}'
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/preferences" -b "$JAR" -c "$JAR"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  assertError 'account suspended'
+  assertEquals '{"status":"suspended","reason":"spam","until":"2100-01-01T00:00:00Z"}' \
+    "$( show_output | jq -c .error.restriction )"
+  request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\"}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  assertError 'account suspended'
+
+  put_status '{"status":"banned","reason":"spam"}'
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/preferences" -b "$JAR" -c "$JAR"
+  assertError 'account banned'
+  assertEquals 'null' "$( show_output | jq .error.restriction.until )"
+
+  sql "update account set status = 'suspended', suspended_until = now() - interval '1 second' where id = $ID"
+  request "http://$FICAI_LISTEN/v1/preferences" -b "$JAR" -c "$JAR"
+  assertStatus 'HTTP/1.1 200 OK'
+
+  put_status '{"status":"banned"}'
+  put_status '{"status":"active","reason":"ignored"}'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals 'null' "$( show_output | jq .reason )"
+  request "http://$FICAI_LISTEN/v1/preferences" -b "$JAR" -c "$JAR"
+  assertStatus 'HTTP/1.1 200 OK'
+}
+
+testPepperRotation() {
+  local EMAIL="pepper-${TEST_TS}@example.com"
+  local ID
+  ID="$( curl -s -b /dev/null -c /dev/null "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}" | jq .id )"
+  login() {
+    request "http://$FICAI_LISTEN/v1/sessions" -b /dev/null -c /dev/null \
+      -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\"}"
+  }
+  # "t2" in unpadded base64
+  assertContains "$( sql "select password_hash from account where id = $ID" )" ',keyid=dDI$'
+
+  sql "update account set password_hash = replace(password_hash, ',keyid=dDI', '') where id = $ID"
+  login
+  assertStatus 'HTTP/1.1 200 OK'
+  assertContains "hashes made with a retired pepper are replaced" \
+    "$( sql "select password_hash from account where id = $ID" )" ',keyid=dDI$'
+
+  sql "update account set password_hash = replace(password_hash, ',keyid=dDI', ',keyid=eHg') where id = $ID"
+  login
+  assertStatus 'HTTP/1.1 403 Forbidden'
+}
+
+testSignedSessionCookie() {
+  local JAR="$SHUNIT_TMPDIR/signed.cookies"
+  local COOKIE
+  curl -s -o /dev/null -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"signed-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  COOKIE="$( grep FicAiSession "$JAR" | cut -f 7 )"
+  assertContains "$COOKIE" '.'
+  session_with() {
+    curl -s -o /dev/null -w "%{http_code}" -b "FicAiSession=$1" "http://$FICAI_LISTEN/v1/preferences"
+  }
+
+  assertEquals 200 "$( session_with "$COOKIE" )"
+  assertEquals "unsigned" 401 "$( session_with "${COOKIE%%.*}" )"
+  assertEquals "tampered" 401 "$( session_with "${COOKIE%%.*}.AAAAAAAAAAAAAAAAAAAAAA" )"
+
+  # Public routes treat a bad cookie as no session, and clear it.
+  local HEADERS
+  HEADERS="$( curl -s -o /dev/null -D - -b "FicAiSession=${COOKIE%%.*}" \
+    "http://$FICAI_LISTEN/v1/tags/signed-cookie-${TEST_TS}/history" )"
+  assertContains "$HEADERS" " 200 "
+  assertContains "$HEADERS" "FicAiSession=;"
+}
+
+testAdminAccountSearch() {
+  local MOD_JAR="$SHUNIT_TMPDIR/search-mod.cookies"
+  local ID
+  curl -s -o /dev/null -b /dev/null -c "$MOD_JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"search-mod-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  ID="$( curl -s -b /dev/null -c /dev/null "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"Search-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}" | jq .id )"
+  sql "insert into signal (account_id, url, tag, tag_canonical, signal) values
+    ($ID, 'https://example.com/search/1', 'Worm', 'worm', true)"
+  search() {
+    request "http://$FICAI_LISTEN/v1/admin/accounts?$1" -b "$MOD_JAR" -c "$MOD_JAR"
+  }
+
+  search "q=search-${TEST_TS}"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+
+  sql "insert into account_permission (account_id, permission)
+    select id, 'user-moderation' from account where email = 'search-mod-${TEST_TS}@example.com'"
+  search "q=search-${TEST_TS}"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "[$ID]" "$( show_output | jq -c '[.accounts[].id]' )"
+  assertEquals '[1,1,0]' "$( show_output | jq -c '.accounts[0] | [.signals, .sessions, .tokens]' )"
+  search "q=$ID"
+  assertEquals "$ID" "$( show_output | jq '.accounts[0].id' )"
+
+  search "q=search-${TEST_TS}&status=banned"
+  assertEquals '[]' "$( show_output | jq -c .accounts )"
+  search "q=search-${TEST_TS}&createdAfter=2100-01-01T00:00:00Z"
+  assertEquals '[]' "$( show_output | jq -c .accounts )"
+  search "q=${TEST_TS}&limit=1"
+  assertEquals "[$ID]" "$( show_output | jq -c '[.accounts[].id]' )"
+  search "q=${TEST_TS}&limit=1&before=$ID"
+  assertEquals "search-mod-${TEST_TS}@example.com" "$( show_output | jq -r '.accounts[0].email' )"
+}
+
+testMergeAccounts() {
+  local ADMIN_JAR="$SHUNIT_TMPDIR/merge-admin.cookies"
+  local SOURCE_JAR="$SHUNIT_TMPDIR/merge-source.cookies"
+  local SOURCE TARGET
+  create() {
+    curl -s -b /dev/null -c "$2" "http://$FICAI_LISTEN/v1/accounts" \
+      -X POST -H "Content-Type: application/json" \
+      --data-binary "{\"email\":\"$1-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}" | jq .id
+  }
+  create merge-admin "$ADMIN_JAR" > /dev/null
+  set_role "merge-admin-${TEST_TS}@example.com" admin
+  SOURCE="$( create merge-source "$SOURCE_JAR" )"
+  TARGET="$( create merge-target /dev/null )"
+  sql "insert into signal (account_id, url, tag, tag_canonical, signal, updated_at) values
+    ($SOURCE, 'https://example.com/merge/1', 'Worm', 'worm', true, now()),
+    ($SOURCE, 'https://example.com/merge/2', 'fluff', 'fluff', false, now()),
+    ($SOURCE, 'https://example.com/merge/3', 'angst', 'angst', true, now() - interval '1 day'),
+    ($TARGET, 'https://example.com/merge/2', 'fluff', 'fluff', true, now() - interval '1 day'),
+    ($TARGET, 'https://example.com/merge/3', 'angst', 'angst', false, now())"
+  merge() {
+    request "http://$FICAI_LISTEN/v1/admin/accounts/$1/merge" -b "$ADMIN_JAR" -c "$ADMIN_JAR" \
+      -X POST -H "Content-Type: application/json" --data-binary "{\"into\":$2}"
+  }
+
+  merge "$SOURCE" "$SOURCE"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  merge "$SOURCE" "$TARGET"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "{\"into\":$TARGET,\"signalsMoved\":2,\"conflicts\":2}" "$( show_output | jq -c . )"
+  assertEquals 'the newer signal wins' '1|t
+2|f
+3|f' "$( sql "select substring(url from '[0-9]+$'), signal from signal where account_id = $TARGET order by url" )"
+  assertEquals 0 "$( sql "select count(1) from signal where account_id = $SOURCE" )"
+  assertEquals "403" "$( curl -s -o /dev/null -w "%{http_code}" -b "$SOURCE_JAR" "http://$FICAI_LISTEN/v1/preferences" )"
+  merge "$SOURCE" "$TARGET"
+  assertStatus 'HTTP/1.1 404 Not Found'
+}
+
+testMagicLink() {
+  local EMAIL="magic-${TEST_TS}@example.com"
+  local TOKEN
+  curl -s -o /dev/null -b /dev/null -c /dev/null "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  request_link() {
+    request "http://$FICAI_LISTEN/v1/sessions/magic-link" -b /dev/null -c /dev/null \
+      -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$1\"}"
+  }
+  # Redirects rather than answering with JSON, so not through `request`.
+  visit() {
+    curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/out" -b /dev/null -c "$SHUNIT_TMPDIR/magic.cookies" \
+      "http://$FICAI_LISTEN/v1/sessions/magic-link?token=$1"
+  }
+
+  request_link "${TEST_TS}.nobody@example.com"
+  assertStatus 'HTTP/1.1 200 OK'
+  request_link "$EMAIL"
+  assertStatus 'HTTP/1.1 200 OK'
+  sleep 1
+  # without an SMTP server, mail goes to the server's output
+  TOKEN="$( grep -A5 "mail to $EMAIL" test.log | grep -o 'token=[A-Za-z0-9_-]*' | tail -1 | cut -d= -f2 )"
+  assertNotEquals "" "$TOKEN"
+
+  visit bogus
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  visit "$TOKEN"
+  assertStatus 'HTTP/1.1 302 Found'
+  assertContains "$( show_headers )" "location: https://$FICAI_DOMAIN/"
+  assertTrue "cookie must be set" "grep -q FicAiSession $SHUNIT_TMPDIR/magic.cookies"
+  request "http://$FICAI_LISTEN/v1/sessions" -b "$SHUNIT_TMPDIR/magic.cookies" -c "$SHUNIT_TMPDIR/magic.cookies"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "$EMAIL" "$( show_output | jq -r .email )"
+  visit "$TOKEN"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'invalid or expired token'
+
+  # A link sent before two-factor authentication was enabled doesn't bypass it.
+  local USED="$TOKEN"
+  request_link "$EMAIL"
+  sleep 1
+  TOKEN="$( grep -A5 "mail to $EMAIL" test.log | grep -o 'token=[A-Za-z0-9_-]*' | tail -1 | cut -d= -f2 )"
+  assertNotEquals "$USED" "$TOKEN"
+  sql "insert into account_totp (account_id, secret, enabled_at) select id, '\\x00', now() from account where email = '$EMAIL'"
+  visit "$TOKEN"
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  assertError 'invalid or expired token'
+}
+
+testWriteLimit() {
+  local ADMIN_JAR="$SHUNIT_TMPDIR/limit-admin.cookies"
+  local JAR="$SHUNIT_TMPDIR/limit.cookies"
+  local ID
+  curl -s -o /dev/null -b /dev/null -c "$ADMIN_JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"limit-admin-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  set_role "limit-admin-${TEST_TS}@example.com" admin
+  ID="$( curl -s -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"limit-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}" | jq .id )"
+  put_tier() {
+    request "http://$FICAI_LISTEN/v1/admin/accounts/$ID/rate-limit-tier" -b "$1" -c "$1" \
+      -X PUT -H "Content-Type: application/json" --data-binary "{\"tier\":\"$2\"}"
+  }
+  write() {
+    curl -s -o /dev/null -w "%{http_code}" -b "$JAR" -c "$JAR" "http://$FICAI_LISTEN/v1/signals" \
+      -X PATCH -H "Content-Type: application/json" --data-binary "$( build_patch_body "$TEST_URL" "$@" )"
+  }
+
+  put_tier "$JAR" trusted-importer
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  put_tier "$ADMIN_JAR" restricted
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals '{"tier":"restricted"}' "$( show_output | jq -c . )"
+  # Each tag counts, and more than the bucket holds never fits.
+  assertEquals 400 "$( write +limited +limited2 %limited3 )"
+  assertEquals 200 "$( write +limited -limited2 )"
+  assertEquals 429 "$( write +limited )"
+
+  put_tier "$ADMIN_JAR" normal
+  assertEquals 200 "$( write +limited )"
+}
+
+testMaxSessions() {
+  local EMAIL="max-sessions-${TEST_TS}@example.com"
+  local i
+  curl -s -o /dev/null -b /dev/null -c "$SHUNIT_TMPDIR/max-sessions-0.cookies" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}"
+  for i in $( seq "$FICAI_MAX_SESSIONS_PER_ACCOUNT" ); do
+    curl -s -o /dev/null -b /dev/null -c "$SHUNIT_TMPDIR/max-sessions-$i.cookies" "http://$FICAI_LISTEN/v1/sessions" \
+      -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$EMAIL\",\"password\":\"pass\"}"
+  done
+  session_status() {
+    curl -s -o /dev/null -w '%{http_code}' -b "$SHUNIT_TMPDIR/max-sessions-$1.cookies" "http://$FICAI_LISTEN/v1/sessions"
+  }
+
+  assertEquals "oldest session ended" 403 "$( session_status 0 )"
+  for i in $( seq "$FICAI_MAX_SESSIONS_PER_ACCOUNT" ); do
+    assertEquals "session $i" 200 "$( session_status $i )"
+  done
+  assertEquals "$FICAI_MAX_SESSIONS_PER_ACCOUNT" "$( sql "select count(*) from session join account on account.id = account_id where email = '$EMAIL'" )"
+}
+
+testAccountStats() {
+  local JAR="$SHUNIT_TMPDIR/stats.cookies"
+  local ID="$( curl -s -b /dev/null -c "$JAR" "http://$FICAI_LISTEN/v1/accounts" \
+    -X POST -H "Content-Type: application/json" \
+    --data-binary "{\"email\":\"stats-${TEST_TS}@example.com\",\"password\":\"pass\",\"inviteCode\":\"$TEST_INVITE\",\"captchaToken\":\"solved\"}" | jq .id )"
+  sql "insert into signal (account_id, url, tag, tag_canonical, signal, created_at) values
+    ($ID, 'https://example.com/stats/1', 'Worm', 'worm', true, now()),
+    ($ID, 'https://example.com/stats/1', 'Fluff', 'fluff', false, now()),
+    ($ID, 'https://example.com/stats/2', 'Worm', 'worm', true, now() - interval '2 days'),
+    ($ID, 'https://example.com/stats/3', 'Worm', 'worm', false, now() - interval '200 days')"
+  stats() {
+    request "http://$FICAI_LISTEN/v1/accounts/stats?$1" -b "$JAR" -c "$JAR"
+  }
+
+  stats ''
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals '[3,2,2]' "$( show_output | jq -c '[.urlsTagged, .signalsFor, .signalsAgainst]' )"
+  assertEquals '[{"tag":"worm","signalsFor":2,"signalsAgainst":1},{"tag":"fluff","signalsFor":0,"signalsAgainst":1}]' \
+    "$( show_output | jq -c .topTags )"
+  assertEquals day "$( show_output | jq -r .bucket )"
+  assertEquals '[2,1]' "$( show_output | jq -c '[(.series | map(.signalsFor) | add), (.series | map(.signalsAgainst) | add)]' )"
+  assertEquals '{"signalsFor":1,"signalsAgainst":1}' "$( show_output | jq -c '.series[-1] | {signalsFor, signalsAgainst}' )"
+
+  stats 'bucket=week&window=52w'
+  assertEquals '[2,2]' "$( show_output | jq -c '[(.series | map(.signalsFor) | add), (.series | map(.signalsAgainst) | add)]' )"
+  stats 'window=forever'
+  assertStatus 'HTTP/1.1 400 Bad Request'
+  curl -s -o /dev/null -w '%{http_code}' -b /dev/null "http://$FICAI_LISTEN/v1/accounts/stats" > "$SHUNIT_TMPDIR/out"
+  assertEquals 403 "$( cat "$SHUNIT_TMPDIR/out" )"
+}
+
+testCors() {
+  from() {
+    local ORIGIN="$1"
+    shift
+    curl -s -D "$SHUNIT_TMPDIR/headers" -o "$SHUNIT_TMPDIR/out" -b /dev/null -H "Origin: $ORIGIN" "$@"
+  }
+
+  from https://app.example.com "http://$FICAI_LISTEN/v1/tags" -X OPTIONS \
+    -H 'Access-Control-Request-Method: PATCH' -H 'Access-Control-Request-Headers: content-type'
+  assertStatus 'HTTP/1.1 200 OK'
+  assertContains "$( show_headers )" 'access-control-allow-origin: https://app.example.com'
+  assertContains "$( show_headers )" 'access-control-allow-credentials: true'
+  assertContains "$( show_headers | grep access-control-allow-methods )" 'PATCH'
+
+  from https://app.example.com "http://$FICAI_LISTEN/v1/tags"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertContains "$( show_headers )" 'access-control-allow-origin: https://app.example.com'
+  # errors too, so that clients can read them
+  from https://app.example.com "http://$FICAI_LISTEN/v1/tokens"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  assertContains "$( show_headers )" 'access-control-allow-origin: https://app.example.com'
+
+  from https://evil.example.com "http://$FICAI_LISTEN/v1/tags"
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  assertError 'origin not allowed'
+  from https://evil.example.com "http://$FICAI_LISTEN/v1/tags" -X OPTIONS \
+    -H 'Access-Control-Request-Method: GET'
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  assertNotContains "$( show_headers )" 'access-control-allow-origin'
+}
+
+testCreateSessionWithWrongEmail() {
+  request "http://$FICAI_LISTEN/v1/sessions" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL2\",\"password\":\"pass\"}"
+
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  assertError 'forbidden'
+}
+
+testCreateSessionWithWrongPassword() {
+  request "http://$FICAI_LISTEN/v1/sessions" \
+    -X POST -H "Content-Type: application/json" --data-binary "{\"email\":\"$TEST_EMAIL1\",\"password\":\"wrong pass\"}"
+
+  assertStatus 'HTTP/1.1 403 Forbidden'
+  assertError 'forbidden'
+  assertEquals "same as for an unknown email" "$( show_output )" "$(
+    curl -s "http://$FICAI_LISTEN/v1/sessions" -X POST -H "Content-Type: application/json" \
+      --data-binary "{\"email\":\"$TEST_EMAIL2\",\"password\":\"wrong pass\"}"
+  )"
+}
+
+testUnauthorizedGetSignalsEmpty() {
+  assertTrue "cookie must be set" "grep -q FicAiSession test.cookies"
+  request "http://$FICAI_LISTEN/v1/sessions" -X DELETE
+
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals "{}" "$( show_output )"
+  assertFalse "cookie must not be set" "grep -q FicAiSession test.cookies"
+
+  request_get
+  assertStatus 'HTTP/1.1 200 OK'
+  assertNoSignal worm
+  assertNoSignal "taylor hebert"
+  assertNoSignal taylor
+}
+
+testGetBex() {
+  request "http://$FICAI_LISTEN/v1/bex/versions/v0.1.0"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals false "$( extractRetired )"
+  assertEquals "${FICAI_BEX_LATEST_VERSION}" "$( extractLatestVersion )"
+
+  request "http://$FICAI_LISTEN/v1/bex/versions/v0.0.0"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertEquals true "$( extractRetired )"
+  assertEquals "${FICAI_BEX_LATEST_VERSION}" "$( extractLatestVersion )"
+}
+
+fichub_requests() {
+  curl -s "http://$FAKE_FICHUB_LISTEN/requests" -G --data-urlencode "q=$1" | jq -r .requests
+}
+
+testGetVersion() {
+  request "http://$FICAI_LISTEN/v1/meta/version"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertNotEquals 'null' "$( show_output | jq -r .gitCommit )"
+  assertNotEquals 'null' "$( show_output | jq -r .builtAt )"
+  assertEquals "$( show_output | jq -r .expectedSchemaVersion )" \
+    "$( show_output | jq -r .schemaVersion )"
+  assertNotContains "$( cat test.log )" "$FICAI_DB_PASSWORD"
+}
+
+testCaching() {
+  request "http://$FICAI_LISTEN/v1/tags"
+  assertStatus 'HTTP/1.1 200 OK'
+  assertContains "$( show_headers | grep cache-control )" 'public, max-age=60'
+  assertContains "$( show_headers | grep vary )" 'accept-language'
+  local ETAG="$( show_headers | grep etag | cut -d' ' -f2 | tr -d '\r' )"
+  assertNotEquals '' "$ETAG"
+
+  # Not JSON, so not with `request`.
+  assertEquals 304 "$( curl -s -o /dev/null -w '%{http_code}' -H "If-None-Match: $ETAG" \
+    "http://$FICAI_LISTEN/v1/tags" )"
+
+  request "http://$FICAI_LISTEN/v1/tags" -H 'If-None-Match: W/"stale"'
+  assertStatus 'HTTP/1.1 200 OK'
+
+  request "http://$FICAI_LISTEN/v1/sessions"
+  assertContains "$( show_headers | grep cache-control )" 'no-store'
+}
+
+# Last, as it uses up the limit of its route.
+testIpLimit() {
+  local I
+  for I in 1 2 3 4 5; do
+    request "http://$FICAI_LISTEN/v1/bex/versions/v0.1.0" -H 'X-Forwarded-For: 203.0.113.7'
+    assertStatus 'HTTP/1.1 200 OK'
+  done
+  request "http://$FICAI_LISTEN/v1/bex/versions/v0.1.0" -H 'X-Forwarded-For: 203.0.113.7'
+  assertStatus 'HTTP/1.1 429 Too Many Requests'
+  assertError 'too many requests'
+  assertContains "$( show_headers )" 'ratelimit-limit: 5'
+  assertContains "$( show_headers )" 'ratelimit-remaining: 0'
+  assertContains "$( show_headers )" 'retry-after: '
+
+  # Other addresses and routes have limits of their own.
+  request "http://$FICAI_LISTEN/v1/bex/versions/v0.1.0" -H 'X-Forwarded-For: 203.0.113.8'
+  assertStatus 'HTTP/1.1 200 OK'
+  request "http://$FICAI_LISTEN/v1/meta/version" -H 'X-Forwarded-For: 203.0.113.7'
   assertStatus 'HTTP/1.1 200 OK'
-  assertEquals true "$( extractRetired )"
-  assertEquals "${FICAI_BEX_LATEST_VERSION}" "$( extractLatestVersion )"
 }
 
 source shunit2